@@ -0,0 +1,21 @@
+use std::process::Command;
+
+/// Records the compiler and target the crate is built with, as dynamic modules share Rust types with
+/// the server and are only loaded if they were built the same way, see `module::BUILD`.
+fn main() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+
+    let version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|version| version.trim().to_string())
+        .unwrap_or_else(|| "unknown rustc".to_string());
+
+    let target = std::env::var("TARGET").unwrap_or_default();
+
+    println!("cargo:rustc-env=REDIS_RUST_BUILD={} {} {}", env!("CARGO_PKG_VERSION"), version, target);
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=RUSTC");
+}
@@ -0,0 +1,139 @@
+//! A module showing off the module API: a counter data type, a keyspace event hook and a timer.
+//!
+//! Build it with `cargo build --example hello_module`, then load it with
+//! `--loadmodule target/debug/examples/libhello_module.so` or MODULE LOAD.
+
+use anyhow::{anyhow, Result};
+use redis_rust::module::{Command, CommandContext, KeyspaceEvent, Module, ModuleRegistry, ModuleType, ModuleValue, Value, NOTIFY_ALL, NOTIFY_GENERIC, NOTIFY_STRING};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const COUNTER_TYPE: &str = "hellocntr";
+
+#[derive(Clone, Debug)]
+struct Counter(i64);
+
+struct CounterType;
+impl ModuleType for CounterType {
+    fn name(&self) -> &str {
+        COUNTER_TYPE
+    }
+
+    fn rdb_save(&self, value: &ModuleValue) -> Vec<u8> {
+        value.downcast_ref::<Counter>().map_or(0, |counter| counter.0).to_le_bytes().to_vec()
+    }
+
+    fn rdb_load(&self, bytes: &[u8], _encoding_version: u16) -> Result<ModuleValue> {
+        let count = i64::from_le_bytes(bytes.try_into().map_err(|_| anyhow!("A counter is 8 bytes long"))?);
+        Ok(ModuleValue::new(COUNTER_TYPE, Counter(count)))
+    }
+}
+
+/// HELLO.INCR <key>, logged to the AOF and replicas as it is, since it is a write command.
+struct IncrCommand;
+impl Command for IncrCommand {
+    fn name(&self) -> &str {
+        "hello.incr"
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let Some(key) = args.into_iter().next().and_then(Value::unpack_as_string) else {
+            return Ok(Value::SimpleError("ERR wrong number of arguments for 'hello.incr' command".to_string()));
+        };
+
+        let storage = context.storage();
+
+        let count = match storage.get_mut(&key) {
+            Some(Value::Module(value)) => match value.downcast_mut::<Counter>() {
+                Some(counter) => {
+                    counter.0 += 1;
+                    counter.0
+                }
+
+                None => return Ok(Value::SimpleError("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()))
+            },
+
+            Some(_) => return Ok(Value::SimpleError("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())),
+            None => {
+                storage.set(&key, Value::Module(ModuleValue::new(COUNTER_TYPE, Counter(1))), None);
+                1
+            }
+        };
+
+        context.notify_keyspace_event(NOTIFY_GENERIC, "hello.incr", &key);
+        Ok(Value::Integer(count))
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+}
+
+/// HELLO.EVENTS, how many keyspace events the module has seen.
+struct EventsCommand(Arc<AtomicI64>);
+impl Command for EventsCommand {
+    fn name(&self) -> &str {
+        "hello.events"
+    }
+
+    fn exec(&self, _args: Vec<Value>, _context: &mut CommandContext) -> Result<Value> {
+        Ok(Value::Integer(self.0.load(Ordering::SeqCst)))
+    }
+}
+
+/// HELLO.LATER <key> <milliseconds>, sets the key to "done" once the delay has passed.
+struct LaterCommand;
+impl Command for LaterCommand {
+    fn name(&self) -> &str {
+        "hello.later"
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let args: Vec<String> = args.into_iter().filter_map(Value::unpack_as_string).collect();
+
+        let [key, delay] = args.as_slice() else {
+            return Ok(Value::SimpleError("ERR wrong number of arguments for 'hello.later' command".to_string()));
+        };
+
+        let Ok(delay) = delay.parse::<u64>() else {
+            return Ok(Value::SimpleError("ERR value is not an integer or out of range".to_string()));
+        };
+
+        let key = key.clone();
+
+        // Timers run outside of any command, so nothing is logged unless they replicate their writes.
+        context.create_timer(Duration::from_millis(delay), move |context| {
+            context.storage().set(&key, Value::BulkString("done".into()), None);
+            context.notify_keyspace_event(NOTIFY_STRING, "set", &key);
+            context.replicate(vec!["SET", key.as_str(), "done"]);
+        });
+
+        Ok(Value::SimpleString("OK".to_string()))
+    }
+}
+
+struct HelloModule;
+impl Module for HelloModule {
+    fn name(&self) -> &str {
+        "hello"
+    }
+
+    fn on_load(&self, registry: &mut ModuleRegistry, _args: &[String]) -> Result<()> {
+        let events = Arc::new(AtomicI64::new(0));
+        let seen = Arc::clone(&events);
+
+        registry.register_type(Box::new(CounterType))?;
+        registry.register_command(Box::new(IncrCommand))?;
+        registry.register_command(Box::new(EventsCommand(events)))?;
+        registry.register_command(Box::new(LaterCommand))?;
+
+        registry.subscribe_to_keyspace_events(NOTIFY_ALL, move |_: &mut CommandContext, _: &KeyspaceEvent| {
+            seen.fetch_add(1, Ordering::SeqCst);
+        });
+
+        Ok(())
+    }
+}
+
+redis_rust::declare_module!(HelloModule);
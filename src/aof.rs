@@ -0,0 +1,738 @@
+use crate::parser::Value;
+use crate::persistence::Snapshot;
+use crate::stream::{Stream, StreamId};
+use anyhow::{anyhow, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+/// How often the `everysec` policy flushes the AOF to disk.
+const FSYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When the AOF is flushed to disk, the `appendfsync` config.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FsyncPolicy {
+    /// After every write, before the client gets its reply.
+    Always,
+    /// Once per second, on another thread, so at most a second of writes can be lost.
+    EverySec,
+    /// Whenever the operating system decides to.
+    No
+}
+
+impl FsyncPolicy {
+    pub fn parse(value: &str) -> Option<FsyncPolicy> {
+        match value.to_lowercase().as_str() {
+            "always" => Some(FsyncPolicy::Always),
+            "everysec" => Some(FsyncPolicy::EverySec),
+            "no" => Some(FsyncPolicy::No),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AofFileType {
+    /// The dataset as it was when the AOF was last rewritten, as an RDB file or as commands.
+    Base,
+    /// A file replaced by a rewrite, kept in the manifest until it is deleted.
+    History,
+    /// Commands logged after the base was written.
+    Incremental
+}
+
+impl AofFileType {
+    fn code(self) -> &'static str {
+        match self {
+            AofFileType::Base => "b",
+            AofFileType::History => "h",
+            AofFileType::Incremental => "i"
+        }
+    }
+
+    fn from_code(code: &str) -> Option<AofFileType> {
+        match code {
+            "b" => Some(AofFileType::Base),
+            "h" => Some(AofFileType::History),
+            "i" => Some(AofFileType::Incremental),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AofFile {
+    pub name: String,
+    pub seq: u64,
+    pub file_type: AofFileType
+}
+
+/// Lists the files an AOF is made of, like Redis 7's multi part AOF: a base file followed by
+/// incremental files, replayed in order. It is stored next to them as `<appendfilename>.manifest`.
+#[derive(Clone, Debug, Default)]
+pub struct Manifest {
+    pub base: Option<AofFile>,
+    pub incremental: Vec<AofFile>,
+    pub history: Vec<AofFile>
+}
+
+impl Manifest {
+    /// Reads the manifest of the AOF named `file_name` in `dir`, if there is one.
+    pub fn load(dir: &Path, file_name: &str) -> Result<Option<Manifest>> {
+        let text = match std::fs::read_to_string(dir.join(manifest_name(file_name))) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into())
+        };
+
+        Manifest::parse(&text).map(Some)
+    }
+
+    /// Parses lines like `file appendonly.aof.1.base.rdb seq 1 type b`.
+    pub fn parse(text: &str) -> Result<Manifest> {
+        let mut manifest = Manifest::default();
+
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            if !words.len().is_multiple_of(2) {
+                return Err(anyhow!("Invalid AOF manifest line '{}'", line));
+            }
+
+            let (mut name, mut seq, mut file_type) = (None, None, None);
+
+            for pair in words.chunks(2) {
+                match pair[0] {
+                    "file" => name = Some(pair[1].to_string()),
+                    "seq" => seq = pair[1].parse::<u64>().ok(),
+                    "type" => file_type = AofFileType::from_code(pair[1]),
+                    // Unknown keys are skipped, so manifests written by newer versions still load.
+                    _ => {}
+                }
+            }
+
+            let (Some(name), Some(seq), Some(file_type)) = (name, seq, file_type) else {
+                return Err(anyhow!("Invalid AOF manifest line '{}'", line));
+            };
+
+            let file = AofFile { name, seq, file_type };
+
+            match file_type {
+                AofFileType::Base if manifest.base.is_some() => return Err(anyhow!("The AOF manifest lists more than one base file")),
+                AofFileType::Base => manifest.base = Some(file),
+                AofFileType::History => manifest.history.push(file),
+                AofFileType::Incremental => {
+                    if manifest.incremental.last().is_some_and(|last| last.seq >= seq) {
+                        return Err(anyhow!("The incremental files of the AOF manifest are out of order"));
+                    }
+
+                    manifest.incremental.push(file);
+                }
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn format(&self) -> String {
+        self.base.iter()
+            .chain(&self.history)
+            .chain(&self.incremental)
+            .map(|file| format!("file {} seq {} type {}\n", file.name, file.seq, file.file_type.code()))
+            .collect()
+    }
+
+    /// Writes the manifest to a temporary file, then moves it in place, so it always lists files that exist.
+    pub fn save(&self, dir: &Path, file_name: &str) -> Result<()> {
+        let temp_path = dir.join(format!("temp-{}", manifest_name(file_name)));
+
+        let mut file = File::create(&temp_path)?;
+        file.write_all(self.format().as_bytes())?;
+        file.sync_all()?;
+
+        std::fs::rename(&temp_path, dir.join(manifest_name(file_name)))?;
+        Ok(())
+    }
+
+    /// Names a new base file, RDB formatted or made of commands, turning the current one into history.
+    pub fn new_base(&mut self, file_name: &str, rdb: bool) -> &AofFile {
+        let seq = self.base.as_ref().map(|base| base.seq + 1).unwrap_or(1);
+        let extension = if rdb { "rdb" } else { "aof" };
+
+        if let Some(mut base) = self.base.take() {
+            base.file_type = AofFileType::History;
+            self.history.push(base);
+        }
+
+        self.base.insert(AofFile { name: format!("{}.{}.base.{}", file_name, seq, extension), seq, file_type: AofFileType::Base })
+    }
+
+    /// Names a new incremental file, which writes go to from now on.
+    pub fn new_incremental(&mut self, file_name: &str) -> &AofFile {
+        let seq = self.incremental.iter().chain(&self.history)
+            .filter(|file| file.name.ends_with(".incr.aof"))
+            .map(|file| file.seq + 1)
+            .max()
+            .unwrap_or(1);
+
+        self.incremental.push(AofFile { name: format!("{}.{}.incr.aof", file_name, seq), seq, file_type: AofFileType::Incremental });
+        self.incremental.last().unwrap()
+    }
+
+    /// Turns the incremental files before sequence `keep_from` into history, once a new base covers
+    /// what they logged. Without `keep_from`, all of them are.
+    pub fn retire_incremental(&mut self, keep_from: Option<u64>) {
+        let (retired, kept) = self.incremental.drain(..).partition(|file| keep_from.is_none_or(|seq| file.seq < seq));
+        self.incremental = kept;
+
+        for mut file in retired {
+            file.file_type = AofFileType::History;
+            self.history.push(file);
+        }
+    }
+
+    /// Deletes the files replaced by a rewrite, then drops them from the saved manifest. Until it is
+    /// saved they are listed as history, so a crash in between never leaves a manifest naming files that are gone.
+    pub fn delete_history(&mut self, dir: &Path, file_name: &str) -> Result<()> {
+        if self.history.is_empty() {
+            return Ok(());
+        }
+
+        for file in self.history.drain(..) {
+            if let Err(e) = std::fs::remove_file(dir.join(&file.name)) {
+                println!("Unable to delete the AOF history file {}: {}", file.name, e);
+            }
+        }
+
+        self.save(dir, file_name)
+    }
+}
+
+fn manifest_name(file_name: &str) -> String {
+    format!("{}.manifest", file_name)
+}
+
+/// Encodes a command the way clients send it, as a RESP array of bulk strings.
+pub fn encode_command<A: AsRef<[u8]>>(args: &[A]) -> Vec<u8> {
+    let mut encoded = format!("*{}\r\n", args.len()).into_bytes();
+
+    for arg in args {
+        let arg = arg.as_ref();
+
+        encoded.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        encoded.extend_from_slice(arg);
+        encoded.extend_from_slice(b"\r\n");
+    }
+
+    encoded
+}
+
+pub enum AofEntry {
+    Command(Vec<Vec<u8>>),
+    End,
+    /// The file ends in the middle of a command, as it does when the server died while writing it.
+    Truncated,
+    Corrupt(String)
+}
+
+/// Reads the commands logged in an AOF file one at a time.
+pub struct AofReader<R: BufRead> {
+    reader: R,
+    offset: u64
+}
+
+impl<R: BufRead> AofReader<R> {
+    pub fn new(reader: R) -> AofReader<R> {
+        AofReader { reader, offset: 0 }
+    }
+
+    /// How many bytes the commands read so far take.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn next_entry(&mut self) -> Result<AofEntry> {
+        let mut consumed = 0;
+
+        let count = match self.read_line(&mut consumed)? {
+            Line::End if consumed == 0 => return Ok(AofEntry::End),
+            Line::End => return Ok(AofEntry::Truncated),
+            Line::Text(line) => match line.strip_prefix('*').and_then(|count| count.parse::<usize>().ok()) {
+                Some(count) if count > 0 => count,
+                _ => return Ok(AofEntry::Corrupt(format!("expected a command, found '{}'", line)))
+            }
+        };
+
+        let mut args = Vec::with_capacity(count.min(1024));
+
+        for _ in 0..count {
+            let len = match self.read_line(&mut consumed)? {
+                Line::End => return Ok(AofEntry::Truncated),
+                Line::Text(line) => match line.strip_prefix('$').and_then(|len| len.parse::<usize>().ok()) {
+                    Some(len) => len,
+                    None => return Ok(AofEntry::Corrupt(format!("expected a bulk string, found '{}'", line)))
+                }
+            };
+
+            let mut bytes = Vec::new();
+
+            if (&mut self.reader).take(len as u64 + 2).read_to_end(&mut bytes)? < len + 2 {
+                return Ok(AofEntry::Truncated);
+            }
+
+            consumed += bytes.len() as u64;
+
+            if !bytes.ends_with(b"\r\n") {
+                return Ok(AofEntry::Corrupt("a bulk string isn't terminated by CRLF".to_string()));
+            }
+
+            bytes.truncate(len);
+            args.push(bytes);
+        }
+
+        self.offset += consumed;
+        Ok(AofEntry::Command(args))
+    }
+
+    fn read_line(&mut self, consumed: &mut u64) -> Result<Line> {
+        let mut bytes = Vec::new();
+        self.reader.read_until(b'\n', &mut bytes)?;
+        *consumed += bytes.len() as u64;
+
+        if !bytes.ends_with(b"\r\n") {
+            return Ok(Line::End);
+        }
+
+        bytes.truncate(bytes.len() - 2);
+        Ok(Line::Text(String::from_utf8_lossy(&bytes).into_owned()))
+    }
+}
+
+/// Writes a snapshot as the commands recreating it, for a base file when `aof-use-rdb-preamble` is off.
+/// Only strings and streams can be recreated by commands, so a snapshot holding anything else fails.
+pub fn write_commands<W: Write>(snapshot: &Snapshot, mut writer: W) -> Result<W> {
+    for code in &snapshot.functions {
+        writer.write_all(&encode_command(&["FUNCTION", "LOAD", code]))?;
+    }
+
+    for (key, value, expire) in &snapshot.keys {
+        let commands = match value {
+            Value::BulkString(bytes) => {
+                let mut command = vec![b"SET".to_vec(), key.clone().into_bytes(), bytes.clone()];
+
+                if let Some(expire) = expire {
+                    let at = expire.duration_since(UNIX_EPOCH).map(|duration| duration.as_millis()).unwrap_or_default();
+                    command.extend([b"PXAT".to_vec(), at.to_string().into_bytes()]);
+                }
+
+                vec![command]
+            }
+
+            Value::Stream(stream) if expire.is_none() => stream_commands(key, stream).into_iter()
+                .map(|command| command.into_iter().map(String::into_bytes).collect())
+                .collect(),
+            _ => return Err(anyhow!("The key '{}' can't be rewritten as commands, turn on aof-use-rdb-preamble to rewrite it", key))
+        };
+
+        for command in commands {
+            writer.write_all(&encode_command(&command))?;
+        }
+    }
+
+    Ok(writer)
+}
+
+/// The commands recreating a stream: its entries, its bookkeeping, then its groups with their consumers
+/// and pending entries. Entries pending but deleted from the stream can't be claimed, so they are dropped.
+fn stream_commands(key: &str, stream: &Stream) -> Vec<Vec<String>> {
+    let to_strings = |args: &[&dyn ToString]| args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>();
+    let mut commands = Vec::new();
+
+    for entry in stream.range(StreamId::MIN, StreamId::MAX) {
+        let fields = entry.fields.into_iter().flat_map(|(field, value)| [field, value]);
+        commands.push(to_strings(&[&"XADD", &key, &entry.id]).into_iter().chain(fields).collect());
+    }
+
+    // An empty stream is created by adding an entry trimmed right away, its last ID is then set by XSETID.
+    if commands.is_empty() {
+        let id = stream.last_id().max(StreamId::new(0, 1));
+        commands.push(to_strings(&[&"XADD", &key, &"MAXLEN", &"0", &id, &"x", &"y"]));
+    }
+
+    commands.push(to_strings(&[
+        &"XSETID", &key, &stream.last_id(), &"ENTRIESADDED", &stream.entries_added(), &"MAXDELETEDID", &stream.max_deleted_entry_id()
+    ]));
+
+    for (name, group) in stream.groups() {
+        let entries_read = group.entries_read.map(|entries_read| entries_read as i64).unwrap_or(-1);
+        commands.push(to_strings(&[&"XGROUP", &"CREATE", &key, name, &group.last_delivered_id, &"ENTRIESREAD", &entries_read]));
+
+        for consumer in group.consumers().keys() {
+            commands.push(to_strings(&[&"XGROUP", &"CREATECONSUMER", &key, name, consumer]));
+        }
+
+        for (id, pending) in group.pending().iter().filter(|(id, _)| stream.get(**id).is_some()) {
+            commands.push(to_strings(&[
+                &"XCLAIM", &key, name, &pending.consumer, &"0", id,
+                &"TIME", &pending.delivery_time, &"RETRYCOUNT", &pending.delivery_count, &"FORCE", &"JUSTID"
+            ]));
+        }
+    }
+
+    commands
+}
+
+/// A rewrite of the AOF in flight: a new base file is written from a snapshot on another thread,
+/// while writes go to an incremental file opened when it started.
+pub struct AofRewrite {
+    handle: Option<JoinHandle<Result<u64>>>,
+    pub temp_path: PathBuf,
+    pub rdb: bool,
+    /// The incremental files from this sequence on hold the writes made after the snapshot. Without
+    /// it, none do, as the AOF was off when the rewrite started.
+    pub keep_from: Option<u64>,
+    /// The incremental file opened as the AOF was turned on. It is only listed in the manifest along
+    /// with the base it follows, as it misses what was written before.
+    pub pending_incremental: Option<AofFile>
+}
+
+impl AofRewrite {
+    pub fn start(snapshot: Snapshot, temp_path: PathBuf, rdb: bool, keep_from: Option<u64>, pending_incremental: Option<AofFile>) -> AofRewrite {
+        let path = temp_path.clone();
+        let handle = std::thread::spawn(move || write_base(&snapshot, &path, rdb));
+
+        AofRewrite { handle: Some(handle), temp_path, rdb, keep_from, pending_incremental }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.as_ref().is_none_or(JoinHandle::is_finished)
+    }
+
+    /// Waits for the new base file, returning its size.
+    pub fn result(&mut self) -> Result<u64> {
+        let handle = self.handle.take().ok_or_else(|| anyhow!("The AOF rewrite was already collected"))?;
+        handle.join().unwrap_or_else(|_| Err(anyhow!("The AOF rewrite thread panicked")))
+    }
+}
+
+fn write_base(snapshot: &Snapshot, path: &Path, rdb: bool) -> Result<u64> {
+    let writer = BufWriter::new(File::create(path)?);
+
+    let writer = if rdb {
+        snapshot.write(writer)?
+    } else {
+        write_commands(snapshot, writer)?
+    };
+
+    let file = writer.into_inner()?;
+    file.sync_all()?;
+    Ok(file.metadata()?.len())
+}
+
+enum Line {
+    Text(String),
+    /// The file ended before the line did.
+    End
+}
+
+/// The incremental file writes are currently appended to.
+pub struct AppendOnlyFile {
+    file: File,
+    pub fsync: FsyncPolicy,
+    /// The size of the files replayed before this one: the base and older incremental files.
+    pub preceding_size: u64,
+    /// The size of the AOF right after it was last rewritten, which automatic rewrites measure its growth from.
+    pub base_size: u64,
+    written: u64,
+    /// Whether something was written since the last fsync.
+    unsynced: bool,
+    last_fsync: Instant,
+    background_fsync: Option<JoinHandle<std::io::Result<()>>>
+}
+
+impl AppendOnlyFile {
+    pub fn open(path: &Path, fsync: FsyncPolicy) -> Result<AppendOnlyFile> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow!("Can't open the append-only file {}: {}", path.display(), e))?;
+
+        let written = file.metadata()?.len();
+
+        Ok(AppendOnlyFile {
+            file,
+            fsync,
+            preceding_size: 0,
+            base_size: 0,
+            written,
+            unsynced: false,
+            last_fsync: Instant::now(),
+            background_fsync: None
+        })
+    }
+
+    /// The size of the whole AOF, every file listed in the manifest included.
+    pub fn size(&self) -> u64 {
+        self.preceding_size + self.written
+    }
+
+    /// Logs commands, already RESP encoded. With `appendfsync always` they reach the disk before this returns.
+    pub fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.file.write_all(data)?;
+        self.written += data.len() as u64;
+        self.unsynced = true;
+
+        if self.fsync == FsyncPolicy::Always {
+            self.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes what was written to disk right away, waiting for an fsync already in flight first.
+    pub fn sync(&mut self) -> Result<()> {
+        if let Some(handle) = self.background_fsync.take() {
+            let _ = handle.join();
+        }
+
+        self.file.sync_data()?;
+        self.unsynced = false;
+        self.last_fsync = Instant::now();
+        Ok(())
+    }
+
+    /// Whether everything written is on disk, an fsync in flight only counting once it is done.
+    pub fn is_synced(&self) -> bool {
+        !self.unsynced && self.background_fsync.as_ref().is_none_or(|handle| handle.is_finished())
+    }
+
+    /// Starts the once per second fsync of `appendfsync everysec` when it is due. It runs on its own
+    /// thread, as the disk may take long to answer.
+    pub fn cron(&mut self) {
+        if self.fsync != FsyncPolicy::EverySec || !self.unsynced || self.last_fsync.elapsed() < FSYNC_INTERVAL {
+            return;
+        }
+
+        if self.background_fsync.as_ref().is_some_and(|handle| !handle.is_finished()) {
+            return;
+        }
+
+        if let Some(Ok(Err(e))) = self.background_fsync.take().map(JoinHandle::join) {
+            println!("Fsync of the append-only file failed: {}", e);
+        }
+
+        match self.file.try_clone() {
+            Ok(file) => {
+                self.background_fsync = Some(std::thread::spawn(move || file.sync_data()));
+                self.unsynced = false;
+                self.last_fsync = Instant::now();
+            }
+
+            Err(e) => println!("Unable to fsync the append-only file: {}", e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::RDBFile;
+    use std::collections::HashMap;
+    use std::time::SystemTime;
+
+    /// A manifest as Redis 7 writes it, one base and two incremental files.
+    const REDIS_MANIFEST: &str = "\
+        file appendonly.aof.2.base.rdb seq 2 type b\n\
+        file appendonly.aof.3.incr.aof seq 3 type i\n\
+        file appendonly.aof.4.incr.aof seq 4 type i\n";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("redis-rust-aof-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn names(files: &[AofFile]) -> Vec<&str> {
+        files.iter().map(|file| file.name.as_str()).collect()
+    }
+
+    fn read_all(bytes: &[u8]) -> (Vec<Vec<Vec<u8>>>, AofEntry) {
+        let mut reader = AofReader::new(bytes);
+        let mut commands = Vec::new();
+
+        loop {
+            match reader.next_entry().unwrap() {
+                AofEntry::Command(args) => commands.push(args),
+                last => return (commands, last)
+            }
+        }
+    }
+
+    fn snapshot(keys: Vec<(String, Value, Option<SystemTime>)>) -> Snapshot {
+        Snapshot { keys, functions: vec![], module_types: HashMap::new(), aof_base: true }
+    }
+
+    #[test]
+    fn parses_a_redis_manifest() {
+        let manifest = Manifest::parse(REDIS_MANIFEST).unwrap();
+
+        assert_eq!(manifest.base.as_ref().map(|base| (base.name.as_str(), base.seq)), Some(("appendonly.aof.2.base.rdb", 2)));
+        assert_eq!(names(&manifest.incremental), ["appendonly.aof.3.incr.aof", "appendonly.aof.4.incr.aof"]);
+        assert!(manifest.history.is_empty());
+        assert_eq!(manifest.format(), REDIS_MANIFEST);
+
+        // Keys of newer versions are skipped, blank lines and comments too.
+        let manifest = Manifest::parse("# comment\n\nfile a.1.incr.aof seq 1 type i startoffset 0 endoffset 10\n").unwrap();
+        assert_eq!(names(&manifest.incremental), ["a.1.incr.aof"]);
+    }
+
+    #[test]
+    fn refuses_invalid_manifests() {
+        let invalid = [
+            "file a seq 1",
+            "file a seq 1 type x",
+            "file a seq one type b",
+            "file a.1.base.rdb seq 1 type b\nfile a.2.base.rdb seq 2 type b",
+            "file a.2.incr.aof seq 2 type i\nfile a.1.incr.aof seq 1 type i"
+        ];
+
+        for text in invalid {
+            assert!(Manifest::parse(text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn a_rewrite_swaps_the_base_and_retires_what_it_covers() {
+        let dir = temp_dir("manifest");
+        let mut manifest = Manifest::parse(REDIS_MANIFEST).unwrap();
+
+        for file in manifest.base.iter().chain(&manifest.incremental) {
+            std::fs::write(dir.join(&file.name), b"").unwrap();
+        }
+
+        // Writes go to a new incremental file as soon as the rewrite starts.
+        let keep_from = manifest.new_incremental("appendonly.aof").seq;
+        assert_eq!(keep_from, 5);
+
+        assert_eq!(manifest.new_base("appendonly.aof", true).name, "appendonly.aof.3.base.rdb");
+        manifest.retire_incremental(Some(keep_from));
+
+        assert_eq!(names(&manifest.incremental), ["appendonly.aof.5.incr.aof"]);
+        assert_eq!(names(&manifest.history), ["appendonly.aof.2.base.rdb", "appendonly.aof.3.incr.aof", "appendonly.aof.4.incr.aof"]);
+
+        // Saved while the replaced files are still listed as history, then again once they are gone.
+        manifest.save(&dir, "appendonly.aof").unwrap();
+        assert_eq!(Manifest::load(&dir, "appendonly.aof").unwrap().unwrap().history.len(), 3);
+
+        manifest.delete_history(&dir, "appendonly.aof").unwrap();
+
+        let saved = Manifest::load(&dir, "appendonly.aof").unwrap().unwrap();
+        assert_eq!(saved.format(), "file appendonly.aof.3.base.rdb seq 3 type b\nfile appendonly.aof.5.incr.aof seq 5 type i\n");
+        assert!(!dir.join("appendonly.aof.4.incr.aof").exists());
+
+        // Sequences keep counting past the files that were deleted.
+        assert_eq!(saved.clone().new_incremental("appendonly.aof").seq, 6);
+        assert!(Manifest::load(&temp_dir("no-manifest"), "appendonly.aof").unwrap().is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_command_cut_anywhere_is_truncated() {
+        let commands = [encode_command(&["MULTI"]), encode_command(&["SET", "key", "value"]), encode_command(&["EXEC"])];
+        let file = commands.concat();
+
+        // Where each command ends, the file could end cleanly.
+        let ends: Vec<usize> = commands.iter().scan(0, |end, command| {
+            *end += command.len();
+            Some(*end)
+        }).collect();
+
+        for length in 0..=file.len() {
+            let (read, last) = read_all(&file[..length]);
+
+            assert_eq!(read.len(), ends.iter().filter(|end| **end <= length).count(), "cut at {}", length);
+            assert_eq!(matches!(last, AofEntry::End), length == 0 || ends.contains(&length), "cut at {}", length);
+        }
+
+        // Only what was read in full is counted, which is where a truncated file is cut back to.
+        let mut reader = AofReader::new(&file[..file.len() - 1]);
+        while let AofEntry::Command(_) = reader.next_entry().unwrap() {}
+        assert_eq!(reader.offset(), ends[1] as u64);
+    }
+
+    #[test]
+    fn garbage_is_corrupt_rather_than_truncated() {
+        for file in [&b"SET key value\r\n"[..], b"*1\r\nSET\r\n", b"*1\r\n$3\r\nSETX\r\n", b"*0\r\n"] {
+            assert!(matches!(read_all(file).1, AofEntry::Corrupt(_)), "{:?}", file.escape_ascii().to_string());
+        }
+    }
+
+    #[test]
+    fn a_base_of_commands_recreates_the_snapshot() {
+        let expire = UNIX_EPOCH + Duration::from_millis(4102444800000);
+        let mut stream = Stream::new();
+        stream.append(StreamId::new(1, 1), vec![("f".to_string(), "v".to_string())]);
+        stream.create_group("g", StreamId::new(1, 1), Some(1));
+
+        let dataset = snapshot(vec![
+            ("binary".to_string(), Value::BulkString(vec![0, 0xff, b'\r', b'\n']), None),
+            ("expiring".to_string(), Value::BulkString(b"soon".to_vec()), Some(expire)),
+            ("stream".to_string(), Value::Stream(stream), None)
+        ]);
+
+        let (commands, last) = read_all(&write_commands(&dataset, Vec::new()).unwrap());
+        assert!(matches!(last, AofEntry::End));
+
+        let commands: Vec<String> = commands.iter()
+            .map(|command| command.iter().map(|arg| arg.escape_ascii().to_string()).collect::<Vec<_>>().join(" "))
+            .collect();
+
+        assert_eq!(commands, [
+            "SET binary \\x00\\xff\\r\\n",
+            "SET expiring soon PXAT 4102444800000",
+            "XADD stream 1-1 f v",
+            "XSETID stream 1-1 ENTRIESADDED 1 MAXDELETEDID 0-0",
+            "XGROUP CREATE stream g 1-1 ENTRIESREAD 1"
+        ]);
+
+        let set = snapshot(vec![("set".to_string(), Value::Set(["member".to_string()].into()), None)]);
+        assert!(write_commands(&set, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn a_rewrite_writes_its_base_with_an_rdb_preamble_or_as_commands() {
+        let dir = temp_dir("rewrite");
+        let keys = vec![("key".to_string(), Value::BulkString(b"value".to_vec()), None)];
+
+        for rdb in [true, false] {
+            let path = dir.join(if rdb { "base.rdb" } else { "base.aof" });
+            let mut rewrite = AofRewrite::start(snapshot(keys.clone()), path.clone(), rdb, Some(1), None);
+
+            let size = rewrite.result().unwrap();
+            let written = std::fs::read(&path).unwrap();
+            assert_eq!(size, written.len() as u64);
+
+            if rdb {
+                assert!(written.starts_with(b"REDIS0011"));
+
+                let mut rdb_file = RDBFile::from(&path, &|_| None).unwrap();
+                assert!(rdb_file.take_functions().is_empty());
+
+                let mut storage = crate::storage::Storage::new();
+                storage.import_data(rdb_file);
+                assert!(matches!(storage.get("key"), Some(Value::BulkString(value)) if value == b"value"));
+            } else {
+                assert_eq!(written, encode_command(&["SET", "key", "value"]));
+            }
+
+            assert!(rewrite.result().is_err(), "collected twice");
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
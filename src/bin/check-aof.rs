@@ -0,0 +1,61 @@
+//! Validates an AOF, optionally cutting a damaged last file back to its last valid command.
+//!
+//! Usage: `check-aof [--fix] <file.manifest|file.aof>`
+
+use redis_rust::tools;
+use std::path::Path;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
+    let (fix, path) = match args.as_slice() {
+        [_, path] => (false, path),
+        [_, option, path] if option == "--fix" => (true, path),
+        _ => {
+            eprintln!("Usage: {} [--fix] <file.manifest|file.aof>", args[0]);
+            std::process::exit(1);
+        }
+    };
+
+    let reports = match tools::check_aof(Path::new(path)) {
+        Ok(reports) => reports,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut damaged = false;
+
+    for (index, report) in reports.iter().enumerate() {
+        let Some(corruption) = &report.corruption else {
+            println!("AOF {} is valid: {} bytes, {} items", report.name, report.size, report.items);
+            continue;
+        };
+
+        println!("AOF {} is damaged: {}", report.name, corruption.message);
+        println!("AOF analyzed: size={}, ok_up_to={}, diff={}", report.size, corruption.item_offset, report.size - corruption.item_offset);
+
+        // The server only ever writes to the last file, so that is the only one a crash can leave damaged.
+        let is_last = index + 1 == reports.len();
+
+        if !fix {
+            damaged = true;
+        } else if !is_last || report.name.ends_with(".rdb") {
+            println!("Only the last incremental file of an AOF can be fixed, {} must be restored from a backup", report.name);
+            damaged = true;
+        } else {
+            match tools::truncate_aof(Path::new(&report.name), corruption.item_offset) {
+                Ok(()) => println!("Successfully truncated AOF {} to {} bytes", report.name, corruption.item_offset),
+                Err(e) => {
+                    println!("Failed to truncate AOF {}: {}", report.name, e);
+                    damaged = true;
+                }
+            }
+        }
+    }
+
+    if damaged {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,68 @@
+//! Checks an RDB file for corruption, reporting what it holds and where it stops making sense.
+//!
+//! Usage: `check-rdb <file.rdb>`
+
+use redis_rust::tools;
+use std::path::Path;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
+    let [_, path] = args.as_slice() else {
+        eprintln!("Usage: {} <rdb-file-name>", args[0]);
+        std::process::exit(1);
+    };
+
+    let path = Path::new(path);
+    println!("[offset 0] Checking RDB file {}", path.display());
+
+    match tools::rdb_version(path) {
+        Ok(version) => println!("[offset 9] RDB version {}", version),
+        Err(e) => {
+            println!("--- RDB ERROR DETECTED ---");
+            println!("[offset 0] {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let report = match tools::check_rdb(path) {
+        Ok(report) => report,
+        Err(e) => {
+            println!("--- RDB ERROR DETECTED ---");
+            println!("[offset 0] {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    for (key, value) in &report.aux {
+        println!("[info] AUX FIELD {} = '{}'", key, value);
+    }
+
+    if report.functions > 0 {
+        println!("[info] {} function libraries", report.functions);
+    }
+
+    for (db, keys) in &report.keys_per_db {
+        println!("[info] DB {}: {} keys", db, keys);
+    }
+
+    for (value_type, keys) in &report.keys_per_type {
+        println!("[info] {} keys of type {}", keys, value_type);
+    }
+
+    println!("[info] {} keys read", report.keys_per_db.values().sum::<u64>());
+    println!("[info] {} expires", report.expires);
+    println!("[info] {} already expired", report.already_expired);
+
+    match report.corruption {
+        Some(corruption) => {
+            println!("--- RDB ERROR DETECTED ---");
+            println!("[offset {}] {}", corruption.offset, corruption.message);
+            println!("[additional info] While reading the item starting at offset {}", corruption.item_offset);
+            println!("[additional info] Reading stopped {} bytes before the end of the {} bytes file", report.size.saturating_sub(corruption.offset), report.size);
+            std::process::exit(1);
+        }
+
+        None => println!("[offset {}] \\o/ RDB looks OK! \\o/", report.size)
+    }
+}
@@ -0,0 +1,26 @@
+//! Exports the keys of an RDB file as JSON lines, one `{"db", "key", "type", "ttl", "value"}` object per key.
+//!
+//! Usage: `rdb-dump <file.rdb>`
+
+use redis_rust::tools;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
+    let [_, path] = args.as_slice() else {
+        eprintln!("Usage: {} <rdb-file-name>", args[0]);
+        std::process::exit(1);
+    };
+
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    let result = tools::dump_rdb(Path::new(path), &mut out);
+
+    let _ = out.flush();
+
+    if let Err(e) = result {
+        eprintln!("Unable to dump {}: {}", path, e);
+        std::process::exit(1);
+    }
+}
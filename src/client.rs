@@ -0,0 +1,140 @@
+use crate::parser::Value;
+use crate::pubsub::SubscriptionKind;
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::Notify;
+
+pub type ClientId = u64;
+
+/// Per-connection state. Everything written to the client, replies and pushed messages alike,
+/// goes through `sender` to the connection's writer task so ordering is preserved.
+pub struct Client {
+    sender: UnboundedSender<Value>,
+    /// Notified to make the connection hang up, as when a replica has to resync from scratch.
+    closing: Arc<Notify>,
+    /// The peer's address, or none for the internal clients loading the AOF or applying a master's writes.
+    pub address: Option<SocketAddr>,
+    /// The port a replica listens on, as told by REPLCONF listening-port.
+    pub listening_port: Option<u16>,
+    /// Whether a replica can load an RDB file streamed without its size upfront, as told by REPLCONF capa eof.
+    pub capa_eof: bool,
+    /// The replication offset right after the client's last write, which WAIT and WAITAOF wait for.
+    pub write_offset: u64,
+    pub protocol: u8,
+    pub channels: BTreeSet<String>,
+    pub patterns: BTreeSet<String>,
+    pub shard_channels: BTreeSet<String>,
+    pub transaction: Option<Transaction>,
+    pub watched_keys: BTreeSet<String>,
+    /// Set once a watched key is modified, making the next EXEC fail.
+    pub watch_dirty: bool
+}
+
+/// Commands queued between MULTI and EXEC.
+#[derive(Default)]
+pub struct Transaction {
+    pub commands: Vec<(String, Vec<Value>)>,
+    /// Set when queueing a command failed, so EXEC must refuse to run the transaction.
+    pub aborted: bool
+}
+
+impl Client {
+    pub fn new(sender: UnboundedSender<Value>, address: Option<SocketAddr>) -> Client {
+        Client {
+            sender,
+            closing: Arc::new(Notify::new()),
+            address,
+            listening_port: None,
+            capa_eof: false,
+            write_offset: 0,
+            protocol: 2,
+            channels: BTreeSet::new(),
+            patterns: BTreeSet::new(),
+            shard_channels: BTreeSet::new(),
+            transaction: None,
+            watched_keys: BTreeSet::new(),
+            watch_dirty: false
+        }
+    }
+
+    /// A handle to queue values for the writer task from another thread.
+    pub fn sender(&self) -> UnboundedSender<Value> {
+        self.sender.clone()
+    }
+
+    /// Queues a value for the writer task; a disconnected client silently drops it.
+    pub fn send(&self, value: Value) {
+        let _ = self.sender.send(value);
+    }
+
+    /// Asks the connection to hang up once what was queued before is written.
+    pub fn close(&self) {
+        self.closing.notify_one();
+    }
+
+    pub fn closing(&self) -> Arc<Notify> {
+        Arc::clone(&self.closing)
+    }
+
+    /// The RESP null a client expects in place of a missing array.
+    pub fn null_array(&self) -> Value {
+        if self.protocol >= 3 {
+            Value::Null
+        } else {
+            Value::NullArray
+        }
+    }
+
+    /// Wraps out-of-band data as a RESP3 push frame, or a plain array for RESP2 clients.
+    pub fn push_value(&self, items: Vec<Value>) -> Value {
+        if self.protocol >= 3 {
+            Value::Push(items)
+        } else {
+            Value::Array(items)
+        }
+    }
+
+    /// A RESP3 map, or a flat array of alternating keys and values for RESP2 clients.
+    pub fn map_value(&self, pairs: Vec<(Value, Value)>) -> Value {
+        if self.protocol >= 3 {
+            Value::Map(pairs)
+        } else {
+            Value::Array(pairs.into_iter().flat_map(|(key, value)| [key, value]).collect())
+        }
+    }
+
+    pub fn push(&self, items: Vec<Value>) {
+        self.send(self.push_value(items));
+    }
+
+    pub fn subscriptions(&self, kind: SubscriptionKind) -> &BTreeSet<String> {
+        match kind {
+            SubscriptionKind::Channel => &self.channels,
+            SubscriptionKind::Pattern => &self.patterns,
+            SubscriptionKind::Shard => &self.shard_channels
+        }
+    }
+
+    pub fn subscriptions_mut(&mut self, kind: SubscriptionKind) -> &mut BTreeSet<String> {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::Shard => &mut self.shard_channels
+        }
+    }
+
+    /// The count reported in (un)subscribe confirmations: shard subscriptions are tallied on their own.
+    pub fn subscription_count(&self, kind: SubscriptionKind) -> usize {
+        match kind {
+            SubscriptionKind::Shard => self.shard_channels.len(),
+            _ => self.channels.len() + self.patterns.len()
+        }
+    }
+
+    /// RESP2 clients with active subscriptions may only run subscription management commands.
+    pub fn in_subscribed_mode(&self) -> bool {
+        self.protocol < 3 && !(self.channels.is_empty() && self.patterns.is_empty() && self.shard_channels.is_empty())
+    }
+}
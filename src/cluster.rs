@@ -0,0 +1,30 @@
+/// Number of hash slots the cluster keyspace is divided into.
+pub const CLUSTER_SLOTS: u16 = 16384;
+
+/// Maps a key to its cluster hash slot. If the key contains a non-empty `{...}` hash tag,
+/// only the tag is hashed so related keys can be forced into the same slot.
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    let hashed = match key.iter().position(|&byte| byte == b'{') {
+        Some(open) => match key[open + 1..].iter().position(|&byte| byte == b'}') {
+            Some(length) if length > 0 => &key[open + 1..open + 1 + length],
+            _ => key
+        },
+
+        None => key
+    };
+
+    crc16(hashed) % CLUSTER_SLOTS
+}
+
+/// CRC16-CCITT (XModem), the checksum used by Redis Cluster.
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
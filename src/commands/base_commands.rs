@@ -1,4 +1,4 @@
-use crate::commands::{Command, CommandContext};
+use crate::commands::{unpack_args, Command, CommandContext};
 use crate::parser::Value;
 
 pub struct PingCommand;
@@ -7,8 +7,22 @@ impl Command for PingCommand {
         "ping"
     }
 
-    fn exec(&self, _args: Vec<Value>, _context: &mut CommandContext) -> anyhow::Result<Value> {
-        Ok(Value::SimpleString("PONG".to_string()))
+    fn arity(&self) -> i32 {
+        -1
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        let message = args.into_iter().next().and_then(|arg| arg.unpack_as_string());
+
+        // Subscribed RESP2 clients can only read replies shaped like pushed messages.
+        if context.client().in_subscribed_mode() {
+            return Ok(Value::Array(vec![Value::BulkString("pong".into()), Value::BulkString(message.unwrap_or_default().into())]));
+        }
+
+        match message {
+            Some(message) => Ok(Value::BulkString(message.into())),
+            None => Ok(Value::SimpleString("PONG".to_string()))
+        }
     }
 }
 
@@ -18,7 +32,53 @@ impl Command for EchoCommand {
         "echo"
     }
 
+    fn arity(&self) -> i32 {
+        2
+    }
+
     fn exec(&self, args: Vec<Value>, _context: &mut CommandContext) -> anyhow::Result<Value> {
         Ok(args.first().unwrap().clone())
     }
-}
\ No newline at end of file
+}
+pub struct HelloCommand;
+impl Command for HelloCommand {
+    fn name(&self) -> &str {
+        "hello"
+    }
+
+    fn arity(&self) -> i32 {
+        -1
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        let args = unpack_args(args)?;
+
+        if args.len() > 1 {
+            return Ok(Value::SimpleError("ERR syntax error".to_string()));
+        }
+
+        if let Some(protocol) = args.first() {
+            match protocol.parse::<u8>() {
+                Ok(protocol @ 2..=3) => context.client_mut().protocol = protocol,
+                Ok(_) => return Ok(Value::SimpleError("NOPROTO unsupported protocol version".to_string())),
+                Err(_) => return Ok(Value::SimpleError("ERR Protocol version is not an integer or out of range".to_string()))
+            }
+        }
+
+        let info = vec![
+            ("server", Value::BulkString("redis".into())),
+            ("version", Value::BulkString(env!("CARGO_PKG_VERSION").to_string().into())),
+            ("proto", Value::Integer(context.client().protocol as i64)),
+            ("id", Value::Integer(context.client_id as i64)),
+            ("mode", Value::BulkString("standalone".into())),
+            ("role", Value::BulkString("master".into())),
+            ("modules", Value::Array(vec![]))
+        ];
+
+        Ok(context.client().map_value(
+            info.into_iter()
+                .map(|(name, value)| (Value::BulkString(name.to_string().into()), value))
+                .collect()
+        ))
+    }
+}
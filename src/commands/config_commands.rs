@@ -1,7 +1,10 @@
 use anyhow::anyhow;
-use crate::commands::{Command, CommandContext};
-use crate::config::ConfigKey;
+use crate::aof::FsyncPolicy;
+use crate::commands::{unpack_args, Command, CommandContext};
+use crate::config::{parse_memory, ConfigKey};
+use crate::notifications::{flags_to_string, parse_flags};
 use crate::parser::Value;
+use crate::persistence::parse_save_points;
 
 pub struct ConfigCommand;
 impl Command for ConfigCommand {
@@ -9,12 +12,18 @@ impl Command for ConfigCommand {
         "config"
     }
 
+    fn arity(&self) -> i32 {
+        -2
+    }
+
     fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
-        if args.len() < 1 {
+        let args = unpack_args(args)?;
+
+        if args.is_empty() {
             return Ok(Value::SimpleError("Missing arguments! Correct usage CONFIG <sub-command>".to_string()));
         }
 
-        let sub_command = args[0].clone().unpack_as_string().unwrap().to_lowercase();
+        let sub_command = args[0].to_lowercase();
 
         match sub_command.as_str() {
             "get" => {
@@ -22,15 +31,165 @@ impl Command for ConfigCommand {
                     return Ok(Value::SimpleError("Missing arguments! Correct usage CONFIG GET <option>".to_string()));
                 }
 
-                let get_option = args[1].clone().unpack_as_string().unwrap();
+                let get_option = args[1].clone();
 
                 match get_option.as_str() {
                     "dir" => Ok(Value::Array(vec![Value::SimpleString("dir".to_string()), Value::SimpleString(context.config.get(ConfigKey::Dir))])),
                     "dbfilename" => Ok(Value::SimpleString(context.config.get(ConfigKey::DbFilename))),
+                    "notify-keyspace-events" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::NotifyKeyspaceEvents).into())])),
+                    "busy-reply-threshold" | "lua-time-limit" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::BusyReplyThreshold).into())])),
+                    "save" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::Save).into())])),
+                    "appendonly" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::AppendOnly).into())])),
+                    "appendfsync" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::AppendFsync).into())])),
+                    "appendfilename" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::AppendFilename).into())])),
+                    "appenddirname" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::AppendDirname).into())])),
+                    "aof-load-truncated" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::AofLoadTruncated).into())])),
+                    "auto-aof-rewrite-percentage" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::AutoAofRewritePercentage).into())])),
+                    "auto-aof-rewrite-min-size" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::AutoAofRewriteMinSize).into())])),
+                    "aof-use-rdb-preamble" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::AofUseRdbPreamble).into())])),
+                    "port" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::Port).into())])),
+                    "repl-backlog-size" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::ReplBacklogSize).into())])),
+                    "repl-timeout" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::ReplTimeout).into())])),
+                    "repl-ping-replica-period" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::ReplPingReplicaPeriod).into())])),
+                    "replica-read-only" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::ReplicaReadOnly).into())])),
+                    "replica-serve-stale-data" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::ReplicaServeStaleData).into())])),
+                    "repl-diskless-sync" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::ReplDisklessSync).into())])),
+                    "repl-diskless-load" => Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(context.config.get(ConfigKey::ReplDisklessLoad).into())])),
+                    "replicaof" => {
+                        let master = context.master_address().map(|(host, port)| format!("{} {}", host, port)).unwrap_or_default();
+                        Ok(Value::Array(vec![Value::BulkString(get_option.into()), Value::BulkString(master.into())]))
+                    }
                     _ => Err(anyhow!("Invalid config get option {}", get_option))
                 }
             }
 
+            "set" => {
+                if args.len() != 3 {
+                    return Ok(Value::SimpleError("Missing arguments! Correct usage CONFIG SET <option> <value>".to_string()));
+                }
+
+                let set_option = args[1].to_lowercase();
+                let value = args[2].clone();
+
+                match set_option.as_str() {
+                    "dir" => context.config.set(ConfigKey::Dir, &value),
+                    "dbfilename" => context.config.set(ConfigKey::DbFilename, &value),
+                    "notify-keyspace-events" => match parse_flags(&value) {
+                        // Stored in canonical form, so CONFIG GET reports what is actually enabled.
+                        Some(flags) => context.config.set(ConfigKey::NotifyKeyspaceEvents, &flags_to_string(flags)),
+                        None => return Ok(Value::SimpleError(format!("ERR Invalid argument '{}' for CONFIG SET 'notify-keyspace-events'", value)))
+                    },
+
+                    "busy-reply-threshold" | "lua-time-limit" => match value.parse::<u64>() {
+                        Ok(_) => context.config.set(ConfigKey::BusyReplyThreshold, &value),
+                        Err(_) => return Ok(Value::SimpleError(format!("ERR Invalid argument '{}' for CONFIG SET '{}'", value, set_option)))
+                    },
+
+                    "save" => match parse_save_points(&value) {
+                        Some(_) => context.config.set(ConfigKey::Save, &value),
+                        None => return Ok(Value::SimpleError(format!("ERR Invalid argument '{}' for CONFIG SET 'save'", value)))
+                    },
+
+                    "appendonly" => match value.to_lowercase().as_str() {
+                        // Writes are only logged once a rewrite wrote a base file holding everything written so far.
+                        "yes" if context.config.get(ConfigKey::AppendOnly) != "yes" => {
+                            context.config.set(ConfigKey::AppendOnly, "yes");
+
+                            if context.is_rewriting_aof() {
+                                context.aof_rewrite_scheduled = true;
+                            } else if let Err(e) = context.start_aof_rewrite() {
+                                context.config.set(ConfigKey::AppendOnly, "no");
+                                return Ok(Value::SimpleError(format!("ERR Unable to turn on AOF: {}", e)));
+                            }
+                        }
+
+                        "yes" => {}
+
+                        "no" => {
+                            context.close_append_only();
+                            context.config.set(ConfigKey::AppendOnly, "no");
+                        }
+
+                        _ => return Ok(Value::SimpleError(format!("ERR Invalid argument '{}' for CONFIG SET 'appendonly'", value)))
+                    },
+
+                    "appendfsync" => match FsyncPolicy::parse(&value) {
+                        Some(fsync) => {
+                            context.set_fsync_policy(fsync);
+                            context.config.set(ConfigKey::AppendFsync, &value.to_lowercase());
+                        }
+
+                        None => return Ok(Value::SimpleError(format!("ERR Invalid argument '{}' for CONFIG SET 'appendfsync'", value)))
+                    },
+
+                    "aof-load-truncated" => match value.to_lowercase().as_str() {
+                        "yes" | "no" => context.config.set(ConfigKey::AofLoadTruncated, &value.to_lowercase()),
+                        _ => return Ok(Value::SimpleError(format!("ERR Invalid argument '{}' for CONFIG SET 'aof-load-truncated'", value)))
+                    },
+
+                    "auto-aof-rewrite-percentage" => match value.parse::<u64>() {
+                        Ok(_) => context.config.set(ConfigKey::AutoAofRewritePercentage, &value),
+                        Err(_) => return Ok(Value::SimpleError(format!("ERR Invalid argument '{}' for CONFIG SET 'auto-aof-rewrite-percentage'", value)))
+                    },
+
+                    "auto-aof-rewrite-min-size" => match parse_memory(&value) {
+                        Some(_) => context.config.set(ConfigKey::AutoAofRewriteMinSize, &value),
+                        None => return Ok(Value::SimpleError(format!("ERR Invalid argument '{}' for CONFIG SET 'auto-aof-rewrite-min-size'", value)))
+                    },
+
+                    "aof-use-rdb-preamble" => match value.to_lowercase().as_str() {
+                        "yes" | "no" => context.config.set(ConfigKey::AofUseRdbPreamble, &value.to_lowercase()),
+                        _ => return Ok(Value::SimpleError(format!("ERR Invalid argument '{}' for CONFIG SET 'aof-use-rdb-preamble'", value)))
+                    },
+
+                    "repl-backlog-size" => match parse_memory(&value) {
+                        Some(size) => {
+                            if let Some(backlog) = context.replication.backlog.as_mut() {
+                                backlog.resize(size as usize);
+                            }
+
+                            context.config.set(ConfigKey::ReplBacklogSize, &value)
+                        }
+
+                        None => return Ok(Value::SimpleError(format!("ERR Invalid argument '{}' for CONFIG SET 'repl-backlog-size'", value)))
+                    },
+
+                    "repl-timeout" | "repl-ping-replica-period" => match value.parse::<u64>() {
+                        Ok(seconds) if seconds > 0 => {
+                            let key = if set_option == "repl-timeout" { ConfigKey::ReplTimeout } else { ConfigKey::ReplPingReplicaPeriod };
+                            context.config.set(key, &value)
+                        }
+
+                        _ => return Ok(Value::SimpleError(format!("ERR Invalid argument '{}' for CONFIG SET '{}'", value, set_option)))
+                    },
+
+                    "replica-read-only" | "replica-serve-stale-data" => match value.to_lowercase().as_str() {
+                        "yes" | "no" => {
+                            let key = if set_option == "replica-read-only" { ConfigKey::ReplicaReadOnly } else { ConfigKey::ReplicaServeStaleData };
+                            context.config.set(key, &value.to_lowercase())
+                        }
+
+                        _ => return Ok(Value::SimpleError(format!("ERR Invalid argument '{}' for CONFIG SET '{}'", value, set_option)))
+                    },
+
+                    "repl-diskless-sync" => match value.to_lowercase().as_str() {
+                        "yes" | "no" => context.config.set(ConfigKey::ReplDisklessSync, &value.to_lowercase()),
+                        _ => return Ok(Value::SimpleError(format!("ERR Invalid argument '{}' for CONFIG SET 'repl-diskless-sync'", value)))
+                    },
+
+                    "repl-diskless-load" => match value.to_lowercase().as_str() {
+                        "disabled" | "on-empty-db" | "swapdb" => context.config.set(ConfigKey::ReplDisklessLoad, &value.to_lowercase()),
+                        _ => return Ok(Value::SimpleError(format!("ERR Invalid argument '{}' for CONFIG SET 'repl-diskless-load'", value)))
+                    },
+
+                    "port" | "appendfilename" | "appenddirname" => return Ok(Value::SimpleError(format!("ERR CONFIG SET failed (possibly related to argument '{}') - can't set immutable config", set_option))),
+
+                    _ => return Ok(Value::SimpleError(format!("ERR Unknown option or number of arguments for CONFIG SET - '{}'", set_option)))
+                }
+
+                Ok(Value::SimpleString("OK".to_string()))
+            }
+
             _ => Err(anyhow!("Invalid config sub command {}", sub_command))
         }
     }
@@ -0,0 +1,222 @@
+use crate::commands::{unpack_args_with_bytes, Command, CommandContext};
+use crate::parser::Value;
+use crate::pubsub::glob_match;
+use crate::rdb;
+use crate::scripting::{Library, ScriptEngine};
+
+/// The sub commands changing the libraries, which are logged to the AOF and refused by read-only replicas.
+const WRITE_SUB_COMMANDS: [&str; 4] = ["load", "delete", "flush", "restore"];
+
+pub struct FunctionCommand;
+impl Command for FunctionCommand {
+    fn name(&self) -> &str {
+        "function"
+    }
+
+    fn arity(&self) -> i32 {
+        -2
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        // A RESTORE payload is binary, so the arguments are kept as they came for it and for the AOF.
+        let raw_args: Vec<Vec<u8>> = args.iter().map(|arg| arg.clone().unpack_as_bytes().unwrap_or_default()).collect();
+        let is_restore = args.first().and_then(|arg| arg.clone().unpack_as_string()).is_some_and(|arg| arg.eq_ignore_ascii_case("restore"));
+        let (args, _) = unpack_args_with_bytes(args, if is_restore { 1 } else { usize::MAX })?;
+
+        if args.is_empty() {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage FUNCTION <LOAD|DELETE|FLUSH|LIST|STATS|DUMP|RESTORE> ...".to_string()));
+        }
+
+        let sub_command = args[0].to_lowercase();
+
+        if WRITE_SUB_COMMANDS.contains(&sub_command.as_str()) {
+            if let Some(error) = context.replica_refusal("function", true) {
+                return Ok(error);
+            }
+        }
+
+        let engine = context.scripting.as_mut().expect("Scripts can't be nested!");
+
+        let reply = match (sub_command.as_str(), &args[1..]) {
+            ("load", [code]) => Ok(load(engine, code, false)),
+            ("load", [replace, code]) if replace.eq_ignore_ascii_case("replace") => Ok(load(engine, code, true)),
+
+            ("delete", [name]) => match engine.delete_library(name) {
+                true => Ok(Value::SimpleString("OK".to_string())),
+                false => Ok(Value::SimpleError("ERR Library not found".to_string()))
+            },
+
+            ("flush", [] | [_]) => {
+                if let Some(mode) = args.get(1).filter(|mode| !mode.eq_ignore_ascii_case("sync") && !mode.eq_ignore_ascii_case("async")) {
+                    return Ok(Value::SimpleError(format!("ERR FUNCTION FLUSH only supports SYNC|ASYNC option, got '{}'", mode)));
+                }
+
+                engine.flush_libraries();
+                Ok(Value::SimpleString("OK".to_string()))
+            }
+
+            ("list", options) => Ok(list(context, options)),
+
+            ("stats", []) => Ok(stats(context)),
+
+            ("dump", []) => Ok(Value::BulkString(dump(engine))),
+
+            ("restore", [_] | [_, _]) => {
+                let policy = args.get(2).map(|policy| policy.to_lowercase()).unwrap_or("append".to_string());
+
+                if !["append", "replace", "flush"].contains(&policy.as_str()) {
+                    return Ok(Value::SimpleError("ERR Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE.".to_string()));
+                }
+
+                match restore(engine, &raw_args[1], &policy) {
+                    Ok(()) => Ok(Value::SimpleString("OK".to_string())),
+                    Err(e) => Ok(Value::SimpleError(e.to_string()))
+                }
+            }
+
+            _ => Ok(Value::SimpleError(format!("ERR unknown subcommand or wrong number of arguments for '{}'", args[0])))
+        };
+
+        if WRITE_SUB_COMMANDS.contains(&sub_command.as_str()) && !matches!(reply, Ok(Value::SimpleError(_))) {
+            context.propagate_as(vec![[b"FUNCTION".to_vec()].into_iter().chain(raw_args).collect()]);
+        }
+
+        reply
+    }
+}
+
+fn load(engine: &mut ScriptEngine, code: &str, replace: bool) -> Value {
+    match engine.load_library(code, replace) {
+        Ok(name) => Value::BulkString(name.into()),
+        Err(e) => Value::SimpleError(e.to_string())
+    }
+}
+
+/// FUNCTION LIST [LIBRARYNAME <pattern>] [WITHCODE]
+fn list(context: &CommandContext, options: &[String]) -> Value {
+    let mut pattern: Option<&str> = None;
+    let mut with_code = false;
+    let mut options = options.iter();
+
+    while let Some(option) = options.next() {
+        match option.to_lowercase().as_str() {
+            "withcode" => with_code = true,
+            "libraryname" => match options.next() {
+                Some(name) => pattern = Some(name),
+                None => return Value::SimpleError("ERR library name argument was not given".to_string())
+            },
+
+            _ => return Value::SimpleError(format!("ERR Unknown argument {}", option))
+        }
+    }
+
+    let client = context.client();
+    let engine = context.scripting.as_ref().expect("Scripts can't be nested!");
+
+    let libraries = engine.libraries()
+        .filter(|library| pattern.is_none_or(|pattern| glob_match(pattern, &library.name)))
+        .map(|library| {
+            let functions = library.functions.iter()
+                .map(|(name, function)| client.map_value(vec![
+                    (Value::BulkString("name".into()), Value::BulkString(name.clone().into())),
+                    (Value::BulkString("description".into()), function.description.clone().map_or(Value::NullBulkString, |description| Value::BulkString(description.into()))),
+                    (Value::BulkString("flags".into()), Value::Array(function.flags.iter().map(|flag| Value::BulkString(flag.clone().into())).collect()))
+                ]))
+                .collect();
+
+            let mut info = vec![
+                (Value::BulkString("library_name".into()), Value::BulkString(library.name.clone().into())),
+                (Value::BulkString("engine".into()), Value::BulkString("LUA".into())),
+                (Value::BulkString("functions".into()), Value::Array(functions))
+            ];
+
+            if with_code {
+                info.push((Value::BulkString("library_code".into()), Value::BulkString(library.code.clone().into())));
+            }
+
+            client.map_value(info)
+        })
+        .collect();
+
+    Value::Array(libraries)
+}
+
+fn stats(context: &CommandContext) -> Value {
+    let client = context.client();
+    let engine = context.scripting.as_ref().expect("Scripts can't be nested!");
+
+    let libraries = engine.libraries().count();
+    let functions: usize = engine.libraries().map(|library| library.functions.len()).sum();
+
+    let lua_stats = client.map_value(vec![
+        (Value::BulkString("libraries_count".into()), Value::Integer(libraries as i64)),
+        (Value::BulkString("functions_count".into()), Value::Integer(functions as i64))
+    ]);
+
+    // Nothing can be running here, as a running function would be holding the context.
+    client.map_value(vec![
+        (Value::BulkString("running_script".into()), client.null_array()),
+        (Value::BulkString("engines".into()), client.map_value(vec![(Value::BulkString("LUA".into()), lua_stats)]))
+    ])
+}
+
+/// Serializes every library's code the way Redis does: one function opcode per library, then the DUMP trailer.
+fn dump(engine: &ScriptEngine) -> Vec<u8> {
+    let mut payload = Vec::new();
+
+    for library in engine.libraries() {
+        payload.push(rdb::OPCODE_FUNCTION);
+        rdb::write_string(&mut payload, library.code.as_bytes());
+    }
+
+    rdb::write_payload_footer(&mut payload);
+    payload
+}
+
+/// Loads the libraries of a FUNCTION DUMP payload. Every library is compiled and checked for
+/// clashes before any is added, so a failed restore leaves the loaded libraries untouched.
+fn restore(engine: &mut ScriptEngine, payload: &[u8], policy: &str) -> anyhow::Result<()> {
+    let body = rdb::verify_payload(payload).map_err(|_| anyhow::anyhow!("ERR payload version or checksum are wrong"))?;
+
+    let mut reader = rdb::RdbReader::new(body);
+    let mut libraries: Vec<Library> = Vec::new();
+
+    while let Some(opcode) = reader.try_read_u8()? {
+        if opcode != rdb::OPCODE_FUNCTION {
+            return Err(anyhow::anyhow!("ERR given type is not a function"));
+        }
+
+        let code = String::from_utf8(reader.read_string()?).map_err(|_| anyhow::anyhow!("ERR given library code is not valid UTF-8"))?;
+        libraries.push(engine.compile_library(&code)?);
+    }
+
+    let replace = policy != "append";
+
+    for (index, library) in libraries.iter().enumerate() {
+        let duplicate = libraries[..index].iter().any(|other| other.name == library.name);
+
+        if duplicate || (policy == "append" && engine.libraries().any(|loaded| loaded.name == library.name)) {
+            return Err(anyhow::anyhow!("ERR Library {} already exists", library.name));
+        }
+
+        let clash = engine.libraries()
+            .filter(|loaded| policy != "flush" && !libraries.iter().any(|restored| restored.name == loaded.name))
+            .chain(libraries[..index].iter())
+            .flat_map(|other| other.functions.keys())
+            .find(|name| library.functions.contains_key(*name));
+
+        if let Some(name) = clash {
+            return Err(anyhow::anyhow!("ERR Function {} already exists", name));
+        }
+    }
+
+    if policy == "flush" {
+        engine.flush_libraries();
+    }
+
+    for library in libraries {
+        engine.add_library(library, replace)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,654 @@
+use crate::commands::{unpack_args, Command, CommandContext, WRONG_TYPE_ERROR};
+use crate::geo::{self, GeoUnit};
+use crate::notifications::NOTIFY_ZSET;
+use crate::parser::Value;
+use crate::sorted_set::SortedSet;
+use anyhow::Result;
+
+pub struct GeoAddCommand;
+impl Command for GeoAddCommand {
+    fn name(&self) -> &str {
+        "geoadd"
+    }
+
+    fn arity(&self) -> i32 {
+        -5
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let args = unpack_args(args)?;
+
+        if args.len() < 4 {
+            return Ok(Value::SimpleError("ERR wrong number of arguments for 'geoadd' command".to_string()));
+        }
+
+        let key = &args[0];
+        let mut cursor = 1;
+        let (mut nx, mut xx, mut ch) = (false, false, false);
+
+        while cursor < args.len() {
+            match args[cursor].to_lowercase().as_str() {
+                "nx" => nx = true,
+                "xx" => xx = true,
+                "ch" => ch = true,
+                _ => break
+            }
+
+            cursor += 1;
+        }
+
+        if nx && xx {
+            return Ok(Value::SimpleError("ERR XX and NX options at the same time are not compatible".to_string()));
+        }
+
+        let triplets = &args[cursor..];
+
+        if triplets.is_empty() || !triplets.len().is_multiple_of(3) {
+            return Ok(Value::SimpleError("ERR syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... ".to_string()));
+        }
+
+        let mut points: Vec<(&str, u64)> = Vec::with_capacity(triplets.len() / 3);
+
+        for triplet in triplets.chunks(3) {
+            let (longitude, latitude) = match (triplet[0].parse::<f64>(), triplet[1].parse::<f64>()) {
+                (Ok(longitude), Ok(latitude)) => (longitude, latitude),
+                _ => return Ok(Value::SimpleError("ERR value is not a valid float".to_string()))
+            };
+
+            if !geo::is_valid_coordinate(longitude, latitude) {
+                return Ok(Value::SimpleError(format!("ERR invalid longitude,latitude pair {:.6},{:.6}", longitude, latitude)));
+            }
+
+            points.push((triplet[2].as_str(), geo::encode(longitude, latitude)));
+        }
+
+        if context.storage.get_mut(key).is_none() {
+            if xx {
+                return Ok(Value::Integer(0));
+            }
+
+            context.storage.set(key, Value::SortedSet(SortedSet::new()), None);
+        }
+
+        let Some(Value::SortedSet(set)) = context.storage.get_mut(key) else {
+            return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string()));
+        };
+
+        let mut added = 0;
+        let mut changed = 0;
+
+        for (member, bits) in points {
+            let score = bits as f64;
+
+            match set.score(member) {
+                Some(_) if nx => continue,
+                Some(previous) => {
+                    if previous != score {
+                        set.insert(member, score);
+                        changed += 1;
+                    }
+                }
+                None if xx => continue,
+                None => {
+                    set.insert(member, score);
+                    added += 1;
+                }
+            }
+        }
+
+        if added + changed > 0 {
+            context.storage.notify(NOTIFY_ZSET, "zadd", key);
+        }
+
+        Ok(Value::Integer(if ch { added + changed } else { added }))
+    }
+}
+
+pub struct GeoPosCommand;
+impl Command for GeoPosCommand {
+    fn name(&self) -> &str {
+        "geopos"
+    }
+
+    fn arity(&self) -> i32 {
+        -2
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let args = unpack_args(args)?;
+
+        if args.is_empty() {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage GEOPOS <key> [<member>]...".to_string()));
+        }
+
+        let set = match context.storage.get_mut(&args[0]) {
+            Some(Value::SortedSet(set)) => Some(&*set),
+            Some(_) => return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string())),
+            None => None
+        };
+
+        Ok(Value::Array(
+            args[1..].iter()
+                .map(|member| match set.and_then(|set| set.score(member)) {
+                    Some(score) => {
+                        let (longitude, latitude) = geo::decode(score as u64);
+                        Value::Array(vec![Value::BulkString(longitude.to_string().into()), Value::BulkString(latitude.to_string().into())])
+                    }
+
+                    None => Value::NullArray
+                })
+                .collect()
+        ))
+    }
+}
+
+pub struct GeoDistCommand;
+impl Command for GeoDistCommand {
+    fn name(&self) -> &str {
+        "geodist"
+    }
+
+    fn arity(&self) -> i32 {
+        -4
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let args = unpack_args(args)?;
+
+        if args.len() < 3 || args.len() > 4 {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage GEODIST <key> <member1> <member2> [M|KM|FT|MI]".to_string()));
+        }
+
+        let unit = match args.get(3) {
+            Some(unit) => match GeoUnit::parse(unit) {
+                Some(unit) => unit,
+                None => return Ok(Value::SimpleError(UNSUPPORTED_UNIT_ERROR.to_string()))
+            },
+
+            None => GeoUnit::Meters
+        };
+
+        let set = match context.storage.get_mut(&args[0]) {
+            Some(Value::SortedSet(set)) => set,
+            Some(_) => return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string())),
+            None => return Ok(Value::NullBulkString)
+        };
+
+        match (set.score(&args[1]), set.score(&args[2])) {
+            (Some(first), Some(second)) => {
+                let (long1, lat1) = geo::decode(first as u64);
+                let (long2, lat2) = geo::decode(second as u64);
+
+                Ok(Value::BulkString(format_distance(geo::distance(long1, lat1, long2, lat2), unit).into()))
+            }
+
+            _ => Ok(Value::NullBulkString)
+        }
+    }
+}
+
+pub struct GeoHashCommand;
+impl Command for GeoHashCommand {
+    fn name(&self) -> &str {
+        "geohash"
+    }
+
+    fn arity(&self) -> i32 {
+        -2
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let args = unpack_args(args)?;
+
+        if args.is_empty() {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage GEOHASH <key> [<member>]...".to_string()));
+        }
+
+        let set = match context.storage.get_mut(&args[0]) {
+            Some(Value::SortedSet(set)) => Some(&*set),
+            Some(_) => return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string())),
+            None => None
+        };
+
+        Ok(Value::Array(
+            args[1..].iter()
+                .map(|member| match set.and_then(|set| set.score(member)) {
+                    Some(score) => Value::BulkString(geo::to_geohash_string(score as u64).into()),
+                    None => Value::NullBulkString
+                })
+                .collect()
+        ))
+    }
+}
+
+pub struct GeoSearchCommand;
+impl Command for GeoSearchCommand {
+    fn name(&self) -> &str {
+        "geosearch"
+    }
+
+    fn arity(&self) -> i32 {
+        -7
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let args = unpack_args(args)?;
+
+        if args.is_empty() {
+            return Ok(Value::SimpleError("ERR wrong number of arguments for 'geosearch' command".to_string()));
+        }
+
+        let query = match GeoSearchQuery::parse(&args[1..], "GEOSEARCH", false) {
+            Ok(query) => query,
+            Err(error) => return Ok(Value::SimpleError(error))
+        };
+
+        let set = match context.storage.get_mut(&args[0]) {
+            Some(Value::SortedSet(set)) => set,
+            Some(_) => return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string())),
+            None => return Ok(Value::Array(vec![]))
+        };
+
+        let matches = match query.run(set) {
+            Ok(matches) => matches,
+            Err(error) => return Ok(Value::SimpleError(error))
+        };
+
+        Ok(Value::Array(matches.iter().map(|point| query.as_reply(point)).collect()))
+    }
+}
+
+pub struct GeoSearchStoreCommand;
+impl Command for GeoSearchStoreCommand {
+    fn name(&self) -> &str {
+        "geosearchstore"
+    }
+
+    fn arity(&self) -> i32 {
+        -8
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let args = unpack_args(args)?;
+
+        if args.len() < 2 {
+            return Ok(Value::SimpleError("ERR wrong number of arguments for 'geosearchstore' command".to_string()));
+        }
+
+        let (destination, source) = (&args[0], &args[1]);
+
+        let query = match GeoSearchQuery::parse(&args[2..], "GEOSEARCHSTORE", true) {
+            Ok(query) => query,
+            Err(error) => return Ok(Value::SimpleError(error))
+        };
+
+        let matches = match context.storage.get_mut(source) {
+            Some(Value::SortedSet(set)) => match query.run(set) {
+                Ok(matches) => matches,
+                Err(error) => return Ok(Value::SimpleError(error))
+            },
+
+            Some(_) => return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string())),
+            None => vec![]
+        };
+
+        if matches.is_empty() {
+            context.storage.remove(destination)?;
+            return Ok(Value::Integer(0));
+        }
+
+        let mut stored = SortedSet::new();
+
+        for point in &matches {
+            let score = if query.store_dist {
+                point.distance / query.unit.to_meters()
+            } else {
+                point.score
+            };
+
+            stored.insert(&point.member, score);
+        }
+
+        context.storage.set(destination, Value::SortedSet(stored), None);
+        context.storage.notify(NOTIFY_ZSET, "geosearchstore", destination);
+
+        Ok(Value::Integer(matches.len() as i64))
+    }
+}
+
+const UNSUPPORTED_UNIT_ERROR: &str = "ERR unsupported unit provided. please use M, KM, FT, MI";
+
+enum GeoOrigin {
+    Member(String),
+    Coordinates(f64, f64)
+}
+
+enum GeoShape {
+    Radius(f64),
+    Box(f64, f64)
+}
+
+#[derive(PartialEq)]
+enum GeoSort {
+    Asc,
+    Desc
+}
+
+struct GeoPoint {
+    member: String,
+    score: f64,
+    distance: f64
+}
+
+struct GeoSearchQuery {
+    origin: GeoOrigin,
+    shape: GeoShape,
+    unit: GeoUnit,
+    sort: Option<GeoSort>,
+    count: Option<usize>,
+    any: bool,
+    with_coord: bool,
+    with_dist: bool,
+    with_hash: bool,
+    store_dist: bool
+}
+
+impl GeoSearchQuery {
+    fn parse(args: &[String], command: &str, store: bool) -> Result<GeoSearchQuery, String> {
+        let syntax_error = || "ERR syntax error".to_string();
+        let mut origin: Option<GeoOrigin> = None;
+        let mut shape: Option<(GeoShape, GeoUnit)> = None;
+        let mut origins = 0;
+        let mut shapes = 0;
+
+        let mut query = GeoSearchQuery {
+            origin: GeoOrigin::Coordinates(0.0, 0.0),
+            shape: GeoShape::Radius(0.0),
+            unit: GeoUnit::Meters,
+            sort: None,
+            count: None,
+            any: false,
+            with_coord: false,
+            with_dist: false,
+            with_hash: false,
+            store_dist: false
+        };
+
+        let mut cursor = 0;
+
+        while cursor < args.len() {
+            let remaining = args.len() - cursor - 1;
+
+            match args[cursor].to_lowercase().as_str() {
+                "frommember" if remaining >= 1 => {
+                    origin = Some(GeoOrigin::Member(args[cursor + 1].clone()));
+                    origins += 1;
+                    cursor += 1;
+                }
+
+                "fromlonlat" if remaining >= 2 => {
+                    let longitude = parse_float(&args[cursor + 1])?;
+                    let latitude = parse_float(&args[cursor + 2])?;
+
+                    if !geo::is_valid_coordinate(longitude, latitude) {
+                        return Err(format!("ERR invalid longitude,latitude pair {:.6},{:.6}", longitude, latitude));
+                    }
+
+                    origin = Some(GeoOrigin::Coordinates(longitude, latitude));
+                    origins += 1;
+                    cursor += 2;
+                }
+
+                "byradius" if remaining >= 2 => {
+                    let radius = parse_float(&args[cursor + 1]).map_err(|_| "ERR need numeric radius".to_string())?;
+
+                    if radius < 0.0 {
+                        return Err("ERR radius cannot be negative".to_string());
+                    }
+
+                    let unit = GeoUnit::parse(&args[cursor + 2]).ok_or(UNSUPPORTED_UNIT_ERROR.to_string())?;
+
+                    shape = Some((GeoShape::Radius(radius * unit.to_meters()), unit));
+                    shapes += 1;
+                    cursor += 2;
+                }
+
+                "bybox" if remaining >= 3 => {
+                    let width = parse_float(&args[cursor + 1]).map_err(|_| "ERR need numeric width".to_string())?;
+                    let height = parse_float(&args[cursor + 2]).map_err(|_| "ERR need numeric height".to_string())?;
+
+                    if width < 0.0 || height < 0.0 {
+                        return Err("ERR height or width cannot be negative".to_string());
+                    }
+
+                    let unit = GeoUnit::parse(&args[cursor + 3]).ok_or(UNSUPPORTED_UNIT_ERROR.to_string())?;
+
+                    shape = Some((GeoShape::Box(width * unit.to_meters(), height * unit.to_meters()), unit));
+                    shapes += 1;
+                    cursor += 3;
+                }
+
+                "asc" => query.sort = Some(GeoSort::Asc),
+                "desc" => query.sort = Some(GeoSort::Desc),
+
+                "count" if remaining >= 1 => {
+                    let count = args[cursor + 1].parse::<i64>().map_err(|_| "ERR value is not an integer or out of range".to_string())?;
+
+                    if count <= 0 {
+                        return Err("ERR COUNT must be > 0".to_string());
+                    }
+
+                    query.count = Some(count as usize);
+                    cursor += 1;
+
+                    if args.get(cursor + 1).is_some_and(|arg| arg.eq_ignore_ascii_case("any")) {
+                        query.any = true;
+                        cursor += 1;
+                    }
+                }
+
+                "any" => query.any = true,
+                "withcoord" if !store => query.with_coord = true,
+                "withdist" if !store => query.with_dist = true,
+                "withhash" if !store => query.with_hash = true,
+                "storedist" if store => query.store_dist = true,
+
+                _ => return Err(syntax_error())
+            }
+
+            cursor += 1;
+        }
+
+        if origins != 1 {
+            return Err(format!("ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for {}", command));
+        }
+
+        if shapes != 1 {
+            return Err(format!("ERR exactly one of BYRADIUS and BYBOX can be specified for {}", command));
+        }
+
+        if query.any && query.count.is_none() {
+            return Err("ERR the ANY argument requires COUNT argument".to_string());
+        }
+
+        if query.count.is_some() && !query.any && query.sort.is_none() {
+            query.sort = Some(GeoSort::Asc);
+        }
+
+        let (shape, unit) = shape.unwrap();
+
+        query.origin = origin.unwrap();
+        query.shape = shape;
+        query.unit = unit;
+
+        Ok(query)
+    }
+
+    fn run(&self, set: &SortedSet) -> Result<Vec<GeoPoint>, String> {
+        let (center_long, center_lat) = match &self.origin {
+            GeoOrigin::Coordinates(longitude, latitude) => (*longitude, *latitude),
+            GeoOrigin::Member(member) => match set.score(member) {
+                Some(score) => geo::decode(score as u64),
+                None => return Err("ERR could not decode requested zset member".to_string())
+            }
+        };
+
+        let (half_width, half_height, radius) = match self.shape {
+            GeoShape::Radius(radius) => (radius, radius, radius),
+            GeoShape::Box(width, height) => (width / 2.0, height / 2.0, (width / 2.0).hypot(height / 2.0))
+        };
+
+        // Only the members in the geohash cells around the center are looked at, then filtered by distance.
+        let candidates = geo::search_ranges(center_long, center_lat, half_width, half_height, radius).into_iter()
+            .flat_map(|range| set.range_by_score(range.start as f64, range.end as f64));
+
+        let mut matches: Vec<GeoPoint> = vec![];
+
+        for (member, score) in candidates {
+            let (longitude, latitude) = geo::decode(score as u64);
+
+            let distance = match self.shape {
+                GeoShape::Radius(radius) => Some(geo::distance(center_long, center_lat, longitude, latitude)).filter(|distance| *distance <= radius),
+                GeoShape::Box(width, height) => geo::distance_if_in_box(width, height, center_long, center_lat, longitude, latitude)
+            };
+
+            if let Some(distance) = distance {
+                matches.push(GeoPoint { member: member.to_string(), score, distance });
+
+                if self.any && self.count.is_some_and(|count| matches.len() >= count) {
+                    break;
+                }
+            }
+        }
+
+        match self.sort {
+            Some(GeoSort::Asc) => matches.sort_by(|a, b| a.distance.total_cmp(&b.distance)),
+            Some(GeoSort::Desc) => matches.sort_by(|a, b| b.distance.total_cmp(&a.distance)),
+            None => {}
+        }
+
+        if let Some(count) = self.count {
+            matches.truncate(count);
+        }
+
+        Ok(matches)
+    }
+
+    fn as_reply(&self, point: &GeoPoint) -> Value {
+        if !self.with_coord && !self.with_dist && !self.with_hash {
+            return Value::BulkString(point.member.clone().into());
+        }
+
+        let mut reply = vec![Value::BulkString(point.member.clone().into())];
+
+        if self.with_dist {
+            reply.push(Value::BulkString(format_distance(point.distance, self.unit).into()));
+        }
+
+        if self.with_hash {
+            reply.push(Value::Integer(point.score as i64));
+        }
+
+        if self.with_coord {
+            let (longitude, latitude) = geo::decode(point.score as u64);
+            reply.push(Value::Array(vec![Value::BulkString(longitude.to_string().into()), Value::BulkString(latitude.to_string().into())]));
+        }
+
+        Value::Array(reply)
+    }
+}
+
+fn parse_float(value: &str) -> Result<f64, String> {
+    value.parse::<f64>().map_err(|_| "ERR value is not a valid float".to_string())
+}
+
+fn format_distance(meters: f64, unit: GeoUnit) -> String {
+    format!("{:.4}", meters / unit.to_meters())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::collections::BTreeSet;
+
+    /// Some points anywhere, and many within a few radii of the center, where they matter.
+    fn points(center: (f64, f64), reach: f64) -> impl Strategy<Value = Vec<(f64, f64)>> {
+        let near = (-3.0..3.0f64, -3.0..3.0f64).prop_map(move |(x, y)| (
+            (center.0 + x * reach).clamp(geo::GEO_LONG_MIN, geo::GEO_LONG_MAX),
+            (center.1 + y * reach).clamp(geo::GEO_LAT_MIN, geo::GEO_LAT_MAX)
+        ));
+
+        let anywhere = (geo::GEO_LONG_MIN..=geo::GEO_LONG_MAX, geo::GEO_LAT_MIN..=geo::GEO_LAT_MAX);
+        prop::collection::vec(prop_oneof![4 => near, 1 => anywhere], 1..200)
+    }
+
+    fn search() -> impl Strategy<Value = ((f64, f64), f64, Vec<(f64, f64)>)> {
+        (-180.0..=180.0f64, -85.0..=85.0f64, -1.0..7.0f64)
+            .prop_flat_map(|(longitude, latitude, magnitude)| {
+                let size = 10f64.powf(magnitude);
+                // A radius of `size` meters spans about `size / 111km` degrees of latitude.
+                (Just((longitude, latitude)), Just(size), points((longitude, latitude), size / 111_000.0))
+            })
+    }
+
+    fn found(args: &[String], set: &SortedSet) -> BTreeSet<String> {
+        let query = GeoSearchQuery::parse(args, "geosearch", false).unwrap_or_else(|e| panic!("{}", e));
+        query.run(set).unwrap().into_iter().map(|point| point.member).collect()
+    }
+
+    fn sorted_set(points: &[(f64, f64)]) -> SortedSet {
+        let mut set = SortedSet::new();
+
+        for (index, (longitude, latitude)) in points.iter().enumerate() {
+            set.insert(&index.to_string(), geo::encode(*longitude, *latitude) as f64);
+        }
+
+        set
+    }
+
+    proptest! {
+        #[test]
+        fn radius_search_finds_what_a_full_scan_finds(((longitude, latitude), radius, points) in search()) {
+            let set = sorted_set(&points);
+            let args: Vec<String> = ["FROMLONLAT", &longitude.to_string(), &latitude.to_string(), "BYRADIUS", &radius.to_string(), "m"]
+                .map(String::from).to_vec();
+
+            let expected: BTreeSet<String> = set.iter()
+                .filter(|(_, score)| {
+                    let (member_long, member_lat) = geo::decode(*score as u64);
+                    geo::distance(longitude, latitude, member_long, member_lat) <= radius
+                })
+                .map(|(member, _)| member.to_string())
+                .collect();
+
+            prop_assert_eq!(found(&args, &set), expected);
+        }
+
+        #[test]
+        fn box_search_finds_what_a_full_scan_finds(((longitude, latitude), size, points) in search(), ratio in 0.2..5.0f64) {
+            let set = sorted_set(&points);
+            let (width, height) = (size * 2.0, size * 2.0 * ratio);
+            let args: Vec<String> = ["FROMLONLAT", &longitude.to_string(), &latitude.to_string(), "BYBOX", &width.to_string(), &height.to_string(), "m"]
+                .map(String::from).to_vec();
+
+            let expected: BTreeSet<String> = set.iter()
+                .filter(|(_, score)| {
+                    let (member_long, member_lat) = geo::decode(*score as u64);
+                    geo::distance_if_in_box(width, height, longitude, latitude, member_long, member_lat).is_some()
+                })
+                .map(|(member, _)| member.to_string())
+                .collect();
+
+            prop_assert_eq!(found(&args, &set), expected);
+        }
+    }
+}
@@ -2,38 +2,1164 @@ mod x_commands;
 mod base_commands;
 mod config_commands;
 mod storage_commands;
+mod geo_commands;
+mod pubsub_commands;
+mod transaction_commands;
+mod script_commands;
+mod function_commands;
+mod module_commands;
+mod persistence_commands;
+mod replication_commands;
 
-use crate::commands::base_commands::{EchoCommand, PingCommand};
+use crate::aof::{encode_command, AofEntry, AofReader, AofRewrite, AppendOnlyFile, FsyncPolicy, Manifest};
+use crate::client::{Client, ClientId};
+use crate::commands::base_commands::{EchoCommand, HelloCommand, PingCommand};
 use crate::commands::config_commands::ConfigCommand;
-use crate::commands::storage_commands::{StorageGetCommand, StorageKeysCommand, StorageSetCommand, StorageValueTypeCommand};
-use crate::commands::x_commands::{StorageXAddCommand, StorageXRangeCommand, StorageXReadCommand};
-use crate::config::Configuration;
+use crate::commands::function_commands::FunctionCommand;
+use crate::commands::geo_commands::{GeoAddCommand, GeoDistCommand, GeoHashCommand, GeoPosCommand, GeoSearchCommand, GeoSearchStoreCommand};
+use crate::commands::persistence_commands::{BgRewriteAofCommand, BgSaveCommand, LastSaveCommand, SaveCommand};
+use crate::commands::replication_commands::{InfoCommand, PsyncCommand, ReplConfCommand, ReplicaOfCommand, RoleCommand, WaitAofCommand, WaitCommand};
+use crate::commands::pubsub_commands::{PSubscribeCommand, PUnsubscribeCommand, PubSubCommand, PublishCommand, SPublishCommand, SSubscribeCommand, SUnsubscribeCommand, SubscribeCommand, UnsubscribeCommand};
+use crate::commands::script_commands::ScriptCommand;
+use crate::commands::storage_commands::{StorageDelCommand, StorageDumpCommand, StorageGetCommand, StorageKeysCommand, StorageRestoreCommand, StorageSetCommand, StorageValueTypeCommand};
+use crate::commands::transaction_commands::{DiscardCommand, MultiCommand, UnwatchCommand, WatchCommand};
+use crate::commands::x_commands::{StorageXAckCommand, StorageXAddCommand, StorageXAutoClaimCommand, StorageXClaimCommand, StorageXDelCommand, StorageXGroupCommand, StorageXInfoCommand, StorageXLenCommand, StorageXPendingCommand, StorageXRangeCommand, StorageXReadCommand, StorageXReadGroupCommand, StorageXRevRangeCommand, StorageXSetIdCommand, StorageXTrimCommand};
+use crate::config::{parse_memory, ConfigKey, Configuration};
+use crate::notifications::{parse_flags, NOTIFY_KEYEVENT, NOTIFY_KEYSPACE, NOTIFY_KEY_MISS, NOTIFY_NEW};
 use crate::parser::Value;
-use crate::storage::Storage;
-use anyhow::Result;
-use std::collections::HashMap;
+use crate::module::{DynamicLibrary, Module, Modules, TimerId};
+use crate::persistence::{parse_save_points, Persistence, Snapshot};
+use crate::pubsub::PubSub;
+use crate::replication::{new_replid, Backlog, DisklessTransfer, LinkState, Replica, ReplicaState, Replication};
+use crate::scripting::{ScriptEngine, ScriptMonitor};
+use crate::storage::{ExpiryMode, RDBFile, Storage};
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::Notify;
 
-trait Command: Send + Sync {
+const WRONG_TYPE_ERROR: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+/// Commands a RESP2 client may still run while it has active subscriptions.
+const SUBSCRIBED_MODE_COMMANDS: [&str; 9] = ["subscribe", "psubscribe", "ssubscribe", "unsubscribe", "punsubscribe", "sunsubscribe", "ping", "quit", "reset"];
+
+/// Commands implemented by the executor itself, because they dispatch other commands or change the command table.
+const EXECUTOR_COMMANDS: [&str; 8] = ["exec", "eval", "evalsha", "eval_ro", "evalsha_ro", "fcall", "fcall_ro", "module"];
+
+/// Commands a replica still serves while the link to its master is down and `replica-serve-stale-data`
+/// is off, as they don't read the dataset. Scripts are checked command by command.
+const STALE_COMMANDS: [&str; 28] = [
+    "info", "replicaof", "replconf", "role", "config", "hello",
+    "subscribe", "psubscribe", "ssubscribe", "unsubscribe", "punsubscribe", "sunsubscribe", "publish", "spublish", "pubsub",
+    "multi", "exec", "discard", "watch", "unwatch", "quit", "reset",
+    "eval", "evalsha", "eval_ro", "evalsha_ro", "fcall", "fcall_ro"
+];
+
+/// Commands that act on a transaction immediately instead of being queued by MULTI.
+const TRANSACTION_CONTROL_COMMANDS: [&str; 6] = ["multi", "exec", "discard", "watch", "quit", "reset"];
+
+/// A command clients can run, keyed by its lowercase name.
+pub trait Command: Send + Sync {
     fn name(&self) -> &str;
     fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value>;
+
+    /// How many arguments the command takes, counting its name, as in Redis: `N` means exactly N
+    /// and `-N` at least N. Checked when MULTI queues the command.
+    fn arity(&self) -> i32 {
+        -1
+    }
+
+    /// Whether the command may modify the dataset.
+    fn is_write(&self) -> bool {
+        false
+    }
+}
+
+/// Reads an argument as a string. Keys and most values are strings, so bytes that aren't UTF-8 are
+/// refused rather than mangled; commands taking binary values read those with [`Value::unpack_as_bytes`].
+fn unpack_arg(arg: Value) -> Result<String> {
+    arg.unpack_as_string().ok_or_else(|| anyhow!("invalid argument, expected a UTF-8 string"))
+}
+
+fn unpack_args(args: Vec<Value>) -> Result<Vec<String>> {
+    args.into_iter().map(unpack_arg).collect()
+}
+
+/// Like [`unpack_args`], but takes the argument at `index` out as bytes, for a binary value like a
+/// RESTORE payload. Its place is left empty, and nothing is taken if there are fewer arguments.
+fn unpack_args_with_bytes(mut args: Vec<Value>, index: usize) -> Result<(Vec<String>, Vec<u8>)> {
+    let bytes = match args.get_mut(index) {
+        Some(arg) => std::mem::replace(arg, Value::Null).unpack_as_bytes().unwrap_or_default(),
+        None => Vec::new()
+    };
+
+    Ok((unpack_args(args)?, bytes))
+}
+
+fn unknown_command_error(command_name: &str, args: &[Value]) -> Value {
+    let args: Vec<String> = args.iter()
+        .map(|arg| format!("'{}'", arg.clone().unpack_as_string().unwrap_or_default()))
+        .collect();
+
+    Value::SimpleError(format!("ERR unknown command '{}', with args beginning with: {}", command_name, args.join(" ")))
 }
 
 pub struct CommandExecutor {
-    commands: HashMap<String, Box<dyn Command>>,
+    commands: HashMap<String, Arc<dyn Command>>,
 }
 
 pub struct CommandContext {
     storage: Storage,
-    config: Configuration
+    config: Configuration,
+    stream_updates: Arc<Notify>,
+    blocking: Option<BlockingRequest>,
+    clients: HashMap<ClientId, Client>,
+    next_client_id: ClientId,
+    client_id: ClientId,
+    pubsub: PubSub,
+    watched_keys: HashMap<String, BTreeSet<ClientId>>,
+    /// Taken out while a script runs, so the script can be handed the rest of the context.
+    scripting: Option<ScriptEngine>,
+    script_monitor: Arc<ScriptMonitor>,
+    modules: Modules,
+    persistence: Persistence,
+    /// The write commands run since the last flush, as they are logged to the AOF.
+    propagated: Vec<Vec<Vec<u8>>>,
+    /// What the running command asked to log instead of itself, see [`CommandContext::propagate_as`].
+    propagation_override: Option<Vec<Vec<Vec<u8>>>>,
+    aof: Option<AppendOnlyFile>,
+    aof_rewrite: Option<AofRewrite>,
+    /// Set when a rewrite is asked for while one runs, to start another as soon as it is done.
+    aof_rewrite_scheduled: bool,
+    /// The replication offset up to which writes are known to be fsynced to the AOF, for WAITAOF.
+    aof_fsynced_offset: u64,
+    replication: Replication
+}
+
+/// Asks the connection to park the client until a stream changes or replicas acknowledge writes,
+/// then run `retry_args` again.
+/// A zero timeout blocks forever.
+pub struct BlockingRequest {
+    pub timeout: Duration,
+    pub retry_args: Vec<Value>
 }
 
 impl CommandContext {
     pub fn new(storage: Storage, config: Configuration) -> CommandContext {
+        let script_monitor = Arc::new(ScriptMonitor::default());
+        let scripting = ScriptEngine::new(Arc::clone(&script_monitor)).expect("Unable to start the Lua interpreter!");
+
         CommandContext {
             storage,
-            config
+            config,
+            stream_updates: Arc::new(Notify::new()),
+            blocking: None,
+            clients: HashMap::new(),
+            next_client_id: 1,
+            client_id: 0,
+            pubsub: PubSub::new(),
+            watched_keys: HashMap::new(),
+            scripting: Some(scripting),
+            script_monitor,
+            modules: Modules::new(),
+            persistence: Persistence::new(),
+            propagated: Vec::new(),
+            propagation_override: None,
+            aof: None,
+            aof_rewrite: None,
+            aof_rewrite_scheduled: false,
+            aof_fsynced_offset: 0,
+            replication: Replication::new()
+        }
+    }
+
+    /// Loads the keys and function libraries of the RDB file named by the `dir` and `dbfilename` configs.
+    /// Returns false if there is no such file yet, like on a first start.
+    pub fn load_rdb(&mut self) -> anyhow::Result<bool> {
+        let path = Path::new(&self.config.get(ConfigKey::Dir)).join(self.config.get(ConfigKey::DbFilename));
+
+        if !path.exists() {
+            return Ok(false);
+        }
+
+        let mut rdb_file = RDBFile::from(&path, &|name| self.modules.module_type(name))?;
+
+        self.load_libraries(rdb_file.take_functions());
+        self.storage.import_data(rdb_file);
+        Ok(true)
+    }
+
+    /// Finishes a background save or diskless transfer that is done, starts the next save the `save`
+    /// rules call for, does the same for AOF rewrites, and flushes the AOF to disk if `appendfsync
+    /// everysec` is due.
+    pub fn persistence_cron(&mut self) {
+        if let Some(saved) = self.persistence.poll_background_save() {
+            self.replication_save_done(saved);
+        }
+
+        self.poll_diskless_transfer();
+
+        let save_points = parse_save_points(&self.config.get(ConfigKey::Save)).unwrap_or_default();
+
+        if self.persistence.should_save(&save_points) {
+            self.background_save();
+        }
+
+        self.poll_aof_rewrite();
+
+        if !self.is_rewriting_aof() && (self.aof_rewrite_scheduled || self.should_rewrite_aof()) {
+            if let Err(e) = self.start_aof_rewrite() {
+                println!("Unable to start the background AOF rewrite: {}", e);
+            }
+        }
+
+        if let Some(aof) = self.aof.as_mut() {
+            aof.cron();
+        }
+
+        self.track_aof_fsync();
+    }
+
+    /// Moves the offset WAITAOF reads up to the replication offset once the AOF is all on disk, waking
+    /// up the clients waiting for it. Writes logged while the AOF is being turned on only count once
+    /// the base they follow is written.
+    fn track_aof_fsync(&mut self) {
+        let turning_on = self.aof_rewrite.as_ref().is_some_and(|rewrite| rewrite.pending_incremental.is_some());
+
+        if self.aof.as_ref().is_some_and(|aof| aof.is_synced()) && !turning_on && self.aof_fsynced_offset != self.replication.offset {
+            self.aof_fsynced_offset = self.replication.offset;
+            self.stream_updates.notify_waiters();
+        }
+    }
+
+    /// Fsyncs the AOF right away, for WAITAOF to go on under `appendfsync no`.
+    fn sync_append_only(&mut self) {
+        if let Some(Err(e)) = self.aof.as_mut().map(AppendOnlyFile::sync) {
+            println!("Fsync of the append-only file failed: {}", e);
+        }
+
+        self.track_aof_fsync();
+    }
+
+    fn aof_dir(&mut self) -> PathBuf {
+        Path::new(&self.config.get(ConfigKey::Dir)).join(self.config.get(ConfigKey::AppendDirname))
+    }
+
+    /// Starts logging writes to the AOF at startup, to its last incremental file. Without an AOF yet,
+    /// one is created by a rewrite.
+    pub fn open_append_only(&mut self) -> Result<()> {
+        let dir = self.aof_dir();
+        let file_name = self.config.get(ConfigKey::AppendFilename);
+
+        let Some(mut manifest) = Manifest::load(&dir, &file_name)?.filter(|manifest| manifest.base.is_some() || !manifest.incremental.is_empty()) else {
+            return self.start_aof_rewrite();
+        };
+
+        if manifest.incremental.is_empty() {
+            File::create(dir.join(&manifest.new_incremental(&file_name).name))?;
+            manifest.save(&dir, &file_name)?;
+        }
+
+        let (last, preceding) = manifest.incremental.split_last().unwrap();
+
+        let preceding_size = manifest.base.iter().chain(preceding)
+            .map(|file| std::fs::metadata(dir.join(&file.name)).map(|metadata| metadata.len()).unwrap_or_default())
+            .sum();
+
+        let mut aof = AppendOnlyFile::open(&dir.join(&last.name), self.fsync_policy())?;
+        aof.preceding_size = preceding_size;
+        aof.base_size = aof.size();
+
+        self.aof = Some(aof);
+        Ok(())
+    }
+
+    fn fsync_policy(&mut self) -> FsyncPolicy {
+        FsyncPolicy::parse(&self.config.get(ConfigKey::AppendFsync)).unwrap_or(FsyncPolicy::EverySec)
+    }
+
+    /// Whether the AOF is being rewritten.
+    fn is_rewriting_aof(&self) -> bool {
+        self.aof_rewrite.is_some()
+    }
+
+    /// Starts rewriting the AOF: the dataset as it is now is written to a new base file on another
+    /// thread, while writes go to a new incremental file. When `appendonly` was just turned on, this
+    /// is when logging starts.
+    fn start_aof_rewrite(&mut self) -> Result<()> {
+        let dir = self.aof_dir();
+        let file_name = self.config.get(ConfigKey::AppendFilename);
+
+        std::fs::create_dir_all(&dir).map_err(|e| anyhow!("Can't create the append-only directory {}: {}", dir.display(), e))?;
+
+        let mut manifest = Manifest::load(&dir, &file_name)?.unwrap_or_default();
+        let turning_on = self.aof.is_none() && self.config.get(ConfigKey::AppendOnly) == "yes";
+        let mut keep_from = None;
+        let mut pending_incremental = None;
+
+        if self.aof.is_some() || turning_on {
+            let incremental = manifest.new_incremental(&file_name).clone();
+            let path = dir.join(&incremental.name);
+            File::create(&path)?;
+
+            let mut aof = AppendOnlyFile::open(&path, self.fsync_policy())?;
+
+            match self.aof.as_mut() {
+                // What was logged so far stays listed until the rewrite is done, so it can still be loaded.
+                Some(previous) => {
+                    previous.sync()?;
+                    manifest.save(&dir, &file_name)?;
+
+                    aof.preceding_size = previous.size();
+                    aof.base_size = previous.base_size;
+                }
+
+                None => {
+                    manifest.incremental.pop();
+                    pending_incremental = Some(incremental.clone());
+                }
+            }
+
+            keep_from = Some(incremental.seq);
+            self.aof = Some(aof);
+        }
+
+        let mut snapshot = self.snapshot();
+        snapshot.aof_base = true;
+
+        let rdb = self.config.get(ConfigKey::AofUseRdbPreamble) == "yes";
+        let temp_path = dir.join(format!("temp-rewriteaof-bg-{}.aof", std::process::id()));
+
+        println!("Background append only file rewriting started");
+        self.aof_rewrite = Some(AofRewrite::start(snapshot, temp_path, rdb, keep_from, pending_incremental));
+        self.aof_rewrite_scheduled = false;
+        Ok(())
+    }
+
+    /// Puts the new base file of a finished rewrite in place, swapping the manifest to list it, followed
+    /// by the incremental files written since the rewrite started.
+    fn poll_aof_rewrite(&mut self) {
+        let Some(mut rewrite) = self.aof_rewrite.take_if(|rewrite| rewrite.is_finished()) else {
+            return;
+        };
+
+        match rewrite.result().and_then(|base_size| self.install_aof_base(&rewrite, base_size)) {
+            Ok(()) => println!("Background AOF rewrite finished successfully"),
+            Err(e) => {
+                println!("Background AOF rewrite failed: {}", e);
+                let _ = std::fs::remove_file(&rewrite.temp_path);
+
+                // Writes are only logged for an AOF that has a base, so turning it on failed as a whole.
+                if rewrite.pending_incremental.is_some() && self.aof.is_some() {
+                    self.close_append_only();
+                    self.config.set(ConfigKey::AppendOnly, "no");
+                }
+            }
+        }
+    }
+
+    fn install_aof_base(&mut self, rewrite: &AofRewrite, base_size: u64) -> Result<()> {
+        let dir = self.aof_dir();
+        let file_name = self.config.get(ConfigKey::AppendFilename);
+        let mut manifest = Manifest::load(&dir, &file_name)?.unwrap_or_default();
+
+        let base = manifest.new_base(&file_name, rewrite.rdb).name.clone();
+        std::fs::rename(&rewrite.temp_path, dir.join(&base))?;
+
+        manifest.retire_incremental(rewrite.keep_from);
+        manifest.incremental.extend(rewrite.pending_incremental.clone());
+        manifest.save(&dir, &file_name)?;
+        manifest.delete_history(&dir, &file_name)?;
+
+        let kept_size: u64 = manifest.incremental.iter().rev().skip(1)
+            .map(|file| std::fs::metadata(dir.join(&file.name)).map(|metadata| metadata.len()).unwrap_or_default())
+            .sum();
+
+        if let Some(aof) = self.aof.as_mut() {
+            aof.preceding_size = base_size + kept_size;
+            aof.base_size = aof.size();
+        }
+
+        Ok(())
+    }
+
+    /// Starts a rewrite once the AOF grew by `auto-aof-rewrite-percentage` since the last one, and is
+    /// at least `auto-aof-rewrite-min-size` large.
+    fn should_rewrite_aof(&mut self) -> bool {
+        let percentage = self.config.get(ConfigKey::AutoAofRewritePercentage).parse::<u64>().unwrap_or(0);
+        let min_size = parse_memory(&self.config.get(ConfigKey::AutoAofRewriteMinSize)).unwrap_or(0);
+
+        let Some(aof) = self.aof.as_ref().filter(|_| percentage > 0 && !self.is_rewriting_aof()) else {
+            return false;
+        };
+
+        let growth = (aof.size() * 100 / aof.base_size.max(1)).saturating_sub(100);
+        aof.size() >= min_size && growth >= percentage
+    }
+
+    /// Stops logging writes, flushing what was logged to disk first.
+    pub fn close_append_only(&mut self) {
+        if let Some(mut aof) = self.aof.take() {
+            if let Err(e) = aof.sync() {
+                println!("Fsync of the append-only file failed: {}", e);
+            }
+        }
+
+        self.aof_fsynced_offset = 0;
+    }
+
+    /// Applies `appendfsync` to the open AOF.
+    fn set_fsync_policy(&mut self, fsync: FsyncPolicy) {
+        if let Some(aof) = self.aof.as_mut() {
+            aof.fsync = fsync;
+        }
+    }
+
+    /// Copies what an RDB file stores, so it can be written after the context is released.
+    fn snapshot(&self) -> Snapshot {
+        let keys = self.storage.snapshot();
+
+        let module_types = keys.iter()
+            .filter_map(|(_, value, _)| match value {
+                Value::Module(module_value) => self.modules.module_type(module_value.type_name()),
+                _ => None
+            })
+            .map(|module_type| (module_type.name().to_string(), module_type))
+            .collect();
+
+        let functions = self.scripting.as_ref()
+            .map(|engine| engine.libraries().map(|library| library.code.clone()).collect())
+            .unwrap_or_default();
+
+        Snapshot { keys, functions, module_types, aof_base: false }
+    }
+
+    /// Writes the RDB file while holding the context, as SAVE does.
+    fn save(&mut self) -> Result<()> {
+        let dirty = self.persistence.dirty;
+        let result = self.snapshot().save(&self.config.get(ConfigKey::Dir), &self.config.get(ConfigKey::DbFilename));
+
+        self.persistence.save_done(&result, dirty);
+        result
+    }
+
+    /// Starts writing the RDB file from a snapshot on another thread.
+    fn background_save(&mut self) {
+        let snapshot = self.snapshot();
+        let (dir, file_name) = (self.config.get(ConfigKey::Dir), self.config.get(ConfigKey::DbFilename));
+
+        println!("Background saving started");
+        self.persistence.start_background_save(snapshot, dir, file_name);
+    }
+
+    /// Loads function libraries read from the RDB file at startup.
+    pub fn load_libraries(&mut self, libraries: Vec<String>) {
+        let engine = self.scripting.as_mut().expect("Scripts can't be nested!");
+
+        for code in libraries {
+            match engine.load_library(&code, true) {
+                Ok(name) => println!("Loaded function library {}", name),
+                Err(e) => println!("Unable to load function library! {}", e)
+            }
+        }
+    }
+
+    pub fn register_client(&mut self, sender: UnboundedSender<Value>, address: Option<SocketAddr>) -> ClientId {
+        let client_id = self.next_client_id;
+
+        self.next_client_id += 1;
+        self.clients.insert(client_id, Client::new(sender, address));
+        client_id
+    }
+
+    /// Notified when the server wants the client's connection closed.
+    pub fn client_closing(&self, client_id: ClientId) -> Option<Arc<Notify>> {
+        self.clients.get(&client_id).map(|client| client.closing())
+    }
+
+    pub fn unregister_client(&mut self, client_id: ClientId) {
+        self.set_client(client_id);
+        self.unwatch_all();
+
+        if self.replication.replicas.remove(&client_id).is_some() {
+            println!("Connection with replica {} lost", client_id);
+        }
+
+        if let Some(client) = self.clients.remove(&client_id) {
+            self.pubsub.remove_client(client_id, &client);
+        }
+    }
+
+    /// Selects the client the next command runs on behalf of.
+    pub fn set_client(&mut self, client_id: ClientId) {
+        self.client_id = client_id;
+        self.update_expiry_mode();
+    }
+
+    /// On a replica, keys only expire when the master says so: they look missing to clients once
+    /// their time is up, while the master's writes still find them.
+    fn update_expiry_mode(&mut self) {
+        self.storage.expiry = match self.replication.master {
+            None => ExpiryMode::Delete,
+            Some(_) if self.replication.master_client == Some(self.client_id) => ExpiryMode::Ignore,
+            Some(_) => ExpiryMode::Hide
+        };
+    }
+
+    /// Queues a reply on a client's connection.
+    pub fn reply(&self, client_id: ClientId, value: Value) {
+        if let Some(client) = self.clients.get(&client_id) {
+            client.send(value);
         }
     }
+
+    /// Removes keys whose time to live has elapsed and announces their expiration.
+    pub fn expire_keys(&mut self) {
+        self.storage.remove_expired();
+        self.dispatch_keyspace_events();
+        self.flush_propagated();
+    }
+
+    /// Logs a DEL for every key that expired, so replicas and the AOF drop them too.
+    fn propagate_expirations(&mut self) {
+        for key in self.storage.take_expired() {
+            self.propagated.push(vec![b"DEL".to_vec(), key.into_bytes()]);
+        }
+    }
+
+    /// The error a replica answers instead of running a command: READONLY for writes with
+    /// `replica-read-only` on, and MASTERDOWN while the link to the master is down with
+    /// `replica-serve-stale-data` off. The internal clients applying the master's writes or
+    /// loading the AOF are never refused.
+    fn replica_refusal(&mut self, command_name: &str, is_write: bool) -> Option<Value> {
+        let link_up = self.replication.master.as_ref()?.state == LinkState::Connected;
+        let external = self.client().address.is_some();
+
+        if external && !link_up && self.config.get(ConfigKey::ReplicaServeStaleData) == "no" && !STALE_COMMANDS.contains(&command_name) {
+            return Some(Value::SimpleError("MASTERDOWN Link with MASTER is down and replica-serve-stale-data is set to 'no'.".to_string()));
+        }
+
+        if external && is_write && self.config.get(ConfigKey::ReplicaReadOnly) == "yes" {
+            return Some(Value::SimpleError("READONLY You can't write against a read only replica.".to_string()));
+        }
+
+        None
+    }
+
+    /// Handles the keyspace events queued by storage: every modification invalidates WATCHes on its key,
+    /// the events `notify-keyspace-events` asks for are published, and module hooks are called.
+    /// Events raised by the hooks are handled in turn, and so are the keys they wrote to.
+    fn dispatch_keyspace_events(&mut self) {
+        loop {
+            let touched = self.storage.take_touched();
+            self.touch_watched_keys(touched);
+
+            let events = self.storage.take_events();
+
+            if events.is_empty() {
+                return;
+            }
+
+            // A new key is always reported along with the write creating it, so it isn't counted twice.
+            self.persistence.dirty += events.iter().filter(|event| event.class != NOTIFY_KEY_MISS && event.class != NOTIFY_NEW).count() as u64;
+
+            let modified = events.iter().filter(|event| event.class != NOTIFY_KEY_MISS).map(|event| event.key.clone()).collect();
+            self.touch_watched_keys(modified);
+
+            let flags = parse_flags(&self.config.get(ConfigKey::NotifyKeyspaceEvents)).unwrap_or(0);
+
+            for event in events.iter().filter(|event| flags & event.class != 0) {
+                if flags & NOTIFY_KEYSPACE != 0 {
+                    self.pubsub.publish(&self.clients, &format!("__keyspace@0__:{}", event.key), event.event.as_bytes());
+                }
+
+                if flags & NOTIFY_KEYEVENT != 0 {
+                    self.pubsub.publish(&self.clients, &format!("__keyevent@0__:{}", event.event), event.key.as_bytes());
+                }
+            }
+
+            for event in events {
+                for (module, hook) in self.modules.hooks(event.class) {
+                    let previous = self.modules.current.replace(module);
+                    hook(self, &event);
+                    self.modules.current = previous;
+                }
+            }
+        }
+    }
+
+    /// Fails the WATCH of every client watching one of `keys`.
+    fn touch_watched_keys(&mut self, keys: Vec<String>) {
+        for key in keys {
+            for client_id in self.watched_keys.get(&key).into_iter().flatten() {
+                if let Some(client) = self.clients.get_mut(client_id) {
+                    client.watch_dirty = true;
+                }
+            }
+        }
+    }
+
+    fn watch(&mut self, key: &str) {
+        self.client_mut().watched_keys.insert(key.to_string());
+        self.watched_keys.entry(key.to_string()).or_default().insert(self.client_id);
+    }
+
+    /// Forgets every key the current client watches and clears its dirty flag.
+    fn unwatch_all(&mut self) {
+        let Some(client) = self.clients.get_mut(&self.client_id) else {
+            return;
+        };
+
+        for key in std::mem::take(&mut client.watched_keys) {
+            if let Some(watchers) = self.watched_keys.get_mut(&key) {
+                watchers.remove(&self.client_id);
+
+                if watchers.is_empty() {
+                    self.watched_keys.remove(&key);
+                }
+            }
+        }
+
+        client.watch_dirty = false;
+    }
+
+    fn client(&self) -> &Client {
+        self.clients.get(&self.client_id).expect("Command executed without a registered client!")
+    }
+
+    fn client_mut(&mut self) -> &mut Client {
+        self.clients.get_mut(&self.client_id).expect("Command executed without a registered client!")
+    }
+
+    /// The keyspace, for commands implemented by modules.
+    pub fn storage(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+
+    /// The client the running command was sent by.
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    /// Calls `callback` once `delay` has passed. Timers created by a module are dropped when it is unloaded.
+    pub fn create_timer<F>(&mut self, delay: Duration, callback: F) -> TimerId
+    where
+        F: FnOnce(&mut CommandContext) + Send + 'static
+    {
+        let module = self.modules.current.clone();
+        self.modules.create_timer(module, delay, Box::new(callback))
+    }
+
+    /// Cancels a timer, returning whether it was still pending.
+    pub fn stop_timer(&mut self, id: TimerId) -> bool {
+        self.modules.stop_timer(id)
+    }
+
+    /// Raises a keyspace event for a write done by a module through [`CommandContext::storage`], which
+    /// invalidates WATCHes on the key and reaches subscribers and hooks like those of built-in commands.
+    pub fn notify_keyspace_event(&mut self, class: u32, event: &'static str, key: &str) {
+        self.storage.notify(class, event, key);
+    }
+
+    /// Logs `command` to the AOF and replicas for a write done by a module. From a command, it replaces
+    /// the command itself, which is otherwise logged as it was sent; from a timer, it is the only way
+    /// its writes reach them.
+    pub fn replicate<A: Into<Vec<u8>>>(&mut self, command: Vec<A>) {
+        self.propagate_as(vec![command]);
+    }
+
+    /// Runs the timers that are due, returning when the next one is. What they replicate is logged
+    /// once they are done.
+    pub fn fire_timers(&mut self) -> Option<Instant> {
+        for (module, callback) in self.modules.take_due_timers() {
+            let previous = std::mem::replace(&mut self.modules.current, module);
+            callback(self);
+            self.modules.current = previous;
+
+            if let Some(commands) = self.propagation_override.take() {
+                self.propagated.extend(commands);
+            }
+        }
+
+        self.dispatch_keyspace_events();
+        self.flush_propagated();
+        self.modules.next_timer_deadline()
+    }
+
+    pub fn timer_updates(&self) -> Arc<Notify> {
+        self.modules.timer_updates()
+    }
+
+    /// Runs a command, attributing what a module's command sets up, like timers, to that module.
+    /// A write command that succeeds is queued for the AOF, unless it says what to log instead.
+    fn run(&mut self, command: &dyn Command, args: Vec<Value>) -> Result<Value> {
+        let owner = self.modules.command_owner(command.name());
+        let previous = std::mem::replace(&mut self.modules.current, owner);
+        let previous_override = self.propagation_override.take();
+        let propagated_args = command.is_write().then(|| args.iter().map(|arg| arg.clone().unpack_as_bytes().unwrap_or_default()).collect::<Vec<_>>());
+
+        let result = command.exec(args, self);
+        self.modules.current = previous;
+
+        // Read commands borrow values mutably too, only writes invalidate WATCHes, even those that
+        // raise no event, like XACK.
+        let touched = self.storage.take_touched();
+
+        if command.is_write() {
+            self.touch_watched_keys(touched);
+        }
+
+        // Keys the command found expired are deleted before it, on replicas too.
+        self.propagate_expirations();
+
+        match std::mem::replace(&mut self.propagation_override, previous_override) {
+            Some(commands) => self.propagated.extend(commands),
+            None => if let (Ok(reply), Some(args)) = (&result, propagated_args) {
+                if !matches!(reply, Value::SimpleError(_)) {
+                    self.propagated.push([command.name().to_uppercase().into_bytes()].into_iter().chain(args).collect());
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Logs `commands` in place of the running one, for commands whose effect can't be reproduced by
+    /// running them again, like those that depend on the clock. Called with no commands, logs nothing.
+    fn propagate_as<A: Into<Vec<u8>>>(&mut self, commands: Vec<Vec<A>>) {
+        let commands = commands.into_iter().map(|command| command.into_iter().map(Into::into).collect());
+        self.propagation_override.get_or_insert_with(Vec::new).extend(commands);
+    }
+
+    /// Appends the writes queued since the last call to the AOF and streams them to replicas. Several
+    /// are wrapped in MULTI/EXEC, so a transaction or a script is replayed all at once, or not at all
+    /// if the file is cut short. A replica relays its master's stream as it is instead, see
+    /// [`CommandExecutor::apply_master_stream`].
+    pub fn flush_propagated(&mut self) {
+        self.propagate_expirations();
+        let commands = std::mem::take(&mut self.propagated);
+        let stream = self.replication.master.is_none() && self.replication.backlog.is_some();
+
+        if commands.is_empty() || (self.aof.is_none() && !stream) {
+            return;
+        }
+
+        let mut encoded = Vec::new();
+        let wrap = commands.len() > 1;
+
+        if wrap {
+            encoded.extend(encode_command(&["MULTI"]));
+        }
+
+        for command in &commands {
+            encoded.extend(encode_command(command));
+        }
+
+        if wrap {
+            encoded.extend(encode_command(&["EXEC"]));
+        }
+
+        if let Some(aof) = self.aof.as_mut() {
+            if let Err(e) = aof.append(&encoded) {
+                println!("Error writing to the append-only file: {}", e);
+            }
+        }
+
+        if stream {
+            self.replication.feed(&encoded, &self.clients);
+        } else if self.replication.master.is_none() {
+            // As in Redis, the offset moves on without replicas too, as WAITAOF tracks AOF fsyncs by it.
+            self.replication.offset += 1;
+        }
+
+        if self.replication.master.is_none() {
+            let offset = self.replication.offset;
+
+            if let Some(client) = self.clients.get_mut(&self.client_id) {
+                client.write_offset = offset;
+            }
+        }
+
+        self.track_aof_fsync();
+    }
+
+    pub fn master_changes(&self) -> Arc<Notify> {
+        self.replication.master_changes()
+    }
+
+    /// Follows another master, or none to become a master, dropping the link to the current one.
+    pub fn set_master(&mut self, master: Option<(String, u16)>) {
+        self.master_link_down();
+        self.replication.set_master(master);
+        self.update_expiry_mode();
+    }
+
+    /// The master REPLICAOF asked to follow, if any.
+    pub fn master_address(&self) -> Option<(String, u16)> {
+        self.replication.master.as_ref().map(|master| (master.host.clone(), master.port))
+    }
+
+    pub fn set_master_link_state(&mut self, state: LinkState) {
+        if let Some(master) = self.replication.master.as_mut() {
+            master.state = state;
+            master.last_io = Instant::now();
+        }
+    }
+
+    /// How long the master may stay silent before its link is considered lost.
+    pub fn repl_timeout(&mut self) -> Duration {
+        Duration::from_secs(self.config.get(ConfigKey::ReplTimeout).parse().unwrap_or(60))
+    }
+
+    /// Whether nothing was heard from the master for longer than `repl-timeout`.
+    pub fn master_timed_out(&mut self) -> bool {
+        let timeout = self.repl_timeout();
+        self.replication.master.as_ref().is_some_and(|master| master.last_io.elapsed() > timeout)
+    }
+
+    /// What the handshake with the master sends: the port this server listens on, and the history and
+    /// offset to resume from. As in Redis, a replica offers its own history even to a master it never
+    /// followed, so the replicas of a failed master can partially resync with the promoted one.
+    pub fn psync_request(&mut self) -> (String, String, u64) {
+        (self.config.get(ConfigKey::Port), self.replication.replid.clone(), self.replication.offset + 1)
+    }
+
+    /// The REPLCONF ACK telling the master the offset of its stream processed so far, and the offset
+    /// this server's AOF is fsynced at.
+    pub fn replication_ack(&self) -> Value {
+        let ack = ["REPLCONF", "ACK", &self.replication.offset.to_string(), "FACK", &self.aof_fsynced_offset.to_string()];
+        Value::Array(ack.map(|arg| Value::BulkString(arg.to_string().into())).to_vec())
+    }
+
+    /// Asks the replicas to acknowledge their offset right away with REPLCONF GETACK, unless they
+    /// were already asked since the write at `offset`.
+    fn request_acks(&mut self, offset: u64) {
+        if self.replication.replicas.is_empty() || self.replication.getack_offset >= offset {
+            return;
+        }
+
+        let getack = ["REPLCONF", "GETACK", "*"].map(str::to_string);
+        self.replication.feed(&encode_command(&getack), &self.clients);
+        self.replication.getack_offset = self.replication.offset;
+    }
+
+    /// Where the RDB file a master sends is received, next to the RDB file it then replaces.
+    fn master_rdb_temp_path(&mut self) -> PathBuf {
+        let unix_time = SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or_default();
+        Path::new(&self.config.get(ConfigKey::Dir)).join(format!("temp-{}.{}.rdb", unix_time, std::process::id()))
+    }
+
+    /// Whether `repl-diskless-load` has the RDB file of a full resync read from memory rather than
+    /// saved first. Either way the dataset is only swapped once the whole file was read, as Redis does
+    /// with `swapdb`.
+    fn loads_diskless(&mut self) -> bool {
+        match self.config.get(ConfigKey::ReplDisklessLoad).as_str() {
+            "swapdb" => true,
+            "on-empty-db" => self.storage.is_empty(),
+            _ => false
+        }
+    }
+
+    /// Swaps the dataset for the RDB file a master sent for a full resync, and takes over its history.
+    /// Unless loaded without the disk, the file replaces this server's RDB file.
+    pub fn load_master_rdb(&mut self, rdb: &[u8], replid: String, offset: u64) -> Result<()> {
+        let mut rdb_file = if self.loads_diskless() {
+            println!("MASTER <-> REPLICA sync: Loading DB in memory");
+            RDBFile::read(rdb, &|name| self.modules.module_type(name))?
+        } else {
+            let temp_path = self.master_rdb_temp_path();
+            let path = Path::new(&self.config.get(ConfigKey::Dir)).join(self.config.get(ConfigKey::DbFilename));
+
+            let saved = std::fs::write(&temp_path, rdb)
+                .and_then(|()| std::fs::rename(&temp_path, &path))
+                .map_err(|e| anyhow!("Can't save the RDB file received from the master to {}: {}", path.display(), e));
+
+            if saved.is_err() {
+                let _ = std::fs::remove_file(&temp_path);
+            }
+
+            saved?;
+            RDBFile::read(rdb, &|name| self.modules.module_type(name))?
+        };
+
+        // Every key may have changed, so every WATCH fails.
+        for client in self.clients.values_mut().filter(|client| !client.watched_keys.is_empty()) {
+            client.watch_dirty = true;
+        }
+
+        self.storage = Storage::new();
+        self.update_expiry_mode();
+        self.scripting.as_mut().expect("Scripts can't be nested!").flush_libraries();
+        self.load_libraries(rdb_file.take_functions());
+        self.storage.import_data(rdb_file);
+
+        self.disconnect_replicas();
+
+        let backlog_size = self.backlog_size();
+        self.replication.reset(replid, offset, backlog_size);
+
+        // The AOF holds the former dataset, so it starts over from the new one.
+        if self.config.get(ConfigKey::AppendOnly) == "yes" {
+            if self.is_rewriting_aof() {
+                self.aof_rewrite_scheduled = true;
+            } else if let Err(e) = self.start_aof_rewrite() {
+                println!("Unable to start the background AOF rewrite: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Goes on with the master's stream after a partial resync, under the id the master now gives its history.
+    pub fn continue_replication(&mut self, replid: Option<String>) {
+        if let Some(replid) = replid.filter(|replid| *replid != self.replication.replid) {
+            self.replication.shift_replid(replid);
+
+            // Sub-replicas learn the new id as they reconnect.
+            self.disconnect_replicas();
+        }
+
+        if self.replication.backlog.is_none() {
+            let backlog_size = self.backlog_size();
+            self.replication.backlog = Some(Backlog::new(backlog_size, self.replication.offset));
+        }
+    }
+
+    /// Registers the client the master's writes run as, once the link is up. Its replies are dropped.
+    pub fn master_link_up(&mut self) {
+        let (sender, _) = tokio::sync::mpsc::unbounded_channel();
+        let client_id = self.register_client(sender, None);
+
+        self.replication.master_client = Some(client_id);
+        self.set_master_link_state(LinkState::Connected);
+    }
+
+    /// Drops the master's client once the link is down, along with any transaction it left open.
+    pub fn master_link_down(&mut self) {
+        if let Some(client_id) = self.replication.master_client.take() {
+            self.unregister_client(client_id);
+        }
+
+        self.set_master_link_state(LinkState::Connect);
+    }
+
+    fn backlog_size(&mut self) -> usize {
+        parse_memory(&self.config.get(ConfigKey::ReplBacklogSize)).unwrap_or(1024 * 1024) as usize
+    }
+
+    /// Hangs up on every replica, so they have to resync.
+    fn disconnect_replicas(&mut self) {
+        for client_id in std::mem::take(&mut self.replication.replicas).into_keys() {
+            if let Some(client) = self.clients.get(&client_id) {
+                client.close();
+            }
+        }
+    }
+
+    /// Answers the PSYNC of the current client: it gets the writes it is missing from the backlog if
+    /// it can, else a full resync starts.
+    fn sync_replica(&mut self, replid: &str, psync_offset: Option<u64>) -> Value {
+        if self.replication.backlog.is_none() {
+            // As in Redis, a master creating its backlog starts a new history, since nothing from
+            // before was kept that a replica could resume.
+            if self.replication.master.is_none() {
+                self.replication.replid = new_replid();
+                self.replication.replid2 = "0".repeat(40);
+                self.replication.second_replid_offset = None;
+            }
+
+            let backlog_size = self.backlog_size();
+            self.replication.backlog = Some(Backlog::new(backlog_size, self.replication.offset));
+        }
+
+        let missing = psync_offset.and_then(|offset| self.replication.partial_resync(replid, offset));
+        let state = if missing.is_some() { ReplicaState::Online } else { ReplicaState::WaitBgsaveStart };
+
+        self.replication.replicas.insert(self.client_id, Replica { state, pending: Vec::new(), ack_offset: 0, aof_ack_offset: 0, last_ack: Instant::now() });
+
+        if let Some(missing) = missing {
+            println!("Partial resynchronization request from replica {} accepted, sending {} bytes of backlog", self.client_id, missing.len());
+
+            let mut reply = format!("+CONTINUE {}\r\n", self.replication.replid).into_bytes();
+            reply.extend(missing);
+            return Value::Raw(reply);
+        }
+
+        println!("Full resync requested by replica {}", self.client_id);
+
+        // The FULLRESYNC line goes out when the save for the replica starts.
+        if self.can_start_replication_save() {
+            self.start_replication_save();
+        }
+
+        Value::Raw(Vec::new())
+    }
+
+    /// Whether neither a save nor a diskless transfer runs, which the next full resync would wait for.
+    fn can_start_replication_save(&self) -> bool {
+        !self.persistence.is_saving() && self.replication.diskless_transfer.is_none()
+    }
+
+    /// Starts the save the replicas waiting for a full resync will load, telling them the offset it is
+    /// taken at. With `repl-diskless-sync yes`, it is streamed to them instead of written to disk, as
+    /// long as they all can load it without knowing its size.
+    fn start_replication_save(&mut self) {
+        let waiting: Vec<ClientId> = self.replication.replicas.iter()
+            .filter(|(_, replica)| replica.state == ReplicaState::WaitBgsaveStart)
+            .map(|(client_id, _)| *client_id)
+            .collect();
+
+        let diskless = self.config.get(ConfigKey::ReplDisklessSync) == "yes"
+            && waiting.iter().all(|client_id| self.clients.get(client_id).is_some_and(|client| client.capa_eof));
+
+        if !diskless {
+            self.background_save();
+        }
+
+        let line = format!("FULLRESYNC {} {}", self.replication.replid, self.replication.offset);
+        let mut targets = Vec::new();
+
+        for client_id in waiting {
+            let replica = self.replication.replicas.get_mut(&client_id).unwrap();
+            replica.state = ReplicaState::WaitBgsaveEnd;
+            replica.pending.clear();
+
+            if let Some(client) = self.clients.get(&client_id) {
+                client.send(Value::SimpleString(line.clone()));
+                targets.push((client_id, client.sender()));
+            }
+        }
+
+        if diskless {
+            println!("Starting diskless transfer to {} replicas", targets.len());
+            self.replication.diskless_transfer = Some(DisklessTransfer::start(self.snapshot(), targets));
+        }
+    }
+
+    /// Brings the replicas a diskless transfer was sent to online once it is done, sending them the
+    /// writes made since, then starts another sync for the replicas that came too late for it.
+    fn poll_diskless_transfer(&mut self) {
+        let Some(transfer) = self.replication.diskless_transfer.take_if(|transfer| transfer.is_finished()) else {
+            return;
+        };
+
+        let replicas = transfer.replicas.clone();
+        let result = transfer.result();
+
+        for client_id in replicas {
+            let Some(replica) = self.replication.replicas.get_mut(&client_id) else {
+                continue;
+            };
+
+            match &result {
+                Ok(()) => {
+                    replica.state = ReplicaState::Online;
+
+                    if let Some(client) = self.clients.get(&client_id) {
+                        client.send(Value::Raw(std::mem::take(&mut replica.pending)));
+                    }
+
+                    println!("Diskless synchronization with replica {} succeeded", client_id);
+                }
+
+                Err(e) => {
+                    println!("Diskless transfer to replica {} failed: {}", client_id, e);
+                    self.replication.replicas.remove(&client_id);
+
+                    if let Some(client) = self.clients.get(&client_id) {
+                        client.close();
+                    }
+                }
+            }
+        }
+
+        let waiting_for_save = self.replication.replicas.values().any(|replica| replica.state == ReplicaState::WaitBgsaveStart);
+
+        if waiting_for_save && self.can_start_replication_save() {
+            self.start_replication_save();
+        }
+    }
+
+    /// Sends the RDB file a save just wrote to the replicas waiting for it, followed by the writes made
+    /// since, then starts another save for the replicas that came too late for this one.
+    fn replication_save_done(&mut self, saved: Result<PathBuf>) {
+        let diskless = self.replication.diskless_transfer.as_ref().map(|transfer| transfer.replicas.clone()).unwrap_or_default();
+
+        let waiting: Vec<ClientId> = self.replication.replicas.iter()
+            .filter(|(client_id, replica)| replica.state == ReplicaState::WaitBgsaveEnd && !diskless.contains(client_id))
+            .map(|(client_id, _)| *client_id)
+            .collect();
+
+        if !waiting.is_empty() {
+            let rdb = saved.and_then(|path| std::fs::read(&path).map_err(|e| anyhow!("Can't read {}: {}", path.display(), e)));
+
+            for client_id in waiting {
+                let Ok(rdb) = &rdb else {
+                    println!("Full resync of replica {} failed: {}", client_id, rdb.as_ref().err().unwrap());
+                    self.replication.replicas.remove(&client_id);
+
+                    if let Some(client) = self.clients.get(&client_id) {
+                        client.close();
+                    }
+
+                    continue;
+                };
+
+                let replica = self.replication.replicas.get_mut(&client_id).unwrap();
+                replica.state = ReplicaState::Online;
+
+                // Unlike a bulk string, the payload isn't followed by a CRLF.
+                let mut payload = format!("${}\r\n", rdb.len()).into_bytes();
+                payload.extend_from_slice(rdb);
+                payload.append(&mut replica.pending);
+
+                if let Some(client) = self.clients.get(&client_id) {
+                    client.send(Value::Raw(payload));
+                }
+
+                println!("Synchronization with replica {} succeeded", client_id);
+            }
+        }
+
+        let waiting_for_save = self.replication.replicas.values().any(|replica| replica.state == ReplicaState::WaitBgsaveStart);
+
+        if waiting_for_save && self.can_start_replication_save() {
+            self.start_replication_save();
+        }
+    }
+
+    /// Pings the replicas through the replication stream every `repl-ping-replica-period` seconds,
+    /// so they can tell an idle master from a lost one.
+    pub fn replication_cron(&mut self) {
+        let period = Duration::from_secs(self.config.get(ConfigKey::ReplPingReplicaPeriod).parse().unwrap_or(10));
+
+        if self.replication.master.is_none() && !self.replication.replicas.is_empty() && self.replication.last_ping.elapsed() >= period {
+            self.replication.last_ping = Instant::now();
+            self.replication.feed(&encode_command(&["PING"]), &self.clients);
+        }
+    }
+
+    pub fn script_monitor(&self) -> Arc<ScriptMonitor> {
+        Arc::clone(&self.script_monitor)
+    }
+
+    pub fn stream_updates(&self) -> Arc<Notify> {
+        Arc::clone(&self.stream_updates)
+    }
+
+    pub fn take_blocking_request(&mut self) -> Option<BlockingRequest> {
+        self.blocking.take()
+    }
+
+    fn block(&mut self, timeout: Duration, retry_args: Vec<Value>) {
+        self.blocking = Some(BlockingRequest { timeout, retry_args });
+    }
 }
 
 impl CommandExecutor {
@@ -47,28 +1173,337 @@ impl CommandExecutor {
     }
 
     pub fn try_exec(&self, command_name: String, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
-        match self.commands.get(&command_name) {
-            Some(command) => command.exec(args, context),
-            None => panic!("Unable to handle command {}!", command_name.to_uppercase()) // TODO -> SEND ERROR
+        if context.client().in_subscribed_mode() && !SUBSCRIBED_MODE_COMMANDS.contains(&command_name.as_str()) {
+            return Ok(Value::SimpleError(format!("ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context", command_name)));
+        }
+
+        let command = self.lookup(&command_name, context);
+
+        if let Some(error) = context.replica_refusal(&command_name, command.as_ref().is_some_and(|command| command.is_write())) {
+            // As in Redis, a refused command fails the transaction it is queued in.
+            if let Some(transaction) = context.client_mut().transaction.as_mut() {
+                transaction.aborted = true;
+            }
+
+            return Ok(error);
+        }
+
+        if context.client().transaction.is_some() && !TRANSACTION_CONTROL_COMMANDS.contains(&command_name.as_str()) {
+            return Ok(self.queue(command_name, args, context));
+        }
+
+        let result = match command {
+            Some(command) => context.run(command.as_ref(), args),
+            None => match command_name.as_str() {
+                "exec" => self.exec_transaction(context),
+                "module" => module_commands::module(self, args, context),
+                "eval" | "evalsha" | "eval_ro" | "evalsha_ro" => script_commands::eval(self, &command_name, args, context),
+                "fcall" | "fcall_ro" => script_commands::fcall(self, &command_name, args, context),
+                _ => Ok(unknown_command_error(&command_name, &args))
+            }
+        };
+
+        context.dispatch_keyspace_events();
+        result
+    }
+
+    /// Queues a command for the client's open transaction. Unknown commands and wrong numbers of
+    /// arguments abort the transaction, so EXEC runs none of it.
+    fn queue(&self, command_name: String, args: Vec<Value>, context: &mut CommandContext) -> Value {
+        let arity = self.arity(&command_name, context);
+        let transaction = context.client_mut().transaction.as_mut().unwrap();
+
+        let Some(arity) = arity else {
+            transaction.aborted = true;
+            return unknown_command_error(&command_name, &args);
+        };
+
+        let given = args.len() as i32 + 1;
+
+        if (arity > 0 && given != arity) || given < -arity {
+            transaction.aborted = true;
+            return Value::SimpleError(format!("ERR wrong number of arguments for '{}' command", command_name));
+        }
+
+        transaction.commands.push((command_name, args));
+        Value::SimpleString("QUEUED".to_string())
+    }
+
+    /// Runs EXEC, which lives here rather than in a [`Command`] because it dispatches the queued commands.
+    /// The whole transaction runs while the context is locked, so no other client can interleave with it.
+    fn exec_transaction(&self, context: &mut CommandContext) -> Result<Value> {
+        let Some(transaction) = context.client_mut().transaction.take() else {
+            return Ok(Value::SimpleError("ERR EXEC without MULTI".to_string()));
+        };
+
+        // A watched key whose time to live ran out since WATCH counts as modified, even if nothing noticed yet.
+        for key in context.client().watched_keys.clone() {
+            context.storage.expire_if_needed(&key);
+        }
+
+        context.dispatch_keyspace_events();
+
+        let dirty = context.client().watch_dirty;
+        context.unwatch_all();
+
+        if transaction.aborted {
+            return Ok(Value::SimpleError("EXECABORT Transaction discarded because of previous errors.".to_string()));
+        }
+
+        if dirty {
+            return Ok(context.client().null_array());
+        }
+
+        let mut replies: Vec<Value> = Vec::with_capacity(transaction.commands.len());
+
+        for (command_name, args) in transaction.commands {
+            let reply = self.try_exec(command_name, args, context)
+                .unwrap_or_else(|e| Value::SimpleError(format!("ERR {}", e)));
+
+            // Blocking commands never block inside a transaction, they just return their immediate reply.
+            context.take_blocking_request();
+            replies.push(reply);
+        }
+
+        Ok(Value::Array(replies))
+    }
+
+    /// Loads the dataset from the AOF named by `appenddirname` and `appendfilename`: its base file, then
+    /// the commands of every incremental file in order. Returns false if there is no AOF yet.
+    pub fn load_append_only_files(&self, context: &mut CommandContext) -> Result<bool> {
+        let dir = context.aof_dir();
+        let file_name = context.config.get(ConfigKey::AppendFilename);
+
+        let Some(manifest) = Manifest::load(&dir, &file_name)? else {
+            return Ok(false);
+        };
+
+        let load_truncated = context.config.get(ConfigKey::AofLoadTruncated) == "yes";
+        let (sender, _) = tokio::sync::mpsc::unbounded_channel();
+        let client_id = context.register_client(sender, None);
+
+        let mut result = Ok(());
+
+        if let Some(base) = &manifest.base {
+            let path = dir.join(&base.name);
+
+            result = if base.name.ends_with(".rdb") {
+                RDBFile::from(&path, &|name| context.modules.module_type(name)).map(|mut rdb_file| {
+                    context.load_libraries(rdb_file.take_functions());
+                    context.storage.import_data(rdb_file);
+                })
+            } else {
+                self.replay_aof_file(context, client_id, &path, false)
+            };
+        }
+
+        for (index, file) in manifest.incremental.iter().enumerate() {
+            if result.is_err() {
+                break;
+            }
+
+            let is_last = index + 1 == manifest.incremental.len();
+            result = self.replay_aof_file(context, client_id, &dir.join(&file.name), is_last && load_truncated);
+        }
+
+        context.unregister_client(client_id);
+        context.propagated.clear();
+        context.persistence.dirty = 0;
+
+        result.map(|()| true)
+    }
+
+    /// Runs the commands logged in an AOF file. A file cut short in the middle of a command or of a
+    /// transaction fails to load, unless `truncate` allows dropping the incomplete tail.
+    fn replay_aof_file(&self, context: &mut CommandContext, client_id: ClientId, path: &Path, truncate: bool) -> Result<()> {
+        let file = File::open(path).map_err(|e| anyhow!("Can't open the append-only file {}: {}", path.display(), e))?;
+        let mut reader = AofReader::new(BufReader::new(file));
+        let mut before_multi: Option<u64> = None;
+
+        loop {
+            let valid_up_to = reader.offset();
+
+            let mut args = match reader.next_entry()? {
+                AofEntry::Command(args) => args,
+                AofEntry::End if before_multi.is_none() => return Ok(()),
+                AofEntry::End | AofEntry::Truncated => break,
+                AofEntry::Corrupt(reason) => return Err(anyhow!("Bad file format reading the append only file {}: {}", path.display(), reason))
+            };
+
+            let command_name = String::from_utf8_lossy(&args.remove(0)).to_lowercase();
+
+            match command_name.as_str() {
+                "multi" => before_multi = Some(valid_up_to),
+                "exec" => before_multi = None,
+                _ => {}
+            }
+
+            context.set_client(client_id);
+            let reply = self.try_exec(command_name.clone(), args.into_iter().map(Value::BulkString).collect(), context)?;
+
+            if let Value::SimpleError(e) = reply {
+                if e.starts_with("ERR unknown command") {
+                    return Err(anyhow!("Unknown command '{}' reading the append only file {}", command_name, path.display()));
+                }
+            }
+
+            context.take_blocking_request();
+            context.propagated.clear();
+        }
+
+        let valid_up_to = before_multi.unwrap_or(reader.offset());
+
+        if !truncate {
+            return Err(anyhow!("Unexpected end of file reading the append only file {}. Set aof-load-truncated to yes to load it anyway, dropping the incomplete command at its end", path.display()));
         }
+
+        println!("!!! Warning: short read while loading the AOF file {}!!!", path.display());
+        println!("AOF {} loaded anyway because aof-load-truncated is enabled, truncating it to {} bytes", path.display(), valid_up_to);
+
+        // A transaction left open is dropped, as its EXEC never made it to the file.
+        context.set_client(client_id);
+        context.client_mut().transaction = None;
+        OpenOptions::new().write(true).open(path)?.set_len(valid_up_to)?;
+        Ok(())
+    }
+
+    /// Runs a command streamed by the master as the master's client, then relays it to this server's
+    /// own replicas. Nothing is answered but REPLCONF GETACK, whose reply is returned to be sent back:
+    /// as in Redis, the offset it reports doesn't count the GETACK itself.
+    pub fn apply_master_stream(&self, context: &mut CommandContext, value: Value, raw: &[u8]) -> Option<Value> {
+        let mut args = match value {
+            Value::Array(items) => items,
+            _ => Vec::new()
+        };
+
+        context.set_master_link_state(LinkState::Connected);
+
+        let mut reply = None;
+
+        if !args.is_empty() {
+            let command_name = args.remove(0).unpack_as_string().unwrap_or_default().to_lowercase();
+            let option = args.first().and_then(|option| option.clone().unpack_as_string()).unwrap_or_default();
+
+            if command_name == "replconf" && option.eq_ignore_ascii_case("getack") {
+                reply = Some(context.replication_ack());
+            } else if let Some(client_id) = context.replication.master_client {
+                context.set_client(client_id);
+
+                if let Err(e) = self.try_exec(command_name.clone(), args, context) {
+                    println!("Error running '{}' streamed by the master: {}", command_name, e);
+                }
+
+                context.take_blocking_request();
+                context.flush_propagated();
+            }
+        }
+
+        context.replication.feed(raw, &context.clients);
+        context.track_aof_fsync();
+        reply
+    }
+
+    /// Loads a module linked into the server, like those handed to [`crate::server::run`].
+    pub fn load_module(&self, module: Box<dyn Module>, context: &mut CommandContext) -> Result<()> {
+        context.modules.load(module, None, None, Vec::new(), &|name| self.is_builtin(name))
+    }
+
+    /// Loads a module from a dynamic library, as `--loadmodule` and MODULE LOAD do.
+    pub fn load_module_library(&self, path: &str, args: Vec<String>, context: &mut CommandContext) -> Result<()> {
+        let (library, module) = DynamicLibrary::open_module(path)?;
+        context.modules.load(module, Some(library), Some(path.to_string()), args, &|name| self.is_builtin(name))
+    }
+
+    /// Finds a built-in command, or else one registered by a module.
+    fn lookup(&self, command_name: &str, context: &CommandContext) -> Option<Arc<dyn Command>> {
+        self.commands.get(command_name).cloned().or_else(|| context.modules.command(command_name))
+    }
+
+    /// The arity of a command, see [`Command::arity`], or none if there is no such command.
+    fn arity(&self, command_name: &str, context: &CommandContext) -> Option<i32> {
+        match command_name {
+            "exec" => Some(1),
+            "eval" | "evalsha" | "eval_ro" | "evalsha_ro" | "fcall" | "fcall_ro" => Some(-3),
+            "module" => Some(-2),
+            _ => self.lookup(command_name, context).map(|command| command.arity())
+        }
+    }
+
+    /// Whether a name is taken by a built-in command, so modules can't register it.
+    fn is_builtin(&self, command_name: &str) -> bool {
+        self.commands.contains_key(command_name) || EXECUTOR_COMMANDS.contains(&command_name)
     }
 
     fn register(&mut self, command: Box<dyn Command>) {
-        self.commands.insert(command.name().to_string(), command);
+        self.commands.insert(command.name().to_string(), Arc::from(command));
     }
 
     fn init_def(&mut self) {
         self.register(Box::new(PingCommand));
         self.register(Box::new(EchoCommand));
+        self.register(Box::new(HelloCommand));
 
         self.register(Box::new(StorageSetCommand));
         self.register(Box::new(StorageGetCommand));
         self.register(Box::new(StorageKeysCommand));
+        self.register(Box::new(StorageDelCommand));
         self.register(Box::new(StorageValueTypeCommand));
+        self.register(Box::new(StorageDumpCommand));
+        self.register(Box::new(StorageRestoreCommand));
 
         self.register(Box::new(StorageXAddCommand));
         self.register(Box::new(StorageXRangeCommand));
+        self.register(Box::new(StorageXRevRangeCommand));
+        self.register(Box::new(StorageXLenCommand));
+        self.register(Box::new(StorageXDelCommand));
+        self.register(Box::new(StorageXSetIdCommand));
+        self.register(Box::new(StorageXInfoCommand));
         self.register(Box::new(StorageXReadCommand));
+        self.register(Box::new(StorageXTrimCommand));
+        self.register(Box::new(StorageXGroupCommand));
+        self.register(Box::new(StorageXReadGroupCommand));
+        self.register(Box::new(StorageXAckCommand));
+        self.register(Box::new(StorageXPendingCommand));
+        self.register(Box::new(StorageXClaimCommand));
+        self.register(Box::new(StorageXAutoClaimCommand));
+
+        self.register(Box::new(GeoAddCommand));
+        self.register(Box::new(GeoPosCommand));
+        self.register(Box::new(GeoDistCommand));
+        self.register(Box::new(GeoHashCommand));
+        self.register(Box::new(GeoSearchCommand));
+        self.register(Box::new(GeoSearchStoreCommand));
+
+        self.register(Box::new(SubscribeCommand));
+        self.register(Box::new(UnsubscribeCommand));
+        self.register(Box::new(PSubscribeCommand));
+        self.register(Box::new(PUnsubscribeCommand));
+        self.register(Box::new(SSubscribeCommand));
+        self.register(Box::new(SUnsubscribeCommand));
+        self.register(Box::new(PublishCommand));
+        self.register(Box::new(SPublishCommand));
+        self.register(Box::new(PubSubCommand));
+
+        self.register(Box::new(MultiCommand));
+        self.register(Box::new(DiscardCommand));
+        self.register(Box::new(WatchCommand));
+        self.register(Box::new(UnwatchCommand));
+
+        self.register(Box::new(SaveCommand));
+        self.register(Box::new(BgSaveCommand));
+        self.register(Box::new(LastSaveCommand));
+        self.register(Box::new(BgRewriteAofCommand));
+
+        self.register(Box::new(ScriptCommand));
+        self.register(Box::new(FunctionCommand));
+
+        self.register(Box::new(ReplicaOfCommand));
+        self.register(Box::new(ReplConfCommand));
+        self.register(Box::new(PsyncCommand));
+        self.register(Box::new(RoleCommand));
+        self.register(Box::new(InfoCommand));
+        self.register(Box::new(WaitCommand));
+        self.register(Box::new(WaitAofCommand));
 
         self.register(Box::new(ConfigCommand))
     }
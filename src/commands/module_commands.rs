@@ -0,0 +1,43 @@
+use crate::commands::{unpack_args, CommandContext, CommandExecutor};
+use crate::parser::Value;
+
+/// Runs MODULE, which lives in the executor because loading a module needs the built-in command names.
+pub fn module(executor: &CommandExecutor, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+    let args = unpack_args(args)?;
+
+    if args.is_empty() {
+        return Ok(Value::SimpleError("Missing arguments! Correct usage MODULE <LOAD|LIST|UNLOAD> ...".to_string()));
+    }
+
+    match (args[0].to_lowercase().as_str(), &args[1..]) {
+        ("load", [path, module_args @ ..]) => match executor.load_module_library(path, module_args.to_vec(), context) {
+            Ok(()) => Ok(Value::SimpleString("OK".to_string())),
+            Err(e) => {
+                println!("Module {} failed to load: {}", path, e);
+                Ok(Value::SimpleError("ERR Error loading the extension. Please check the server logs.".to_string()))
+            }
+        },
+
+        ("list", []) => {
+            let client = context.client();
+
+            Ok(Value::Array(
+                context.modules.list()
+                    .map(|(name, version, path, module_args)| client.map_value(vec![
+                        (Value::BulkString("name".into()), Value::BulkString(name.to_string().into())),
+                        (Value::BulkString("ver".into()), Value::Integer(version)),
+                        (Value::BulkString("path".into()), Value::BulkString(path.unwrap_or_default().to_string().into())),
+                        (Value::BulkString("args".into()), Value::Array(module_args.iter().map(|arg| Value::BulkString(arg.clone().into())).collect()))
+                    ]))
+                    .collect()
+            ))
+        }
+
+        ("unload", [name]) => match context.modules.unload(name) {
+            Ok(()) => Ok(Value::SimpleString("OK".to_string())),
+            Err(e) => Ok(Value::SimpleError(format!("ERR Error unloading module: {}", e)))
+        },
+
+        _ => Ok(Value::SimpleError(format!("ERR unknown subcommand or wrong number of arguments for '{}'", args[0])))
+    }
+}
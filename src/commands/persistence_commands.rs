@@ -0,0 +1,101 @@
+use crate::commands::{unpack_args, Command, CommandContext};
+use crate::parser::Value;
+use std::time::UNIX_EPOCH;
+
+pub struct SaveCommand;
+impl Command for SaveCommand {
+    fn name(&self) -> &str {
+        "save"
+    }
+
+    fn arity(&self) -> i32 {
+        1
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        if !args.is_empty() {
+            return Ok(Value::SimpleError("ERR wrong number of arguments for 'save' command".to_string()));
+        }
+
+        if context.persistence.is_saving() {
+            return Ok(Value::SimpleError("ERR Background save already in progress".to_string()));
+        }
+
+        match context.save() {
+            Ok(()) => Ok(Value::SimpleString("OK".to_string())),
+            Err(e) => Ok(Value::SimpleError(format!("ERR {}", e)))
+        }
+    }
+}
+
+/// BGSAVE [SCHEDULE]
+pub struct BgSaveCommand;
+impl Command for BgSaveCommand {
+    fn name(&self) -> &str {
+        "bgsave"
+    }
+
+    fn arity(&self) -> i32 {
+        -1
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        let args = unpack_args(args)?;
+
+        let schedule = match args.as_slice() {
+            [] => false,
+            [option] if option.eq_ignore_ascii_case("schedule") => true,
+            _ => return Ok(Value::SimpleError("ERR syntax error".to_string()))
+        };
+
+        if context.persistence.is_saving() {
+            if !schedule {
+                return Ok(Value::SimpleError("ERR Background save already in progress".to_string()));
+            }
+
+            context.persistence.save_scheduled = true;
+            return Ok(Value::SimpleString("Background saving scheduled".to_string()));
+        }
+
+        context.background_save();
+        Ok(Value::SimpleString("Background saving started".to_string()))
+    }
+}
+
+pub struct LastSaveCommand;
+impl Command for LastSaveCommand {
+    fn name(&self) -> &str {
+        "lastsave"
+    }
+
+    fn arity(&self) -> i32 {
+        1
+    }
+
+    fn exec(&self, _args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        let last_save = context.persistence.last_save.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or_default();
+        Ok(Value::Integer(last_save as i64))
+    }
+}
+
+pub struct BgRewriteAofCommand;
+impl Command for BgRewriteAofCommand {
+    fn name(&self) -> &str {
+        "bgrewriteaof"
+    }
+
+    fn arity(&self) -> i32 {
+        1
+    }
+
+    fn exec(&self, _args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        if context.is_rewriting_aof() {
+            return Ok(Value::SimpleError("ERR Background append only file rewriting already in progress".to_string()));
+        }
+
+        match context.start_aof_rewrite() {
+            Ok(()) => Ok(Value::SimpleString("Background append only file rewriting started".to_string())),
+            Err(e) => Ok(Value::SimpleError(format!("ERR {}", e)))
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use crate::cluster::key_hash_slot;
+use crate::commands::{unpack_args, unpack_args_with_bytes, Command, CommandContext};
+use crate::parser::Value;
+use crate::pubsub::{glob_match, SubscriptionKind};
+
+pub struct SubscribeCommand;
+impl Command for SubscribeCommand {
+    fn name(&self) -> &str {
+        "subscribe"
+    }
+
+    fn arity(&self) -> i32 {
+        -2
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        if args.is_empty() {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage SUBSCRIBE <channel> [<channel>]...".to_string()));
+        }
+
+        Ok(change_subscriptions(unpack_args(args)?, context, SubscriptionKind::Channel, true))
+    }
+}
+
+pub struct UnsubscribeCommand;
+impl Command for UnsubscribeCommand {
+    fn name(&self) -> &str {
+        "unsubscribe"
+    }
+
+    fn arity(&self) -> i32 {
+        -1
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        Ok(change_subscriptions(unpack_args(args)?, context, SubscriptionKind::Channel, false))
+    }
+}
+
+pub struct PSubscribeCommand;
+impl Command for PSubscribeCommand {
+    fn name(&self) -> &str {
+        "psubscribe"
+    }
+
+    fn arity(&self) -> i32 {
+        -2
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        if args.is_empty() {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage PSUBSCRIBE <pattern> [<pattern>]...".to_string()));
+        }
+
+        Ok(change_subscriptions(unpack_args(args)?, context, SubscriptionKind::Pattern, true))
+    }
+}
+
+pub struct PUnsubscribeCommand;
+impl Command for PUnsubscribeCommand {
+    fn name(&self) -> &str {
+        "punsubscribe"
+    }
+
+    fn arity(&self) -> i32 {
+        -1
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        Ok(change_subscriptions(unpack_args(args)?, context, SubscriptionKind::Pattern, false))
+    }
+}
+
+pub struct SSubscribeCommand;
+impl Command for SSubscribeCommand {
+    fn name(&self) -> &str {
+        "ssubscribe"
+    }
+
+    fn arity(&self) -> i32 {
+        -2
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        if args.is_empty() {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage SSUBSCRIBE <shardchannel> [<shardchannel>]...".to_string()));
+        }
+
+        Ok(change_subscriptions(unpack_args(args)?, context, SubscriptionKind::Shard, true))
+    }
+}
+
+pub struct SUnsubscribeCommand;
+impl Command for SUnsubscribeCommand {
+    fn name(&self) -> &str {
+        "sunsubscribe"
+    }
+
+    fn arity(&self) -> i32 {
+        -1
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        Ok(change_subscriptions(unpack_args(args)?, context, SubscriptionKind::Shard, false))
+    }
+}
+
+/// Applies a (P|S)SUBSCRIBE or (P|S)UNSUBSCRIBE, confirming each name with its own reply.
+/// All confirmations but the last are pushed straight away; the last one becomes the command reply.
+/// Unsubscribing without names drops every subscription of that kind.
+fn change_subscriptions(names: Vec<String>, context: &mut CommandContext, kind: SubscriptionKind, subscribe: bool) -> Value {
+    // Shard channels named together must live in one slot, as a cluster node only owns some of them.
+    if kind == SubscriptionKind::Shard && names.windows(2).any(|pair| key_hash_slot(pair[0].as_bytes()) != key_hash_slot(pair[1].as_bytes())) {
+        return Value::SimpleError("CROSSSLOT Keys in request don't hash to the same slot".to_string());
+    }
+
+    let client_id = context.client_id;
+    let client = context.clients.get_mut(&client_id).expect("Command executed without a registered client!");
+
+    let reply_kind = match (kind, subscribe) {
+        (SubscriptionKind::Channel, true) => "subscribe",
+        (SubscriptionKind::Channel, false) => "unsubscribe",
+        (SubscriptionKind::Pattern, true) => "psubscribe",
+        (SubscriptionKind::Pattern, false) => "punsubscribe",
+        (SubscriptionKind::Shard, true) => "ssubscribe",
+        (SubscriptionKind::Shard, false) => "sunsubscribe"
+    };
+
+    let names = if names.is_empty() {
+        client.subscriptions(kind).iter().cloned().collect()
+    } else {
+        names
+    };
+
+    if names.is_empty() {
+        return client.push_value(vec![
+            Value::BulkString(reply_kind.to_string().into()),
+            Value::NullBulkString,
+            Value::Integer(client.subscription_count(kind) as i64)
+        ]);
+    }
+
+    let mut confirmations: Vec<Value> = names.into_iter()
+        .map(|name| {
+            if subscribe {
+                context.pubsub.subscribe(kind, &name, client_id, client);
+            } else {
+                context.pubsub.unsubscribe(kind, &name, client_id, client);
+            }
+
+            client.push_value(vec![
+                Value::BulkString(reply_kind.to_string().into()),
+                Value::BulkString(name.into()),
+                Value::Integer(client.subscription_count(kind) as i64)
+            ])
+        })
+        .collect();
+
+    let last = confirmations.pop().unwrap();
+
+    for confirmation in confirmations {
+        client.send(confirmation);
+    }
+
+    last
+}
+
+pub struct PublishCommand;
+impl Command for PublishCommand {
+    fn name(&self) -> &str {
+        "publish"
+    }
+
+    fn arity(&self) -> i32 {
+        3
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        // The message is binary safe, only the channel is read as text.
+        let (args, message) = unpack_args_with_bytes(args, 1)?;
+
+        if args.len() != 2 {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage PUBLISH <channel> <message>".to_string()));
+        }
+
+        Ok(Value::Integer(context.pubsub.publish(&context.clients, &args[0], &message) as i64))
+    }
+}
+
+pub struct SPublishCommand;
+impl Command for SPublishCommand {
+    fn name(&self) -> &str {
+        "spublish"
+    }
+
+    fn arity(&self) -> i32 {
+        3
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        let (args, message) = unpack_args_with_bytes(args, 1)?;
+
+        if args.len() != 2 {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage SPUBLISH <shardchannel> <message>".to_string()));
+        }
+
+        Ok(Value::Integer(context.pubsub.spublish(&context.clients, &args[0], &message) as i64))
+    }
+}
+
+pub struct PubSubCommand;
+impl Command for PubSubCommand {
+    fn name(&self) -> &str {
+        "pubsub"
+    }
+
+    fn arity(&self) -> i32 {
+        -2
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        let args = unpack_args(args)?;
+
+        if args.is_empty() {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage PUBSUB <CHANNELS|NUMSUB|NUMPAT|SHARDCHANNELS|SHARDNUMSUB> ...".to_string()));
+        }
+
+        match (args[0].to_lowercase().as_str(), &args[1..]) {
+            (sub_command @ ("channels" | "shardchannels"), [] | [_]) => {
+                let kind = if sub_command == "shardchannels" { SubscriptionKind::Shard } else { SubscriptionKind::Channel };
+                let pattern = args.get(1);
+
+                Ok(Value::Array(
+                    context.pubsub.channels(kind)
+                        .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel)))
+                        .map(|channel| Value::BulkString(channel.to_string().into()))
+                        .collect()
+                ))
+            }
+
+            (sub_command @ ("numsub" | "shardnumsub"), channels) => {
+                let kind = if sub_command == "shardnumsub" { SubscriptionKind::Shard } else { SubscriptionKind::Channel };
+
+                Ok(Value::Array(
+                    channels.iter()
+                        .flat_map(|channel| [
+                            Value::BulkString(channel.clone().into()),
+                            Value::Integer(context.pubsub.subscriber_count(kind, channel) as i64)
+                        ])
+                        .collect()
+                ))
+            }
+
+            ("numpat", []) => Ok(Value::Integer(context.pubsub.pattern_count() as i64)),
+
+            _ => Ok(Value::SimpleError(format!("ERR unknown subcommand or wrong number of arguments for '{}'", args[0])))
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use crate::aof::FsyncPolicy;
+use crate::commands::{unpack_args, Command, CommandContext};
+use crate::parser::Value;
+use crate::replication::{new_replid, LinkState};
+use std::time::Duration;
+
+/// REPLICAOF host port | REPLICAOF NO ONE
+pub struct ReplicaOfCommand;
+impl Command for ReplicaOfCommand {
+    fn name(&self) -> &str {
+        "replicaof"
+    }
+
+    fn arity(&self) -> i32 {
+        3
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        let args = unpack_args(args)?;
+
+        let [host, port] = args.as_slice() else {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage REPLICAOF <host> <port> | NO ONE".to_string()));
+        };
+
+        if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+            if context.replication.master.is_some() {
+                context.set_master(None);
+
+                // The history goes on under a new id, which the former master's replicas can still resume.
+                context.replication.shift_replid(new_replid());
+                println!("MASTER MODE enabled");
+            }
+
+            return Ok(Value::SimpleString("OK".to_string()));
+        }
+
+        let Ok(port) = port.parse::<u16>() else {
+            return Ok(Value::SimpleError("ERR Invalid master port".to_string()));
+        };
+
+        if context.master_address().is_some_and(|(current_host, current_port)| current_host.eq_ignore_ascii_case(host) && current_port == port) {
+            return Ok(Value::SimpleString("OK Already connected to specified master".to_string()));
+        }
+
+        context.set_master(Some((host.clone(), port)));
+        println!("Connecting to MASTER {}:{}", host, port);
+
+        Ok(Value::SimpleString("OK".to_string()))
+    }
+}
+
+/// REPLCONF <option> <value> [<option> <value> ...], sent by replicas to their master.
+pub struct ReplConfCommand;
+impl Command for ReplConfCommand {
+    fn name(&self) -> &str {
+        "replconf"
+    }
+
+    fn arity(&self) -> i32 {
+        -1
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        let args = unpack_args(args)?;
+
+        if !args.len().is_multiple_of(2) {
+            return Ok(Value::SimpleError("ERR syntax error".to_string()));
+        }
+
+        for (index, pair) in args.chunks(2).enumerate() {
+            match pair[0].to_lowercase().as_str() {
+                "listening-port" => match pair[1].parse::<u16>() {
+                    Ok(port) => context.client_mut().listening_port = Some(port),
+                    Err(_) => return Ok(Value::SimpleError("ERR value is not an integer or out of range".to_string()))
+                },
+
+                // Acknowledgements are not answered, as the replica doesn't read replies.
+                "ack" => {
+                    acknowledge(&args[index * 2..], context);
+                    return Ok(Value::Raw(Vec::new()));
+                }
+
+                // Only a master asks for acknowledgements, and its link answers those itself.
+                "getack" => return Ok(Value::Raw(Vec::new())),
+
+                // Only diskless syncs need a capability, any other is supported or not needed.
+                "capa" => if pair[1].eq_ignore_ascii_case("eof") {
+                    context.client_mut().capa_eof = true;
+                },
+
+                "ip-address" => {}
+
+                option => return Ok(Value::SimpleError(format!("ERR Unrecognized REPLCONF option: {}", option)))
+            }
+        }
+
+        Ok(Value::SimpleString("OK".to_string()))
+    }
+}
+
+/// Takes in REPLCONF ACK <offset> [FACK <aofoffset>], waking up the clients in WAIT and WAITAOF.
+fn acknowledge(args: &[String], context: &mut CommandContext) {
+    let client_id = context.client_id;
+
+    let Some(replica) = context.replication.replicas.get_mut(&client_id) else {
+        return;
+    };
+
+    if let Ok(offset) = args[1].parse::<u64>() {
+        replica.ack_offset = offset;
+    }
+
+    if let [_, _, fack, aof_offset, ..] = args {
+        if let (true, Ok(aof_offset)) = (fack.eq_ignore_ascii_case("fack"), aof_offset.parse::<u64>()) {
+            replica.aof_ack_offset = aof_offset;
+        }
+    }
+
+    replica.last_ack = std::time::Instant::now();
+    context.stream_updates.notify_waiters();
+}
+
+/// WAIT numreplicas timeout
+pub struct WaitCommand;
+impl Command for WaitCommand {
+    fn name(&self) -> &str {
+        "wait"
+    }
+
+    fn arity(&self) -> i32 {
+        3
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        let raw_args = args.clone();
+        let args = unpack_args(args)?;
+
+        let [num_replicas, timeout] = args.as_slice() else {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage WAIT <numreplicas> <timeout>".to_string()));
+        };
+
+        if context.replication.master.is_some() {
+            return Ok(Value::SimpleError("ERR WAIT cannot be used with replica instances. Please also note that writes to replicas are just local and are not propagated.".to_string()));
+        }
+
+        let Ok(num_replicas) = num_replicas.parse::<i64>() else {
+            return Ok(Value::SimpleError("ERR value is not an integer or out of range".to_string()));
+        };
+
+        let timeout = match parse_wait_timeout(timeout) {
+            Ok(timeout) => timeout,
+            Err(error) => return Ok(error)
+        };
+
+        let offset = context.client().write_offset;
+        let acknowledged = context.replication.acknowledged(offset) as i64;
+
+        if acknowledged < num_replicas {
+            context.request_acks(offset);
+            context.block(timeout, raw_args);
+        }
+
+        Ok(Value::Integer(acknowledged))
+    }
+}
+
+/// WAITAOF numlocal numreplicas timeout
+pub struct WaitAofCommand;
+impl Command for WaitAofCommand {
+    fn name(&self) -> &str {
+        "waitaof"
+    }
+
+    fn arity(&self) -> i32 {
+        4
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        let raw_args = args.clone();
+        let args = unpack_args(args)?;
+
+        let [num_local, num_replicas, timeout] = args.as_slice() else {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage WAITAOF <numlocal> <numreplicas> <timeout>".to_string()));
+        };
+
+        if context.replication.master.is_some() {
+            return Ok(Value::SimpleError("ERR WAITAOF cannot be used with replica instances. Please also note that writes to replicas are just local and are not propagated.".to_string()));
+        }
+
+        let (Ok(num_local), Ok(num_replicas)) = (num_local.parse::<u64>(), num_replicas.parse::<u64>()) else {
+            return Ok(Value::SimpleError("ERR value is out of range, must be positive".to_string()));
+        };
+
+        let timeout = match parse_wait_timeout(timeout) {
+            Ok(timeout) => timeout,
+            Err(error) => return Ok(error)
+        };
+
+        if num_local > 0 && context.aof.is_none() {
+            return Ok(Value::SimpleError("ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.".to_string()));
+        }
+
+        let offset = context.client().write_offset;
+
+        // Under `appendfsync no` nothing would ever fsync the AOF, so it is done on demand.
+        if num_local > 0 && context.aof_fsynced_offset < offset && context.fsync_policy() == FsyncPolicy::No {
+            context.sync_append_only();
+        }
+
+        let local = (context.aof.is_some() && context.aof_fsynced_offset >= offset) as u64;
+        let acknowledged = context.replication.aof_acknowledged(offset) as u64;
+
+        if local < num_local || acknowledged < num_replicas {
+            if acknowledged < num_replicas {
+                context.request_acks(offset);
+            }
+
+            context.block(timeout, raw_args);
+        }
+
+        Ok(Value::Array(vec![Value::Integer(local as i64), Value::Integer(acknowledged as i64)]))
+    }
+}
+
+/// A timeout in milliseconds, zero meaning forever.
+fn parse_wait_timeout(timeout: &str) -> Result<Duration, Value> {
+    match timeout.parse::<i64>() {
+        Ok(timeout) if timeout < 0 => Err(Value::SimpleError("ERR timeout is negative".to_string())),
+        Ok(timeout) => Ok(Duration::from_millis(timeout as u64)),
+        Err(_) => Err(Value::SimpleError("ERR timeout is not an integer or out of range".to_string()))
+    }
+}
+
+/// PSYNC replicationid offset
+pub struct PsyncCommand;
+impl Command for PsyncCommand {
+    fn name(&self) -> &str {
+        "psync"
+    }
+
+    fn arity(&self) -> i32 {
+        -3
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        let args = unpack_args(args)?;
+
+        let [replid, offset] = args.as_slice() else {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage PSYNC <replicationid> <offset>".to_string()));
+        };
+
+        if context.replication.master.as_ref().is_some_and(|master| master.state != LinkState::Connected) {
+            return Ok(Value::SimpleError("NOMASTERLINK Can't SYNC while not connected with my master".to_string()));
+        }
+
+        if context.replication.replicas.contains_key(&context.client_id) {
+            return Ok(Value::Raw(Vec::new()));
+        }
+
+        // A replica that never synced asks with `? -1`.
+        let offset = offset.parse::<u64>().ok();
+        Ok(context.sync_replica(replid, offset))
+    }
+}
+
+pub struct RoleCommand;
+impl Command for RoleCommand {
+    fn name(&self) -> &str {
+        "role"
+    }
+
+    fn arity(&self) -> i32 {
+        1
+    }
+
+    fn exec(&self, _args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        let replication = &context.replication;
+
+        let Some(master) = replication.master.as_ref() else {
+            let replicas = replication.replicas.iter()
+                .filter_map(|(client_id, replica)| context.clients.get(client_id).map(|client| (client, replica)))
+                .map(|(client, replica)| Value::Array(vec![
+                    Value::BulkString(client.address.map(|address| address.ip().to_string()).unwrap_or_default().into()),
+                    Value::BulkString(client.listening_port.unwrap_or_default().to_string().into()),
+                    Value::BulkString(replica.ack_offset.to_string().into())
+                ]))
+                .collect();
+
+            return Ok(Value::Array(vec![
+                Value::BulkString("master".into()),
+                Value::Integer(replication.offset as i64),
+                Value::Array(replicas)
+            ]));
+        };
+
+        let offset = match master.state {
+            LinkState::Connected => replication.offset as i64,
+            _ => -1
+        };
+
+        Ok(Value::Array(vec![
+            Value::BulkString("slave".into()),
+            Value::BulkString(master.host.clone().into()),
+            Value::Integer(master.port as i64),
+            Value::BulkString(master.state.name().to_string().into()),
+            Value::Integer(offset)
+        ]))
+    }
+}
+
+/// INFO [section]. Only the replication section is reported.
+pub struct InfoCommand;
+impl Command for InfoCommand {
+    fn name(&self) -> &str {
+        "info"
+    }
+
+    fn arity(&self) -> i32 {
+        -1
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        let sections = unpack_args(args)?;
+        let all = sections.is_empty() || sections.iter().any(|section| ["all", "default", "everything"].contains(&section.to_lowercase().as_str()));
+
+        if !all && !sections.iter().any(|section| section.eq_ignore_ascii_case("replication")) {
+            return Ok(Value::BulkString(Vec::new()));
+        }
+
+        Ok(Value::BulkString(replication_info(context).into()))
+    }
+}
+
+fn replication_info(context: &CommandContext) -> String {
+    let replication = &context.replication;
+    let mut lines = vec!["# Replication".to_string()];
+
+    match replication.master.as_ref() {
+        Some(master) => {
+            lines.push("role:slave".to_string());
+            lines.push(format!("master_host:{}", master.host));
+            lines.push(format!("master_port:{}", master.port));
+            lines.push(format!("master_link_status:{}", if master.state == LinkState::Connected { "up" } else { "down" }));
+            lines.push(format!("master_last_io_seconds_ago:{}", master.last_io.elapsed().as_secs()));
+            lines.push(format!("master_sync_in_progress:{}", (master.state == LinkState::Transfer) as u8));
+            lines.push(format!("slave_read_repl_offset:{}", replication.offset));
+            lines.push(format!("slave_repl_offset:{}", replication.offset));
+        }
+
+        None => lines.push("role:master".to_string())
+    }
+
+    lines.push(format!("connected_slaves:{}", replication.replicas.len()));
+
+    for (index, (client_id, replica)) in replication.replicas.iter().enumerate() {
+        let Some(client) = context.clients.get(client_id) else {
+            continue;
+        };
+
+        lines.push(format!(
+            "slave{}:ip={},port={},state={},offset={},lag={}",
+            index,
+            client.address.map(|address| address.ip().to_string()).unwrap_or_default(),
+            client.listening_port.unwrap_or_default(),
+            replica.state.name(),
+            replica.ack_offset,
+            replica.last_ack.elapsed().as_secs()
+        ));
+    }
+
+    let second_replid_offset = replication.second_replid_offset.map(|offset| offset as i64).unwrap_or(-1);
+
+    lines.push(format!("master_replid:{}", replication.replid));
+    lines.push(format!("master_replid2:{}", replication.replid2));
+    lines.push(format!("master_repl_offset:{}", replication.offset));
+    lines.push(format!("second_repl_offset:{}", second_replid_offset));
+    lines.push(format!("repl_backlog_active:{}", replication.backlog.is_some() as u8));
+
+    if let Some(backlog) = replication.backlog.as_ref() {
+        lines.push(format!("repl_backlog_size:{}", backlog.capacity()));
+        lines.push(format!("repl_backlog_first_byte_offset:{}", backlog.start_offset() + 1));
+        lines.push(format!("repl_backlog_histlen:{}", backlog.len()));
+    }
+
+    lines.join("\r\n") + "\r\n"
+}
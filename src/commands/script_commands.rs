@@ -0,0 +1,186 @@
+use crate::commands::{unpack_args, Command, CommandContext, CommandExecutor, EXECUTOR_COMMANDS};
+use crate::config::ConfigKey;
+use crate::parser::Value;
+use std::time::Duration;
+
+/// Commands scripts may not call, as they would change the state of the calling connection or nest scripts.
+const NO_SCRIPT_COMMANDS: [&str; 14] = [
+    "multi", "discard", "watch", "unwatch",
+    "subscribe", "psubscribe", "ssubscribe", "unsubscribe", "punsubscribe", "sunsubscribe",
+    "script", "function", "hello", "config"
+];
+
+/// Runs EVAL, EVALSHA and their read-only variants: `<script|sha1> <numkeys> [<key>...] [<arg>...]`.
+pub fn eval(executor: &CommandExecutor, command_name: &str, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+    let ScriptCall { name: script, keys, argv } = match split_keys(command_name, args) {
+        Ok(call) => call,
+        Err(error) => return Ok(error)
+    };
+
+    let read_only = command_name.ends_with("_ro");
+    let busy_threshold = busy_threshold(context);
+
+    let mut engine = context.scripting.take().expect("Scripts can't be nested!");
+
+    let sha = if command_name.starts_with("evalsha") {
+        Ok(script)
+    } else {
+        engine.load(&script)
+    };
+
+    let reply = match sha {
+        Ok(sha) => engine.run(&sha, &keys, &argv, busy_threshold, |call_args| call_from_script(executor, call_args, read_only, context)),
+        Err(e) => Value::SimpleError(e.to_string())
+    };
+
+    context.scripting = Some(engine);
+    Ok(reply)
+}
+
+/// Runs FCALL and FCALL_RO: `<function> <numkeys> [<key>...] [<arg>...]`.
+/// Functions flagged `no-writes` are read-only wherever they are called from.
+pub fn fcall(executor: &CommandExecutor, command_name: &str, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+    let ScriptCall { name, keys, argv } = match split_keys(command_name, args) {
+        Ok(call) => call,
+        Err(error) => return Ok(error)
+    };
+
+    let busy_threshold = busy_threshold(context);
+    let engine = context.scripting.take().expect("Scripts can't be nested!");
+
+    let reply = match engine.find_function(&name) {
+        None => Value::SimpleError("ERR Function not found".to_string()),
+        Some(function) => {
+            let no_writes = function.flags.iter().any(|flag| flag == "no-writes");
+
+            if command_name.ends_with("_ro") && !no_writes {
+                Value::SimpleError("ERR Can not execute a script with write flag using *_ro command.".to_string())
+            } else {
+                engine.call_function(&name, &keys, &argv, busy_threshold, |call_args| call_from_script(executor, call_args, no_writes, context))
+            }
+        }
+    };
+
+    context.scripting = Some(engine);
+    Ok(reply)
+}
+
+/// What EVAL or FCALL is asked to run. Keys and arguments are handed to Lua as bytes, as they may be binary.
+struct ScriptCall {
+    /// The script, its SHA1 or the function name.
+    name: String,
+    keys: Vec<Vec<u8>>,
+    argv: Vec<Vec<u8>>
+}
+
+/// Splits the arguments into the script or function name, its keys and the remaining arguments.
+fn split_keys(command_name: &str, mut args: Vec<Value>) -> Result<ScriptCall, Value> {
+    if args.len() < 2 {
+        return Err(Value::SimpleError(format!("ERR wrong number of arguments for '{}' command", command_name)));
+    }
+
+    let mut keys: Vec<Vec<u8>> = args.split_off(2).into_iter().map(|arg| arg.unpack_as_bytes().unwrap_or_default()).collect();
+    let args = match unpack_args(args) {
+        Ok(args) => args,
+        Err(e) => return Err(Value::SimpleError(format!("ERR {}", e)))
+    };
+
+    let num_keys = match args[1].parse::<i64>() {
+        Ok(num_keys) if num_keys < 0 => return Err(Value::SimpleError("ERR Number of keys can't be negative".to_string())),
+        Ok(num_keys) if num_keys as usize > keys.len() => return Err(Value::SimpleError("ERR Number of keys can't be greater than number of args".to_string())),
+        Ok(num_keys) => num_keys as usize,
+        Err(_) => return Err(Value::SimpleError("ERR value is not an integer or out of range".to_string()))
+    };
+
+    let argv = keys.split_off(num_keys);
+    Ok(ScriptCall { name: args[0].clone(), keys, argv })
+}
+
+fn busy_threshold(context: &mut CommandContext) -> Duration {
+    Duration::from_millis(context.config.get(ConfigKey::BusyReplyThreshold).parse().unwrap_or(5000))
+}
+
+/// Executes a `redis.call`/`redis.pcall` on behalf of the running script.
+fn call_from_script(executor: &CommandExecutor, args: Vec<Vec<u8>>, read_only: bool, context: &mut CommandContext) -> Value {
+    let command_name = String::from_utf8_lossy(&args[0]).to_lowercase();
+
+    let Some(command) = executor.lookup(&command_name, context) else {
+        if EXECUTOR_COMMANDS.contains(&command_name.as_str()) {
+            return Value::SimpleError("ERR This Redis command is not allowed from script".to_string());
+        }
+
+        return Value::SimpleError("ERR Unknown Redis command called from script".to_string());
+    };
+
+    if NO_SCRIPT_COMMANDS.contains(&command_name.as_str()) {
+        return Value::SimpleError("ERR This Redis command is not allowed from script".to_string());
+    }
+
+    if let Some(error) = context.replica_refusal(&command_name, command.is_write()) {
+        return error;
+    }
+
+    if command.is_write() {
+        if read_only {
+            return Value::SimpleError("ERR Write commands are not allowed from read-only scripts.".to_string());
+        }
+
+        context.script_monitor.mark_write();
+    }
+
+    let args = args.into_iter().skip(1).map(Value::BulkString).collect();
+    let reply = context.run(command.as_ref(), args).unwrap_or_else(|e| Value::SimpleError(format!("ERR {}", e)));
+
+    // Scripts run atomically, so a blocking command just returns what it has right away.
+    context.take_blocking_request();
+    reply
+}
+
+pub struct ScriptCommand;
+impl Command for ScriptCommand {
+    fn name(&self) -> &str {
+        "script"
+    }
+
+    fn arity(&self) -> i32 {
+        -2
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        let args = unpack_args(args)?;
+
+        if args.is_empty() {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage SCRIPT <LOAD|EXISTS|FLUSH|KILL> ...".to_string()));
+        }
+
+        let engine = context.scripting.as_mut().expect("Scripts can't be nested!");
+
+        match (args[0].to_lowercase().as_str(), &args[1..]) {
+            ("load", [body]) => match engine.load(body) {
+                Ok(sha) => Ok(Value::BulkString(sha.into())),
+                Err(e) => Ok(Value::SimpleError(e.to_string()))
+            },
+
+            ("exists", shas) if !shas.is_empty() => Ok(Value::Array(
+                shas.iter()
+                    .map(|sha| Value::Integer(engine.exists(sha) as i64))
+                    .collect()
+            )),
+
+            ("flush", [] | [_]) => {
+                if let Some(mode) = args.get(1).filter(|mode| !mode.eq_ignore_ascii_case("sync") && !mode.eq_ignore_ascii_case("async")) {
+                    return Ok(Value::SimpleError(format!("ERR SCRIPT FLUSH only support SYNC|ASYNC option, got '{}'", mode)));
+                }
+
+                engine.flush();
+                Ok(Value::SimpleString("OK".to_string()))
+            }
+
+            // Reaching here means no script holds the context, so there is nothing to kill;
+            // a killable script is handled by the connection before it waits for the lock.
+            ("kill", []) => Ok(context.script_monitor.kill()),
+
+            _ => Ok(Value::SimpleError(format!("ERR unknown subcommand or wrong number of arguments for '{}'", args[0])))
+        }
+    }
+}
@@ -1,6 +1,8 @@
-use std::time::{Duration, SystemTime};
-use crate::commands::{Command, CommandContext};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::commands::{unpack_arg, unpack_args, unpack_args_with_bytes, Command, CommandContext, WRONG_TYPE_ERROR};
+use crate::notifications::{NOTIFY_GENERIC, NOTIFY_KEY_MISS, NOTIFY_STRING};
 use crate::parser::Value;
+use crate::storage;
 
 pub struct StorageSetCommand;
 impl Command for StorageSetCommand {
@@ -8,25 +10,67 @@ impl Command for StorageSetCommand {
         "set"
     }
 
+    fn arity(&self) -> i32 {
+        -3
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
     fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
         if args.len() < 2 {
-            return Ok(Value::SimpleError("Missing arguments! Correct usage SET <key> <value> [<expiration-in-millis>]".to_string()));
+            return Ok(Value::SimpleError("Missing arguments! Correct usage SET <key> <value> [PX <milliseconds> | PXAT <unix-time-milliseconds>]".to_string()));
         }
 
-        let key: String = args.first().unwrap().clone().unpack_as_string().unwrap();
-        let value: Value = args[1].clone();
+        let (args, value) = unpack_args_with_bytes(args, 1)?;
+        let key = args[0].clone();
+        let value = Value::BulkString(value);
         let mut expiration: Option<SystemTime> = None;
 
         if args.len() > 2 {
-            let option = args[2].clone().unpack_as_string().unwrap().to_lowercase();
+            let option = args[2].to_lowercase();
+
+            if option == "px" || option == "pxat" {
+                let milliseconds = match args.get(3) {
+                    Some(milliseconds) => milliseconds,
+                    None => return Ok(Value::SimpleError("ERR syntax error".to_string()))
+                };
+
+                let milliseconds = match milliseconds.parse::<u64>() {
+                    Ok(milliseconds) => Duration::from_millis(milliseconds),
+                    Err(_) => return Ok(Value::SimpleError("ERR value is not an integer or out of range".to_string()))
+                };
 
-            match option.as_str() {
-                "px" => expiration = Some(SystemTime::now() + Duration::from_millis(args[3].clone().unpack_as_string().unwrap().parse::<u64>()?)),
-                _ => println!("{} is an invalid option!", option)
+                expiration = match option.as_str() {
+                    "px" => SystemTime::now().checked_add(milliseconds),
+                    _ => UNIX_EPOCH.checked_add(milliseconds)
+                };
+
+                if expiration.is_none() {
+                    return Ok(Value::SimpleError("ERR invalid expire time in 'set' command".to_string()));
+                }
+            } else {
+                println!("{} is an invalid option!", option);
             }
         }
 
-        Ok(context.storage.set(key.as_str(), value, expiration))
+        // A relative expiration is logged as an absolute one, so replaying it later expires the key on time.
+        if let Some(expiration) = expiration {
+            let at = expiration.duration_since(UNIX_EPOCH).map(|duration| duration.as_millis()).unwrap_or_default();
+            let value = value.clone().unpack_as_bytes().unwrap_or_default();
+
+            context.propagate_as(vec![vec![b"SET".to_vec(), key.clone().into_bytes(), value, b"PXAT".to_vec(), at.to_string().into_bytes()]]);
+        }
+
+        let reply = context.storage.set(key.as_str(), value, expiration);
+        context.storage.notify(NOTIFY_STRING, "set", &key);
+
+        if expiration.is_some() {
+            context.storage.notify(NOTIFY_GENERIC, "expire", &key);
+        }
+
+        Ok(reply)
     }
 }
 
@@ -36,16 +80,24 @@ impl Command for StorageGetCommand {
         "get"
     }
 
+    fn arity(&self) -> i32 {
+        2
+    }
+
     fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
-        if args.len() < 1 {
+        if args.is_empty() {
             return Ok(Value::SimpleError("Missing arguments! Correct usage GET <key>".to_string()));
         }
 
-        let key: String = args.first().unwrap().clone().unpack_as_string().unwrap();
+        let key = unpack_arg(args[0].clone())?;
 
         match context.storage.get(key.as_str()) {
+            Some(Value::Stream(_) | Value::SortedSet(_) | Value::Set(_) | Value::Hash(_) | Value::Module(_)) => Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string())),
             Some(value) => Ok(value.clone()),
-            _ => Ok(Value::NullBulkString)
+            _ => {
+                context.storage.notify(NOTIFY_KEY_MISS, "keymiss", &key);
+                Ok(Value::NullBulkString)
+            }
         }
     }
 }
@@ -56,8 +108,47 @@ impl Command for StorageKeysCommand {
         "keys"
     }
 
+    fn arity(&self) -> i32 {
+        2
+    }
+
     fn exec(&self, _args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
-        Ok(Value::Array(context.storage.keys().iter().map(|k| Value::BulkString(k.clone())).collect::<Vec<Value>>()))
+        Ok(Value::Array(context.storage.keys().iter().map(|k| Value::BulkString(k.clone().into())).collect::<Vec<Value>>()))
+    }
+}
+
+/// DEL key [key ...]
+pub struct StorageDelCommand;
+impl Command for StorageDelCommand {
+    fn name(&self) -> &str {
+        "del"
+    }
+
+    fn arity(&self) -> i32 {
+        -2
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        let keys = unpack_args(args)?;
+
+        if keys.is_empty() {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage DEL <key> [<key> ...]".to_string()));
+        }
+
+        let mut removed = 0;
+
+        for key in &keys {
+            if context.storage.contains_key(key) {
+                context.storage.remove(key)?;
+                removed += 1;
+            }
+        }
+
+        Ok(Value::Integer(removed))
     }
 }
 
@@ -67,16 +158,149 @@ impl Command for StorageValueTypeCommand {
         "type"
     }
 
+    fn arity(&self) -> i32 {
+        2
+    }
+
     fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
-        if args.len() < 1 {
+        if args.is_empty() {
             return Ok(Value::SimpleError("Missing arguments! Correct usage TYPE <key>".to_string()));
         }
 
-        let key: String = args.first().unwrap().clone().unpack_as_string().unwrap();
+        let key = unpack_arg(args[0].clone())?;
 
         match context.storage.get(key.as_str()) {
+            Some(Value::Module(value)) => Ok(Value::SimpleString(value.type_name().to_string())),
             Some(value) => Ok(Value::SimpleString(value.get_type().to_string().to_lowercase())),
             _ => Ok(Value::SimpleString("none".to_string())),
         }
     }
-}
\ No newline at end of file
+}
+pub struct StorageDumpCommand;
+impl Command for StorageDumpCommand {
+    fn name(&self) -> &str {
+        "dump"
+    }
+
+    fn arity(&self) -> i32 {
+        2
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        if args.len() != 1 {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage DUMP <key>".to_string()));
+        }
+
+        let key = unpack_arg(args[0].clone())?;
+
+        let Some(value) = context.storage.get(key.as_str()) else {
+            context.storage.notify(NOTIFY_KEY_MISS, "keymiss", &key);
+            return Ok(Value::NullBulkString);
+        };
+
+        match storage::dump_value(&value, &|name| context.modules.module_type(name)) {
+            Ok(payload) => Ok(Value::BulkString(payload)),
+            Err(e) => Ok(Value::SimpleError(format!("ERR {}", e)))
+        }
+    }
+}
+
+/// RESTORE key ttl serialized-value [REPLACE] [ABSTTL] [IDLETIME seconds] [FREQ frequency]
+pub struct StorageRestoreCommand;
+impl Command for StorageRestoreCommand {
+    fn name(&self) -> &str {
+        "restore"
+    }
+
+    fn arity(&self) -> i32 {
+        -4
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        let (args, payload) = unpack_args_with_bytes(args, 2)?;
+
+        if args.len() < 3 {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage RESTORE <key> <ttl> <serialized-value> [REPLACE] [ABSTTL] [IDLETIME <seconds>] [FREQ <frequency>]".to_string()));
+        }
+
+        let key = &args[0];
+        let mut replace = false;
+        let mut absolute_ttl = false;
+        let mut idle_time: Option<i64> = None;
+        let mut frequency: Option<i64> = None;
+
+        let mut options = args[3..].iter();
+
+        while let Some(option) = options.next() {
+            match option.to_lowercase().as_str() {
+                "replace" => replace = true,
+                "absttl" => absolute_ttl = true,
+
+                "idletime" if frequency.is_none() => match options.next().map(|value| value.parse::<i64>()) {
+                    Some(Ok(seconds)) if seconds >= 0 => idle_time = Some(seconds),
+                    Some(Ok(_)) => return Ok(Value::SimpleError("ERR Invalid IDLETIME value, must be >= 0".to_string())),
+                    Some(Err(_)) => return Ok(Value::SimpleError("ERR value is not an integer or out of range".to_string())),
+                    None => return Ok(Value::SimpleError("ERR syntax error".to_string()))
+                },
+
+                "freq" if idle_time.is_none() => match options.next().map(|value| value.parse::<i64>()) {
+                    Some(Ok(count)) if (0..=255).contains(&count) => frequency = Some(count),
+                    Some(Ok(_)) => return Ok(Value::SimpleError("ERR Invalid FREQ value, must be >= 0 and <= 255".to_string())),
+                    Some(Err(_)) => return Ok(Value::SimpleError("ERR value is not an integer or out of range".to_string())),
+                    None => return Ok(Value::SimpleError("ERR syntax error".to_string()))
+                },
+
+                _ => return Ok(Value::SimpleError("ERR syntax error".to_string()))
+            }
+        }
+
+        let ttl = match args[1].parse::<i64>() {
+            Ok(ttl) if ttl >= 0 => ttl as u64,
+            Ok(_) => return Ok(Value::SimpleError("ERR Invalid TTL value, must be >= 0".to_string())),
+            Err(_) => return Ok(Value::SimpleError("ERR value is not an integer or out of range".to_string()))
+        };
+
+        if !replace && context.storage.get(key).is_some() {
+            return Ok(Value::SimpleError("BUSYKEY Target key name already exists.".to_string()));
+        }
+
+        let value = match storage::restore_value(&payload, &|name| context.modules.module_type(name)) {
+            Ok(value) => value,
+            Err(e) => return Ok(Value::SimpleError(e.to_string()))
+        };
+
+        // Access times and frequencies are not tracked, so IDLETIME and FREQ are only validated.
+        let expiration = match (ttl, absolute_ttl) {
+            (0, _) => None,
+            (ttl, true) => Some(UNIX_EPOCH + Duration::from_millis(ttl)),
+            (ttl, false) => Some(SystemTime::now() + Duration::from_millis(ttl))
+        };
+
+        // A relative TTL is logged as an absolute one, like SET PX, so replaying it later expires the key on time.
+        if let Some(expiration) = expiration.filter(|_| !absolute_ttl) {
+            let at = expiration.duration_since(UNIX_EPOCH).map(|duration| duration.as_millis()).unwrap_or_default();
+            let mut command = vec![b"RESTORE".to_vec(), key.clone().into_bytes(), at.to_string().into_bytes(), payload.clone(), b"ABSTTL".to_vec()];
+
+            if replace {
+                command.push(b"REPLACE".to_vec());
+            }
+
+            context.propagate_as(vec![command]);
+        }
+
+        // A key restored with a TTL already in the past is only deleted, as it would expire right away.
+        if expiration.is_some_and(|expiration| expiration <= SystemTime::now()) {
+            context.storage.remove(key)?;
+            return Ok(Value::SimpleString("OK".to_string()));
+        }
+
+        context.storage.set(key, value, expiration);
+        context.storage.notify(NOTIFY_GENERIC, "restore", key);
+
+        Ok(Value::SimpleString("OK".to_string()))
+    }
+}
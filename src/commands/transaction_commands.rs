@@ -0,0 +1,88 @@
+use crate::client::Transaction;
+use crate::commands::{unpack_args, Command, CommandContext};
+use crate::parser::Value;
+
+pub struct MultiCommand;
+impl Command for MultiCommand {
+    fn name(&self) -> &str {
+        "multi"
+    }
+
+    fn arity(&self) -> i32 {
+        1
+    }
+
+    fn exec(&self, _args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        let client = context.client_mut();
+
+        if client.transaction.is_some() {
+            return Ok(Value::SimpleError("ERR MULTI calls can not be nested".to_string()));
+        }
+
+        client.transaction = Some(Transaction::default());
+        Ok(Value::SimpleString("OK".to_string()))
+    }
+}
+
+pub struct DiscardCommand;
+impl Command for DiscardCommand {
+    fn name(&self) -> &str {
+        "discard"
+    }
+
+    fn arity(&self) -> i32 {
+        1
+    }
+
+    fn exec(&self, _args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        if context.client_mut().transaction.take().is_none() {
+            return Ok(Value::SimpleError("ERR DISCARD without MULTI".to_string()));
+        }
+
+        context.unwatch_all();
+        Ok(Value::SimpleString("OK".to_string()))
+    }
+}
+
+pub struct WatchCommand;
+impl Command for WatchCommand {
+    fn name(&self) -> &str {
+        "watch"
+    }
+
+    fn arity(&self) -> i32 {
+        -2
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        if args.is_empty() {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage WATCH <key> [<key>]...".to_string()));
+        }
+
+        if context.client().transaction.is_some() {
+            return Ok(Value::SimpleError("ERR WATCH inside MULTI is not allowed".to_string()));
+        }
+
+        for key in unpack_args(args)? {
+            context.watch(&key);
+        }
+
+        Ok(Value::SimpleString("OK".to_string()))
+    }
+}
+
+pub struct UnwatchCommand;
+impl Command for UnwatchCommand {
+    fn name(&self) -> &str {
+        "unwatch"
+    }
+
+    fn arity(&self) -> i32 {
+        1
+    }
+
+    fn exec(&self, _args: Vec<Value>, context: &mut CommandContext) -> anyhow::Result<Value> {
+        context.unwatch_all();
+        Ok(Value::SimpleString("OK".to_string()))
+    }
+}
@@ -1,9 +1,12 @@
-use crate::commands::{Command, CommandContext};
-use crate::parser::{StreamEntry, Value};
-use crate::storage::DataContainer;
+use crate::commands::{unpack_arg, unpack_args, Command, CommandContext, WRONG_TYPE_ERROR};
+use crate::notifications::NOTIFY_STREAM;
+use crate::parser::Value;
+use crate::stream::{now_millis, ConsumerGroup, Stream, StreamEntry, StreamId, TrimStrategy, INVALID_STREAM_ID_ERROR, STREAM_NODE_MAX_ENTRIES};
 use anyhow::{anyhow, Result};
-use std::collections::HashMap;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Approximate trimming evicts at most this many entries per call unless LIMIT says otherwise.
+const DEFAULT_TRIM_LIMIT: usize = 100 * STREAM_NODE_MAX_ENTRIES;
 
 pub struct StorageXAddCommand;
 impl Command for StorageXAddCommand {
@@ -11,71 +14,152 @@ impl Command for StorageXAddCommand {
         "xadd"
     }
 
+    fn arity(&self) -> i32 {
+        -5
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
     fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let args = unpack_args(args)?;
+
         if args.len() < 2 {
-            return Ok(Value::SimpleError("Missing arguments! Correct usage XADD <key> <id> [<key>] [<value>]...".to_string()));
+            return Ok(Value::SimpleError("Missing arguments! Correct usage XADD <key> [NOMKSTREAM] [<MAXLEN|MINID> [=|~] <threshold> [LIMIT <count>]] <id> <field> <value> [<field> <value>]...".to_string()));
         }
 
-        let key = args.first().unwrap().clone().unpack_as_string().unwrap();
-        let id = args[1].clone().unpack_as_string().unwrap();
+        let key = &args[0];
+        let mut cursor = 1;
+        let mut no_mk_stream = false;
+        let mut trim: Option<TrimOptions> = None;
 
-        let mut values: HashMap<String, DataContainer> = HashMap::new();
+        loop {
+            match args.get(cursor).map(|arg| arg.to_lowercase()).as_deref() {
+                Some("nomkstream") => {
+                    no_mk_stream = true;
+                    cursor += 1;
+                }
 
-        for i in 2..args.len() - 1 {
-            let entry_key = args[i].clone().unpack_as_string().unwrap();
-            let entry_value = args[i + 1].clone();
+                Some("maxlen") | Some("minid") | Some("limit") => match TrimOptions::parse(&args, &mut cursor) {
+                    Ok(options) => trim = Some(options),
+                    Err(e) => return Ok(Value::SimpleError(e))
+                },
 
-            values.insert(entry_key, DataContainer::create(entry_value, None));
+                _ => break
+            }
         }
 
-        match context.storage.get(&key) {
-            Some(value) => {
-                if let Value::Stream(mut entries) = value {
-                    let last_entry = entries.last().unwrap();
-                    let (millis, sequence) = match generate_stream_id(id, &entries) {
-                        Ok(values) => values,
-                        _ => return Ok(Value::SimpleError("The ID must have both values as integers! Example: 1-1".to_string(), ))
-                    };
+        let fields_args = args.get(cursor + 1..).unwrap_or_default();
 
-                    println!("Adding to stream {}-{}", millis, sequence);
+        if fields_args.is_empty() || !fields_args.len().is_multiple_of(2) {
+            return Ok(Value::SimpleError("ERR wrong number of arguments for 'xadd' command".to_string()));
+        }
 
-                    if millis == 0 && sequence == 0 {
-                        return Ok(Value::SimpleError("ERR The ID specified in XADD must be greater than 0-0".to_string()));
-                    }
+        let fields: Vec<(String, String)> = fields_args
+            .chunks(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect();
 
-                    if (millis == last_entry.millis_time && sequence == last_entry.sequence_number) || millis < last_entry.millis_time {
-                        return Ok(Value::SimpleError("ERR The ID specified in XADD is equal or smaller than the target stream top item".to_string()));
-                    }
+        let last_id = match context.storage.get_mut(key) {
+            Some(Value::Stream(stream)) => Some(stream.last_id()),
+            Some(_) => return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string())),
+            None => None
+        };
 
-                    let mut entry = StreamEntry::new(millis, sequence);
-                    entry.storage.add_all(values);
-                    entries.push(entry);
+        if last_id.is_none() && no_mk_stream {
+            return Ok(Value::NullBulkString);
+        }
 
-                    context.storage.set(&key, Value::Stream(entries), None);
+        let id = match generate_stream_id(&args[cursor], last_id.unwrap_or(StreamId::MIN)) {
+            Ok(id) => id,
+            Err(e) => return Ok(Value::SimpleError(e.to_string()))
+        };
 
-                    Ok(Value::BulkString(format!("{}-{}", millis, sequence)))
-                } else {
-                    Ok(Value::SimpleError("Not a stream!".to_string()))
-                }
-            },
+        if last_id.is_none() {
+            context.storage.set(key, Value::Stream(Stream::new()), None);
+        }
 
-            None => {
-                let (millis, sequence) = generate_stream_id(id, &vec![])?;
+        let Some(Value::Stream(stream)) = context.storage.get_mut(key) else {
+            return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string()));
+        };
 
-                println!("Creating stream {}-{}", millis, sequence);
+        stream.append(id, fields);
 
-                if millis == 0 && sequence == 0 {
-                    return Ok(Value::SimpleError("ERR The ID specified in XADD must be greater than 0-0".to_string()));
-                }
+        let trimmed = match trim {
+            Some(trim) => stream.trim(trim.strategy, trim.approximate, trim.limit),
+            None => 0
+        };
+
+        // Logged with the ID it got, and the trim it did, rather than what was asked for.
+        let mut propagated = vec![["XADD".to_string(), key.clone(), id.to_string()].into_iter().chain(fields_args.iter().cloned()).collect()];
+
+        if trimmed > 0 {
+            propagated.push(exact_trim(key, stream));
+        }
+
+        context.propagate_as(propagated);
+
+        context.stream_updates.notify_waiters();
+        context.storage.notify(NOTIFY_STREAM, "xadd", key);
+
+        if trimmed > 0 {
+            context.storage.notify(NOTIFY_STREAM, "xtrim", key);
+        }
+
+        Ok(Value::BulkString(id.to_string().into()))
+    }
+}
+
+pub struct StorageXTrimCommand;
+impl Command for StorageXTrimCommand {
+    fn name(&self) -> &str {
+        "xtrim"
+    }
+
+    fn arity(&self) -> i32 {
+        -4
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let args = unpack_args(args)?;
 
-                let mut entry = StreamEntry::new(millis, sequence);
-                entry.storage.add_all(values);
+        if args.len() < 3 {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage XTRIM <key> <MAXLEN|MINID> [=|~] <threshold> [LIMIT <count>]".to_string()));
+        }
+
+        let mut cursor = 1;
+
+        let trim = match TrimOptions::parse(&args, &mut cursor) {
+            Ok(trim) => trim,
+            Err(e) => return Ok(Value::SimpleError(e))
+        };
+
+        if cursor != args.len() {
+            return Ok(Value::SimpleError("ERR syntax error".to_string()));
+        }
 
-                context.storage.set(&key, Value::Stream(vec![entry]), None);
-                Ok(Value::BulkString(format!("{}-{}", millis, sequence)))
+        let (trimmed, propagated) = match context.storage.get_mut(&args[0]) {
+            Some(Value::Stream(stream)) => {
+                let trimmed = stream.trim(trim.strategy, trim.approximate, trim.limit);
+                (trimmed, (trimmed > 0).then(|| exact_trim(&args[0], stream)))
             }
+
+            Some(_) => return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string())),
+            None => (0, None)
+        };
+
+        context.propagate_as(propagated.into_iter().collect());
+
+        if trimmed > 0 {
+            context.storage.notify(NOTIFY_STREAM, "xtrim", &args[0]);
         }
 
+        Ok(Value::Integer(trimmed as i64))
     }
 }
 
@@ -85,150 +169,1564 @@ impl Command for StorageXRangeCommand {
         "xrange"
     }
 
+    fn arity(&self) -> i32 {
+        -4
+    }
+
     fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
         if args.len() < 3 {
-            return Ok(Value::SimpleError("Missing arguments! Correct usage XRANGE <key> <start> <end>".to_string()))
+            return Ok(Value::SimpleError("Missing arguments! Correct usage XRANGE <key> <start> <end> [COUNT <count>]".to_string()))
         }
 
-        let key = args.first().unwrap().clone().unpack_as_string().unwrap();
-        let min_arg = args[1].clone().unpack_as_string().unwrap();
-        let max_arg = args[2].clone().unpack_as_string().unwrap();
+        exec_range(unpack_args(args)?, context, false)
+    }
+}
 
-        let min: Option<Vec<i128>> = if min_arg.len() == 1 && min_arg.chars().next().unwrap() == '-' {
-            None
-        } else {
-            Some(
-                min_arg.split('-')
-                    .map(|v| v.parse::<i128>().unwrap())
-                    .collect()
-            )
+pub struct StorageXRevRangeCommand;
+impl Command for StorageXRevRangeCommand {
+    fn name(&self) -> &str {
+        "xrevrange"
+    }
+
+    fn arity(&self) -> i32 {
+        -4
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        if args.len() < 3 {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage XREVRANGE <key> <end> <start> [COUNT <count>]".to_string()))
+        }
+
+        exec_range(unpack_args(args)?, context, true)
+    }
+}
+
+/// Shared implementation of XRANGE and XREVRANGE, the latter taking its bounds in reverse order.
+fn exec_range(args: Vec<String>, context: &mut CommandContext, reverse: bool) -> Result<Value> {
+    let (start_arg, end_arg) = if reverse {
+        (&args[2], &args[1])
+    } else {
+        (&args[1], &args[2])
+    };
+
+    let (start, end) = match (StreamId::parse_range_start(start_arg), StreamId::parse_range_end(end_arg)) {
+        (Ok(start), Ok(end)) => (start, end),
+        (Err(e), _) | (_, Err(e)) => return Ok(Value::SimpleError(e.to_string()))
+    };
+
+    let count = match &args[3..] {
+        [] => usize::MAX,
+        [option, count] if option.eq_ignore_ascii_case("count") => match count.parse::<i64>() {
+            Ok(count) => count.max(0) as usize,
+            Err(_) => return Ok(Value::SimpleError("ERR value is not an integer or out of range".to_string()))
+        },
+
+        _ => return Ok(Value::SimpleError("ERR syntax error".to_string()))
+    };
+
+    match context.storage.get_mut(&args[0]) {
+        Some(Value::Stream(stream)) => {
+            let entries: Vec<Value> = if reverse {
+                stream.rev_range(start, end).take(count).map(|entry| entry.as_array_value()).collect()
+            } else {
+                stream.range(start, end).take(count).map(|entry| entry.as_array_value()).collect()
+            };
+
+            Ok(Value::Array(entries))
+        }
+
+        Some(_) => Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string())),
+        None => Ok(Value::Array(vec![]))
+    }
+}
+
+pub struct StorageXLenCommand;
+impl Command for StorageXLenCommand {
+    fn name(&self) -> &str {
+        "xlen"
+    }
+
+    fn arity(&self) -> i32 {
+        2
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        if args.len() != 1 {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage XLEN <key>".to_string()));
+        }
+
+        let key = unpack_arg(args[0].clone())?;
+
+        match context.storage.get_mut(&key) {
+            Some(Value::Stream(stream)) => Ok(Value::Integer(stream.len() as i64)),
+            Some(_) => Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string())),
+            None => Ok(Value::Integer(0))
+        }
+    }
+}
+
+pub struct StorageXDelCommand;
+impl Command for StorageXDelCommand {
+    fn name(&self) -> &str {
+        "xdel"
+    }
+
+    fn arity(&self) -> i32 {
+        -3
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let args = unpack_args(args)?;
+
+        if args.len() < 2 {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage XDEL <key> <id> [<id>]...".to_string()));
+        }
+
+        let mut ids: Vec<StreamId> = Vec::with_capacity(args.len() - 1);
+
+        for id in &args[1..] {
+            match StreamId::parse(id, 0) {
+                Ok(id) => ids.push(id),
+                Err(e) => return Ok(Value::SimpleError(e.to_string()))
+            }
+        }
+
+        let deleted = match context.storage.get_mut(&args[0]) {
+            Some(Value::Stream(stream)) => ids.into_iter().filter(|id| stream.delete(*id)).count(),
+            Some(_) => return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string())),
+            None => 0
         };
 
-        let max: Option<Vec<i128>> = if max_arg.len() == 1 && max_arg.chars().next().unwrap() == '+' {
-            None
-        } else {
-            Some(
-                max_arg.split('-')
-                    .map(|v| v.parse::<i128>().unwrap())
+        if deleted > 0 {
+            context.storage.notify(NOTIFY_STREAM, "xdel", &args[0]);
+        }
+
+        Ok(Value::Integer(deleted as i64))
+    }
+}
+
+pub struct StorageXSetIdCommand;
+impl Command for StorageXSetIdCommand {
+    fn name(&self) -> &str {
+        "xsetid"
+    }
+
+    fn arity(&self) -> i32 {
+        -3
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let args = unpack_args(args)?;
+
+        if args.len() < 2 {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage XSETID <key> <last-id> [ENTRIESADDED <entries-added>] [MAXDELETEDID <max-deleted-id>]".to_string()));
+        }
+
+        let last_id = match StreamId::parse(&args[1], 0) {
+            Ok(id) => id,
+            Err(e) => return Ok(Value::SimpleError(e.to_string()))
+        };
+
+        let mut entries_added: Option<u64> = None;
+        let mut max_deleted_entry_id: Option<StreamId> = None;
+        let mut cursor = 2;
+
+        while cursor < args.len() {
+            let Some(value) = args.get(cursor + 1) else {
+                return Ok(Value::SimpleError("ERR syntax error".to_string()));
+            };
+
+            match args[cursor].to_lowercase().as_str() {
+                "entriesadded" => match value.parse::<i64>() {
+                    Ok(value) if value >= 0 => entries_added = Some(value as u64),
+                    _ => return Ok(Value::SimpleError("ERR entries_added must be positive".to_string()))
+                },
+
+                "maxdeletedid" => match StreamId::parse(value, 0) {
+                    Ok(id) if id <= last_id => max_deleted_entry_id = Some(id),
+                    Ok(_) => return Ok(Value::SimpleError("ERR The ID specified in XSETID is smaller than the provided max_deleted_entry_id".to_string())),
+                    Err(e) => return Ok(Value::SimpleError(e.to_string()))
+                },
+
+                _ => return Ok(Value::SimpleError("ERR syntax error".to_string()))
+            }
+
+            cursor += 2;
+        }
+
+        let stream = match context.storage.get_mut(&args[0]) {
+            Some(Value::Stream(stream)) => stream,
+            Some(_) => return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string())),
+            None => return Ok(Value::SimpleError("ERR no such key".to_string()))
+        };
+
+        if stream.last_entry().is_some_and(|entry| last_id < entry.id) {
+            return Ok(Value::SimpleError("ERR The ID specified in XSETID is smaller than the target stream top item".to_string()));
+        }
+
+        if entries_added.is_some_and(|entries_added| entries_added < stream.len() as u64) {
+            return Ok(Value::SimpleError("ERR The entries_added specified in XSETID is smaller than the target stream length".to_string()));
+        }
+
+        stream.set_id(last_id, entries_added, max_deleted_entry_id);
+        context.storage.notify(NOTIFY_STREAM, "xsetid", &args[0]);
+        Ok(Value::SimpleString("OK".to_string()))
+    }
+}
+
+pub struct StorageXInfoCommand;
+impl Command for StorageXInfoCommand {
+    fn name(&self) -> &str {
+        "xinfo"
+    }
+
+    fn arity(&self) -> i32 {
+        -2
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let args = unpack_args(args)?;
+
+        if args.len() < 2 {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage XINFO <STREAM|GROUPS|CONSUMERS> <key> ...".to_string()));
+        }
+
+        let sub_command = args[0].to_lowercase();
+        let key = &args[1];
+
+        let stream = match context.storage.get_mut(key) {
+            Some(Value::Stream(stream)) => &*stream,
+            Some(_) => return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string())),
+            None => return Ok(Value::SimpleError("ERR no such key".to_string()))
+        };
+
+        let now = now_millis();
+
+        match sub_command.as_str() {
+            "stream" => {
+                let full_count = match &args[2..] {
+                    [] => None,
+                    [full] if full.eq_ignore_ascii_case("full") => Some(10),
+                    [full, option, count] if full.eq_ignore_ascii_case("full") && option.eq_ignore_ascii_case("count") => match count.parse::<i64>() {
+                        Ok(count) if count > 0 => Some(count as usize),
+                        Ok(_) => Some(usize::MAX),
+                        Err(_) => return Ok(Value::SimpleError("ERR value is not an integer or out of range".to_string()))
+                    },
+
+                    _ => return Ok(Value::SimpleError("ERR syntax error".to_string()))
+                };
+
+                Ok(stream_info(stream, full_count))
+            }
+
+            "groups" if args.len() == 2 => Ok(Value::Array(
+                stream.groups()
+                    .iter()
+                    .map(|(name, group)| map_value(vec![
+                        ("name", Value::BulkString(name.clone().into())),
+                        ("consumers", Value::Integer(group.consumers().len() as i64)),
+                        ("pending", Value::Integer(group.pending().len() as i64)),
+                        ("last-delivered-id", Value::BulkString(group.last_delivered_id.to_string().into())),
+                        ("entries-read", optional_integer(group.entries_read)),
+                        ("lag", optional_integer(stream.lag(group)))
+                    ]))
                     .collect()
-            )
-        };
-
-        match context.storage.get(key.as_str()) {
-            Some(value) => {
-                if let Value::Stream(stream_entries) = value {
-                    let res = Value::Array(
-                        stream_entries.iter()
-                            .filter(|entry|
-                                min.as_ref().map(|m| entry.millis_time >= m[0] && entry.sequence_number >= m[1] as i64).unwrap_or(true) &&
-                                    max.as_ref().map(|m| entry.millis_time <= m[0] && entry.sequence_number <= m[1] as i64).unwrap_or(true)
-                            ) // ((min != None && entry.millis_time >= min.unwrap()[0]) && (max != None && entry.millis_time <= max.unwrap()[0])) && (entry.sequence_number >= min.unwrap()[1] as i64 && entry.sequence_number <= max.unwrap()[1] as i64)
-                            .map(|entry| entry.as_array_value())
-                            .collect()
-                    );
+            )),
 
-                    Ok(res)
-                } else {
-                    Ok(Value::SimpleError("Not a stream".to_string()))
-                }
+            "consumers" if args.len() == 3 => {
+                let Some(group) = stream.group(&args[2]) else {
+                    return Ok(Value::SimpleError(format!("NOGROUP No such consumer group '{}' for key name '{}'", args[2], key)));
+                };
+
+                Ok(Value::Array(
+                    group.consumers()
+                        .iter()
+                        .map(|(name, consumer)| map_value(vec![
+                            ("name", Value::BulkString(name.clone().into())),
+                            ("pending", Value::Integer(consumer.pending.len() as i64)),
+                            ("idle", Value::Integer(now.saturating_sub(consumer.seen_time) as i64)),
+                            ("inactive", Value::Integer(consumer.active_time.map(|active_time| now.saturating_sub(active_time) as i64).unwrap_or(-1)))
+                        ]))
+                        .collect()
+                ))
             }
 
-            _ => Ok(Value::NullBulkString)
+            _ => Ok(Value::SimpleError(format!("ERR unknown subcommand or wrong number of arguments for '{}'", args[0])))
         }
     }
 }
 
+/// Builds the XINFO STREAM reply, or the FULL variant listing up to `full_count` entries and PEL items.
+fn stream_info(stream: &Stream, full_count: Option<usize>) -> Value {
+    let entry_or_nil = |entry: Option<StreamEntry>| entry.map(|entry| entry.as_array_value()).unwrap_or(Value::NullBulkString);
+
+    let mut info = vec![
+        ("length", Value::Integer(stream.len() as i64)),
+        ("radix-tree-keys", Value::Integer(stream.node_count() as i64)),
+        ("radix-tree-nodes", Value::Integer(stream.node_count() as i64)),
+        ("last-generated-id", Value::BulkString(stream.last_id().to_string().into())),
+        ("max-deleted-entry-id", Value::BulkString(stream.max_deleted_entry_id().to_string().into())),
+        ("entries-added", Value::Integer(stream.entries_added() as i64)),
+        ("recorded-first-entry-id", Value::BulkString(stream.first_id().unwrap_or(StreamId::MIN).to_string().into()))
+    ];
+
+    let Some(count) = full_count else {
+        info.push(("groups", Value::Integer(stream.groups().len() as i64)));
+        info.push(("first-entry", entry_or_nil(stream.first_entry())));
+        info.push(("last-entry", entry_or_nil(stream.last_entry())));
+
+        return map_value(info);
+    };
+
+    info.push(("entries", Value::Array(
+        stream.range(StreamId::MIN, StreamId::MAX)
+            .take(count)
+            .map(|entry| entry.as_array_value())
+            .collect()
+    )));
+
+    info.push(("groups", Value::Array(
+        stream.groups()
+            .iter()
+            .map(|(name, group)| map_value(vec![
+                ("name", Value::BulkString(name.clone().into())),
+                ("last-delivered-id", Value::BulkString(group.last_delivered_id.to_string().into())),
+                ("entries-read", optional_integer(group.entries_read)),
+                ("lag", optional_integer(stream.lag(group))),
+                ("pel-count", Value::Integer(group.pending().len() as i64)),
+                ("pending", Value::Array(
+                    group.pending()
+                        .iter()
+                        .take(count)
+                        .map(|(id, pending)| Value::Array(vec![
+                            Value::BulkString(id.to_string().into()),
+                            Value::BulkString(pending.consumer.clone().into()),
+                            Value::Integer(pending.delivery_time as i64),
+                            Value::Integer(pending.delivery_count as i64)
+                        ]))
+                        .collect()
+                )),
+                ("consumers", Value::Array(
+                    group.consumers()
+                        .iter()
+                        .map(|(consumer_name, consumer)| map_value(vec![
+                            ("name", Value::BulkString(consumer_name.clone().into())),
+                            ("seen-time", Value::Integer(consumer.seen_time as i64)),
+                            ("active-time", Value::Integer(consumer.active_time.map(|active_time| active_time as i64).unwrap_or(-1))),
+                            ("pel-count", Value::Integer(consumer.pending.len() as i64)),
+                            ("pending", Value::Array(
+                                consumer.pending
+                                    .iter()
+                                    .take(count)
+                                    .filter_map(|id| group.pending().get(id).map(|pending| (id, pending)))
+                                    .map(|(id, pending)| Value::Array(vec![
+                                        Value::BulkString(id.to_string().into()),
+                                        Value::Integer(pending.delivery_time as i64),
+                                        Value::Integer(pending.delivery_count as i64)
+                                    ]))
+                                    .collect()
+                            ))
+                        ]))
+                        .collect()
+                ))
+            ]))
+            .collect()
+    )));
+
+    map_value(info)
+}
+
+fn map_value(pairs: Vec<(&str, Value)>) -> Value {
+    Value::Array(
+        pairs.into_iter()
+            .flat_map(|(name, value)| [Value::BulkString(name.to_string().into()), value])
+            .collect()
+    )
+}
+
+fn optional_integer(value: Option<u64>) -> Value {
+    value.map(|value| Value::Integer(value as i64)).unwrap_or(Value::NullBulkString)
+}
+
 pub struct StorageXReadCommand;
 impl Command for StorageXReadCommand {
     fn name(&self) -> &str {
         "xread"
     }
 
+    fn arity(&self) -> i32 {
+        -4
+    }
+
     fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
-        if args.len() < 3 {
-            return Ok(Value::SimpleError("Missing arguments! Correct usage XREAD <type> <key> <id>".to_string()));
-        }
-
-        let read_type = args.first().unwrap().clone().unpack_as_string().unwrap().to_lowercase();
-        let key = args[1].clone().unpack_as_string().unwrap();
-        let id = args[2].clone().unpack_as_string().unwrap();
-
-        let (millis_time, sequence_number) = parse_stream_id(id)?;
-
-        match context.storage.get(&key) {
-            Some(value) => {
-                match read_type.as_str() {
-                    "streams" => {
-                        if let Value::Stream(stream_entries) = value {
-                            Ok(Value::Array(
-                                vec![
-                                    Value::BulkString(key),
-                                    Value::Array(
-                                        stream_entries.iter()
-                                            .filter(|entry| entry.millis_time >= millis_time && entry.sequence_number >= sequence_number)
-                                            .map(|entry| {
-                                                println!("{:?}", entry.as_array_value().serialize());
-                                                entry.as_array_value()
-                                            })
-                                            .collect::<Vec<Value>>()
-                                    )
-                                ]
-                            ))
-                        } else {
-                            Ok(Value::SimpleError("Not a stream!".to_string()))
-                        }
-                    }
+        let args = unpack_args(args)?;
+
+        let ReadOptions { count, block, group, streams_index: cursor, .. } = match ReadOptions::parse(&args, "xread") {
+            Ok(options) => options,
+            Err(e) => return Ok(Value::SimpleError(e))
+        };
+
+        if group.is_some() {
+            return Ok(Value::SimpleError("ERR The GROUP option is only supported by XREADGROUP. You called XREAD instead.".to_string()));
+        }
 
-                    _ => Ok(Value::SimpleError("Invalid type!".to_string()))
+        let streams_args = &args[cursor..];
+
+        if streams_args.is_empty() || !streams_args.len().is_multiple_of(2) {
+            return Ok(Value::SimpleError("ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.".to_string()));
+        }
+
+        let (keys, ids) = streams_args.split_at(streams_args.len() / 2);
+        let mut resolved_ids: Vec<String> = Vec::with_capacity(ids.len());
+        let mut reply: Vec<Value> = vec![];
+
+        for (key, id) in keys.iter().zip(ids) {
+            let stream = match context.storage.get_mut(key) {
+                Some(Value::Stream(stream)) => Some(&*stream),
+                Some(_) => return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string())),
+                None => None
+            };
+
+            let last_id = stream.map(|stream| stream.last_id()).unwrap_or(StreamId::MIN);
+
+            let entries: Vec<Value> = match id.as_str() {
+                "$" => vec![],
+                ">" => return Ok(Value::SimpleError("ERR The > ID can be specified only when calling XREADGROUP using the GROUP <group> <consumer> option.".to_string())),
+                "+" => stream.and_then(|stream| stream.last_entry())
+                    .map(|entry| vec![entry.as_array_value()])
+                    .unwrap_or_default(),
+
+                id => {
+                    let start = match StreamId::parse(id, 0) {
+                        Ok(id) => id.next(),
+                        Err(e) => return Ok(Value::SimpleError(e.to_string()))
+                    };
+
+                    match (stream, start) {
+                        (Some(stream), Some(start)) => stream.range(start, StreamId::MAX)
+                            .take(count.unwrap_or(usize::MAX))
+                            .map(|entry| entry.as_array_value())
+                            .collect(),
+
+                        _ => vec![]
+                    }
                 }
+            };
+
+            resolved_ids.push(match id.as_str() {
+                "$" | "+" => last_id.to_string(),
+                id => id.to_string()
+            });
+
+            if !entries.is_empty() {
+                reply.push(Value::Array(vec![Value::BulkString(key.clone().into()), Value::Array(entries)]));
             }
+        }
+
+        if !reply.is_empty() {
+            return Ok(Value::Array(reply));
+        }
+
+        if let Some(timeout) = block {
+            // Retry with `$` and `+` pinned to the IDs seen now, so only entries added while blocked are served.
+            let mut retry_args = args[..cursor].to_vec();
+            retry_args.extend_from_slice(keys);
+            retry_args.extend(resolved_ids);
 
-            None => Ok(Value::NullBulkString)
+            context.block(timeout, retry_args.into_iter().map(|arg| Value::BulkString(arg.into())).collect());
         }
+
+        Ok(Value::NullArray)
     }
 }
 
-fn parse_stream_id(id: String) -> Result<(i128, i64)> {
-    let splitted_id: Vec<&str> = id.split("-").collect();
+pub struct StorageXGroupCommand;
+impl Command for StorageXGroupCommand {
+    fn name(&self) -> &str {
+        "xgroup"
+    }
 
-    if splitted_id.len() < 2 {
-        return Err(anyhow!("Invalid stream id length: {}", id));
+    fn arity(&self) -> i32 {
+        -2
     }
 
-    let millis_time = splitted_id[0].parse::<i128>()?;
-    let sequence_number = splitted_id[1].parse::<i64>()?;
+    fn is_write(&self) -> bool {
+        true
+    }
 
-    Ok((millis_time, sequence_number))
-}
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let args = unpack_args(args)?;
 
-fn generate_stream_id(id: String, entries: &Vec<StreamEntry>) -> Result<(i128, i64)> {
-    let splitted_id: Vec<&str> = id.split("-").collect();
+        if args.len() < 3 {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage XGROUP <CREATE|SETID|DESTROY|CREATECONSUMER|DELCONSUMER> <key> <group> ...".to_string()));
+        }
 
-    if splitted_id.len() > 1 {
-        let milliseconds_time: i128 = splitted_id[0].parse()?;
-        let def_sequence_value: i64 = if milliseconds_time <= 0 {
-            1
-        } else {
-            0
+        let sub_command = args[0].to_lowercase();
+        let (key, group_name) = (&args[1], &args[2]);
+        let now = now_millis();
+
+        let mk_stream = sub_command == "create" && args[3..].iter().any(|arg| arg.eq_ignore_ascii_case("mkstream"));
+
+        match context.storage.get_mut(key) {
+            Some(Value::Stream(_)) => {}
+            Some(_) => return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string())),
+            None if mk_stream => {
+                context.storage.set(key, Value::Stream(Stream::new()), None);
+            }
+
+            None => return Ok(Value::SimpleError("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.".to_string()))
+        }
+
+        let Some(Value::Stream(stream)) = context.storage.get_mut(key) else {
+            return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string()));
         };
 
-        let sequence_number: i64 = if splitted_id[1].starts_with('*') {
-            entries
-                .last()
-                .and_then(|entry| {
-                    if entry.millis_time == milliseconds_time {
-                        Some(entry.sequence_number + 1)
-                    } else {
-                        None
+        let no_group = || Ok(Value::SimpleError(format!("NOGROUP No such consumer group '{}' for key name '{}'", group_name, key)));
+
+        match sub_command.as_str() {
+            "create" | "setid" if args.len() >= 4 => {
+                let mut entries_read: Option<Option<u64>> = None;
+                let mut cursor = 4;
+
+                while cursor < args.len() {
+                    match args[cursor].to_lowercase().as_str() {
+                        "mkstream" if sub_command == "create" => {}
+                        "entriesread" if cursor + 1 < args.len() => {
+                            entries_read = match args[cursor + 1].parse::<i64>() {
+                                Ok(-1) => Some(None),
+                                Ok(value) if value >= 0 => Some(Some(value as u64)),
+                                _ => return Ok(Value::SimpleError("ERR value for ENTRIESREAD must be positive or -1".to_string()))
+                            };
+
+                            cursor += 1;
+                        }
+
+                        _ => return Ok(Value::SimpleError("ERR syntax error".to_string()))
                     }
-                })
-                .unwrap_or(def_sequence_value)
-        } else {
-            splitted_id[1].parse()?
-        };
 
-        return Ok((milliseconds_time, sequence_number))
-    }
+                    cursor += 1;
+                }
+
+                let (id, entries_read) = if args[3] == "$" {
+                    (stream.last_id(), entries_read.unwrap_or(Some(stream.entries_added())))
+                } else {
+                    match StreamId::parse(&args[3], 0) {
+                        Ok(id) => (id, entries_read.unwrap_or(None)),
+                        Err(e) => return Ok(Value::SimpleError(e.to_string()))
+                    }
+                };
+
+                if sub_command == "create" {
+                    if !stream.create_group(group_name, id, entries_read) {
+                        return Ok(Value::SimpleError("BUSYGROUP Consumer Group name already exists".to_string()));
+                    }
+
+                    context.storage.notify(NOTIFY_STREAM, "xgroup-create", key);
+                } else {
+                    let Some(group) = stream.group_mut(group_name) else {
+                        return no_group();
+                    };
+
+                    group.last_delivered_id = id;
+                    group.entries_read = entries_read;
+                    context.storage.notify(NOTIFY_STREAM, "xgroup-setid", key);
+                }
+
+                Ok(Value::SimpleString("OK".to_string()))
+            }
+
+            "destroy" => {
+                let destroyed = stream.destroy_group(group_name);
+
+                if destroyed {
+                    context.stream_updates.notify_waiters();
+                    context.storage.notify(NOTIFY_STREAM, "xgroup-destroy", key);
+                }
+
+                Ok(Value::Integer(destroyed as i64))
+            }
+
+            "createconsumer" if args.len() == 4 => {
+                let Some(group) = stream.group_mut(group_name) else {
+                    return no_group();
+                };
+
+                let created = group.create_consumer(&args[3], now);
+
+                if created {
+                    context.storage.notify(NOTIFY_STREAM, "xgroup-createconsumer", key);
+                }
 
-    Ok((SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i128, 0))
-}
\ No newline at end of file
+                Ok(Value::Integer(created as i64))
+            }
+
+            "delconsumer" if args.len() == 4 => {
+                let Some(group) = stream.group_mut(group_name) else {
+                    return no_group();
+                };
+
+                let pending = group.delete_consumer(&args[3]);
+
+                if pending.is_some() {
+                    context.storage.notify(NOTIFY_STREAM, "xgroup-delconsumer", key);
+                }
+
+                Ok(Value::Integer(pending.unwrap_or(0) as i64))
+            }
+
+            _ => Ok(Value::SimpleError(format!("ERR unknown subcommand or wrong number of arguments for '{}'", args[0])))
+        }
+    }
+}
+
+pub struct StorageXReadGroupCommand;
+impl Command for StorageXReadGroupCommand {
+    fn name(&self) -> &str {
+        "xreadgroup"
+    }
+
+    fn arity(&self) -> i32 {
+        -7
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let raw_args = args.clone();
+        let args = unpack_args(args)?;
+
+        let options = match ReadOptions::parse(&args, "xreadgroup") {
+            Ok(options) => options,
+            Err(e) => return Ok(Value::SimpleError(e))
+        };
+
+        let Some((group_name, consumer)) = &options.group else {
+            return Ok(Value::SimpleError("ERR Missing GROUP option for XREADGROUP".to_string()));
+        };
+
+        let streams_args = &args[options.streams_index..];
+
+        if streams_args.is_empty() || !streams_args.len().is_multiple_of(2) {
+            return Ok(Value::SimpleError("ERR Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' must be specified.".to_string()));
+        }
+
+        let (keys, ids) = streams_args.split_at(streams_args.len() / 2);
+        let mut history_ids: Vec<Option<StreamId>> = Vec::with_capacity(ids.len());
+
+        for (key, id) in keys.iter().zip(ids) {
+            history_ids.push(match id.as_str() {
+                ">" => None,
+                "$" => return Ok(Value::SimpleError("ERR The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set.".to_string())),
+                id => match StreamId::parse(id, 0) {
+                    Ok(id) => Some(id),
+                    Err(e) => return Ok(Value::SimpleError(e.to_string()))
+                }
+            });
+
+            match context.storage.get_mut(key) {
+                Some(Value::Stream(stream)) if stream.group(group_name).is_some() => {}
+                Some(Value::Stream(_)) | None => return Ok(Value::SimpleError(format!("NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option", key, group_name))),
+                Some(_) => return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string()))
+            }
+        }
+
+        let now = now_millis();
+        let mut reply: Vec<Value> = vec![];
+        let mut propagated: Vec<Vec<String>> = vec![];
+
+        for (key, history_id) in keys.iter().zip(&history_ids) {
+            let Some(Value::Stream(stream)) = context.storage.get_mut(key) else {
+                continue;
+            };
+
+            let new_consumer = !stream.group(group_name).unwrap().consumers().contains_key(consumer);
+
+            if new_consumer {
+                propagated.push(create_consumer_command(key, group_name, consumer));
+            }
+
+            match history_id {
+                None => {
+                    let entries = stream.read_group(group_name, consumer, options.count, options.no_ack, now);
+
+                    if !entries.is_empty() {
+                        let group = stream.group(group_name).unwrap();
+
+                        if !options.no_ack {
+                            let ids: Vec<StreamId> = entries.iter().map(|entry| entry.id).collect();
+                            propagated.extend(pending_commands(key, group_name, group, &ids));
+                        }
+
+                        propagated.push(set_id_command(key, group_name, group));
+
+                        reply.push(Value::Array(vec![
+                            Value::BulkString(key.clone().into()),
+                            Value::Array(entries.iter().map(|entry| entry.as_array_value()).collect())
+                        ]));
+                    }
+                }
+
+                Some(history_id) => {
+                    let group = stream.group_mut(group_name).unwrap();
+                    let pending: Vec<StreamId> = match history_id.next() {
+                        Some(start) => group.touch_consumer(consumer, now).pending
+                            .range(start..)
+                            .take(options.count.unwrap_or(usize::MAX))
+                            .copied()
+                            .collect(),
+
+                        None => vec![]
+                    };
+
+                    let entries = pending.into_iter()
+                        .map(|id| match stream.get(id) {
+                            Some(entry) => entry.as_array_value(),
+                            None => Value::Array(vec![Value::BulkString(id.to_string().into()), Value::NullArray])
+                        })
+                        .collect();
+
+                    reply.push(Value::Array(vec![Value::BulkString(key.clone().into()), Value::Array(entries)]));
+                }
+            }
+
+            if new_consumer {
+                context.storage.notify(NOTIFY_STREAM, "xgroup-createconsumer", key);
+            }
+        }
+
+        context.propagate_as(propagated);
+
+        if !reply.is_empty() {
+            return Ok(Value::Array(reply));
+        }
+
+        if let Some(timeout) = options.block {
+            context.block(timeout, raw_args);
+        }
+
+        Ok(Value::NullArray)
+    }
+}
+
+pub struct StorageXAckCommand;
+impl Command for StorageXAckCommand {
+    fn name(&self) -> &str {
+        "xack"
+    }
+
+    fn arity(&self) -> i32 {
+        -4
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let args = unpack_args(args)?;
+
+        if args.len() < 3 {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage XACK <key> <group> <id> [<id>]...".to_string()));
+        }
+
+        let mut ids: Vec<StreamId> = Vec::with_capacity(args.len() - 2);
+
+        for id in &args[2..] {
+            match StreamId::parse(id, 0) {
+                Ok(id) => ids.push(id),
+                Err(e) => return Ok(Value::SimpleError(e.to_string()))
+            }
+        }
+
+        let group = match context.storage.get_mut(&args[0]) {
+            Some(Value::Stream(stream)) => stream.group_mut(&args[1]),
+            Some(_) => return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string())),
+            None => None
+        };
+
+        match group {
+            Some(group) => Ok(Value::Integer(ids.into_iter().filter(|id| group.ack(*id)).count() as i64)),
+            None => Ok(Value::Integer(0))
+        }
+    }
+}
+
+pub struct StorageXPendingCommand;
+impl Command for StorageXPendingCommand {
+    fn name(&self) -> &str {
+        "xpending"
+    }
+
+    fn arity(&self) -> i32 {
+        -3
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let args = unpack_args(args)?;
+
+        if args.len() < 2 {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage XPENDING <key> <group> [[IDLE <min-idle-time>] <start> <end> <count> [<consumer>]]".to_string()));
+        }
+
+        let (key, group_name) = (&args[0], &args[1]);
+        let mut cursor = 2;
+        let mut min_idle: Option<u64> = None;
+
+        if args.get(cursor).is_some_and(|arg| arg.eq_ignore_ascii_case("idle")) {
+            match args.get(cursor + 1).map(|idle| idle.parse::<i64>()) {
+                Some(Ok(idle)) => min_idle = Some(idle.max(0) as u64),
+                _ => return Ok(Value::SimpleError("ERR value is not an integer or out of range".to_string()))
+            }
+
+            cursor += 2;
+        }
+
+        let extended = args.len() > cursor;
+
+        if (extended && !(3..=4).contains(&(args.len() - cursor))) || (min_idle.is_some() && !extended) {
+            return Ok(Value::SimpleError("ERR syntax error".to_string()));
+        }
+
+        let range = if extended {
+            let start = StreamId::parse_range_start(&args[cursor]);
+            let end = StreamId::parse_range_end(&args[cursor + 1]);
+            let count = args[cursor + 2].parse::<i64>();
+
+            match (start, end, count) {
+                (Ok(start), Ok(end), Ok(count)) => Some((start, end, count.max(0) as usize, args.get(cursor + 3))),
+                (Err(e), _, _) | (_, Err(e), _) => return Ok(Value::SimpleError(e.to_string())),
+                (_, _, Err(_)) => return Ok(Value::SimpleError("ERR value is not an integer or out of range".to_string()))
+            }
+        } else {
+            None
+        };
+
+        let group = match context.storage.get_mut(key) {
+            Some(Value::Stream(stream)) => stream.group(group_name),
+            Some(_) => return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string())),
+            None => None
+        };
+
+        let Some(group) = group else {
+            return Ok(Value::SimpleError(format!("NOGROUP No such key '{}' or consumer group '{}'", key, group_name)));
+        };
+
+        let pending = group.pending();
+
+        let Some((start, end, count, consumer)) = range else {
+            if pending.is_empty() {
+                return Ok(Value::Array(vec![Value::Integer(0), Value::NullBulkString, Value::NullBulkString, Value::NullArray]));
+            }
+
+            let consumers = group.consumers()
+                .iter()
+                .filter(|(_, consumer)| !consumer.pending.is_empty())
+                .map(|(name, consumer)| Value::Array(vec![Value::BulkString(name.clone().into()), Value::BulkString(consumer.pending.len().to_string().into())]))
+                .collect();
+
+            return Ok(Value::Array(vec![
+                Value::Integer(pending.len() as i64),
+                Value::BulkString(pending.first_key_value().unwrap().0.to_string().into()),
+                Value::BulkString(pending.last_key_value().unwrap().0.to_string().into()),
+                Value::Array(consumers)
+            ]));
+        };
+
+        if start > end {
+            return Ok(Value::Array(vec![]));
+        }
+
+        let now = now_millis();
+
+        Ok(Value::Array(
+            pending.range(start..=end)
+                .filter(|(_, entry)| consumer.is_none_or(|consumer| &entry.consumer == consumer))
+                .filter(|(_, entry)| min_idle.is_none_or(|min_idle| now.saturating_sub(entry.delivery_time) >= min_idle))
+                .take(count)
+                .map(|(id, entry)| Value::Array(vec![
+                    Value::BulkString(id.to_string().into()),
+                    Value::BulkString(entry.consumer.clone().into()),
+                    Value::Integer(now.saturating_sub(entry.delivery_time) as i64),
+                    Value::Integer(entry.delivery_count as i64)
+                ]))
+                .collect()
+        ))
+    }
+}
+
+pub struct StorageXClaimCommand;
+impl Command for StorageXClaimCommand {
+    fn name(&self) -> &str {
+        "xclaim"
+    }
+
+    fn arity(&self) -> i32 {
+        -6
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let args = unpack_args(args)?;
+
+        if args.len() < 5 {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage XCLAIM <key> <group> <consumer> <min-idle-time> <id> [<id>]... [IDLE <ms>] [TIME <unix-time-milliseconds>] [RETRYCOUNT <count>] [FORCE] [JUSTID] [LASTID <lastid>]".to_string()));
+        }
+
+        let (key, group_name, consumer) = (&args[0], &args[1], &args[2]);
+
+        let Ok(min_idle) = args[3].parse::<i64>() else {
+            return Ok(Value::SimpleError("ERR Invalid min-idle-time argument for XCLAIM".to_string()));
+        };
+
+        let min_idle = min_idle.max(0) as u64;
+        let now = now_millis();
+        let mut cursor = 4;
+        let mut ids: Vec<StreamId> = vec![];
+
+        while let Some(Ok(id)) = args.get(cursor).map(|id| StreamId::parse(id, 0)) {
+            ids.push(id);
+            cursor += 1;
+        }
+
+        let mut delivery_time = now;
+        let mut retry_count: Option<u64> = None;
+        let mut force = false;
+        let mut just_id = false;
+        let mut last_id: Option<StreamId> = None;
+
+        while cursor < args.len() {
+            let value = args.get(cursor + 1);
+
+            match args[cursor].to_lowercase().as_str() {
+                "force" => force = true,
+                "justid" => just_id = true,
+                "idle" if value.is_some() => {
+                    match value.unwrap().parse::<i64>() {
+                        Ok(idle) => delivery_time = now.saturating_sub(idle.max(0) as u64),
+                        Err(_) => return Ok(Value::SimpleError("ERR Invalid IDLE option argument for XCLAIM".to_string()))
+                    }
+
+                    cursor += 1;
+                }
+
+                "time" if value.is_some() => {
+                    match value.unwrap().parse::<i64>() {
+                        Ok(time) => delivery_time = time.max(0) as u64,
+                        Err(_) => return Ok(Value::SimpleError("ERR Invalid TIME option argument for XCLAIM".to_string()))
+                    }
+
+                    cursor += 1;
+                }
+
+                "retrycount" if value.is_some() => {
+                    match value.unwrap().parse::<i64>() {
+                        Ok(count) => retry_count = Some(count.max(0) as u64),
+                        Err(_) => return Ok(Value::SimpleError("ERR Invalid RETRYCOUNT option argument for XCLAIM".to_string()))
+                    }
+
+                    cursor += 1;
+                }
+
+                "lastid" if value.is_some() => {
+                    match StreamId::parse(value.unwrap(), 0) {
+                        Ok(id) => last_id = Some(id),
+                        Err(e) => return Ok(Value::SimpleError(e.to_string()))
+                    }
+
+                    cursor += 1;
+                }
+
+                _ => return Ok(Value::SimpleError(format!("ERR Unrecognized XCLAIM option '{}'", args[cursor])))
+            }
+
+            cursor += 1;
+        }
+
+        let stream = match context.storage.get_mut(key) {
+            Some(Value::Stream(stream)) if stream.group(group_name).is_some() => stream,
+            Some(Value::Stream(_)) | None => return Ok(Value::SimpleError(format!("NOGROUP No such key '{}' or consumer group '{}'", key, group_name))),
+            Some(_) => return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string()))
+        };
+
+        let entries: Vec<Option<StreamEntry>> = ids.iter().map(|id| stream.get(*id)).collect();
+        let group = stream.group_mut(group_name).unwrap();
+
+        let last_id = last_id.filter(|last_id| *last_id > group.last_delivered_id);
+
+        if let Some(last_id) = last_id {
+            group.last_delivered_id = last_id;
+        }
+
+        let new_consumer = !group.consumers().contains_key(consumer);
+        group.touch_consumer(consumer, now);
+
+        let mut reply: Vec<Value> = vec![];
+        let mut changed: Vec<StreamId> = vec![];
+
+        for (id, entry) in ids.into_iter().zip(entries) {
+            let claimable = match group.pending().get(&id) {
+                Some(pending) => now.saturating_sub(pending.delivery_time) >= min_idle,
+                None => force && entry.is_some()
+            };
+
+            if !claimable {
+                continue;
+            }
+
+            changed.push(id);
+
+            let Some(entry) = entry else {
+                group.ack(id);
+                continue;
+            };
+
+            group.claim(id, consumer, now, delivery_time, retry_count, !just_id);
+
+            reply.push(if just_id {
+                Value::BulkString(id.to_string().into())
+            } else {
+                entry.as_array_value()
+            });
+        }
+
+        // Logged with the delivery times and counts the entries ended up with, as IDLE is relative to now.
+        let mut propagated = pending_commands(key, group_name, group, &changed);
+
+        if new_consumer {
+            propagated.insert(0, create_consumer_command(key, group_name, consumer));
+        }
+
+        if last_id.is_some() {
+            propagated.push(set_id_command(key, group_name, group));
+        }
+
+        if new_consumer {
+            context.storage.notify(NOTIFY_STREAM, "xgroup-createconsumer", key);
+        }
+
+        context.propagate_as(propagated);
+        Ok(Value::Array(reply))
+    }
+}
+
+pub struct StorageXAutoClaimCommand;
+impl Command for StorageXAutoClaimCommand {
+    fn name(&self) -> &str {
+        "xautoclaim"
+    }
+
+    fn arity(&self) -> i32 {
+        -6
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn exec(&self, args: Vec<Value>, context: &mut CommandContext) -> Result<Value> {
+        let args = unpack_args(args)?;
+
+        if args.len() < 5 {
+            return Ok(Value::SimpleError("Missing arguments! Correct usage XAUTOCLAIM <key> <group> <consumer> <min-idle-time> <start> [COUNT <count>] [JUSTID]".to_string()));
+        }
+
+        let (key, group_name, consumer) = (&args[0], &args[1], &args[2]);
+
+        let Ok(min_idle) = args[3].parse::<i64>() else {
+            return Ok(Value::SimpleError("ERR Invalid min-idle-time argument for XAUTOCLAIM".to_string()));
+        };
+
+        let start = match StreamId::parse_range_start(&args[4]) {
+            Ok(start) => start,
+            Err(e) => return Ok(Value::SimpleError(e.to_string()))
+        };
+
+        let min_idle = min_idle.max(0) as u64;
+        let mut count = 100;
+        let mut just_id = false;
+        let mut cursor = 5;
+
+        while cursor < args.len() {
+            match args[cursor].to_lowercase().as_str() {
+                "justid" => just_id = true,
+                "count" if cursor + 1 < args.len() => {
+                    match args[cursor + 1].parse::<i64>() {
+                        Ok(value) if value > 0 => count = value as usize,
+                        _ => return Ok(Value::SimpleError("ERR COUNT must be > 0".to_string()))
+                    }
+
+                    cursor += 1;
+                }
+
+                _ => return Ok(Value::SimpleError("ERR syntax error".to_string()))
+            }
+
+            cursor += 1;
+        }
+
+        let stream = match context.storage.get_mut(key) {
+            Some(Value::Stream(stream)) if stream.group(group_name).is_some() => stream,
+            Some(Value::Stream(_)) | None => return Ok(Value::SimpleError(format!("NOGROUP No such key '{}' or consumer group '{}'", key, group_name))),
+            Some(_) => return Ok(Value::SimpleError(WRONG_TYPE_ERROR.to_string()))
+        };
+
+        let now = now_millis();
+
+        // Like Redis, bound the work done per call by scanning at most ten times COUNT pending entries.
+        let mut pending = stream.group(group_name).unwrap().pending().range(start..);
+
+        let scanned: Vec<(StreamId, bool)> = pending.by_ref()
+            .take(count.saturating_mul(10))
+            .map(|(id, pending)| (*id, now.saturating_sub(pending.delivery_time) >= min_idle))
+            .collect();
+
+        let after_scanned = pending.next().map(|(id, _)| *id);
+        let entries: Vec<Option<StreamEntry>> = scanned.iter().map(|(id, _)| stream.get(*id)).collect();
+        let group = stream.group_mut(group_name).unwrap();
+
+        let new_consumer = !group.consumers().contains_key(consumer);
+        group.touch_consumer(consumer, now);
+
+        let mut claimed: Vec<Value> = vec![];
+        let mut deleted: Vec<Value> = vec![];
+        let mut changed: Vec<StreamId> = vec![];
+        let mut next_cursor = after_scanned;
+
+        for (index, ((id, idle_enough), entry)) in scanned.iter().zip(entries).enumerate() {
+            match entry {
+                None => {
+                    group.ack(*id);
+                    deleted.push(Value::BulkString(id.to_string().into()));
+                    changed.push(*id);
+                }
+
+                Some(entry) if *idle_enough => {
+                    group.claim(*id, consumer, now, now, None, !just_id);
+                    changed.push(*id);
+
+                    claimed.push(if just_id {
+                        Value::BulkString(id.to_string().into())
+                    } else {
+                        entry.as_array_value()
+                    });
+                }
+
+                Some(_) => {}
+            }
+
+            if claimed.len() == count {
+                next_cursor = scanned.get(index + 1).map(|(id, _)| *id).or(after_scanned);
+                break;
+            }
+        }
+
+        let mut propagated = pending_commands(key, group_name, group, &changed);
+
+        if new_consumer {
+            propagated.insert(0, create_consumer_command(key, group_name, consumer));
+            context.storage.notify(NOTIFY_STREAM, "xgroup-createconsumer", key);
+        }
+
+        context.propagate_as(propagated);
+
+        Ok(Value::Array(vec![
+            Value::BulkString(next_cursor.unwrap_or(StreamId::MIN).to_string().into()),
+            Value::Array(claimed),
+            Value::Array(deleted)
+        ]))
+    }
+}
+
+struct ReadOptions {
+    count: Option<usize>,
+    block: Option<Duration>,
+    no_ack: bool,
+    group: Option<(String, String)>,
+    streams_index: usize
+}
+
+impl ReadOptions {
+    /// Parses the options of XREAD and XREADGROUP up to and including `STREAMS`.
+    fn parse(args: &[String], command: &str) -> Result<ReadOptions, String> {
+        let mut options = ReadOptions {
+            count: None,
+            block: None,
+            no_ack: false,
+            group: None,
+            streams_index: 0
+        };
+
+        let mut cursor = 0;
+
+        loop {
+            match args.get(cursor).map(|arg| arg.to_lowercase()).as_deref() {
+                Some("count") if cursor + 1 < args.len() => {
+                    options.count = match args[cursor + 1].parse::<i64>() {
+                        Ok(count) if count > 0 => Some(count as usize),
+                        Ok(_) => None,
+                        Err(_) => return Err("ERR value is not an integer or out of range".to_string())
+                    };
+
+                    cursor += 2;
+                }
+
+                Some("block") if cursor + 1 < args.len() => {
+                    options.block = match args[cursor + 1].parse::<i64>() {
+                        Ok(timeout) if timeout >= 0 => Some(Duration::from_millis(timeout as u64)),
+                        Ok(_) => return Err("ERR timeout is negative".to_string()),
+                        Err(_) => return Err("ERR timeout is not an integer or out of range".to_string())
+                    };
+
+                    cursor += 2;
+                }
+
+                Some("group") if cursor + 2 < args.len() => {
+                    options.group = Some((args[cursor + 1].clone(), args[cursor + 2].clone()));
+                    cursor += 3;
+                }
+
+                Some("noack") if command == "xreadgroup" => {
+                    options.no_ack = true;
+                    cursor += 1;
+                }
+
+                Some("streams") => {
+                    options.streams_index = cursor + 1;
+                    return Ok(options);
+                }
+
+                _ => return Err("ERR syntax error".to_string())
+            }
+        }
+    }
+}
+
+struct TrimOptions {
+    strategy: TrimStrategy,
+    approximate: bool,
+    limit: usize
+}
+
+impl TrimOptions {
+    /// Parses `<MAXLEN|MINID> [=|~] <threshold> [LIMIT <count>]` starting at `cursor`, leaving it on the next argument.
+    fn parse(args: &[String], cursor: &mut usize) -> Result<TrimOptions, String> {
+        let syntax_error = || "ERR syntax error".to_string();
+        let mut strategy: Option<TrimStrategy> = None;
+        let mut approximate = false;
+        let mut limit: Option<usize> = None;
+
+        while let Some(arg) = args.get(*cursor) {
+            match arg.to_lowercase().as_str() {
+                option @ ("maxlen" | "minid") if strategy.is_none() => {
+                    *cursor += 1;
+
+                    match args.get(*cursor).map(|arg| arg.as_str()) {
+                        Some("~") => {
+                            approximate = true;
+                            *cursor += 1;
+                        }
+
+                        Some("=") => *cursor += 1,
+                        _ => {}
+                    }
+
+                    let threshold = args.get(*cursor).ok_or_else(syntax_error)?;
+
+                    strategy = Some(if option == "maxlen" {
+                        match threshold.parse::<i64>() {
+                            Ok(max_len) if max_len >= 0 => TrimStrategy::MaxLen(max_len as usize),
+                            Ok(_) => return Err("ERR The MAXLEN argument must be >= 0.".to_string()),
+                            Err(_) => return Err("ERR value is not an integer or out of range".to_string())
+                        }
+                    } else {
+                        TrimStrategy::MinId(StreamId::parse(threshold, 0).map_err(|e| e.to_string())?)
+                    });
+                }
+
+                "limit" if limit.is_none() => {
+                    *cursor += 1;
+
+                    match args.get(*cursor).ok_or_else(syntax_error)?.parse::<i64>() {
+                        Ok(count) if count >= 0 => limit = Some(count as usize),
+                        Ok(_) => return Err("ERR The LIMIT argument must be >= 0.".to_string()),
+                        Err(_) => return Err("ERR value is not an integer or out of range".to_string())
+                    }
+                }
+
+                _ => break
+            }
+
+            *cursor += 1;
+        }
+
+        let strategy = strategy.ok_or_else(syntax_error)?;
+
+        if limit.is_some() && !approximate {
+            return Err("ERR syntax error, LIMIT cannot be used without the special ~ option".to_string());
+        }
+
+        Ok(TrimOptions {
+            strategy,
+            approximate,
+            limit: limit.unwrap_or(if approximate { DEFAULT_TRIM_LIMIT } else { 0 })
+        })
+    }
+}
+
+/// The commands logged for the pending entries `ids` of a group: an XCLAIM recording the exact delivery
+/// time and count of each entry still pending, and an XACK for the others. Commands reading or claiming
+/// entries depend on the clock and on the consumers' state, so they can't be logged as they were run.
+fn pending_commands(key: &str, group_name: &str, group: &ConsumerGroup, ids: &[StreamId]) -> Vec<Vec<String>> {
+    ids.iter()
+        .map(|id| match group.pending().get(id) {
+            Some(pending) => vec![
+                "XCLAIM".to_string(), key.to_string(), group_name.to_string(), pending.consumer.clone(), "0".to_string(), id.to_string(),
+                "TIME".to_string(), pending.delivery_time.to_string(), "RETRYCOUNT".to_string(), pending.delivery_count.to_string(),
+                "FORCE".to_string(), "JUSTID".to_string()
+            ],
+
+            None => vec!["XACK".to_string(), key.to_string(), group_name.to_string(), id.to_string()]
+        })
+        .collect()
+}
+
+/// The XGROUP SETID logged to record how far a group has read.
+fn set_id_command(key: &str, group_name: &str, group: &ConsumerGroup) -> Vec<String> {
+    let entries_read = group.entries_read.map(|entries_read| entries_read as i64).unwrap_or(-1);
+
+    vec![
+        "XGROUP".to_string(), "SETID".to_string(), key.to_string(), group_name.to_string(),
+        group.last_delivered_id.to_string(), "ENTRIESREAD".to_string(), entries_read.to_string()
+    ]
+}
+
+fn create_consumer_command(key: &str, group_name: &str, consumer: &str) -> Vec<String> {
+    vec!["XGROUP".to_string(), "CREATECONSUMER".to_string(), key.to_string(), group_name.to_string(), consumer.to_string()]
+}
+
+/// The XTRIM logged for a trim: removing everything before the first entry left. An approximate trim
+/// depends on how entries are split into nodes, which replaying the log doesn't reproduce.
+fn exact_trim(key: &str, stream: &Stream) -> Vec<String> {
+    let threshold = match stream.first_id() {
+        Some(first_id) => ["MINID".to_string(), "=".to_string(), first_id.to_string()],
+        None => ["MAXLEN".to_string(), "=".to_string(), "0".to_string()]
+    };
+
+    ["XTRIM".to_string(), key.to_string()].into_iter().chain(threshold).collect()
+}
+
+/// Resolves the ID requested by XADD (`*`, `<ms>-*` or an explicit ID) against the current top item.
+fn generate_stream_id(id: &str, last_id: StreamId) -> Result<StreamId> {
+    let exhausted = || anyhow!("ERR The stream has exhausted the last possible ID, unable to add more items");
+    let too_small = || anyhow!("ERR The ID specified in XADD is equal or smaller than the target stream top item");
+
+    if id == "*" {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+
+        return if last_id.millis >= now {
+            last_id.next().ok_or_else(exhausted)
+        } else {
+            Ok(StreamId::new(now, 0))
+        };
+    }
+
+    if let Some(millis) = id.strip_suffix("-*") {
+        let millis = millis.parse::<u64>().map_err(|_| anyhow!(INVALID_STREAM_ID_ERROR))?;
+
+        return if last_id.millis == millis {
+            last_id.seq.checked_add(1)
+                .map(|seq| StreamId::new(millis, seq))
+                .ok_or_else(exhausted)
+        } else if last_id.millis > millis {
+            Err(too_small())
+        } else {
+            Ok(StreamId::new(millis, if millis == 0 { 1 } else { 0 }))
+        };
+    }
+
+    let id = StreamId::parse(id, 0)?;
+
+    if id == StreamId::MIN {
+        return Err(anyhow!("ERR The ID specified in XADD must be greater than 0-0"));
+    }
+
+    if id <= last_id {
+        return Err(too_small());
+    }
+
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::CommandExecutor;
+    use crate::config::Configuration;
+    use crate::storage::Storage;
+
+    const IDS: [&str; 5] = ["1-0", "1-1", "2-0", "2-5", "3-0"];
+
+    struct Server {
+        executor: CommandExecutor,
+        context: CommandContext
+    }
+
+    impl Server {
+        /// A context with a client, holding the stream `s` with the entries in [`IDS`].
+        fn new() -> Server {
+            let mut context = CommandContext::new(Storage::new(), Configuration::new());
+            let (sender, _) = tokio::sync::mpsc::unbounded_channel();
+            let client_id = context.register_client(sender, None);
+            context.set_client(client_id);
+
+            let mut server = Server { executor: CommandExecutor::new(), context };
+
+            for id in IDS {
+                server.call(&["XADD", "s", id, "field", id]);
+            }
+
+            server
+        }
+
+        fn call(&mut self, args: &[&str]) -> Value {
+            let values = args[1..].iter().map(|arg| Value::BulkString(arg.as_bytes().to_vec())).collect();
+            self.executor.try_exec(args[0].to_lowercase(), values, &mut self.context).unwrap()
+        }
+
+        /// The IDs of the entries XRANGE or XREVRANGE replies with, or its error.
+        fn range(&mut self, command: &str, start: &str, end: &str) -> Result<Vec<String>, String> {
+            match self.call(&[command, "s", start, end]) {
+                Value::Array(entries) => Ok(entries.into_iter().map(entry_id).collect()),
+                Value::SimpleError(e) => Err(e),
+                reply => panic!("Unexpected reply {:?}", reply)
+            }
+        }
+
+        /// The IDs of the entries XREAD or XREADGROUP replies with for `s`, none for a null reply.
+        fn read(&mut self, args: &[&str]) -> Result<Vec<String>, String> {
+            match self.call(args) {
+                Value::Array(streams) => match streams.into_iter().next() {
+                    Some(Value::Array(stream)) => match stream.into_iter().nth(1) {
+                        Some(Value::Array(entries)) => Ok(entries.into_iter().map(entry_id).collect()),
+                        reply => panic!("Unexpected stream {:?}", reply)
+                    },
+
+                    reply => panic!("Unexpected streams {:?}", reply)
+                },
+
+                Value::Map(streams) => match streams.into_iter().next() {
+                    Some((_, Value::Array(entries))) => Ok(entries.into_iter().map(entry_id).collect()),
+                    reply => panic!("Unexpected streams {:?}", reply)
+                },
+
+                Value::NullArray | Value::Null => Ok(vec![]),
+                Value::SimpleError(e) => Err(e),
+                reply => panic!("Unexpected reply {:?}", reply)
+            }
+        }
+    }
+
+    fn entry_id(entry: Value) -> String {
+        match entry {
+            Value::Array(entry) => entry.into_iter().next().and_then(Value::unpack_as_string).unwrap(),
+            entry => panic!("Unexpected entry {:?}", entry)
+        }
+    }
+
+    fn ids(ids: &[&str]) -> Result<Vec<String>, String> {
+        Ok(ids.iter().map(|id| id.to_string()).collect())
+    }
+
+    #[test]
+    fn range_bounds_are_inclusive_unless_prefixed() {
+        let mut server = Server::new();
+
+        assert_eq!(server.range("XRANGE", "-", "+"), ids(&IDS));
+        assert_eq!(server.range("XRANGE", "1-1", "2-5"), ids(&["1-1", "2-0", "2-5"]));
+        assert_eq!(server.range("XRANGE", "(1-1", "(2-5"), ids(&["2-0"]));
+        assert_eq!(server.range("XRANGE", "(1-1", "+"), ids(&["2-0", "2-5", "3-0"]));
+        assert_eq!(server.range("XRANGE", "-", "(2-0"), ids(&["1-0", "1-1"]));
+        assert_eq!(server.range("XRANGE", "(2-5", "(3-0"), ids(&[]));
+
+        assert_eq!(server.range("XREVRANGE", "+", "-"), ids(&["3-0", "2-5", "2-0", "1-1", "1-0"]));
+        assert_eq!(server.range("XREVRANGE", "(3-0", "(1-0"), ids(&["2-5", "2-0", "1-1"]));
+    }
+
+    #[test]
+    fn incomplete_ids_cover_their_whole_millisecond() {
+        let mut server = Server::new();
+
+        assert_eq!(server.range("XRANGE", "2", "2"), ids(&["2-0", "2-5"]));
+        assert_eq!(server.range("XRANGE", "1", "2"), ids(&["1-0", "1-1", "2-0", "2-5"]));
+
+        // Exclusive, a start skips `<ms>-0` and an end `<ms>-<max>`, so the rest of the millisecond stays.
+        assert_eq!(server.range("XRANGE", "(1", "+"), ids(&["1-1", "2-0", "2-5", "3-0"]));
+        assert_eq!(server.range("XRANGE", "-", "(3"), ids(&IDS));
+        assert_eq!(server.range("XREVRANGE", "(2", "(1"), ids(&["2-5", "2-0", "1-1"]));
+    }
+
+    #[test]
+    fn invalid_range_bounds_are_refused() {
+        let mut server = Server::new();
+
+        // `5-*` only makes sense to XADD, which picks the sequence.
+        for (start, end) in [("5-*", "+"), ("-", "5-*"), ("(-", "+"), ("-", "(+"), ("1-x", "+"), ("-1", "+"), ("$", "+"), ("-", ">")] {
+            assert_eq!(server.range("XRANGE", start, end), Err(INVALID_STREAM_ID_ERROR.to_string()), "{} {}", start, end);
+        }
+
+        assert_eq!(server.range("XRANGE", "(18446744073709551615-18446744073709551615", "+"), Err("ERR invalid start ID for the interval".to_string()));
+        assert_eq!(server.range("XRANGE", "-", "(0-0"), Err("ERR invalid end ID for the interval".to_string()));
+    }
+
+    #[test]
+    fn xadd_completes_an_incomplete_id() {
+        let mut server = Server::new();
+
+        assert!(matches!(server.call(&["XADD", "s", "5-*", "f", "v"]), Value::BulkString(id) if id == b"5-0"));
+        assert!(matches!(server.call(&["XADD", "s", "5-*", "f", "v"]), Value::BulkString(id) if id == b"5-1"));
+        assert!(matches!(server.call(&["XADD", "s", "4-*", "f", "v"]), Value::SimpleError(_)));
+        assert_eq!(server.range("XRANGE", "5", "5"), ids(&["5-0", "5-1"]));
+    }
+
+    #[test]
+    fn special_read_ids_are_only_accepted_where_they_mean_something() {
+        let mut server = Server::new();
+
+        // `$` is the last ID when the command runs, so nothing is new yet.
+        assert_eq!(server.read(&["XREAD", "STREAMS", "s", "$"]), ids(&[]));
+        assert_eq!(server.read(&["XREAD", "STREAMS", "s", "2-0"]), ids(&["2-5", "3-0"]));
+        // As in Redis, an incomplete ID reads after `<ms>-0`, not after the whole millisecond.
+        assert_eq!(server.read(&["XREAD", "STREAMS", "s", "2"]), ids(&["2-5", "3-0"]));
+        assert!(server.read(&["XREAD", "STREAMS", "s", ">"]).unwrap_err().contains("XREADGROUP"));
+
+        assert!(matches!(server.call(&["XGROUP", "CREATE", "s", "g", "2-0"]), Value::SimpleString(_)));
+
+        // `>` reads what the group hasn't been delivered yet, an ID reads the consumer's pending entries.
+        assert_eq!(server.read(&["XREADGROUP", "GROUP", "g", "c", "COUNT", "1", "STREAMS", "s", ">"]), ids(&["2-5"]));
+        assert_eq!(server.read(&["XREADGROUP", "GROUP", "g", "c", "STREAMS", "s", ">"]), ids(&["3-0"]));
+        assert_eq!(server.read(&["XREADGROUP", "GROUP", "g", "c", "STREAMS", "s", ">"]), ids(&[]));
+        assert_eq!(server.read(&["XREADGROUP", "GROUP", "g", "c", "STREAMS", "s", "0"]), ids(&["2-5", "3-0"]));
+        assert_eq!(server.read(&["XREADGROUP", "GROUP", "g", "c", "STREAMS", "s", "(2-5"]), Err(INVALID_STREAM_ID_ERROR.to_string()));
+        assert!(server.read(&["XREADGROUP", "GROUP", "g", "c", "STREAMS", "s", "$"]).unwrap_err().contains("meaningless"));
+
+        // Created at `$`, a group only gets what is added afterwards.
+        assert!(matches!(server.call(&["XGROUP", "CREATE", "s", "late", "$"]), Value::SimpleString(_)));
+        assert_eq!(server.read(&["XREADGROUP", "GROUP", "late", "c", "STREAMS", "s", ">"]), ids(&[]));
+
+        server.call(&["XADD", "s", "4-0", "f", "v"]);
+        assert_eq!(server.read(&["XREADGROUP", "GROUP", "late", "c", "STREAMS", "s", ">"]), ids(&["4-0"]));
+    }
+}
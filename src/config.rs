@@ -4,13 +4,51 @@ use std::collections::HashMap;
 pub enum ConfigKey {
     Dir,
     DbFilename,
+    NotifyKeyspaceEvents,
+    BusyReplyThreshold,
+    Save,
+    AppendOnly,
+    AppendFsync,
+    AppendFilename,
+    AppendDirname,
+    AofLoadTruncated,
+    AutoAofRewritePercentage,
+    AutoAofRewriteMinSize,
+    AofUseRdbPreamble,
+    Port,
+    ReplBacklogSize,
+    ReplTimeout,
+    ReplPingReplicaPeriod,
+    ReplicaReadOnly,
+    ReplicaServeStaleData,
+    ReplDisklessSync,
+    ReplDisklessLoad,
 }
 
 impl ConfigKey {
     pub fn get_def_value(self) -> String {
         match self {
-            ConfigKey::Dir => "DIR".into(),
-            ConfigKey::DbFilename => "DB_FILENAME".into(),
+            ConfigKey::Dir => ".".into(),
+            ConfigKey::DbFilename => "dump.rdb".into(),
+            ConfigKey::NotifyKeyspaceEvents => "".into(),
+            ConfigKey::BusyReplyThreshold => "5000".into(),
+            ConfigKey::Save => "3600 1 300 100 60 10000".into(),
+            ConfigKey::AppendOnly => "no".into(),
+            ConfigKey::AppendFsync => "everysec".into(),
+            ConfigKey::AppendFilename => "appendonly.aof".into(),
+            ConfigKey::AppendDirname => "appendonlydir".into(),
+            ConfigKey::AofLoadTruncated => "yes".into(),
+            ConfigKey::AutoAofRewritePercentage => "100".into(),
+            ConfigKey::AutoAofRewriteMinSize => "64mb".into(),
+            ConfigKey::AofUseRdbPreamble => "yes".into(),
+            ConfigKey::Port => "6379".into(),
+            ConfigKey::ReplBacklogSize => "1mb".into(),
+            ConfigKey::ReplTimeout => "60".into(),
+            ConfigKey::ReplPingReplicaPeriod => "10".into(),
+            ConfigKey::ReplicaReadOnly => "yes".into(),
+            ConfigKey::ReplicaServeStaleData => "yes".into(),
+            ConfigKey::ReplDisklessSync => "no".into(),
+            ConfigKey::ReplDisklessLoad => "disabled".into(),
         }
     }
 }
@@ -35,8 +73,29 @@ impl Configuration {
         self.options.get(&key).cloned().unwrap_or(key.get_def_value())
     }
 
+    #[allow(dead_code)]
     pub fn delete(&mut self, key: ConfigKey) {
         self.options.remove(&key);
     }
+}
+
+/// Parses a size like `64mb` into bytes. Units follow Redis: `k`, `m` and `g` are powers of 1000,
+/// `kb`, `mb` and `gb` powers of 1024.
+pub fn parse_memory(value: &str) -> Option<u64> {
+    let value = value.to_lowercase();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
 
-}
\ No newline at end of file
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
@@ -0,0 +1,268 @@
+use std::ops::Range;
+
+const GEO_STEP_MAX: u32 = 26;
+
+pub const GEO_LAT_MIN: f64 = -85.05112878;
+pub const GEO_LAT_MAX: f64 = 85.05112878;
+pub const GEO_LONG_MIN: f64 = -180.0;
+pub const GEO_LONG_MAX: f64 = 180.0;
+
+const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+/// Half the circumference of the earth at the equator, in Web Mercator meters.
+const MERCATOR_MAX: f64 = 20037726.37;
+const GEO_ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GeoUnit {
+    Meters,
+    Kilometers,
+    Miles,
+    Feet
+}
+
+impl GeoUnit {
+    pub fn parse(unit: &str) -> Option<GeoUnit> {
+        match unit.to_lowercase().as_str() {
+            "m" => Some(GeoUnit::Meters),
+            "km" => Some(GeoUnit::Kilometers),
+            "mi" => Some(GeoUnit::Miles),
+            "ft" => Some(GeoUnit::Feet),
+            _ => None
+        }
+    }
+
+    pub fn to_meters(self) -> f64 {
+        match self {
+            GeoUnit::Meters => 1.0,
+            GeoUnit::Kilometers => 1000.0,
+            GeoUnit::Miles => 1609.34,
+            GeoUnit::Feet => 0.3048
+        }
+    }
+}
+
+pub fn is_valid_coordinate(longitude: f64, latitude: f64) -> bool {
+    (GEO_LONG_MIN..=GEO_LONG_MAX).contains(&longitude) && (GEO_LAT_MIN..=GEO_LAT_MAX).contains(&latitude)
+}
+
+/// Encodes a coordinate into the 52-bit interleaved geohash used as the sorted set score.
+pub fn encode(longitude: f64, latitude: f64) -> u64 {
+    encode_in_range(longitude, latitude, GEO_LAT_MIN, GEO_LAT_MAX)
+}
+
+/// Decodes a 52-bit geohash score back into the `(longitude, latitude)` center of its cell.
+pub fn decode(bits: u64) -> (f64, f64) {
+    let (lat_cell, long_cell) = deinterleave(bits);
+    let cells = (1u64 << GEO_STEP_MAX) as f64;
+
+    let lat_min = GEO_LAT_MIN + (lat_cell as f64 / cells) * (GEO_LAT_MAX - GEO_LAT_MIN);
+    let lat_max = GEO_LAT_MIN + ((lat_cell + 1) as f64 / cells) * (GEO_LAT_MAX - GEO_LAT_MIN);
+    let long_min = GEO_LONG_MIN + (long_cell as f64 / cells) * (GEO_LONG_MAX - GEO_LONG_MIN);
+    let long_max = GEO_LONG_MIN + ((long_cell + 1) as f64 / cells) * (GEO_LONG_MAX - GEO_LONG_MIN);
+
+    let longitude = ((long_min + long_max) / 2.0).clamp(GEO_LONG_MIN, GEO_LONG_MAX);
+    let latitude = ((lat_min + lat_max) / 2.0).clamp(GEO_LAT_MIN, GEO_LAT_MAX);
+
+    (longitude, latitude)
+}
+
+/// Renders the standard 11 character geohash string, which uses the full [-90, 90] latitude range
+/// rather than the Web Mercator bounds used for scores.
+pub fn to_geohash_string(bits: u64) -> String {
+    let (longitude, latitude) = decode(bits);
+    let standard_bits = encode_in_range(longitude, latitude, -90.0, 90.0);
+
+    (0..11)
+        .map(|i| {
+            let index = if i == 10 {
+                0
+            } else {
+                (standard_bits >> (52 - (i + 1) * 5)) & 0x1F
+            };
+
+            GEO_ALPHABET[index as usize] as char
+        })
+        .collect()
+}
+
+/// Great-circle distance in meters between two coordinates using the haversine formula.
+pub fn distance(long1: f64, lat1: f64, long2: f64, lat2: f64) -> f64 {
+    let v = ((long2.to_radians() - long1.to_radians()) / 2.0).sin();
+
+    if v == 0.0 {
+        return latitude_distance(lat1, lat2);
+    }
+
+    let lat1 = lat1.to_radians();
+    let lat2 = lat2.to_radians();
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let a = u * u + lat1.cos() * lat2.cos() * v * v;
+
+    2.0 * EARTH_RADIUS_IN_METERS * a.sqrt().asin()
+}
+
+/// Returns the distance from the box center when the point lies within a `width` x `height` box (in meters).
+pub fn distance_if_in_box(width: f64, height: f64, center_long: f64, center_lat: f64, long: f64, lat: f64) -> Option<f64> {
+    if latitude_distance(lat, center_lat) > height / 2.0 {
+        return None;
+    }
+
+    if distance(long, lat, center_long, lat) > width / 2.0 {
+        return None;
+    }
+
+    Some(distance(center_long, center_lat, long, lat))
+}
+
+/// The score ranges to scan for members within `radius` meters of a center, or within a box of
+/// `half_width` x `half_height` meters around it, `radius` then reaching its corners. As Redis'
+/// `geohashCalculateAreasByShapeWGS84` does, these are the cell holding the center at a precision
+/// where cells are about as large as the area, and the neighbouring cells the area reaches into.
+pub fn search_ranges(longitude: f64, latitude: f64, half_width: f64, half_height: f64, radius: f64) -> Vec<Range<u64>> {
+    let lat_delta = (half_height / EARTH_RADIUS_IN_METERS).to_degrees();
+    let long_delta_top = (half_width / EARTH_RADIUS_IN_METERS / (latitude + lat_delta).to_radians().cos()).to_degrees();
+    let long_delta_bottom = (half_width / EARTH_RADIUS_IN_METERS / (latitude - lat_delta).to_radians().cos()).to_degrees();
+
+    // The box is widest on the side closest to the equator.
+    let long_delta = if latitude < 0.0 { long_delta_bottom } else { long_delta_top };
+    let (long_min, long_max) = (longitude - long_delta, longitude + long_delta);
+    let (lat_min, lat_max) = (latitude - lat_delta, latitude + lat_delta);
+
+    let mut step = estimate_step(radius, latitude);
+    let mut center = Cell::containing(longitude, latitude, step);
+
+    // Near the edges of its cell, the area may reach past the neighbours at the estimated step.
+    let too_small = center.neighbour(1, 0).bounds().3 < lat_max || center.neighbour(-1, 0).bounds().1 > lat_min
+        || center.neighbour(0, 1).bounds().2 < long_max || center.neighbour(0, -1).bounds().0 > long_min;
+
+    if step > 1 && too_small {
+        step -= 1;
+        center = Cell::containing(longitude, latitude, step);
+    }
+
+    let (cell_long_min, cell_lat_min, cell_long_max, cell_lat_max) = center.bounds();
+    let mut ranges = vec![];
+
+    for lat_move in -1..=1 {
+        for long_move in -1..=1 {
+            // Neighbours the area doesn't reach into are skipped, except for coarse cells that wrap around.
+            let useless = step >= 2 && (
+                (lat_move == -1 && cell_lat_min < lat_min) || (lat_move == 1 && cell_lat_max > lat_max) ||
+                (long_move == -1 && cell_long_min < long_min) || (long_move == 1 && cell_long_max > long_max)
+            );
+
+            if !useless {
+                ranges.push(center.neighbour(lat_move, long_move).score_range());
+            }
+        }
+    }
+
+    // Coarse cells can be their own neighbours.
+    ranges.sort_by_key(|range| range.start);
+    ranges.dedup();
+    ranges
+}
+
+/// How many bits per coordinate the cells should have to be about as large as `radius`.
+fn estimate_step(mut radius: f64, latitude: f64) -> u32 {
+    if radius == 0.0 {
+        return GEO_STEP_MAX;
+    }
+
+    let mut step: i32 = 1;
+
+    while radius < MERCATOR_MAX {
+        radius *= 2.0;
+        step += 1;
+    }
+
+    // Leaves room for the area to be included in most cases, and more towards the poles where cells get narrower.
+    step -= 2;
+
+    if !(-66.0..=66.0).contains(&latitude) {
+        step -= 1;
+
+        if !(-80.0..=80.0).contains(&latitude) {
+            step -= 1;
+        }
+    }
+
+    step.clamp(1, GEO_STEP_MAX as i32) as u32
+}
+
+/// A geohash cell with `step` bits per coordinate.
+#[derive(Clone, Copy, Debug)]
+struct Cell {
+    lat: u64,
+    long: u64,
+    step: u32
+}
+
+impl Cell {
+    fn containing(longitude: f64, latitude: f64, step: u32) -> Cell {
+        let (lat, long) = deinterleave(encode(longitude, latitude));
+        let shift = GEO_STEP_MAX - step;
+
+        Cell { lat: lat >> shift, long: long >> shift, step }
+    }
+
+    /// The cell `lat_move` rows north and `long_move` columns east, wrapping around like geohashes do.
+    fn neighbour(self, lat_move: i64, long_move: i64) -> Cell {
+        let mask = (1u64 << self.step) - 1;
+
+        Cell {
+            lat: self.lat.wrapping_add_signed(lat_move) & mask,
+            long: self.long.wrapping_add_signed(long_move) & mask,
+            step: self.step
+        }
+    }
+
+    /// The `(long_min, lat_min, long_max, lat_max)` corners of the cell.
+    fn bounds(self) -> (f64, f64, f64, f64) {
+        let cells = (1u64 << self.step) as f64;
+        let lat_span = GEO_LAT_MAX - GEO_LAT_MIN;
+        let long_span = GEO_LONG_MAX - GEO_LONG_MIN;
+
+        (
+            GEO_LONG_MIN + self.long as f64 / cells * long_span,
+            GEO_LAT_MIN + self.lat as f64 / cells * lat_span,
+            GEO_LONG_MIN + (self.long + 1) as f64 / cells * long_span,
+            GEO_LAT_MIN + (self.lat + 1) as f64 / cells * lat_span
+        )
+    }
+
+    /// The scores of the points inside the cell, which share its bits as their prefix.
+    fn score_range(self) -> Range<u64> {
+        let shift = 2 * (GEO_STEP_MAX - self.step);
+        let prefix = interleave(self.lat, self.long);
+
+        (prefix << shift)..((prefix + 1) << shift)
+    }
+}
+
+fn latitude_distance(lat1: f64, lat2: f64) -> f64 {
+    EARTH_RADIUS_IN_METERS * (lat2.to_radians() - lat1.to_radians()).abs()
+}
+
+fn encode_in_range(longitude: f64, latitude: f64, lat_min: f64, lat_max: f64) -> u64 {
+    let cells = (1u64 << GEO_STEP_MAX) as f64;
+    let lat_offset = (latitude - lat_min) / (lat_max - lat_min) * cells;
+    let long_offset = (longitude - GEO_LONG_MIN) / (GEO_LONG_MAX - GEO_LONG_MIN) * cells;
+
+    let max_cell = (1u64 << GEO_STEP_MAX) - 1;
+
+    interleave((lat_offset as u64).min(max_cell), (long_offset as u64).min(max_cell))
+}
+
+/// Interleaves latitude bits into even positions and longitude bits into odd positions.
+fn interleave(lat: u64, long: u64) -> u64 {
+    (0..GEO_STEP_MAX).fold(0, |bits, i| {
+        bits | (((lat >> i) & 1) << (2 * i)) | (((long >> i) & 1) << (2 * i + 1))
+    })
+}
+
+fn deinterleave(bits: u64) -> (u64, u64) {
+    (0..GEO_STEP_MAX).fold((0, 0), |(lat, long), i| {
+        (lat | (((bits >> (2 * i)) & 1) << i), long | (((bits >> (2 * i + 1)) & 1) << i))
+    })
+}
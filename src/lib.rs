@@ -0,0 +1,25 @@
+//! A Redis compatible server. Besides the `redis-rust` binary, the crate can be embedded through
+//! [`server::run`] and extended through the [`module`] API. The [`tools`] behind the `check-rdb`,
+//! `check-aof` and `rdb-dump` binaries inspect persisted files offline.
+
+mod parser;
+mod response;
+mod storage;
+mod config;
+mod commands;
+mod sorted_set;
+mod geo;
+mod stream;
+mod client;
+mod pubsub;
+mod cluster;
+mod notifications;
+mod scripting;
+mod rdb;
+mod persistence;
+mod aof;
+mod replication;
+
+pub mod module;
+pub mod server;
+pub mod tools;
@@ -0,0 +1,426 @@
+//! The API for extending the server with modules: custom commands, data types stored in RDB files,
+//! keyspace event hooks and timers.
+//!
+//! A module is any [`Module`]. It is either handed to [`crate::server::run`] when embedding the server,
+//! or built as a `cdylib` exporting itself with [`declare_module!`](crate::declare_module) and loaded
+//! with `--loadmodule` or MODULE LOAD. Dynamic modules share Rust types with the server, so they must be
+//! built by the same compiler against the same version of this crate, which is checked before their
+//! code runs, see [`BUILD`].
+
+use anyhow::{anyhow, Result};
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+pub use crate::client::ClientId;
+pub use crate::commands::{Command, CommandContext};
+pub use crate::notifications::{
+    KeyspaceEvent, NOTIFY_ALL, NOTIFY_EVICTED, NOTIFY_EXPIRED, NOTIFY_GENERIC, NOTIFY_HASH, NOTIFY_KEY_MISS,
+    NOTIFY_LIST, NOTIFY_NEW, NOTIFY_SET, NOTIFY_STREAM, NOTIFY_STRING, NOTIFY_ZSET
+};
+pub use crate::parser::Value;
+pub use crate::storage::Storage;
+
+/// Bumped whenever the module API changes incompatibly; dynamic modules built against another version are refused.
+pub const API_VERSION: u32 = 3;
+
+/// The version of this crate, the compiler and the target it was built with. Rust has no stable ABI,
+/// so dynamic modules are only loaded if theirs is the same.
+pub const BUILD: &CStr = match CStr::from_bytes_with_nul(concat!(env!("REDIS_RUST_BUILD"), "\0").as_bytes()) {
+    Ok(build) => build,
+    Err(_) => panic!("The build description contains a NUL byte")
+};
+
+const ENTRY_SYMBOL: &str = "redis_rust_module_entry";
+
+/// What the entry point exported by [`declare_module!`](crate::declare_module) returns. Only plain C
+/// types, so it can be read whatever the module was built with; `create` is called once `api_version`
+/// and `build` match the server's.
+#[repr(C)]
+pub struct ModuleEntry {
+    pub api_version: u32,
+    pub build: *const c_char,
+    /// Returns a `Box<Box<dyn Module>>` turned into a raw pointer.
+    pub create: extern "C" fn() -> *mut c_void
+}
+
+pub trait Module: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn version(&self) -> i64 {
+        1
+    }
+
+    /// Registers what the module provides. On error the load is aborted and every registration undone.
+    fn on_load(&self, registry: &mut ModuleRegistry, args: &[String]) -> Result<()>;
+
+    fn on_unload(&self) {}
+}
+
+/// A data type implemented by a module, whose values are stored as [`Value::Module`].
+pub trait ModuleType: Send + Sync {
+    /// The name TYPE replies with, which also identifies the type in RDB files. As in Redis,
+    /// it is exactly 9 characters out of `A-Z`, `a-z`, `0-9`, `-` and `_`.
+    fn name(&self) -> &str;
+
+    /// Stored along with every value, so `rdb_load` can read values saved by older versions of the module.
+    fn encoding_version(&self) -> u16 {
+        0
+    }
+
+    fn rdb_save(&self, value: &ModuleValue) -> Vec<u8>;
+    fn rdb_load(&self, bytes: &[u8], encoding_version: u16) -> Result<ModuleValue>;
+}
+
+/// Data held by a module value. Implemented for every cloneable, debuggable type.
+pub trait ModuleData: Any + Debug + Send + Sync {
+    fn clone_data(&self) -> Box<dyn ModuleData>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any + Debug + Clone + Send + Sync> ModuleData for T {
+    fn clone_data(&self) -> Box<dyn ModuleData> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// A value of a [`ModuleType`], tagged with the name of its type.
+#[derive(Debug)]
+pub struct ModuleValue {
+    type_name: String,
+    data: Box<dyn ModuleData>
+}
+
+impl ModuleValue {
+    pub fn new<T: ModuleData>(type_name: &str, data: T) -> ModuleValue {
+        ModuleValue { type_name: type_name.to_string(), data: Box::new(data) }
+    }
+
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.data.as_any().downcast_ref()
+    }
+
+    pub fn downcast_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.data.as_any_mut().downcast_mut()
+    }
+}
+
+impl Clone for ModuleValue {
+    fn clone(&self) -> ModuleValue {
+        ModuleValue { type_name: self.type_name.clone(), data: self.data.clone_data() }
+    }
+}
+
+pub type EventHook = dyn Fn(&mut CommandContext, &KeyspaceEvent) + Send + Sync;
+pub type TimerCallback = Box<dyn FnOnce(&mut CommandContext) + Send>;
+pub type TimerId = u64;
+
+/// Handed to [`Module::on_load`] to register what the module provides.
+pub struct ModuleRegistry<'a> {
+    module: String,
+    modules: &'a mut Modules,
+    is_reserved: &'a dyn Fn(&str) -> bool
+}
+
+impl ModuleRegistry<'_> {
+    pub fn register_command(&mut self, command: Box<dyn Command>) -> Result<()> {
+        let name = command.name().to_lowercase();
+
+        if (self.is_reserved)(&name) || self.modules.commands.contains_key(&name) {
+            return Err(anyhow!("Command {} already exists", name));
+        }
+
+        self.modules.commands.insert(name, (self.module.clone(), Arc::from(command)));
+        Ok(())
+    }
+
+    pub fn register_type(&mut self, module_type: Box<dyn ModuleType>) -> Result<()> {
+        let name = module_type.name().to_string();
+
+        if name.len() != 9 || !name.chars().all(|char| crate::rdb::MODULE_TYPE_CHARSET.contains(char)) {
+            return Err(anyhow!("Invalid module type name {}", name));
+        }
+
+        if self.modules.types.contains_key(&name) {
+            return Err(anyhow!("Module type {} already exists", name));
+        }
+
+        self.modules.types.insert(name, (self.module.clone(), Arc::from(module_type)));
+        Ok(())
+    }
+
+    /// Calls `hook` for every keyspace event in one of the `classes` (`NOTIFY_*` flags), whatever
+    /// `notify-keyspace-events` is set to.
+    pub fn subscribe_to_keyspace_events<F>(&mut self, classes: u32, hook: F)
+    where
+        F: Fn(&mut CommandContext, &KeyspaceEvent) + Send + Sync + 'static
+    {
+        self.modules.hooks.push((self.module.clone(), classes, Arc::new(hook)));
+    }
+
+    pub fn create_timer<F>(&mut self, delay: Duration, callback: F) -> TimerId
+    where
+        F: FnOnce(&mut CommandContext) + Send + 'static
+    {
+        self.modules.create_timer(Some(self.module.clone()), delay, Box::new(callback))
+    }
+}
+
+struct LoadedModule {
+    // Dropped before the library, whose code its destructor may run.
+    module: Box<dyn Module>,
+    path: Option<String>,
+    args: Vec<String>,
+    _library: Option<DynamicLibrary>
+}
+
+struct Timer {
+    deadline: Instant,
+    module: Option<String>,
+    callback: TimerCallback
+}
+
+/// Everything registered by loaded modules. Things are tagged with the module that registered them,
+/// so they can be dropped when it is unloaded.
+pub(crate) struct Modules {
+    loaded: BTreeMap<String, LoadedModule>,
+    commands: HashMap<String, (String, Arc<dyn Command>)>,
+    types: HashMap<String, (String, Arc<dyn ModuleType>)>,
+    hooks: Vec<(String, u32, Arc<EventHook>)>,
+    timers: BTreeMap<TimerId, Timer>,
+    next_timer_id: TimerId,
+    timer_updates: Arc<Notify>,
+    /// The module whose code is running, which owns the timers it creates.
+    pub(crate) current: Option<String>
+}
+
+impl Modules {
+    pub(crate) fn new() -> Modules {
+        Modules {
+            loaded: BTreeMap::new(),
+            commands: HashMap::new(),
+            types: HashMap::new(),
+            hooks: Vec::new(),
+            timers: BTreeMap::new(),
+            next_timer_id: 1,
+            timer_updates: Arc::new(Notify::new()),
+            current: None
+        }
+    }
+
+    /// Runs a module's `on_load` and keeps it if it succeeds. `is_reserved` tells which command names are built in.
+    pub(crate) fn load(&mut self, module: Box<dyn Module>, library: Option<DynamicLibrary>, path: Option<String>, args: Vec<String>, is_reserved: &dyn Fn(&str) -> bool) -> Result<()> {
+        let name = module.name().to_string();
+
+        if self.loaded.contains_key(&name) {
+            return Err(anyhow!("Module {} is already loaded", name));
+        }
+
+        let mut registry = ModuleRegistry { module: name.clone(), modules: self, is_reserved };
+
+        if let Err(e) = module.on_load(&mut registry, &args) {
+            self.remove_registrations(&name);
+
+            // The module's code lives in the library, so it goes last.
+            drop(module);
+            drop(library);
+            return Err(e);
+        }
+
+        self.loaded.insert(name, LoadedModule { module, path, args, _library: library });
+        Ok(())
+    }
+
+    pub(crate) fn unload(&mut self, name: &str) -> Result<()> {
+        if !self.loaded.contains_key(name) {
+            return Err(anyhow!("no such module with that name"));
+        }
+
+        // Values of the module's types may still be stored, and they can't outlive its code.
+        if self.types.values().any(|(module, _)| module == name) {
+            return Err(anyhow!("the module exports one or more module-side data types, can't unload"));
+        }
+
+        let loaded = self.loaded.remove(name).unwrap();
+        loaded.module.on_unload();
+
+        self.remove_registrations(name);
+        Ok(())
+    }
+
+    fn remove_registrations(&mut self, name: &str) {
+        self.commands.retain(|_, (module, _)| module != name);
+        self.types.retain(|_, (module, _)| module != name);
+        self.hooks.retain(|(module, _, _)| module != name);
+        self.timers.retain(|_, timer| timer.module.as_deref() != Some(name));
+    }
+
+    /// The name, version, path and arguments of every loaded module.
+    pub(crate) fn list(&self) -> impl Iterator<Item = (&str, i64, Option<&str>, &[String])> {
+        self.loaded.iter().map(|(name, loaded)| (name.as_str(), loaded.module.version(), loaded.path.as_deref(), loaded.args.as_slice()))
+    }
+
+    pub(crate) fn command(&self, name: &str) -> Option<Arc<dyn Command>> {
+        self.commands.get(name).map(|(_, command)| Arc::clone(command))
+    }
+
+    pub(crate) fn command_owner(&self, name: &str) -> Option<String> {
+        self.commands.get(name).map(|(module, _)| module.clone())
+    }
+
+    pub(crate) fn module_type(&self, name: &str) -> Option<Arc<dyn ModuleType>> {
+        self.types.get(name).map(|(_, module_type)| Arc::clone(module_type))
+    }
+
+    /// The hooks interested in an event class, with the module each belongs to.
+    pub(crate) fn hooks(&self, class: u32) -> Vec<(String, Arc<EventHook>)> {
+        self.hooks.iter()
+            .filter(|(_, classes, _)| classes & class != 0)
+            .map(|(module, _, hook)| (module.clone(), Arc::clone(hook)))
+            .collect()
+    }
+
+    pub(crate) fn create_timer(&mut self, module: Option<String>, delay: Duration, callback: TimerCallback) -> TimerId {
+        let id = self.next_timer_id;
+
+        self.next_timer_id += 1;
+        self.timers.insert(id, Timer { deadline: Instant::now() + delay, module, callback });
+        self.timer_updates.notify_one();
+        id
+    }
+
+    pub(crate) fn stop_timer(&mut self, id: TimerId) -> bool {
+        self.timers.remove(&id).is_some()
+    }
+
+    /// Removes the timers that are due, in deadline order.
+    pub(crate) fn take_due_timers(&mut self) -> Vec<(Option<String>, TimerCallback)> {
+        let now = Instant::now();
+        let mut due: Vec<(TimerId, Instant)> = self.timers.iter()
+            .filter(|(_, timer)| timer.deadline <= now)
+            .map(|(id, timer)| (*id, timer.deadline))
+            .collect();
+
+        due.sort_by_key(|(id, deadline)| (*deadline, *id));
+
+        due.into_iter()
+            .filter_map(|(id, _)| self.timers.remove(&id))
+            .map(|timer| (timer.module, timer.callback))
+            .collect()
+    }
+
+    pub(crate) fn next_timer_deadline(&self) -> Option<Instant> {
+        self.timers.values().map(|timer| timer.deadline).min()
+    }
+
+    pub(crate) fn timer_updates(&self) -> Arc<Notify> {
+        Arc::clone(&self.timer_updates)
+    }
+}
+
+/// Exports a module from a `cdylib`, so it can be loaded with `--loadmodule` or MODULE LOAD.
+/// Takes an expression building the [`Module`].
+#[macro_export]
+macro_rules! declare_module {
+    ($constructor:expr) => {
+        #[no_mangle]
+        pub extern "C" fn redis_rust_module_entry() -> $crate::module::ModuleEntry {
+            extern "C" fn create() -> *mut ::std::ffi::c_void {
+                let module: Box<dyn $crate::module::Module> = Box::new($constructor);
+                Box::into_raw(Box::new(module)) as *mut ::std::ffi::c_void
+            }
+
+            $crate::module::ModuleEntry {
+                api_version: $crate::module::API_VERSION,
+                build: $crate::module::BUILD.as_ptr(),
+                create
+            }
+        }
+    };
+}
+
+/// A shared library opened with `dlopen`, closed once dropped.
+pub(crate) struct DynamicLibrary {
+    handle: *mut c_void
+}
+
+// The handle is only used to look symbols up and close the library, which the loader makes thread safe.
+unsafe impl Send for DynamicLibrary {}
+unsafe impl Sync for DynamicLibrary {}
+
+impl DynamicLibrary {
+    /// Opens a library built with [`declare_module!`](crate::declare_module) and creates its module.
+    pub(crate) fn open_module(path: &str) -> Result<(DynamicLibrary, Box<dyn Module>)> {
+        let c_path = CString::new(path)?;
+        let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+
+        if handle.is_null() {
+            return Err(anyhow!("{}", dl_error()));
+        }
+
+        let library = DynamicLibrary { handle };
+
+        // Matches the signature `declare_module!` exports, which only involves C types.
+        let entry: extern "C" fn() -> ModuleEntry = unsafe { std::mem::transmute(library.symbol(ENTRY_SYMBOL)?) };
+        let entry = entry();
+
+        if entry.api_version != API_VERSION {
+            return Err(anyhow!("Module API version {} is not supported, expected {}", entry.api_version, API_VERSION));
+        }
+
+        let build = unsafe { CStr::from_ptr(entry.build) };
+
+        if build != BUILD {
+            return Err(anyhow!("Module built as {:?}, but the server is {:?}; rebuild it with the same compiler and version of redis-rust", build, BUILD));
+        }
+
+        // The builds match, so the module's `Box<dyn Module>` is laid out as ours.
+        let module = unsafe { *Box::from_raw((entry.create)() as *mut Box<dyn Module>) };
+
+        Ok((library, module))
+    }
+
+    fn symbol(&self, name: &str) -> Result<*mut c_void> {
+        let c_name = CString::new(name)?;
+        let symbol = unsafe { libc::dlsym(self.handle, c_name.as_ptr()) };
+
+        if symbol.is_null() {
+            return Err(anyhow!("Missing symbol {}, was the module exported with declare_module!?", name));
+        }
+
+        Ok(symbol)
+    }
+}
+
+impl Drop for DynamicLibrary {
+    fn drop(&mut self) {
+        unsafe { libc::dlclose(self.handle) };
+    }
+}
+
+fn dl_error() -> String {
+    let error = unsafe { libc::dlerror() };
+
+    if error.is_null() {
+        return "Unknown error".to_string();
+    }
+
+    unsafe { CStr::from_ptr(error) }.to_string_lossy().into_owned()
+}
@@ -0,0 +1,72 @@
+/// Keyspace event classes, one bit per flag character accepted by `notify-keyspace-events`.
+pub const NOTIFY_KEYSPACE: u32 = 1 << 0;
+pub const NOTIFY_KEYEVENT: u32 = 1 << 1;
+pub const NOTIFY_GENERIC: u32 = 1 << 2;
+pub const NOTIFY_STRING: u32 = 1 << 3;
+pub const NOTIFY_LIST: u32 = 1 << 4;
+pub const NOTIFY_SET: u32 = 1 << 5;
+pub const NOTIFY_HASH: u32 = 1 << 6;
+pub const NOTIFY_ZSET: u32 = 1 << 7;
+pub const NOTIFY_EXPIRED: u32 = 1 << 8;
+pub const NOTIFY_EVICTED: u32 = 1 << 9;
+pub const NOTIFY_STREAM: u32 = 1 << 10;
+pub const NOTIFY_KEY_MISS: u32 = 1 << 11;
+pub const NOTIFY_NEW: u32 = 1 << 12;
+
+/// Everything `A` stands for; key misses and new keys must be asked for explicitly.
+pub const NOTIFY_ALL: u32 = NOTIFY_GENERIC | NOTIFY_STRING | NOTIFY_LIST | NOTIFY_SET | NOTIFY_HASH | NOTIFY_ZSET | NOTIFY_EXPIRED | NOTIFY_EVICTED | NOTIFY_STREAM;
+
+const CLASS_FLAGS: [(char, u32); 9] = [
+    ('g', NOTIFY_GENERIC),
+    ('$', NOTIFY_STRING),
+    ('l', NOTIFY_LIST),
+    ('s', NOTIFY_SET),
+    ('h', NOTIFY_HASH),
+    ('z', NOTIFY_ZSET),
+    ('x', NOTIFY_EXPIRED),
+    ('e', NOTIFY_EVICTED),
+    ('t', NOTIFY_STREAM)
+];
+
+/// An event raised by a keyspace change, waiting to be published once the command finishes.
+#[derive(Clone, Debug)]
+pub struct KeyspaceEvent {
+    pub class: u32,
+    pub event: &'static str,
+    pub key: String
+}
+
+/// Parses a `notify-keyspace-events` flag string, returning `None` on an unknown character.
+pub fn parse_flags(flags: &str) -> Option<u32> {
+    flags.chars().try_fold(0, |parsed, flag| {
+        let class = match flag {
+            'A' => NOTIFY_ALL,
+            'K' => NOTIFY_KEYSPACE,
+            'E' => NOTIFY_KEYEVENT,
+            'm' => NOTIFY_KEY_MISS,
+            'n' => NOTIFY_NEW,
+            _ => CLASS_FLAGS.iter().find(|(character, _)| *character == flag)?.1
+        };
+
+        Some(parsed | class)
+    })
+}
+
+/// Formats flags back into their canonical string, collapsing the full class set into `A`.
+pub fn flags_to_string(flags: u32) -> String {
+    let mut result = String::new();
+
+    if flags & NOTIFY_ALL == NOTIFY_ALL {
+        result.push('A');
+    } else {
+        result.extend(CLASS_FLAGS.iter().filter(|(_, class)| flags & class != 0).map(|(character, _)| *character));
+    }
+
+    for (character, class) in [('K', NOTIFY_KEYSPACE), ('E', NOTIFY_KEYEVENT), ('m', NOTIFY_KEY_MISS), ('n', NOTIFY_NEW)] {
+        if flags & class != 0 {
+            result.push(character);
+        }
+    }
+
+    result
+}
@@ -1,7 +1,7 @@
-use crate::storage::Storage;
-use anyhow::anyhow;
-use anyhow::Result;
-use bytes::BytesMut;
+use crate::module::ModuleValue;
+use crate::sorted_set::SortedSet;
+use crate::stream::Stream;
+use std::collections::{HashMap, HashSet};
 use strum_macros::{Display, EnumString};
 
 #[derive(Display, EnumString, PartialEq)]
@@ -11,69 +11,48 @@ pub enum Type {
     Set,
     ZSet,
     Hash,
-    Stream
+    Stream,
+    Module
 }
 
 #[derive(Clone, Debug)]
 pub enum Value {
     SimpleString(String),
-    BulkString(String),
+    /// Binary safe: the bytes of a DUMP payload go through as they are.
+    BulkString(Vec<u8>),
     Boolean(bool),
     Integer(i64),
     Array(Vec<Value>),
-    Stream(Vec<StreamEntry>),
+    Map(Vec<(Value, Value)>),
+    Push(Vec<Value>),
+    Stream(Stream),
+    SortedSet(SortedSet),
+    Set(HashSet<String>),
+    Hash(HashMap<String, String>),
+    Module(ModuleValue),
     SimpleError(String),
+    /// Bytes written to the connection as they are, like the RDB file of a replica's full resync.
+    /// Empty, it is the reply of commands that answer nothing, like REPLCONF ACK.
+    Raw(Vec<u8>),
     NullBulkString,
+    NullArray,
     Null
 }
 
-#[derive(Clone, Debug)]
-pub struct StreamEntry {
-    pub millis_time: i128,
-    pub sequence_number: i64,
-    pub storage: Storage
-}
-
-impl StreamEntry {
-    pub fn new(millis_time: i128, sequence_number: i64) -> Self {
-        StreamEntry {
-            millis_time,
-            sequence_number,
-            storage: Storage::new()
-        }
-    }
-
-    pub fn as_array_value(&self) -> Value {
-        Value::Array(vec![
-            Value::BulkString(format!("{}-{}", self.millis_time, self.sequence_number)),
-            Value::Array(
-                self.clone()
-                    .storage
-                    .get_all()
-                    .iter()
-                    .flat_map(|(key, data)| {
-                        vec![
-                            Value::BulkString(key.to_string()),
-                            data.get_value().clone(),
-                        ]
-                    })
-                    .collect()
-            ),
-        ])
-    }
-}
-
 impl Value {
-    pub fn serialize(self) -> String {
+    pub fn serialize(self) -> Vec<u8> {
         match self {
-            Value::SimpleString(s) => format!("+{}\r\n", s),
-            Value::BulkString(s) => format!("${}\r\n{}\r\n", s.len(), s),
-            Value::Boolean(b) => format!("#{}\r\n", b.to_string().chars().next().unwrap()),
-            Value::Integer(i) => format!(":{}\r\n", i),
-            Value::Array(arr) => format!("*{}\r\n{}", arr.len(), arr.iter().map(|v| v.clone().serialize()).collect::<Vec<_>>().join("")),
-            Value::SimpleError(s) => format!("-{}\r\n", s),
-            Value::NullBulkString => "$-1\r\n".to_string(),
-            Value::Null => "_\r\n".to_string(),
+            Value::SimpleString(s) => format!("+{}\r\n", s).into_bytes(),
+            Value::BulkString(s) => [format!("${}\r\n", s.len()).as_bytes(), &s, b"\r\n"].concat(),
+            Value::Boolean(b) => format!("#{}\r\n", b.to_string().chars().next().unwrap()).into_bytes(),
+            Value::Integer(i) => format!(":{}\r\n", i).into_bytes(),
+            Value::Array(arr) => [format!("*{}\r\n", arr.len()).into_bytes()].into_iter().chain(arr.into_iter().map(|v| v.serialize())).collect::<Vec<_>>().concat(),
+            Value::Map(pairs) => [format!("%{}\r\n", pairs.len()).into_bytes()].into_iter().chain(pairs.into_iter().flat_map(|(k, v)| [k.serialize(), v.serialize()])).collect::<Vec<_>>().concat(),
+            Value::Push(arr) => [format!(">{}\r\n", arr.len()).into_bytes()].into_iter().chain(arr.into_iter().map(|v| v.serialize())).collect::<Vec<_>>().concat(),
+            Value::SimpleError(s) => format!("-{}\r\n", s).into_bytes(),
+            Value::NullBulkString => b"$-1\r\n".to_vec(),
+            Value::NullArray => b"*-1\r\n".to_vec(),
+            Value::Null => b"_\r\n".to_vec(),
             _ => panic!("Tried to serialize unserializable value!")
         }
     }
@@ -81,7 +60,7 @@ impl Value {
     pub fn unpack_as_string(self) -> Option<String> {
         match self {
             Value::SimpleString(s) => Some(s),
-            Value::BulkString(s) => Some(s),
+            Value::BulkString(s) => String::from_utf8(s).ok(),
             Value::Boolean(b) => Some(b.to_string()),
             Value::Integer(i) => Some(i.to_string()),
             Value::SimpleError(s) => Some(s),
@@ -90,80 +69,118 @@ impl Value {
         }
     }
 
+    /// Like [`Value::unpack_as_string`], but keeps bulk strings that aren't UTF-8.
+    pub fn unpack_as_bytes(self) -> Option<Vec<u8>> {
+        match self {
+            Value::BulkString(s) => Some(s),
+            value => value.unpack_as_string().map(String::into_bytes)
+        }
+    }
+
     pub fn get_type(&self) -> Type {
         match self {
             Value::Array(_) => Type::List,
             Value::Stream(_) => Type::Stream,
+            Value::SortedSet(_) => Type::ZSet,
+            Value::Set(_) => Type::Set,
+            Value::Hash(_) => Type::Hash,
+            Value::Module(_) => Type::Module,
             _ => Type::String,
         }
     }
 
 }
 
-pub fn parse_message(buffer: BytesMut) -> Result<(Value, usize)> {
-    match buffer[0] as char {
-        '+' => parse_simple_string(buffer),
-        '$' => parse_bulk_string(buffer),
-        '*' => parse_array(buffer),
-        _ => Err(anyhow!("{:?} is an invalid value type!", buffer)),
-    }
+/// Why a message couldn't be parsed.
+#[derive(Debug)]
+pub enum ParseError {
+    /// The message hasn't fully arrived yet, reading more may complete it.
+    Incomplete,
+    /// The bytes aren't a valid message, however many more follow.
+    Invalid(String)
 }
 
-fn parse_simple_string(buffer: BytesMut) -> Result<(Value, usize)> {
-    if let Some((line, parsed)) = read_until_end(&buffer[1..]) {
-        let buffer_str: String = buffer_to_string(line);
-        return Ok((Value::SimpleString(buffer_str), parsed + 1));
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Incomplete => write!(f, "Incomplete message"),
+            ParseError::Invalid(reason) => write!(f, "{}", reason)
+        }
     }
-
-    Err(anyhow!("{:?} is an invalid SimpleString!", buffer))
 }
 
-fn parse_bulk_string(buffer: BytesMut) -> Result<(Value, usize)> {
-    let (str_length, bytes_consumed) = if let Some((line, parsed)) = read_until_end(&buffer[1..]) {
-        let str_length = parse_int(line)?;
+impl std::error::Error for ParseError {}
+
+type ParseResult = std::result::Result<(Value, usize), ParseError>;
 
-        (str_length, parsed + 1)
-    } else {
-        return Err(anyhow!("{:?} is an invalid Bulk String Format!", buffer))
+/// The longest line accepted before its CRLF, like a bulk string's length, as in Redis.
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// The largest bulk string accepted, Redis's default `proto-max-bulk-len`.
+const MAX_BULK_LENGTH: usize = 512 * 1024 * 1024;
+
+/// Parses the message at the start of `buffer`, returning it along with how many bytes it took.
+pub fn parse_message(buffer: &[u8]) -> ParseResult {
+    let Some(&first) = buffer.first() else {
+        return Err(ParseError::Incomplete);
     };
 
+    match first {
+        b'+' => parse_simple_string(buffer),
+        b'$' => parse_bulk_string(buffer),
+        b'*' => parse_array(buffer),
+        b':' => parse_integer(buffer),
+        b'#' => parse_boolean(buffer),
+        b'_' => read_line(buffer).map(|(_, parsed)| (Value::Null, parsed)),
+        _ => Err(ParseError::Invalid(format!("expected '*' or '$', got '{}'", first.escape_ascii())))
+    }
+}
+
+fn parse_simple_string(buffer: &[u8]) -> ParseResult {
+    let (line, parsed) = read_line(buffer)?;
+    Ok((Value::SimpleString(buffer_to_string(line)), parsed))
+}
+
+fn parse_bulk_string(buffer: &[u8]) -> ParseResult {
+    let (line, bytes_consumed) = read_line(buffer)?;
+    let str_length = parse_length(line, MAX_BULK_LENGTH, "bulk")?;
+
     let end_of_str = bytes_consumed + str_length;
     let total_parsed = end_of_str + 2;
 
-    Ok((Value::SimpleString(buffer_to_string(&buffer[bytes_consumed..end_of_str])), total_parsed))
-}
+    if buffer.len() < total_parsed {
+        return Err(ParseError::Incomplete);
+    }
 
-fn parse_integer(buffer: BytesMut) -> Result<(Value, usize)> {
-    if let Some((line, parsed)) = read_until_end(&buffer[1..]) {
-        let buffer_str: String = buffer_to_string(line);
-        return Ok((Value::Integer(buffer_str.parse::<i64>()?), parsed + 1));
+    if &buffer[end_of_str..total_parsed] != b"\r\n" {
+        return Err(ParseError::Invalid("bulk string not followed by CRLF".to_string()));
     }
 
-    Err(anyhow!("{:?} is an invalid Integer!", buffer))
+    Ok((Value::BulkString(buffer[bytes_consumed..end_of_str].to_vec()), total_parsed))
 }
 
-fn parse_boolean(buffer: BytesMut) -> Result<(Value, usize)> {
-    if let Some((line, parsed)) = read_until_end(&buffer[1..]) {
-        let buffer_str: String = buffer_to_string(line);
-        return Ok((Value::Boolean(buffer_str.parse::<bool>()?), parsed + 1));
+fn parse_integer(buffer: &[u8]) -> ParseResult {
+    let (line, parsed) = read_line(buffer)?;
+
+    match buffer_to_string(line).parse::<i64>() {
+        Ok(integer) => Ok((Value::Integer(integer), parsed)),
+        Err(_) => Err(ParseError::Invalid("invalid integer".to_string()))
     }
+}
 
-    Err(anyhow!("{:?} is an invalid Integer!", buffer))
+fn parse_boolean(buffer: &[u8]) -> ParseResult {
+    let (line, parsed) = read_line(buffer)?;
+    Ok((Value::Boolean(line == b"t"), parsed))
 }
 
-fn parse_array(buffer: BytesMut) -> Result<(Value, usize)> {
-    let (array_length, mut bytes_consumed) = if let Some((line, parsed)) = read_until_end(&buffer[1..]) {
-        let array_size = parse_int(line)?;
-
-        (array_size, parsed + 1)
-    } else {
-        return Err(anyhow!("{:?} is an invalid Array Format!", buffer))
-    };
+fn parse_array(buffer: &[u8]) -> ParseResult {
+    let (line, mut bytes_consumed) = read_line(buffer)?;
+    let array_length = parse_length(line, i32::MAX as usize, "multibulk")?;
 
     let mut array_items: Vec<Value> = Vec::new();
 
     for _ in 0..array_length {
-        let (item, parsed) = parse_message(BytesMut::from(&buffer[bytes_consumed..]))?;
+        let (item, parsed) = parse_message(&buffer[bytes_consumed..])?;
 
         array_items.push(item);
         bytes_consumed += parsed;
@@ -172,23 +189,69 @@ fn parse_array(buffer: BytesMut) -> Result<(Value, usize)> {
     Ok((Value::Array(array_items), bytes_consumed))
 }
 
-fn read_until_end(buffer: &[u8]) -> Option<(&[u8], usize)> {
-    for i in 1..buffer.len() {
-        let previous_char = buffer[i - 1] as char;
-        let cur_char: char = buffer[i] as char;
+/// Reads the line following the type byte, returning it without its CRLF along with the bytes read
+/// up to the end of the CRLF.
+fn read_line(buffer: &[u8]) -> std::result::Result<(&[u8], usize), ParseError> {
+    let searched = &buffer[1..buffer.len().min(MAX_LINE_LENGTH + 3)];
 
-        if cur_char == '\n' && previous_char == '\r' {
-            return Some((&buffer[0..(i - 1)], i + 1));
-        }
+    match searched.windows(2).position(|window| window == b"\r\n") {
+        Some(end) => Ok((&searched[..end], end + 3)),
+        None if buffer.len() > MAX_LINE_LENGTH + 2 => Err(ParseError::Invalid("too big line".to_string())),
+        None => Err(ParseError::Incomplete)
     }
-
-    None
 }
 
 fn buffer_to_string(buffer: &[u8]) -> String {
-    String::from_utf8(buffer.to_vec()).expect("Invalid UTF-8")
+    String::from_utf8_lossy(buffer).into_owned()
+}
+
+fn parse_length(buffer: &[u8], max: usize, kind: &str) -> std::result::Result<usize, ParseError> {
+    match std::str::from_utf8(buffer).ok().and_then(|length| length.parse::<usize>().ok()) {
+        Some(length) if length <= max => Ok(length),
+        _ => Err(ParseError::Invalid(format!("invalid {} length", kind)))
+    }
 }
 
-fn parse_int(buffer: &[u8]) -> Result<usize> {
-    Ok(String::from_utf8(buffer.to_vec()).expect("Invalid UTF-8").parse::<usize>()?)
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_message_cut_anywhere_is_incomplete() {
+        let message = b"*2\r\n$3\r\nGET\r\n$5\r\nk\xffy\r\n\r\n";
+
+        for end in 0..message.len() {
+            assert!(matches!(parse_message(&message[..end]), Err(ParseError::Incomplete)), "cut at {}", end);
+        }
+
+        let (value, parsed) = parse_message(message).unwrap();
+        assert_eq!(parsed, message.len());
+        assert_eq!(value.serialize(), message);
+    }
+
+    #[test]
+    fn malformed_messages_are_invalid() {
+        let malformed: [&[u8]; 6] = [
+            b"GET k\r\n",
+            b"*1\r\n!3\r\nGET\r\n",
+            b"*x\r\n",
+            b"$-5\r\nhello\r\n",
+            b"$3\r\nGETXX",
+            b"$999999999999\r\n"
+        ];
+
+        for message in malformed {
+            assert!(matches!(parse_message(message), Err(ParseError::Invalid(_))), "{:?}", message.escape_ascii().to_string());
+        }
+    }
+
+    #[test]
+    fn a_line_without_its_crlf_is_refused_past_the_limit() {
+        let mut message = b"$".to_vec();
+        message.extend(std::iter::repeat_n(b'1', MAX_LINE_LENGTH));
+        assert!(matches!(parse_message(&message), Err(ParseError::Incomplete)));
+
+        message.extend_from_slice(b"11");
+        assert!(matches!(parse_message(&message), Err(ParseError::Invalid(_))));
+    }
+}
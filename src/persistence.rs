@@ -0,0 +1,188 @@
+use crate::module::ModuleType;
+use crate::parser::Value;
+use crate::rdb::RdbWriter;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long to wait before retrying a background save triggered by a save point that failed.
+const SAVE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// A point in time copy of everything an RDB file stores, so the file can be written without holding the context.
+pub struct Snapshot {
+    pub keys: Vec<(String, Value, Option<SystemTime>)>,
+    pub functions: Vec<String>,
+    pub module_types: HashMap<String, Arc<dyn ModuleType>>,
+    /// Whether the file is the base of an AOF, which the `aof-base` aux field records.
+    pub aof_base: bool
+}
+
+impl Snapshot {
+    pub fn write<W: Write>(&self, writer: W) -> Result<W> {
+        let mut writer = RdbWriter::new(writer);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or_default();
+
+        writer.write_header()?;
+        writer.write_aux("redis-ver", env!("CARGO_PKG_VERSION"))?;
+        writer.write_aux("redis-bits", &(usize::BITS).to_string())?;
+        writer.write_aux("ctime", &now.to_string())?;
+        writer.write_aux("aof-base", if self.aof_base { "1" } else { "0" })?;
+
+        for code in &self.functions {
+            writer.write_function(code)?;
+        }
+
+        let expires = self.keys.iter().filter(|(_, _, expire)| expire.is_some()).count();
+        writer.write_select_db(0, self.keys.len() as u64, expires as u64)?;
+
+        let module_types = |name: &str| self.module_types.get(name).cloned();
+
+        for (key, value, expire) in &self.keys {
+            writer.write_key(key, value, *expire, &module_types)?;
+        }
+
+        writer.finish()
+    }
+
+    /// Writes the snapshot to a temporary file next to `path`, then moves it in place, so a failed
+    /// save never leaves a truncated file behind.
+    pub fn save(&self, dir: &str, file_name: &str) -> Result<()> {
+        let temp_path = format!("{}/temp-{}.rdb", dir, std::process::id());
+
+        let result = File::create(&temp_path)
+            .map_err(|e| anyhow!("Failed opening the temp RDB file {} for saving: {}", temp_path, e))
+            .and_then(|file| self.write(BufWriter::new(file)))
+            .and_then(|writer| Ok(writer.into_inner()?.sync_all()?));
+
+        if let Err(e) = result {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(e);
+        }
+
+        std::fs::rename(&temp_path, format!("{}/{}", dir, file_name))?;
+        Ok(())
+    }
+}
+
+/// A `save <seconds> <changes>` rule: snapshot once `changes` writes happened and `seconds` passed since the last save.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SavePoint {
+    pub seconds: u64,
+    pub changes: u64
+}
+
+/// Parses the `save` config, a list of `<seconds> <changes>` pairs. An empty value disables snapshotting.
+pub fn parse_save_points(config: &str) -> Option<Vec<SavePoint>> {
+    let numbers = config.split_whitespace().map(|number| number.parse::<u64>().ok()).collect::<Option<Vec<_>>>()?;
+
+    if numbers.len() % 2 != 0 {
+        return None;
+    }
+
+    Some(numbers.chunks(2).map(|pair| SavePoint { seconds: pair[0], changes: pair[1] }).collect())
+}
+
+/// The snapshotting state: how much changed since the last save, and the background save in flight.
+pub struct Persistence {
+    /// Writes since the last successful save.
+    pub dirty: u64,
+    pub last_save: SystemTime,
+    last_save_attempt: SystemTime,
+    last_save_failed: bool,
+    background_save: Option<BackgroundSave>,
+    /// Set by BGSAVE SCHEDULE while another save runs, to start one as soon as it is done.
+    pub save_scheduled: bool
+}
+
+struct BackgroundSave {
+    handle: JoinHandle<Result<()>>,
+    dirty_at_start: u64,
+    /// Where the file is written, which a CONFIG SET meanwhile doesn't change.
+    path: PathBuf
+}
+
+impl Persistence {
+    pub fn new() -> Persistence {
+        let now = SystemTime::now();
+
+        Persistence {
+            dirty: 0,
+            last_save: now,
+            last_save_attempt: now,
+            last_save_failed: false,
+            background_save: None,
+            save_scheduled: false
+        }
+    }
+
+    pub fn is_saving(&self) -> bool {
+        self.background_save.is_some()
+    }
+
+    /// Records the outcome of a save that covered every write counted at `dirty_at_start`.
+    pub fn save_done(&mut self, result: &Result<()>, dirty_at_start: u64) {
+        self.last_save_failed = result.is_err();
+
+        match result {
+            Ok(()) => {
+                self.dirty -= dirty_at_start.min(self.dirty);
+                self.last_save = SystemTime::now();
+            }
+
+            Err(e) => println!("Saving the RDB file failed: {}", e)
+        }
+    }
+
+    /// Writes `snapshot` on its own thread.
+    pub fn start_background_save(&mut self, snapshot: Snapshot, dir: String, file_name: String) {
+        self.last_save_attempt = SystemTime::now();
+        self.save_scheduled = false;
+
+        let path = Path::new(&dir).join(&file_name);
+        let handle = std::thread::spawn(move || snapshot.save(&dir, &file_name));
+        self.background_save = Some(BackgroundSave { handle, dirty_at_start: self.dirty, path });
+    }
+
+    /// Collects the background save if it finished, returning the file it wrote or why it failed.
+    pub fn poll_background_save(&mut self) -> Option<Result<PathBuf>> {
+        if !self.background_save.as_ref().is_some_and(|save| save.handle.is_finished()) {
+            return None;
+        }
+
+        let save = self.background_save.take().unwrap();
+        let result = save.handle.join().unwrap_or_else(|_| Err(anyhow!("The background save thread panicked")));
+
+        self.save_done(&result, save.dirty_at_start);
+
+        if result.is_ok() {
+            println!("Background saving terminated with success");
+        }
+
+        Some(result.map(|()| save.path))
+    }
+
+    /// Whether a save point calls for a snapshot now. After a failed save, retries wait a few seconds.
+    pub fn should_save(&self, save_points: &[SavePoint]) -> bool {
+        if self.is_saving() {
+            return false;
+        }
+
+        if self.save_scheduled {
+            return true;
+        }
+
+        let since_save = self.last_save.elapsed().unwrap_or_default();
+        let since_attempt = self.last_save_attempt.elapsed().unwrap_or_default();
+
+        save_points.iter().any(|point| {
+            self.dirty >= point.changes
+                && since_save.as_secs() > point.seconds
+                && (!self.last_save_failed || since_attempt > SAVE_RETRY_DELAY)
+        })
+    }
+}
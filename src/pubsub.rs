@@ -0,0 +1,232 @@
+use crate::client::{Client, ClientId};
+use crate::parser::Value;
+use std::collections::{BTreeSet, HashMap};
+
+/// Index from channels, patterns and shard channels to the clients subscribed to them.
+#[derive(Default)]
+pub struct PubSub {
+    channels: HashMap<String, BTreeSet<ClientId>>,
+    patterns: HashMap<String, BTreeSet<ClientId>>,
+    shard_channels: HashMap<String, BTreeSet<ClientId>>
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SubscriptionKind {
+    Channel,
+    Pattern,
+    Shard
+}
+
+impl PubSub {
+    pub fn new() -> PubSub {
+        PubSub::default()
+    }
+
+    /// Returns whether the client was not already subscribed to `name`.
+    pub fn subscribe(&mut self, kind: SubscriptionKind, name: &str, client_id: ClientId, client: &mut Client) -> bool {
+        self.index_mut(kind).entry(name.to_string()).or_default().insert(client_id);
+        client.subscriptions_mut(kind).insert(name.to_string())
+    }
+
+    pub fn unsubscribe(&mut self, kind: SubscriptionKind, name: &str, client_id: ClientId, client: &mut Client) -> bool {
+        remove_subscriber(self.index_mut(kind), name, client_id);
+        client.subscriptions_mut(kind).remove(name)
+    }
+
+    /// Drops every subscription held by a disconnecting client.
+    pub fn remove_client(&mut self, client_id: ClientId, client: &Client) {
+        for channel in &client.channels {
+            remove_subscriber(&mut self.channels, channel, client_id);
+        }
+
+        for pattern in &client.patterns {
+            remove_subscriber(&mut self.patterns, pattern, client_id);
+        }
+
+        for channel in &client.shard_channels {
+            remove_subscriber(&mut self.shard_channels, channel, client_id);
+        }
+    }
+
+    /// Delivers `message` to channel and pattern subscribers, returning how many receptions occurred.
+    pub fn publish(&self, clients: &HashMap<ClientId, Client>, channel: &str, message: &[u8]) -> usize {
+        let mut receivers = 0;
+
+        if let Some(subscribers) = self.channels.get(channel) {
+            for client in subscribers.iter().filter_map(|client_id| clients.get(client_id)) {
+                client.push(vec![
+                    Value::BulkString("message".into()),
+                    Value::BulkString(channel.to_string().into()),
+                    Value::BulkString(message.to_vec())
+                ]);
+
+                receivers += 1;
+            }
+        }
+
+        for (pattern, subscribers) in self.patterns.iter().filter(|(pattern, _)| glob_match(pattern, channel)) {
+            for client in subscribers.iter().filter_map(|client_id| clients.get(client_id)) {
+                client.push(vec![
+                    Value::BulkString("pmessage".into()),
+                    Value::BulkString(pattern.clone().into()),
+                    Value::BulkString(channel.to_string().into()),
+                    Value::BulkString(message.to_vec())
+                ]);
+
+                receivers += 1;
+            }
+        }
+
+        receivers
+    }
+
+    /// Delivers `message` to the subscribers of a shard channel. Every slot is served locally,
+    /// so unlike PUBLISH this never fans out to pattern subscribers.
+    pub fn spublish(&self, clients: &HashMap<ClientId, Client>, channel: &str, message: &[u8]) -> usize {
+        let Some(subscribers) = self.shard_channels.get(channel) else {
+            return 0;
+        };
+
+        subscribers.iter()
+            .filter_map(|client_id| clients.get(client_id))
+            .map(|client| client.push(vec![
+                Value::BulkString("smessage".into()),
+                Value::BulkString(channel.to_string().into()),
+                Value::BulkString(message.to_vec())
+            ]))
+            .count()
+    }
+
+    /// Active shard channels for `Shard`, active plain channels otherwise.
+    pub fn channels(&self, kind: SubscriptionKind) -> impl Iterator<Item = &str> {
+        let index = match kind {
+            SubscriptionKind::Shard => &self.shard_channels,
+            _ => &self.channels
+        };
+
+        index.keys().map(|channel| channel.as_str())
+    }
+
+    pub fn subscriber_count(&self, kind: SubscriptionKind, channel: &str) -> usize {
+        let index = match kind {
+            SubscriptionKind::Shard => &self.shard_channels,
+            _ => &self.channels
+        };
+
+        index.get(channel).map(|subscribers| subscribers.len()).unwrap_or(0)
+    }
+
+    pub fn pattern_count(&self) -> usize {
+        self.patterns.len()
+    }
+
+    fn index_mut(&mut self, kind: SubscriptionKind) -> &mut HashMap<String, BTreeSet<ClientId>> {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::Shard => &mut self.shard_channels
+        }
+    }
+}
+
+fn remove_subscriber(index: &mut HashMap<String, BTreeSet<ClientId>>, name: &str, client_id: ClientId) {
+    if let Some(subscribers) = index.get_mut(name) {
+        subscribers.remove(&client_id);
+
+        if subscribers.is_empty() {
+            index.remove(name);
+        }
+    }
+}
+
+/// Glob-style matching as used by PSUBSCRIBE: `*`, `?`, `[...]` classes with `^` and ranges, and `\` escapes.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    match_bytes(pattern.as_bytes(), text.as_bytes())
+}
+
+fn match_bytes(mut pattern: &[u8], mut text: &[u8]) -> bool {
+    while let Some(&token) = pattern.first() {
+        match token {
+            b'*' => {
+                while pattern.first() == Some(&b'*') {
+                    pattern = &pattern[1..];
+                }
+
+                if pattern.is_empty() {
+                    return true;
+                }
+
+                return (0..=text.len()).any(|skip| match_bytes(pattern, &text[skip..]));
+            }
+
+            b'?' => {
+                if text.is_empty() {
+                    return false;
+                }
+
+                text = &text[1..];
+                pattern = &pattern[1..];
+            }
+
+            b'[' => {
+                let Some(&current) = text.first() else {
+                    return false;
+                };
+
+                let mut class = &pattern[1..];
+                let negate = class.first() == Some(&b'^');
+
+                if negate {
+                    class = &class[1..];
+                }
+
+                let mut matched = false;
+
+                while let Some(&item) = class.first() {
+                    if item == b']' {
+                        break;
+                    }
+
+                    if item == b'\\' && class.len() >= 2 {
+                        matched |= class[1] == current;
+                        class = &class[2..];
+                    } else if class.len() >= 3 && class[1] == b'-' && class[2] != b']' {
+                        let (start, end) = if item <= class[2] { (item, class[2]) } else { (class[2], item) };
+
+                        matched |= (start..=end).contains(&current);
+                        class = &class[3..];
+                    } else {
+                        matched |= item == current;
+                        class = &class[1..];
+                    }
+                }
+
+                if matched == negate {
+                    return false;
+                }
+
+                // Skip the closing bracket; an unterminated class consumes the rest of the pattern.
+                pattern = class.get(1..).unwrap_or_default();
+                text = &text[1..];
+            }
+
+            _ => {
+                let literal = if token == b'\\' && pattern.len() >= 2 {
+                    pattern = &pattern[1..];
+                    pattern[0]
+                } else {
+                    token
+                };
+
+                if text.first() != Some(&literal) {
+                    return false;
+                }
+
+                text = &text[1..];
+                pattern = &pattern[1..];
+            }
+        }
+    }
+
+    text.is_empty()
+}
@@ -0,0 +1,1637 @@
+use crate::module::ModuleType;
+use crate::parser::Value;
+use crate::sorted_set::SortedSet;
+use crate::stream::{Stream, StreamEntry, StreamId, STREAM_NODE_MAX_ENTRIES};
+use anyhow::{anyhow, Result};
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The RDB format version written by this server, matching Redis 7.2.
+pub const RDB_VERSION: u16 = 11;
+
+/// The newest RDB format version that can be read. Redis 7.4 files are read as long as they
+/// don't use its hash field expiration types.
+const MAX_READ_VERSION: u16 = 12;
+
+pub const OPCODE_SLOT_INFO: u8 = 0xF4;
+pub const OPCODE_FUNCTION: u8 = 0xF5;
+pub const OPCODE_FUNCTION_PRE_GA: u8 = 0xF6;
+pub const OPCODE_MODULE_AUX: u8 = 0xF7;
+pub const OPCODE_IDLE: u8 = 0xF8;
+pub const OPCODE_FREQ: u8 = 0xF9;
+pub const OPCODE_AUX: u8 = 0xFA;
+pub const OPCODE_RESIZEDB: u8 = 0xFB;
+pub const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+pub const OPCODE_EXPIRETIME: u8 = 0xFD;
+pub const OPCODE_SELECTDB: u8 = 0xFE;
+pub const OPCODE_EOF: u8 = 0xFF;
+
+pub const TYPE_STRING: u8 = 0;
+pub const TYPE_LIST: u8 = 1;
+pub const TYPE_SET: u8 = 2;
+pub const TYPE_ZSET: u8 = 3;
+pub const TYPE_HASH: u8 = 4;
+pub const TYPE_ZSET_2: u8 = 5;
+pub const TYPE_MODULE: u8 = 6;
+pub const TYPE_MODULE_2: u8 = 7;
+pub const TYPE_HASH_ZIPMAP: u8 = 9;
+pub const TYPE_LIST_ZIPLIST: u8 = 10;
+pub const TYPE_SET_INTSET: u8 = 11;
+pub const TYPE_ZSET_ZIPLIST: u8 = 12;
+pub const TYPE_HASH_ZIPLIST: u8 = 13;
+pub const TYPE_LIST_QUICKLIST: u8 = 14;
+pub const TYPE_STREAM_LISTPACKS: u8 = 15;
+pub const TYPE_HASH_LISTPACK: u8 = 16;
+pub const TYPE_ZSET_LISTPACK: u8 = 17;
+pub const TYPE_LIST_QUICKLIST_2: u8 = 18;
+pub const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+pub const TYPE_SET_LISTPACK: u8 = 20;
+pub const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+/// Special string encodings, flagged by `0b11` in the top bits of a length.
+const ENCODING_INT8: u8 = 0;
+const ENCODING_INT16: u8 = 1;
+const ENCODING_INT32: u8 = 2;
+const ENCODING_LZF: u8 = 3;
+
+/// Opcodes framing the values saved by module types.
+const MODULE_OPCODE_EOF: u64 = 0;
+const MODULE_OPCODE_SINT: u64 = 1;
+const MODULE_OPCODE_UINT: u64 = 2;
+const MODULE_OPCODE_FLOAT: u64 = 3;
+const MODULE_OPCODE_DOUBLE: u64 = 4;
+const MODULE_OPCODE_STRING: u64 = 5;
+
+/// Quicklist 2 node containers.
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+const QUICKLIST_NODE_PACKED: u64 = 2;
+
+/// Up to how much [`RdbReader::read_bytes`] allocates before seeing the data is really there.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The most an LZF back reference expands: 3 bytes standing for up to 264.
+const LZF_MAX_EXPANSION: usize = 88;
+
+const STREAM_ITEM_FLAG_DELETED: i64 = 1 << 0;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 1 << 1;
+
+/// Characters of a module type name, each packed into 6 bits of the type's id.
+pub const MODULE_TYPE_CHARSET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Finds the module type registered under a name, for loading module values.
+pub type ModuleTypeLookup<'a> = &'a dyn Fn(&str) -> Option<Arc<dyn ModuleType>>;
+
+/// Something found in an RDB file, in file order.
+pub enum RdbItem {
+    Aux(String, String),
+    Function(String),
+    Key {
+        db: u64,
+        key: String,
+        value: Value,
+        expire: Option<SystemTime>
+    }
+}
+
+/// Reads RDB data from any byte source, keeping a running CRC64 of everything read.
+pub struct RdbReader<R: Read> {
+    reader: R,
+    checksum: u64,
+    position: u64,
+    /// Where the item being read by [`RdbReader::read_file`] starts.
+    item_start: u64
+}
+
+enum Length {
+    Length(u64),
+    Encoding(u8)
+}
+
+impl<R: Read> RdbReader<R> {
+    pub fn new(reader: R) -> RdbReader<R> {
+        RdbReader { reader, checksum: 0, position: 0, item_start: 0 }
+    }
+
+    /// How many bytes were read so far. After an error, where the data stopped making sense.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Where the last item [`RdbReader::read_file`] started reading begins, the key of a failed read included.
+    pub fn item_start(&self) -> u64 {
+        self.item_start
+    }
+
+    pub fn read_bytes(&mut self, length: usize) -> Result<Vec<u8>> {
+        // The length comes from the data itself, so a corrupt one only grows the buffer as far as the input goes.
+        let mut buffer = Vec::with_capacity(length.min(READ_CHUNK_SIZE));
+
+        (&mut self.reader).take(length as u64).read_to_end(&mut buffer)
+            .map_err(|e| anyhow!("Failed reading {} bytes at offset {}: {}", length, self.position, e))?;
+
+        if buffer.len() < length {
+            return Err(anyhow!("Unexpected end of file at offset {} reading {} bytes, only {} left", self.position, length, buffer.len()));
+        }
+
+        self.checksum = crc64(self.checksum, &buffer);
+        self.position += length as u64;
+
+        Ok(buffer)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.read_bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    /// Reads one byte, or `None` if the source is exhausted.
+    pub fn try_read_u8(&mut self) -> Result<Option<u8>> {
+        let mut buffer = [0; 1];
+
+        match self.reader.read(&mut buffer)? {
+            0 => Ok(None),
+            _ => {
+                self.checksum = crc64(self.checksum, &buffer);
+                self.position += 1;
+                Ok(Some(buffer[0]))
+            }
+        }
+    }
+
+    /// Reads the 9 byte `REDIS<version>` header, returning the version.
+    pub fn read_header(&mut self) -> Result<u16> {
+        let header = self.read_bytes(9).map_err(|_| anyhow!("Invalid RDB file! File is too short"))?;
+
+        if &header[..5] != b"REDIS" {
+            return Err(anyhow!("Invalid RDB file! Missing Redis Magic String"));
+        }
+
+        let version = std::str::from_utf8(&header[5..]).ok()
+            .and_then(|version| version.parse::<u16>().ok())
+            .ok_or_else(|| anyhow!("Invalid RDB file! Bad version {:?}", String::from_utf8_lossy(&header[5..])))?;
+
+        if version == 0 || version > MAX_READ_VERSION {
+            return Err(anyhow!("Can't handle RDB format version {}", version));
+        }
+
+        Ok(version)
+    }
+
+    fn read_length_or_encoding(&mut self) -> Result<Length> {
+        let first_byte = self.read_u8()?;
+
+        match first_byte >> 6 {
+            0b00 => Ok(Length::Length((first_byte & 0x3F) as u64)),
+            0b01 => Ok(Length::Length((((first_byte & 0x3F) as u64) << 8) | self.read_u8()? as u64)),
+            0b11 => Ok(Length::Encoding(first_byte & 0x3F)),
+            _ => match first_byte {
+                0x80 => Ok(Length::Length(u32::from_be_bytes(self.read_array()?) as u64)),
+                0x81 => Ok(Length::Length(u64::from_be_bytes(self.read_array()?))),
+                _ => Err(anyhow!("Unknown length encoding {:#04x} at offset {}", first_byte, self.position - 1))
+            }
+        }
+    }
+
+    pub fn read_length(&mut self) -> Result<u64> {
+        match self.read_length_or_encoding()? {
+            Length::Length(length) => Ok(length),
+            Length::Encoding(encoding) => Err(anyhow!("Expected a length but found string encoding {} at offset {}", encoding, self.position - 1))
+        }
+    }
+
+    /// Reads a string in any encoding: raw, an integer or LZF compressed.
+    pub fn read_string(&mut self) -> Result<Vec<u8>> {
+        match self.read_length_or_encoding()? {
+            Length::Length(length) => self.read_bytes(length as usize),
+            Length::Encoding(ENCODING_INT8) => Ok(i8::from_le_bytes(self.read_array()?).to_string().into_bytes()),
+            Length::Encoding(ENCODING_INT16) => Ok(i16::from_le_bytes(self.read_array()?).to_string().into_bytes()),
+            Length::Encoding(ENCODING_INT32) => Ok(i32::from_le_bytes(self.read_array()?).to_string().into_bytes()),
+            Length::Encoding(ENCODING_LZF) => {
+                let compressed_length = self.read_length()? as usize;
+                let length = self.read_length()? as usize;
+                let compressed = self.read_bytes(compressed_length)?;
+
+                lzf_decompress(&compressed, length)
+            }
+
+            Length::Encoding(encoding) => Err(anyhow!("Unknown string encoding {} at offset {}", encoding, self.position - 1))
+        }
+    }
+
+    /// Reads a string as text, failing on one that isn't UTF-8.
+    pub fn read_text(&mut self) -> Result<String> {
+        into_text(self.read_string()?)
+    }
+
+    /// Reads an RDB file from the header through the checksum, handing every item found to `visit`.
+    pub fn read_file<F>(&mut self, module_types: ModuleTypeLookup, mut visit: F) -> Result<()>
+    where
+        F: FnMut(RdbItem) -> Result<()>
+    {
+        let version = self.read_header()?;
+
+        let mut db = 0;
+        let mut expire: Option<SystemTime> = None;
+
+        loop {
+            self.item_start = self.position;
+            let opcode = self.read_u8()?;
+
+            match opcode {
+                OPCODE_EOF => break,
+
+                OPCODE_SELECTDB => {
+                    db = self.read_length()?;
+                }
+
+                OPCODE_RESIZEDB => {
+                    self.read_length()?;
+                    self.read_length()?;
+                }
+
+                OPCODE_SLOT_INFO => {
+                    for _ in 0..3 {
+                        self.read_length()?;
+                    }
+                }
+
+                OPCODE_AUX => {
+                    let key = self.read_text()?;
+                    let value = self.read_text()?;
+
+                    visit(RdbItem::Aux(key, value))?;
+                }
+
+                OPCODE_EXPIRETIME => expire = Some(UNIX_EPOCH + Duration::from_secs(u32::from_le_bytes(self.read_array()?) as u64)),
+                OPCODE_EXPIRETIME_MS => expire = Some(UNIX_EPOCH + Duration::from_millis(u64::from_le_bytes(self.read_array()?))),
+
+                // Eviction hints for the next key, which aren't tracked.
+                OPCODE_FREQ => {
+                    self.read_u8()?;
+                }
+
+                OPCODE_IDLE => {
+                    self.read_length()?;
+                }
+
+                OPCODE_FUNCTION => {
+                    let code = String::from_utf8(self.read_string()?).map_err(|_| anyhow!("Invalid UTF-8 sequence in function library"))?;
+                    visit(RdbItem::Function(code))?;
+                }
+
+                OPCODE_FUNCTION_PRE_GA => return Err(anyhow!("Pre-release function format not supported")),
+
+                OPCODE_MODULE_AUX => {
+                    self.read_length()?;
+                    self.read_length()?;
+                    self.read_length()?;
+                    self.skip_module_value()?;
+                }
+
+                value_type => {
+                    let key = self.read_text()?;
+                    let value = self.read_value(value_type, module_types)?;
+
+                    visit(RdbItem::Key { db, key, value, expire: expire.take() })?;
+                }
+            }
+        }
+
+        if version >= 5 {
+            let expected = self.checksum;
+            let checksum = u64::from_le_bytes(self.read_array()?);
+
+            // A zero checksum means the server that wrote the file had checksums disabled.
+            if checksum != 0 && checksum != expected {
+                return Err(anyhow!("Wrong RDB checksum, expected {:016x} but got {:016x}", expected, checksum));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads a value of the given RDB type.
+    pub fn read_value(&mut self, value_type: u8, module_types: ModuleTypeLookup) -> Result<Value> {
+        match value_type {
+            TYPE_STRING => Ok(Value::BulkString(self.read_string()?)),
+
+            TYPE_LIST => {
+                let length = self.read_length()?;
+                Ok(Value::Array((0..length).map(|_| Ok(Value::BulkString(self.read_string()?))).collect::<Result<_>>()?))
+            }
+
+            TYPE_SET => {
+                let length = self.read_length()?;
+                Ok(Value::Set((0..length).map(|_| self.read_text()).collect::<Result<_>>()?))
+            }
+
+            TYPE_ZSET | TYPE_ZSET_2 => {
+                let length = self.read_length()?;
+                let mut sorted_set = SortedSet::new();
+
+                for _ in 0..length {
+                    let member = self.read_text()?;
+
+                    let score = match value_type {
+                        TYPE_ZSET_2 => f64::from_le_bytes(self.read_array()?),
+                        _ => self.read_string_double()?
+                    };
+
+                    sorted_set.insert(&member, score);
+                }
+
+                Ok(Value::SortedSet(sorted_set))
+            }
+
+            TYPE_HASH => {
+                let length = self.read_length()?;
+                Ok(Value::Hash((0..length).map(|_| Ok((self.read_text()?, self.read_text()?))).collect::<Result<_>>()?))
+            }
+
+            TYPE_MODULE => Err(anyhow!("Module values in the pre Redis 4.0 format are not supported")),
+            TYPE_MODULE_2 => self.read_module_value(module_types),
+
+            TYPE_HASH_ZIPMAP => Ok(Value::Hash(pairs(zipmap_entries(&self.read_string()?)?)?.into_iter().collect())),
+            TYPE_LIST_ZIPLIST => Ok(list_value(ziplist_entries(&self.read_string()?)?)),
+            TYPE_SET_INTSET => Ok(Value::Set(intset_entries(&self.read_string()?)?.into_iter().collect())),
+            TYPE_ZSET_ZIPLIST => sorted_set_value(ziplist_entries(&self.read_string()?)?),
+            TYPE_HASH_ZIPLIST => Ok(Value::Hash(pairs(ziplist_entries(&self.read_string()?)?)?.into_iter().collect())),
+
+            TYPE_LIST_QUICKLIST => {
+                let nodes = self.read_length()?;
+                let mut entries = Vec::new();
+
+                for _ in 0..nodes {
+                    entries.extend(ziplist_entries(&self.read_string()?)?);
+                }
+
+                Ok(list_value(entries))
+            }
+
+            TYPE_LIST_QUICKLIST_2 => {
+                let nodes = self.read_length()?;
+                let mut entries = Vec::new();
+
+                for _ in 0..nodes {
+                    match self.read_length()? {
+                        QUICKLIST_NODE_PLAIN => entries.push(self.read_string()?),
+                        QUICKLIST_NODE_PACKED => entries.extend(listpack_entries(&self.read_string()?)?),
+                        container => return Err(anyhow!("Unknown quicklist node container {}", container))
+                    }
+                }
+
+                Ok(list_value(entries))
+            }
+
+            TYPE_HASH_LISTPACK => Ok(Value::Hash(pairs(listpack_entries(&self.read_string()?)?)?.into_iter().collect())),
+            TYPE_ZSET_LISTPACK => sorted_set_value(listpack_entries(&self.read_string()?)?),
+            TYPE_SET_LISTPACK => Ok(Value::Set(listpack_entries(&self.read_string()?)?.into_iter().map(into_text).collect::<Result<_>>()?)),
+
+            TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => Ok(Value::Stream(self.read_stream(value_type)?)),
+
+            _ => Err(anyhow!("Unknown RDB value type {} at offset {}", value_type, self.position))
+        }
+    }
+
+    /// Reads a score saved as text, where the lengths 253 to 255 stand for NaN and the infinities.
+    fn read_string_double(&mut self) -> Result<f64> {
+        match self.read_u8()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            length => {
+                let text = self.read_bytes(length as usize)?;
+
+                std::str::from_utf8(&text).ok()
+                    .and_then(|text| text.parse().ok())
+                    .ok_or_else(|| anyhow!("Invalid score {:?}", String::from_utf8_lossy(&text)))
+            }
+        }
+    }
+
+    fn read_stream_id(&mut self) -> Result<StreamId> {
+        Ok(StreamId::new(self.read_length()?, self.read_length()?))
+    }
+
+    fn read_raw_stream_id(&mut self) -> Result<StreamId> {
+        raw_stream_id(&self.read_bytes(16)?)
+    }
+
+    fn read_stream(&mut self, value_type: u8) -> Result<Stream> {
+        let mut stream = Stream::new();
+        let nodes = self.read_length()?;
+
+        for _ in 0..nodes {
+            let master_id = raw_stream_id(&self.read_string()?)?;
+            read_stream_node(&mut stream, master_id, &listpack_entries(&self.read_string()?)?)?;
+        }
+
+        let _length = self.read_length()?;
+        let last_id = self.read_stream_id()?;
+
+        let (max_deleted_entry_id, entries_added) = if value_type >= TYPE_STREAM_LISTPACKS_2 {
+            let _first_id = self.read_stream_id()?;
+            (self.read_stream_id()?, self.read_length()?)
+        } else {
+            (StreamId::MIN, stream.len() as u64)
+        };
+
+        stream.set_id(last_id, Some(entries_added), Some(max_deleted_entry_id));
+
+        let groups = self.read_length()?;
+
+        for _ in 0..groups {
+            let name = self.read_text()?;
+            let last_delivered_id = self.read_stream_id()?;
+
+            // An unknown counter is saved as -1.
+            let entries_read = match value_type >= TYPE_STREAM_LISTPACKS_2 {
+                true => Some(self.read_length()?).filter(|entries_read| *entries_read != u64::MAX),
+                false => None
+            };
+
+            stream.create_group(&name, last_delivered_id, entries_read);
+            let group = stream.group_mut(&name).unwrap();
+
+            let pending = self.read_length()?;
+
+            for _ in 0..pending {
+                let id = self.read_raw_stream_id()?;
+                let delivery_time = u64::from_le_bytes(self.read_array()?);
+                let delivery_count = self.read_length()?;
+
+                group.restore_pending(id, delivery_time, delivery_count);
+            }
+
+            let consumers = self.read_length()?;
+
+            for _ in 0..consumers {
+                let consumer = self.read_text()?;
+                let seen_time = u64::from_le_bytes(self.read_array()?);
+
+                let active_time = match value_type >= TYPE_STREAM_LISTPACKS_3 {
+                    true => Some(u64::from_le_bytes(self.read_array()?)).filter(|active_time| *active_time != u64::MAX),
+                    false => None
+                };
+
+                let owned = self.read_length()?;
+                let owned = (0..owned).map(|_| self.read_raw_stream_id()).collect::<Result<Vec<_>>>()?;
+
+                if !group.restore_consumer(&consumer, seen_time, active_time, owned) {
+                    return Err(anyhow!("Consumer {} of group {} owns an entry missing from the group's pending list", consumer, name));
+                }
+            }
+        }
+
+        Ok(stream)
+    }
+
+    fn read_module_value(&mut self, module_types: ModuleTypeLookup) -> Result<Value> {
+        let (type_name, encoding_version) = decode_module_type_id(self.read_length()?);
+
+        let module_type = module_types(&type_name).ok_or_else(|| anyhow!(
+            "The RDB file contains module data for the module type '{}', that the responsible module is not able to load", type_name
+        ))?;
+
+        // Values are saved as one string holding what `ModuleType::rdb_save` returned.
+        let payload = match self.read_length()? {
+            MODULE_OPCODE_STRING => self.read_string()?,
+            opcode => return Err(anyhow!("Module value of type {} starts with unsupported opcode {}", type_name, opcode))
+        };
+
+        if self.read_length()? != MODULE_OPCODE_EOF {
+            return Err(anyhow!("Module value of type {} holds more than one string", type_name));
+        }
+
+        Ok(Value::Module(module_type.rdb_load(&payload, encoding_version)?))
+    }
+
+    /// Skips module data up to its end opcode, without needing the module to understand it.
+    fn skip_module_value(&mut self) -> Result<()> {
+        loop {
+            match self.read_length()? {
+                MODULE_OPCODE_EOF => return Ok(()),
+                MODULE_OPCODE_SINT | MODULE_OPCODE_UINT => {
+                    self.read_length()?;
+                }
+
+                MODULE_OPCODE_FLOAT => {
+                    self.read_bytes(4)?;
+                }
+
+                MODULE_OPCODE_DOUBLE => {
+                    self.read_bytes(8)?;
+                }
+
+                MODULE_OPCODE_STRING => {
+                    self.read_string()?;
+                }
+
+                opcode => return Err(anyhow!("Unknown module opcode {}", opcode))
+            }
+        }
+    }
+}
+
+/// Adds the entries of one stream listpack node. The node starts with a master entry holding the
+/// fields most entries share, then every entry stores its id as a delta from the node's id.
+fn read_stream_node(stream: &mut Stream, master_id: StreamId, entries: &[Vec<u8>]) -> Result<()> {
+    let mut entries = entries.iter();
+    let mut next = || entries.next().ok_or_else(|| anyhow!("Truncated stream listpack"));
+
+    let count = parse_int(next()?)? as usize;
+    let deleted = parse_int(next()?)? as usize;
+    let master_field_count = parse_int(next()?)? as usize;
+
+    let master_fields = (0..master_field_count).map(|_| into_text(next()?.clone())).collect::<Result<Vec<_>>>()?;
+
+    // The master entry ends with a zero terminator.
+    next()?;
+
+    for _ in 0..count + deleted {
+        let flags = parse_int(next()?)?;
+        let id = StreamId::new(
+            master_id.millis.wrapping_add(parse_int(next()?)? as u64),
+            master_id.seq.wrapping_add(parse_int(next()?)? as u64)
+        );
+
+        let fields: Vec<(String, String)> = if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            master_fields.iter().map(|field| Ok((field.clone(), into_text(next()?.clone())?))).collect::<Result<_>>()?
+        } else {
+            let field_count = parse_int(next()?)?;
+            (0..field_count).map(|_| Ok((into_text(next()?.clone())?, into_text(next()?.clone())?))).collect::<Result<_>>()?
+        };
+
+        // Every entry ends with the number of listpack elements it used, for walking the node backwards.
+        next()?;
+
+        if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+            stream.append(id, fields);
+        }
+    }
+
+    Ok(())
+}
+
+fn raw_stream_id(bytes: &[u8]) -> Result<StreamId> {
+    if bytes.len() != 16 {
+        return Err(anyhow!("Invalid stream id of {} bytes", bytes.len()));
+    }
+
+    Ok(StreamId::new(u64::from_be_bytes(bytes[..8].try_into()?), u64::from_be_bytes(bytes[8..].try_into()?)))
+}
+
+/// Keys, members and fields are held as text, unlike string values, so one that isn't UTF-8 can't be
+/// loaded without corrupting it.
+fn into_text(bytes: Vec<u8>) -> Result<String> {
+    String::from_utf8(bytes)
+        .map_err(|e| anyhow!("Can't load the non UTF-8 string {:?} until keys, members and fields are byte-based", String::from_utf8_lossy(e.as_bytes())))
+}
+
+fn parse_int(bytes: &[u8]) -> Result<i64> {
+    std::str::from_utf8(bytes).ok()
+        .and_then(|text| text.parse().ok())
+        .ok_or_else(|| anyhow!("Expected an integer, got {:?}", String::from_utf8_lossy(bytes)))
+}
+
+fn list_value(entries: Vec<Vec<u8>>) -> Value {
+    Value::Array(entries.into_iter().map(Value::BulkString).collect())
+}
+
+fn pairs(entries: Vec<Vec<u8>>) -> Result<Vec<(String, String)>> {
+    if !entries.len().is_multiple_of(2) {
+        return Err(anyhow!("Expected field and value pairs but got {} elements", entries.len()));
+    }
+
+    let mut entries = entries.into_iter().map(into_text).collect::<Result<Vec<_>>>()?.into_iter();
+    Ok(std::iter::from_fn(|| Some((entries.next()?, entries.next()?))).collect())
+}
+
+fn sorted_set_value(entries: Vec<Vec<u8>>) -> Result<Value> {
+    let mut sorted_set = SortedSet::new();
+
+    for (member, score) in pairs(entries)? {
+        let score = score.parse::<f64>().map_err(|_| anyhow!("Invalid score {:?}", score))?;
+        sorted_set.insert(&member, score);
+    }
+
+    Ok(Value::SortedSet(sorted_set))
+}
+
+/// Bounds checked reads over an encoded blob.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> Cursor<'a> {
+    fn new(bytes: &'a [u8], position: usize) -> Cursor<'a> {
+        Cursor { bytes, position }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        let bytes = self.position.checked_add(length)
+            .and_then(|end| self.bytes.get(self.position..end))
+            .ok_or_else(|| anyhow!("Truncated encoding"))?;
+
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn peek(&self) -> Result<u8> {
+        self.bytes.get(self.position).copied().ok_or_else(|| anyhow!("Truncated encoding"))
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+}
+
+/// Decodes a listpack, turning integer entries into their decimal text.
+fn listpack_entries(bytes: &[u8]) -> Result<Vec<Vec<u8>>> {
+    // Skips the total size (4 bytes) and element count (2 bytes) header.
+    let mut cursor = Cursor::new(bytes, 6);
+    let mut entries = Vec::new();
+
+    loop {
+        let start = cursor.position;
+        let encoding = cursor.u8()?;
+
+        let entry = match encoding {
+            0xFF => return Ok(entries),
+            _ if encoding & 0x80 == 0 => (encoding as i64).to_string().into_bytes(),
+            _ if encoding & 0xC0 == 0x80 => cursor.take((encoding & 0x3F) as usize)?.to_vec(),
+            _ if encoding & 0xE0 == 0xC0 => {
+                let value = (((encoding & 0x1F) as i64) << 8) | cursor.u8()? as i64;
+
+                // A 13 bit two's complement integer.
+                let value = if value >= 1 << 12 { value - (1 << 13) } else { value };
+                value.to_string().into_bytes()
+            }
+
+            _ if encoding & 0xF0 == 0xE0 => {
+                let length = (((encoding & 0x0F) as usize) << 8) | cursor.u8()? as usize;
+                cursor.take(length)?.to_vec()
+            }
+
+            0xF0 => {
+                let length = u32::from_le_bytes(cursor.array()?) as usize;
+                cursor.take(length)?.to_vec()
+            }
+
+            0xF1 => i16::from_le_bytes(cursor.array()?).to_string().into_bytes(),
+            0xF2 => {
+                let [low, middle, high] = cursor.array()?;
+                (i32::from_le_bytes([0, low, middle, high]) >> 8).to_string().into_bytes()
+            }
+
+            0xF3 => i32::from_le_bytes(cursor.array()?).to_string().into_bytes(),
+            0xF4 => i64::from_le_bytes(cursor.array()?).to_string().into_bytes(),
+            _ => return Err(anyhow!("Unknown listpack encoding {:#04x}", encoding))
+        };
+
+        // Each entry is followed by its own length, in 7 bit groups, for walking backwards.
+        let entry_length = cursor.position - start;
+        cursor.take(backlen_size(entry_length))?;
+
+        entries.push(entry);
+    }
+}
+
+/// Decodes a ziplist, the encoding listpacks replaced in Redis 7.
+fn ziplist_entries(bytes: &[u8]) -> Result<Vec<Vec<u8>>> {
+    // Skips the total size (4 bytes), tail offset (4 bytes) and element count (2 bytes) header.
+    let mut cursor = Cursor::new(bytes, 10);
+    let mut entries = Vec::new();
+
+    while cursor.peek()? != 0xFF {
+        // The previous entry's length, in 1 byte or 0xFE followed by 4.
+        if cursor.u8()? == 0xFE {
+            cursor.take(4)?;
+        }
+
+        let encoding = cursor.u8()?;
+
+        let entry = match encoding >> 6 {
+            0b00 => cursor.take((encoding & 0x3F) as usize)?.to_vec(),
+            0b01 => {
+                let length = (((encoding & 0x3F) as usize) << 8) | cursor.u8()? as usize;
+                cursor.take(length)?.to_vec()
+            }
+
+            0b10 => {
+                let length = u32::from_be_bytes(cursor.array()?) as usize;
+                cursor.take(length)?.to_vec()
+            }
+
+            _ => {
+                let value = match encoding {
+                    0xC0 => i16::from_le_bytes(cursor.array()?) as i64,
+                    0xD0 => i32::from_le_bytes(cursor.array()?) as i64,
+                    0xE0 => i64::from_le_bytes(cursor.array()?),
+                    0xF0 => {
+                        let [low, middle, high] = cursor.array()?;
+                        (i32::from_le_bytes([0, low, middle, high]) >> 8) as i64
+                    }
+
+                    0xFE => i8::from_le_bytes(cursor.array()?) as i64,
+                    0xF1..=0xFD => (encoding & 0x0F) as i64 - 1,
+                    _ => return Err(anyhow!("Unknown ziplist encoding {:#04x}", encoding))
+                };
+
+                value.to_string().into_bytes()
+            }
+        };
+
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+fn intset_entries(bytes: &[u8]) -> Result<Vec<String>> {
+    let mut cursor = Cursor::new(bytes, 0);
+    let width = u32::from_le_bytes(cursor.array()?);
+    let length = u32::from_le_bytes(cursor.array()?);
+
+    (0..length)
+        .map(|_| Ok(match width {
+            2 => i16::from_le_bytes(cursor.array()?) as i64,
+            4 => i32::from_le_bytes(cursor.array()?) as i64,
+            8 => i64::from_le_bytes(cursor.array()?),
+            _ => return Err(anyhow!("Invalid intset encoding {}", width))
+        }.to_string()))
+        .collect()
+}
+
+/// Decodes a zipmap, the hash encoding used before Redis 2.6.
+fn zipmap_entries(bytes: &[u8]) -> Result<Vec<Vec<u8>>> {
+    // Skips the entry count, which saturates at 254.
+    let mut cursor = Cursor::new(bytes, 1);
+    let mut entries = Vec::new();
+
+    let read_length = |cursor: &mut Cursor| -> Result<usize> {
+        match cursor.u8()? {
+            254 => Ok(u32::from_le_bytes(cursor.array()?) as usize),
+            255 => Err(anyhow!("Unexpected end of zipmap")),
+            length => Ok(length as usize)
+        }
+    };
+
+    while cursor.peek()? != 0xFF {
+        let key_length = read_length(&mut cursor)?;
+        entries.push(cursor.take(key_length)?.to_vec());
+
+        let value_length = read_length(&mut cursor)?;
+        let free = cursor.u8()? as usize;
+
+        entries.push(cursor.take(value_length)?.to_vec());
+        cursor.take(free)?;
+    }
+
+    Ok(entries)
+}
+
+fn lzf_decompress(input: &[u8], length: usize) -> Result<Vec<u8>> {
+    let mut output: Vec<u8> = Vec::with_capacity(length.min(input.len().saturating_mul(LZF_MAX_EXPANSION)));
+    let mut cursor = Cursor::new(input, 0);
+
+    while cursor.position < input.len() {
+        let control = cursor.u8()? as usize;
+
+        if control < 32 {
+            output.extend_from_slice(cursor.take(control + 1)?);
+            continue;
+        }
+
+        let mut run = control >> 5;
+
+        if run == 7 {
+            run += cursor.u8()? as usize;
+        }
+
+        let distance = ((control & 0x1F) << 8) + cursor.u8()? as usize + 1;
+        let start = output.len().checked_sub(distance).ok_or_else(|| anyhow!("Invalid LZF back reference"))?;
+
+        // The copy may overlap what it produces, so it goes byte by byte.
+        for index in start..start + run + 2 {
+            output.push(output[index]);
+        }
+    }
+
+    if output.len() != length {
+        return Err(anyhow!("LZF data decompressed to {} bytes instead of {}", output.len(), length));
+    }
+
+    Ok(output)
+}
+
+/// How many bytes a listpack entry's trailing length takes, for an entry of `length` bytes.
+fn backlen_size(length: usize) -> usize {
+    match length {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5
+    }
+}
+
+/// Builds a listpack, storing integer-looking elements in their integer encodings like Redis does.
+#[derive(Default)]
+struct Listpack {
+    entries: Vec<u8>,
+    count: usize
+}
+
+impl Listpack {
+    fn push(&mut self, element: &[u8]) {
+        let start = self.entries.len();
+
+        match canonical_int(element) {
+            Some(value @ 0..=127) => self.entries.push(value as u8),
+            Some(value @ -4096..=4095) => {
+                let value = value as u16 & 0x1FFF;
+                self.entries.extend_from_slice(&[0xC0 | (value >> 8) as u8, value as u8]);
+            }
+
+            Some(value) if i16::try_from(value).is_ok() => {
+                self.entries.push(0xF1);
+                self.entries.extend_from_slice(&(value as i16).to_le_bytes());
+            }
+
+            Some(value @ -8388608..=8388607) => {
+                self.entries.push(0xF2);
+                self.entries.extend_from_slice(&(value as i32).to_le_bytes()[..3]);
+            }
+
+            Some(value) if i32::try_from(value).is_ok() => {
+                self.entries.push(0xF3);
+                self.entries.extend_from_slice(&(value as i32).to_le_bytes());
+            }
+
+            Some(value) => {
+                self.entries.push(0xF4);
+                self.entries.extend_from_slice(&value.to_le_bytes());
+            }
+
+            None => {
+                match element.len() {
+                    length @ 0..=63 => self.entries.push(0x80 | length as u8),
+                    length @ 64..=4095 => self.entries.extend_from_slice(&[0xE0 | (length >> 8) as u8, length as u8]),
+                    length => {
+                        self.entries.push(0xF0);
+                        self.entries.extend_from_slice(&(length as u32).to_le_bytes());
+                    }
+                }
+
+                self.entries.extend_from_slice(element);
+            }
+        }
+
+        // The entry's length in 7 bit groups, most significant first, every byte but the first flagged.
+        let length = self.entries.len() - start;
+        let size = backlen_size(length);
+
+        for index in (0..size).rev() {
+            let group = ((length >> (7 * index)) & 0x7F) as u8;
+            self.entries.push(if index == size - 1 { group } else { group | 0x80 });
+        }
+
+        self.count += 1;
+    }
+
+    fn push_int(&mut self, value: i64) {
+        self.push(value.to_string().as_bytes());
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.entries.len() + 7);
+
+        bytes.extend_from_slice(&(self.entries.len() as u32 + 7).to_le_bytes());
+        bytes.extend_from_slice(&(self.count.min(u16::MAX as usize) as u16).to_le_bytes());
+        bytes.extend_from_slice(&self.entries);
+        bytes.push(0xFF);
+
+        bytes
+    }
+}
+
+/// Parses a string holding an integer in its shortest decimal form, so storing it as an integer loses nothing.
+fn canonical_int(bytes: &[u8]) -> Option<i64> {
+    if bytes.is_empty() || bytes.len() > 20 {
+        return None;
+    }
+
+    let value = std::str::from_utf8(bytes).ok()?.parse::<i64>().ok()?;
+    (value.to_string().as_bytes() == bytes).then_some(value)
+}
+
+/// Compresses `input` in the LZF format, or returns `None` unless that saves at least 4 bytes.
+fn lzf_compress(input: &[u8]) -> Option<Vec<u8>> {
+    const HASH_BITS: u32 = 14;
+    const MAX_OFFSET: usize = 1 << 13;
+    const MAX_MATCH: usize = 264;
+    const MAX_LITERAL: usize = 32;
+
+    let limit = input.len().checked_sub(4)?;
+    let mut output = Vec::with_capacity(input.len());
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let mut literal_start = 0;
+    let mut position = 0;
+
+    let flush_literals = |output: &mut Vec<u8>, literals: &[u8]| {
+        for chunk in literals.chunks(MAX_LITERAL) {
+            output.push(chunk.len() as u8 - 1);
+            output.extend_from_slice(chunk);
+        }
+    };
+
+    while position + 2 < input.len() {
+        let hash = (u32::from_le_bytes([input[position], input[position + 1], input[position + 2], 0]).wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize;
+        let candidate = std::mem::replace(&mut table[hash], position);
+
+        let matches = candidate != usize::MAX
+            && position - candidate <= MAX_OFFSET
+            && input[candidate..candidate + 3] == input[position..position + 3];
+
+        if !matches {
+            position += 1;
+            continue;
+        }
+
+        flush_literals(&mut output, &input[literal_start..position]);
+
+        let max_length = MAX_MATCH.min(input.len() - position);
+        let length = 3 + (3..max_length).take_while(|&offset| input[candidate + offset] == input[position + offset]).count();
+        let offset = position - candidate - 1;
+        let run = length - 2;
+
+        if run < 7 {
+            output.push(((run << 5) | (offset >> 8)) as u8);
+        } else {
+            output.push(((7 << 5) | (offset >> 8)) as u8);
+            output.push((run - 7) as u8);
+        }
+
+        output.push(offset as u8);
+
+        position += length;
+        literal_start = position;
+
+        if output.len() > limit {
+            return None;
+        }
+    }
+
+    flush_literals(&mut output, &input[literal_start..]);
+    (output.len() <= limit).then_some(output)
+}
+
+/// Writes RDB data to any byte sink, keeping a running CRC64 for the trailing checksum.
+pub struct RdbWriter<W: Write> {
+    writer: W,
+    checksum: u64
+}
+
+impl<W: Write> RdbWriter<W> {
+    pub fn new(writer: W) -> RdbWriter<W> {
+        RdbWriter { writer, checksum: 0 }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer.write_all(bytes)?;
+        self.checksum = crc64(self.checksum, bytes);
+
+        Ok(())
+    }
+
+    pub fn write_u8(&mut self, byte: u8) -> Result<()> {
+        self.write_bytes(&[byte])
+    }
+
+    pub fn write_length(&mut self, length: u64) -> Result<()> {
+        let mut buffer = Vec::with_capacity(9);
+        write_length(&mut buffer, length);
+
+        self.write_bytes(&buffer)
+    }
+
+    /// Writes a string the most compact way: as an integer when it holds one, LZF compressed when
+    /// it is long enough for that to pay off, or else raw.
+    pub fn write_string(&mut self, string: &[u8]) -> Result<()> {
+        if string.len() <= 11 {
+            match canonical_int(string) {
+                Some(value) if i8::try_from(value).is_ok() => return self.write_bytes(&[0xC0 | ENCODING_INT8, value as u8]),
+                Some(value) if i16::try_from(value).is_ok() => {
+                    self.write_u8(0xC0 | ENCODING_INT16)?;
+                    return self.write_bytes(&(value as i16).to_le_bytes());
+                }
+
+                Some(value) if i32::try_from(value).is_ok() => {
+                    self.write_u8(0xC0 | ENCODING_INT32)?;
+                    return self.write_bytes(&(value as i32).to_le_bytes());
+                }
+
+                _ => {}
+            }
+        }
+
+        if string.len() > 20 {
+            if let Some(compressed) = lzf_compress(string) {
+                self.write_u8(0xC0 | ENCODING_LZF)?;
+                self.write_length(compressed.len() as u64)?;
+                self.write_length(string.len() as u64)?;
+                return self.write_bytes(&compressed);
+            }
+        }
+
+        self.write_length(string.len() as u64)?;
+        self.write_bytes(string)
+    }
+
+    pub fn write_header(&mut self) -> Result<()> {
+        self.write_bytes(format!("REDIS{:04}", RDB_VERSION).as_bytes())
+    }
+
+    pub fn write_aux(&mut self, key: &str, value: &str) -> Result<()> {
+        self.write_u8(OPCODE_AUX)?;
+        self.write_string(key.as_bytes())?;
+        self.write_string(value.as_bytes())
+    }
+
+    pub fn write_function(&mut self, code: &str) -> Result<()> {
+        self.write_u8(OPCODE_FUNCTION)?;
+        self.write_string(code.as_bytes())
+    }
+
+    pub fn write_select_db(&mut self, db: u64, keys: u64, expires: u64) -> Result<()> {
+        self.write_u8(OPCODE_SELECTDB)?;
+        self.write_length(db)?;
+        self.write_u8(OPCODE_RESIZEDB)?;
+        self.write_length(keys)?;
+        self.write_length(expires)
+    }
+
+    pub fn write_key(&mut self, key: &str, value: &Value, expire: Option<SystemTime>, module_types: ModuleTypeLookup) -> Result<()> {
+        if let Some(expire) = expire {
+            let millis = expire.duration_since(UNIX_EPOCH).map(|duration| duration.as_millis() as u64).unwrap_or_default();
+
+            self.write_u8(OPCODE_EXPIRETIME_MS)?;
+            self.write_bytes(&millis.to_le_bytes())?;
+        }
+
+        self.write_u8(value_type(value))?;
+        self.write_string(key.as_bytes())?;
+        self.write_value(value, module_types)
+    }
+
+    /// Writes a value in the encoding [`value_type`] picks for it.
+    pub fn write_value(&mut self, value: &Value, module_types: ModuleTypeLookup) -> Result<()> {
+        match value {
+            Value::Array(elements) => {
+                self.write_length(elements.len() as u64)?;
+
+                for element in elements {
+                    self.write_string(&element.clone().unpack_as_bytes().unwrap_or_default())?;
+                }
+
+                Ok(())
+            }
+
+            Value::Set(members) => {
+                self.write_length(members.len() as u64)?;
+                members.iter().try_for_each(|member| self.write_string(member.as_bytes()))
+            }
+
+            Value::Hash(fields) => {
+                self.write_length(fields.len() as u64)?;
+                fields.iter().try_for_each(|(field, value)| {
+                    self.write_string(field.as_bytes())?;
+                    self.write_string(value.as_bytes())
+                })
+            }
+
+            Value::SortedSet(sorted_set) => {
+                let members: Vec<(&str, f64)> = sorted_set.iter().collect();
+                self.write_length(members.len() as u64)?;
+
+                for (member, score) in members {
+                    self.write_string(member.as_bytes())?;
+                    self.write_bytes(&score.to_le_bytes())?;
+                }
+
+                Ok(())
+            }
+
+            Value::Stream(stream) => self.write_stream(stream),
+
+            Value::Module(module_value) => {
+                let module_type = module_types(module_value.type_name())
+                    .ok_or_else(|| anyhow!("No module provides the type {}", module_value.type_name()))?;
+
+                self.write_length(encode_module_type_id(module_type.name(), module_type.encoding_version()))?;
+                self.write_length(MODULE_OPCODE_STRING)?;
+                self.write_string(&module_type.rdb_save(module_value))?;
+                self.write_length(MODULE_OPCODE_EOF)
+            }
+
+            value => self.write_string(&value.clone().unpack_as_bytes().unwrap_or_default())
+        }
+    }
+
+    fn write_stream_id(&mut self, id: StreamId) -> Result<()> {
+        self.write_length(id.millis)?;
+        self.write_length(id.seq)
+    }
+
+    fn write_raw_stream_id(&mut self, id: StreamId) -> Result<()> {
+        self.write_bytes(&raw_stream_id_bytes(id))
+    }
+
+    /// Writes a stream in the Redis 7.2 layout: listpack nodes of up to 100 entries, each storing its
+    /// entries as deltas from its first entry, then the stream's metadata and its consumer groups.
+    fn write_stream(&mut self, stream: &Stream) -> Result<()> {
+        let entries: Vec<StreamEntry> = stream.range(StreamId::MIN, StreamId::MAX).collect();
+        let nodes: Vec<&[StreamEntry]> = entries.chunks(STREAM_NODE_MAX_ENTRIES).collect();
+
+        self.write_length(nodes.len() as u64)?;
+
+        for node in nodes {
+            let master_id = node[0].id;
+            let master_fields: Vec<&str> = node[0].fields.iter().map(|(field, _)| field.as_str()).collect();
+
+            let mut listpack = Listpack::default();
+            listpack.push_int(node.len() as i64);
+            listpack.push_int(0);
+            listpack.push_int(master_fields.len() as i64);
+
+            for field in &master_fields {
+                listpack.push(field.as_bytes());
+            }
+
+            listpack.push_int(0);
+
+            for entry in node {
+                let same_fields = entry.fields.len() == master_fields.len()
+                    && entry.fields.iter().zip(&master_fields).all(|((field, _), master)| field == master);
+
+                listpack.push_int(if same_fields { STREAM_ITEM_FLAG_SAMEFIELDS } else { 0 });
+                listpack.push_int(entry.id.millis.wrapping_sub(master_id.millis) as i64);
+                listpack.push_int(entry.id.seq.wrapping_sub(master_id.seq) as i64);
+
+                if same_fields {
+                    for (_, value) in &entry.fields {
+                        listpack.push(value.as_bytes());
+                    }
+
+                    listpack.push_int(entry.fields.len() as i64 + 3);
+                } else {
+                    listpack.push_int(entry.fields.len() as i64);
+
+                    for (field, value) in &entry.fields {
+                        listpack.push(field.as_bytes());
+                        listpack.push(value.as_bytes());
+                    }
+
+                    listpack.push_int(entry.fields.len() as i64 * 2 + 4);
+                }
+            }
+
+            self.write_string(&raw_stream_id_bytes(master_id))?;
+            self.write_string(&listpack.into_bytes())?;
+        }
+
+        self.write_length(stream.len() as u64)?;
+        self.write_stream_id(stream.last_id())?;
+        self.write_stream_id(stream.first_id().unwrap_or(StreamId::MIN))?;
+        self.write_stream_id(stream.max_deleted_entry_id())?;
+        self.write_length(stream.entries_added())?;
+
+        self.write_length(stream.groups().len() as u64)?;
+
+        for (name, group) in stream.groups() {
+            self.write_string(name.as_bytes())?;
+            self.write_stream_id(group.last_delivered_id)?;
+            self.write_length(group.entries_read.unwrap_or(u64::MAX))?;
+
+            self.write_length(group.pending().len() as u64)?;
+
+            for (id, pending) in group.pending() {
+                self.write_raw_stream_id(*id)?;
+                self.write_bytes(&pending.delivery_time.to_le_bytes())?;
+                self.write_length(pending.delivery_count)?;
+            }
+
+            self.write_length(group.consumers().len() as u64)?;
+
+            for (name, consumer) in group.consumers() {
+                self.write_string(name.as_bytes())?;
+                self.write_bytes(&consumer.seen_time.to_le_bytes())?;
+                self.write_bytes(&consumer.active_time.unwrap_or(u64::MAX).to_le_bytes())?;
+
+                self.write_length(consumer.pending.len() as u64)?;
+
+                for id in &consumer.pending {
+                    self.write_raw_stream_id(*id)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ends the file with the EOF opcode and the checksum of everything before it.
+    pub fn finish(mut self) -> Result<W> {
+        self.write_u8(OPCODE_EOF)?;
+
+        let checksum = self.checksum;
+        self.write_bytes(&checksum.to_le_bytes())?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+/// The RDB type a value is written as. Lists, sets and hashes use their plain encodings, which every
+/// Redis version reads, rather than the compact ones Redis picks for small values.
+pub fn value_type(value: &Value) -> u8 {
+    match value {
+        Value::Array(_) => TYPE_LIST,
+        Value::Set(_) => TYPE_SET,
+        Value::Hash(_) => TYPE_HASH,
+        Value::SortedSet(_) => TYPE_ZSET_2,
+        Value::Stream(_) => TYPE_STREAM_LISTPACKS_3,
+        Value::Module(_) => TYPE_MODULE_2,
+        _ => TYPE_STRING
+    }
+}
+
+fn raw_stream_id_bytes(id: StreamId) -> [u8; 16] {
+    let mut bytes = [0; 16];
+
+    bytes[..8].copy_from_slice(&id.millis.to_be_bytes());
+    bytes[8..].copy_from_slice(&id.seq.to_be_bytes());
+    bytes
+}
+
+/// Packs a module type's 9 character name and encoding version into its 64 bit id.
+fn encode_module_type_id(name: &str, encoding_version: u16) -> u64 {
+    let name_bits = name.chars().fold(0, |id, char| (id << 6) | MODULE_TYPE_CHARSET.find(char).unwrap_or(0) as u64);
+    (name_bits << 10) | (encoding_version & 0x3FF) as u64
+}
+
+/// Splits a module type id into the type's 9 character name and the encoding version in its low 10 bits.
+pub fn decode_module_type_id(id: u64) -> (String, u16) {
+    let charset = MODULE_TYPE_CHARSET.as_bytes();
+    let name = (0..9).map(|index| charset[((id >> (10 + 6 * (8 - index))) & 0x3F) as usize] as char).collect();
+
+    (name, (id & 0x3FF) as u16)
+}
+
+pub fn write_length(buffer: &mut Vec<u8>, length: u64) {
+    match length {
+        0..=0x3F => buffer.push(length as u8),
+        0x40..=0x3FFF => buffer.extend_from_slice(&(0x4000 | length as u16).to_be_bytes()),
+        0x4000..=0xFFFF_FFFF => {
+            buffer.push(0x80);
+            buffer.extend_from_slice(&(length as u32).to_be_bytes());
+        }
+
+        _ => {
+            buffer.push(0x81);
+            buffer.extend_from_slice(&length.to_be_bytes());
+        }
+    }
+}
+
+pub fn write_string(buffer: &mut Vec<u8>, string: &[u8]) {
+    write_length(buffer, string.len() as u64);
+    buffer.extend_from_slice(string);
+}
+
+/// Appends the trailer of a DUMP style payload: the RDB version and a CRC64 of everything before it.
+pub fn write_payload_footer(buffer: &mut Vec<u8>) {
+    buffer.extend_from_slice(&RDB_VERSION.to_le_bytes());
+
+    let checksum = crc64(0, buffer);
+    buffer.extend_from_slice(&checksum.to_le_bytes());
+}
+
+/// Checks a DUMP style payload's trailer, returning the body it protects.
+pub fn verify_payload(payload: &[u8]) -> Result<&[u8]> {
+    if payload.len() < 10 {
+        return Err(anyhow!("ERR DUMP payload version or checksum are wrong"));
+    }
+
+    let (body, checksum) = payload.split_at(payload.len() - 8);
+    let version = u16::from_le_bytes([body[body.len() - 2], body[body.len() - 1]]);
+
+    if version > RDB_VERSION || crc64(0, body) != u64::from_le_bytes(checksum.try_into()?) {
+        return Err(anyhow!("ERR DUMP payload version or checksum are wrong"));
+    }
+
+    Ok(&body[..body.len() - 2])
+}
+
+/// The CRC-64/Jones checksum Redis uses for RDB files and DUMP payloads.
+pub fn crc64(mut crc: u64, bytes: &[u8]) -> u64 {
+    const POLYNOMIAL: u64 = 0x95AC_9329_AC4B_C9B5;
+
+    for &byte in bytes {
+        crc ^= byte as u64;
+
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLYNOMIAL } else { crc >> 1 };
+        }
+    }
+
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Type;
+    use std::collections::{HashMap, HashSet};
+
+    const NO_MODULES: ModuleTypeLookup = &|_| None;
+
+    /// A Redis 7.2 dump with a key in each encoding it picks for small values, laid out as
+    /// `redis-server` saves it: the aux fields, then the keys of database 0, then the CRC64.
+    const REDIS_7_DUMP: &[u8] = b"\
+        REDIS0011\
+        \xfa\x09redis-ver\x057.2.4\
+        \xfa\x0aredis-bits\xc0@\
+        \xfa\x05ctime\xc2\x00\xf1Se\
+        \xfa\x08used-mem\xc2@B\x0f\x00\
+        \xfa\x08aof-base\xc0\x00\
+        \xfe\x00\xfb\x0b\x01\
+        \x00\x03int\xc0\x0a\
+        \x00\x05int16\xc1\xe8\x03\
+        \x00\x05int32\xc2\x90\xee\xfe\xff\
+        \x00\x03lzf\xc3\x09(\x01aa\xe0\x1b\x00\x01aa\
+        \xfc\x00\xd8\xc3,\xbb\x03\x00\x00\x00\x04text\x0bhello world\
+        \x12\x04list\x01\x02\x13\x13\x00\x00\x00\x03\x00\x81a\x02\x01\x01\x85hello\x06\xff\
+        \x0b\x04ints\x0e\x02\x00\x00\x00\x03\x00\x00\x00\x01\x00\x02\x00\x03\x00\
+        \x14\x03set\x0d\x0d\x00\x00\x00\x02\x00\x81a\x02\x81b\x02\xff\
+        \x10\x04hash\x0d\x0d\x00\x00\x00\x02\x00\x81f\x02\x81v\x02\xff\
+        \x11\x04zset\x14\x14\x00\x00\x00\x04\x00\x81m\x02\x831.5\x04\x81n\x02\x02\x01\xff\
+        \x15\x06stream\x01\x10\x00\x00\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x01\
+        ((\x00\x00\x00\x0f\x00\x02\x01\x00\x01\x01\x01\x81f\x02\x00\x01\x02\x01\x00\x01\x00\x01\x81v\x02\x04\x01\x02\x01\x00\x01\x01\x01\x81w\x02\x04\x01\xff\
+        \x02\x01\x02\x01\x01\x00\x00\x02\
+        \x01\x01g\x01\x01\x01\
+        \x01\x00\x00\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x01\x00h\xe5\xcf\x8b\x01\x00\x00\x01\
+        \x01\x01c\x00h\xe5\xcf\x8b\x01\x00\x00\x00h\xe5\xcf\x8b\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x01\
+        \xff{\xa2/\xd48\xc8\x0d\xc0";
+
+    /// What `SET lzf` with 40 `a`s is saved as: a literal `aa`, a back reference repeating them 36
+    /// times and another literal `aa`.
+    const LZF_STRING: &[u8] = b"\xc3\x09(\x01aa\xe0\x1b\x00\x01aa";
+
+    struct Dump {
+        aux: HashMap<String, String>,
+        keys: HashMap<String, (Value, Option<SystemTime>)>
+    }
+
+    fn read_dump(bytes: &[u8]) -> Result<Dump> {
+        let mut dump = Dump { aux: HashMap::new(), keys: HashMap::new() };
+
+        RdbReader::new(bytes).read_file(NO_MODULES, |item| {
+            match item {
+                RdbItem::Aux(key, value) => {
+                    dump.aux.insert(key, value);
+                }
+
+                RdbItem::Key { db: 0, key, value, expire } => {
+                    dump.keys.insert(key, (value, expire));
+                }
+
+                _ => panic!("Unexpected item")
+            }
+
+            Ok(())
+        })?;
+
+        Ok(dump)
+    }
+
+    fn text(value: &Value) -> String {
+        String::from_utf8(value.clone().unpack_as_bytes().unwrap()).unwrap()
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn reads_every_encoding_of_a_redis_7_dump() {
+        let dump = read_dump(REDIS_7_DUMP).unwrap();
+
+        assert_eq!(dump.aux["redis-ver"], "7.2.4");
+        assert_eq!(dump.aux["redis-bits"], "64");
+        assert_eq!(dump.aux["ctime"], "1700000000");
+        assert_eq!(dump.aux["used-mem"], "1000000");
+        assert_eq!(dump.keys.len(), 11);
+
+        let value = |key: &str| &dump.keys[key].0;
+
+        assert_eq!(text(value("int")), "10");
+        assert_eq!(text(value("int16")), "1000");
+        assert_eq!(text(value("int32")), "-70000");
+        assert_eq!(text(value("lzf")), "a".repeat(40));
+        assert_eq!(text(value("text")), "hello world");
+
+        assert_eq!(dump.keys["text"].1, Some(UNIX_EPOCH + Duration::from_millis(4102444800000)));
+        assert_eq!(dump.keys["int"].1, None);
+
+        let Value::Array(list) = value("list") else { panic!("Not a list") };
+        assert_eq!(list.iter().map(text).collect::<Vec<_>>(), strings(&["a", "1", "hello"]));
+
+        let Value::Set(ints) = value("ints") else { panic!("Not a set") };
+        assert_eq!(*ints, HashSet::from_iter(strings(&["1", "2", "3"])));
+
+        let Value::Set(set) = value("set") else { panic!("Not a set") };
+        assert_eq!(*set, HashSet::from_iter(strings(&["a", "b"])));
+
+        let Value::Hash(hash) = value("hash") else { panic!("Not a hash") };
+        assert_eq!(*hash, HashMap::from([("f".to_string(), "v".to_string())]));
+
+        let Value::SortedSet(zset) = value("zset") else { panic!("Not a sorted set") };
+        assert_eq!(zset.iter().collect::<Vec<_>>(), vec![("m", 1.5), ("n", 2.0)]);
+    }
+
+    #[test]
+    fn reads_a_stream_with_its_consumer_groups() {
+        let dump = read_dump(REDIS_7_DUMP).unwrap();
+        let Value::Stream(stream) = &dump.keys["stream"].0 else { panic!("Not a stream") };
+
+        let entries: Vec<StreamEntry> = stream.range(StreamId::MIN, StreamId::MAX).collect();
+        assert_eq!(entries.iter().map(|entry| entry.id).collect::<Vec<_>>(), vec![StreamId::new(1, 1), StreamId::new(1, 2)]);
+        assert_eq!(entries[1].fields, vec![("f".to_string(), "w".to_string())]);
+
+        assert_eq!(stream.last_id(), StreamId::new(1, 2));
+        assert_eq!(stream.entries_added(), 2);
+
+        let group = &stream.groups()["g"];
+        assert_eq!(group.last_delivered_id, StreamId::new(1, 1));
+        assert_eq!(group.entries_read, Some(1));
+
+        let pending = &group.pending()[&StreamId::new(1, 1)];
+        assert_eq!((pending.delivery_time, pending.delivery_count), (1700000000000, 1));
+
+        let consumer = &group.consumers()["c"];
+        assert_eq!(consumer.active_time, Some(1700000000000));
+        assert!(consumer.pending.contains(&StreamId::new(1, 1)));
+    }
+
+    #[test]
+    fn lengths_are_read_and_written_in_every_encoding() {
+        let encodings: [(&[u8], u64); 5] = [
+            (b"\x0a", 10),
+            (b"\x41\x2c", 300),
+            (b"\x80\x00\x01\x00\x00", 65536),
+            (b"\x81\x00\x00\x00\x01\x00\x00\x00\x00", 1 << 32),
+            (b"\x81\xff\xff\xff\xff\xff\xff\xff\xff", u64::MAX)
+        ];
+
+        for (bytes, length) in encodings {
+            assert_eq!(RdbReader::new(bytes).read_length().unwrap(), length);
+
+            let mut written = Vec::new();
+            write_length(&mut written, length);
+            assert_eq!(written, bytes);
+        }
+
+        assert!(RdbReader::new(&b"\x82"[..]).read_length().is_err());
+        assert!(RdbReader::new(&b"\xc0\x0a"[..]).read_length().is_err());
+    }
+
+    #[test]
+    fn strings_holding_integers_are_written_as_redis_does() {
+        let encodings: [(&str, &[u8]); 7] = [
+            ("10", b"\xc0\x0a"),
+            ("-128", b"\xc0\x80"),
+            ("1000", b"\xc1\xe8\x03"),
+            ("-70000", b"\xc2\x90\xee\xfe\xff"),
+            // Not in their shortest form, or too large, they stay text.
+            ("010", b"\x03010"),
+            ("+1", b"\x02+1"),
+            ("4294967296", b"\x0a4294967296")
+        ];
+
+        for (string, bytes) in encodings {
+            let mut written = Vec::new();
+            RdbWriter::new(&mut written).write_string(string.as_bytes()).unwrap();
+            assert_eq!(written, bytes, "{}", string);
+
+            assert_eq!(RdbReader::new(bytes).read_string().unwrap(), string.as_bytes());
+        }
+    }
+
+    #[test]
+    fn lzf_strings_round_trip() {
+        assert_eq!(RdbReader::new(LZF_STRING).read_string().unwrap(), b"a".repeat(40));
+
+        let long: Vec<u8> = (0..2000).map(|index| b"hello redis world"[index % 17] ^ (index / 300) as u8).collect();
+
+        for string in [b"a".repeat(40), long] {
+            let mut written = Vec::new();
+            RdbWriter::new(&mut written).write_string(&string).unwrap();
+
+            assert_eq!(written[0], 0xC0 | ENCODING_LZF, "worth compressing");
+            assert_eq!(RdbReader::new(&written[..]).read_string().unwrap(), string);
+        }
+
+        // A back reference before the start of the output, and a length that doesn't match.
+        assert!(RdbReader::new(&b"\xc3\x03\x28\x20\x05aa"[..]).read_string().is_err());
+        assert!(RdbReader::new(&b"\xc3\x09\x29\x01aa\xe0\x1b\x00\x01aa"[..]).read_string().is_err());
+    }
+
+    #[test]
+    fn reads_encodings_of_older_versions() {
+        // A zipmap from Redis 2.4, a ziplist from Redis 6 and a quicklist of ziplist nodes from Redis 3.2 to 6.
+        let Value::Hash(hash) = RdbReader::new(&b"\x07\x01\x01f\x01\x00v\xff"[..]).read_value(TYPE_HASH_ZIPMAP, NO_MODULES).unwrap() else {
+            panic!("Not a hash")
+        };
+
+        assert_eq!(hash, HashMap::from([("f".to_string(), "v".to_string())]));
+
+        let ziplist = b"\x10\x10\x00\x00\x00\x0d\x00\x00\x00\x02\x00\x00\x01a\x03\xf6\xff";
+        let quicklist = [&b"\x01"[..], ziplist].concat();
+
+        for (value_type, bytes) in [(TYPE_LIST_ZIPLIST, &ziplist[..]), (TYPE_LIST_QUICKLIST, &quicklist[..])] {
+            let Value::Array(list) = RdbReader::new(bytes).read_value(value_type, NO_MODULES).unwrap() else {
+                panic!("Not a list")
+            };
+
+            assert_eq!(list.iter().map(text).collect::<Vec<_>>(), strings(&["a", "5"]));
+        }
+    }
+
+    #[test]
+    fn crc64_matches_redis() {
+        // The check value of Redis' own CRC64 test.
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6d914c4b8d9ca);
+
+        let (body, checksum) = REDIS_7_DUMP.split_at(REDIS_7_DUMP.len() - 8);
+        assert_eq!(crc64(0, body).to_le_bytes(), checksum);
+    }
+
+    #[test]
+    fn a_corrupted_dump_is_refused() {
+        let mut corrupted = REDIS_7_DUMP.to_vec();
+        let position = corrupted.windows(11).position(|window| window == b"hello world").unwrap();
+        corrupted[position] = b'j';
+
+        let error = read_dump(&corrupted).err().unwrap().to_string();
+        assert!(error.contains("Wrong RDB checksum"), "{}", error);
+
+        // A zero checksum was written with checksums disabled, so there is nothing to compare to.
+        let length = corrupted.len();
+        corrupted[length - 8..].fill(0);
+        assert_eq!(text(&read_dump(&corrupted).unwrap().keys["text"].0), "jello world");
+    }
+
+    #[test]
+    fn a_truncated_dump_is_refused() {
+        for length in 0..REDIS_7_DUMP.len() {
+            assert!(read_dump(&REDIS_7_DUMP[..length]).is_err(), "cut at {}", length);
+        }
+    }
+
+    #[test]
+    fn written_dumps_read_back() {
+        let dump = read_dump(REDIS_7_DUMP).unwrap();
+        let mut writer = RdbWriter::new(Vec::new());
+
+        writer.write_header().unwrap();
+        writer.write_select_db(0, dump.keys.len() as u64, 1).unwrap();
+
+        for (key, (value, expire)) in &dump.keys {
+            writer.write_key(key, value, *expire, NO_MODULES).unwrap();
+        }
+
+        let written = read_dump(&writer.finish().unwrap()).unwrap();
+        assert_eq!(written.keys.len(), dump.keys.len());
+
+        for (key, (value, expire)) in &dump.keys {
+            let (written_value, written_expire) = &written.keys[key];
+
+            assert_eq!(written_expire, expire, "{}", key);
+            assert!(written_value.get_type() == value.get_type(), "{}", key);
+
+            if value.get_type() == Type::String {
+                assert_eq!(text(written_value), text(value), "{}", key);
+            }
+        }
+    }
+}
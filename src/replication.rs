@@ -0,0 +1,597 @@
+use crate::client::{Client, ClientId};
+use crate::commands::{CommandContext, CommandExecutor};
+use crate::parser::Value;
+use crate::persistence::Snapshot;
+use crate::response::RespHandler;
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::Notify;
+
+/// How long a replica waits before connecting again to a master it lost.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// How often a replica tells its master how far it got, with REPLCONF ACK.
+const ACK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The length of the mark ending an RDB file streamed by a diskless sync.
+const EOF_MARK_LENGTH: usize = 40;
+
+/// How much of an RDB file streamed to replicas is queued on their connections at once.
+const TRANSFER_CHUNK_SIZE: usize = 16 * 1024;
+
+/// The last writes streamed to replicas, kept so a replica that briefly lost its link can catch up
+/// on what it missed instead of loading a whole new RDB file.
+pub struct Backlog {
+    buffer: VecDeque<u8>,
+    capacity: usize,
+    /// The replication offset right after the last byte held.
+    end_offset: u64
+}
+
+impl Backlog {
+    pub fn new(capacity: usize, offset: u64) -> Backlog {
+        Backlog { buffer: VecDeque::new(), capacity, end_offset: offset }
+    }
+
+    /// The replication offset of the first byte held.
+    pub fn start_offset(&self) -> u64 {
+        self.end_offset - self.buffer.len() as u64
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Appends bytes, dropping the oldest ones that no longer fit.
+    pub fn append(&mut self, bytes: &[u8]) {
+        self.buffer.extend(bytes);
+        self.end_offset += bytes.len() as u64;
+        self.trim();
+    }
+
+    pub fn resize(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.trim();
+    }
+
+    /// The bytes written after `offset`, or none if some of them were already dropped.
+    pub fn since(&self, offset: u64) -> Option<Vec<u8>> {
+        if offset < self.start_offset() || offset > self.end_offset {
+            return None;
+        }
+
+        Some(self.buffer.range((offset - self.start_offset()) as usize..).copied().collect())
+    }
+
+    fn trim(&mut self) {
+        let excess = self.buffer.len().saturating_sub(self.capacity);
+        self.buffer.drain(..excess);
+    }
+}
+
+/// How far along a replica's full resync is, following Redis's states of the same names.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplicaState {
+    /// Waiting for a running save to end, so a new one can be started for it.
+    WaitBgsaveStart,
+    /// Waiting for the save taken for it, while the writes made since are buffered.
+    WaitBgsaveEnd,
+    Online
+}
+
+impl ReplicaState {
+    pub fn name(self) -> &'static str {
+        match self {
+            ReplicaState::WaitBgsaveStart => "wait_bgsave",
+            ReplicaState::WaitBgsaveEnd => "send_bulk",
+            ReplicaState::Online => "online"
+        }
+    }
+}
+
+/// A connected replica, as seen from its master.
+pub struct Replica {
+    pub state: ReplicaState,
+    /// The writes made since the snapshot it will load was taken.
+    pub pending: Vec<u8>,
+    /// The offset the replica last acknowledged with REPLCONF ACK.
+    pub ack_offset: u64,
+    /// The offset the replica's AOF was last fsynced at, as told by the FACK of REPLCONF ACK.
+    pub aof_ack_offset: u64,
+    pub last_ack: Instant
+}
+
+/// How far the link of a replica to its master is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LinkState {
+    Connect,
+    Connecting,
+    Handshake,
+    Transfer,
+    Connected
+}
+
+impl LinkState {
+    /// The name ROLE reports the state by.
+    pub fn name(self) -> &'static str {
+        match self {
+            LinkState::Connect => "connect",
+            LinkState::Connecting | LinkState::Handshake => "connecting",
+            LinkState::Transfer => "sync",
+            LinkState::Connected => "connected"
+        }
+    }
+}
+
+/// The master a replica follows.
+pub struct MasterLink {
+    pub host: String,
+    pub port: u16,
+    pub state: LinkState,
+    pub last_io: Instant
+}
+
+/// The replication state of the server, as a master to its replicas and as a replica of its master.
+pub struct Replication {
+    /// The id of the history of writes this server holds, which a replica takes from its master.
+    pub replid: String,
+    /// The id the history went by before the last switch, so replicas of the former master can
+    /// partially resync with a promoted replica.
+    pub replid2: String,
+    /// The first offset `replid2` doesn't cover.
+    pub second_replid_offset: Option<u64>,
+    /// How many bytes of writes were streamed to replicas since the history began.
+    pub offset: u64,
+    pub backlog: Option<Backlog>,
+    pub replicas: BTreeMap<ClientId, Replica>,
+    pub master: Option<MasterLink>,
+    /// The client the master's writes run as, while the link to it is up.
+    pub master_client: Option<ClientId>,
+    master_changes: Arc<Notify>,
+    pub last_ping: Instant,
+    /// The offset right after the last REPLCONF GETACK streamed, so WAIT asks replicas only once per write.
+    pub getack_offset: u64,
+    pub diskless_transfer: Option<DisklessTransfer>
+}
+
+impl Replication {
+    pub fn new() -> Replication {
+        Replication {
+            replid: new_replid(),
+            replid2: "0".repeat(40),
+            second_replid_offset: None,
+            offset: 0,
+            backlog: None,
+            replicas: BTreeMap::new(),
+            master: None,
+            master_client: None,
+            master_changes: Arc::new(Notify::new()),
+            last_ping: Instant::now(),
+            getack_offset: 0,
+            diskless_transfer: None
+        }
+    }
+
+    /// Notified whenever the master to follow changes, so the link to the former one is dropped.
+    pub fn master_changes(&self) -> Arc<Notify> {
+        Arc::clone(&self.master_changes)
+    }
+
+    /// Follows another master, or none to become a master.
+    pub fn set_master(&mut self, master: Option<(String, u16)>) {
+        self.master = master.map(|(host, port)| MasterLink { host, port, state: LinkState::Connect, last_io: Instant::now() });
+        self.master_changes.notify_waiters();
+    }
+
+    /// Goes on with the current history under a new id, as a promoted replica does, or a replica
+    /// whose master did.
+    pub fn shift_replid(&mut self, replid: String) {
+        self.replid2 = std::mem::replace(&mut self.replid, replid);
+        self.second_replid_offset = Some(self.offset + 1);
+    }
+
+    /// Takes over the history of a master this replica fully resynced with.
+    pub fn reset(&mut self, replid: String, offset: u64, backlog_size: usize) {
+        self.replid = replid;
+        self.replid2 = "0".repeat(40);
+        self.second_replid_offset = None;
+        self.offset = offset;
+        self.backlog = Some(Backlog::new(backlog_size, offset));
+    }
+
+    /// The bytes a replica resuming the history `replid` at `psync_offset` is missing, or none if it
+    /// needs a full resync. As in Redis, the offset is that of the first byte the replica wants.
+    pub fn partial_resync(&self, replid: &str, psync_offset: u64) -> Option<Vec<u8>> {
+        let backlog = self.backlog.as_ref()?;
+
+        let known = replid == self.replid
+            || (replid == self.replid2 && self.second_replid_offset.is_some_and(|offset| psync_offset <= offset));
+
+        if !known || psync_offset == 0 {
+            return None;
+        }
+
+        backlog.since(psync_offset - 1)
+    }
+
+    /// How many replicas acknowledged receiving the writes up to `offset`.
+    pub fn acknowledged(&self, offset: u64) -> usize {
+        self.replicas.values().filter(|replica| replica.ack_offset >= offset).count()
+    }
+
+    /// How many replicas have fsynced the writes up to `offset` to their AOF.
+    pub fn aof_acknowledged(&self, offset: u64) -> usize {
+        self.replicas.values().filter(|replica| replica.aof_ack_offset >= offset).count()
+    }
+
+    /// Streams writes to the replicas and keeps them in the backlog. Nothing is streamed before a
+    /// replica ever connected, as there is no backlog yet.
+    pub fn feed(&mut self, bytes: &[u8], clients: &HashMap<ClientId, Client>) {
+        let Some(backlog) = self.backlog.as_mut() else {
+            return;
+        };
+
+        backlog.append(bytes);
+        self.offset += bytes.len() as u64;
+
+        for (client_id, replica) in self.replicas.iter_mut() {
+            match replica.state {
+                ReplicaState::Online => if let Some(client) = clients.get(client_id) {
+                    client.send(Value::Raw(bytes.to_vec()));
+                },
+
+                ReplicaState::WaitBgsaveEnd => replica.pending.extend_from_slice(bytes),
+                ReplicaState::WaitBgsaveStart => {}
+            }
+        }
+    }
+}
+
+/// An RDB file streamed to replicas straight from memory, with `repl-diskless-sync yes`.
+pub struct DisklessTransfer {
+    handle: JoinHandle<Result<()>>,
+    /// The replicas it is sent to, which come online once it is done.
+    pub replicas: Vec<ClientId>
+}
+
+impl DisklessTransfer {
+    /// Writes `snapshot` to the replicas on its own thread. Its size isn't known upfront, so it is
+    /// framed as `$EOF:<mark>\r\n<payload><mark>`, the mark being 40 random characters.
+    pub fn start(snapshot: Snapshot, replicas: Vec<(ClientId, UnboundedSender<Value>)>) -> DisklessTransfer {
+        let (replicas, senders) = replicas.into_iter().unzip();
+
+        let handle = std::thread::spawn(move || {
+            let mark = new_replid();
+            let mut writer = ReplicaWriter { senders, buffer: Vec::new() };
+
+            write!(writer, "$EOF:{}\r\n", mark)?;
+            let mut writer = snapshot.write(writer)?;
+            writer.write_all(mark.as_bytes())?;
+            writer.flush()?;
+            Ok(())
+        });
+
+        DisklessTransfer { handle, replicas }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    pub fn result(self) -> Result<()> {
+        self.handle.join().unwrap_or_else(|_| Err(anyhow!("The diskless transfer thread panicked")))
+    }
+}
+
+/// Queues what is written on the connections of replicas, a chunk at a time.
+struct ReplicaWriter {
+    senders: Vec<UnboundedSender<Value>>,
+    buffer: Vec<u8>
+}
+
+impl Write for ReplicaWriter {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(bytes);
+
+        if self.buffer.len() >= TRANSFER_CHUNK_SIZE {
+            self.flush()?;
+        }
+
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let chunk = std::mem::take(&mut self.buffer);
+
+        for sender in &self.senders {
+            let _ = sender.send(Value::Raw(chunk.clone()));
+        }
+
+        Ok(())
+    }
+}
+
+/// A random looking 40 character id. The standard library has no random numbers, so the clock,
+/// the process id and a counter are hashed instead.
+pub fn new_replid() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let seed = format!("{:?}-{}-{}", SystemTime::now(), std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed));
+    sha1_smol::Sha1::from(seed).digest().to_string()
+}
+
+/// Keeps this server following the master REPLICAOF names: connects, resyncs and applies the master's
+/// stream, reconnecting a second after the link drops, and hanging up as soon as the master changes.
+pub async fn run_replica_link(executor: Arc<CommandExecutor>, context: Arc<Mutex<CommandContext>>) {
+    let master_changes = context.lock().unwrap().master_changes();
+
+    loop {
+        // Registered before reading the master, so a change right after can't be missed.
+        let changed = master_changes.notified();
+        tokio::pin!(changed);
+        changed.as_mut().enable();
+
+        let Some((host, port)) = context.lock().unwrap().master_address() else {
+            changed.await;
+            continue;
+        };
+
+        tokio::select! {
+            result = follow_master(&host, port, &executor, &context) => {
+                if let Err(e) = result {
+                    println!("Lost the link to MASTER {}:{}: {}", host, port, e);
+                }
+
+                context.lock().unwrap().master_link_down();
+
+                tokio::select! {
+                    _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+                    _ = changed => {}
+                }
+            }
+
+            _ = changed.as_mut() => {}
+        }
+    }
+}
+
+/// Connects to the master, resyncs, then applies its stream until the link fails.
+async fn follow_master(host: &str, port: u16, executor: &CommandExecutor, context: &Mutex<CommandContext>) -> Result<()> {
+    context.lock().unwrap().set_master_link_state(LinkState::Connecting);
+
+    let socket = TcpStream::connect((host, port)).await?;
+    let (mut handler, sender) = RespHandler::new(socket);
+
+    println!("MASTER <-> REPLICA sync started");
+    context.lock().unwrap().set_master_link_state(LinkState::Handshake);
+
+    let (listening_port, replid, psync_offset) = context.lock().unwrap().psync_request();
+
+    request(&mut handler, &sender, &["PING"]).await?;
+    request(&mut handler, &sender, &["REPLCONF", "listening-port", &listening_port]).await?;
+    request(&mut handler, &sender, &["REPLCONF", "capa", "eof", "capa", "psync2"]).await?;
+
+    let reply = request(&mut handler, &sender, &["PSYNC", &replid, &psync_offset.to_string()]).await?;
+    let mut words = reply.split_whitespace();
+
+    match words.next() {
+        Some("+FULLRESYNC") => {
+            let (Some(replid), Some(Ok(offset))) = (words.next(), words.next().map(str::parse::<u64>)) else {
+                return Err(anyhow!("Bad FULLRESYNC reply '{}'", reply));
+            };
+
+            println!("Full resync from master: {}:{}", replid, offset);
+            context.lock().unwrap().set_master_link_state(LinkState::Transfer);
+
+            let rdb = receive_rdb(&mut handler).await?;
+            tokio::task::block_in_place(|| context.lock().unwrap().load_master_rdb(&rdb, replid.to_string(), offset))?;
+
+            println!("MASTER <-> REPLICA sync: Finished with success");
+        }
+
+        Some("+CONTINUE") => {
+            context.lock().unwrap().continue_replication(words.next().map(str::to_string));
+            println!("MASTER <-> REPLICA sync: Master accepted a Partial Resynchronization");
+        }
+
+        _ => return Err(anyhow!("Unexpected reply to PSYNC: {}", reply))
+    }
+
+    context.lock().unwrap().master_link_up();
+
+    let mut ack_interval = tokio::time::interval(ACK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            value = handler.read_raw_value() => {
+                let Some((value, raw)) = value? else {
+                    return Err(anyhow!("Connection closed by the master"));
+                };
+
+                let reply = tokio::task::block_in_place(|| executor.apply_master_stream(&mut context.lock().unwrap(), value, &raw));
+
+                if let Some(reply) = reply {
+                    let _ = sender.send(reply);
+                }
+            }
+
+            _ = ack_interval.tick() => {
+                let mut context = context.lock().unwrap();
+
+                if context.master_timed_out() {
+                    return Err(anyhow!("Timeout, no data nor PING received from the master"));
+                }
+
+                let _ = sender.send(context.replication_ack());
+            }
+        }
+    }
+}
+
+/// Sends a handshake command and reads its one line reply, failing on errors.
+async fn request(handler: &mut RespHandler, sender: &UnboundedSender<Value>, args: &[&str]) -> Result<String> {
+    let _ = sender.send(Value::Array(args.iter().map(|arg| Value::BulkString(arg.to_string().into())).collect()));
+
+    let reply = handler.read_line().await?.ok_or_else(|| anyhow!("Connection closed by the master"))?;
+
+    if reply.starts_with('-') {
+        return Err(anyhow!("The master replied to {} with: {}", args[0], reply));
+    }
+
+    Ok(reply)
+}
+
+/// Receives the RDB file of a full resync. It comes as a bulk length followed by the payload, or
+/// from a diskless sync as `$EOF:<mark>` followed by the payload ending with the mark.
+async fn receive_rdb(handler: &mut RespHandler) -> Result<Bytes> {
+    // The master may send newlines to keep the link alive while it prepares the file.
+    let line = loop {
+        let line = handler.read_line().await?.ok_or_else(|| anyhow!("Connection closed by the master"))?;
+        let line = line.trim_start_matches('\n');
+
+        if !line.is_empty() {
+            break line.to_string();
+        }
+    };
+
+    let rdb = match line.strip_prefix("$EOF:") {
+        Some(mark) if mark.len() == EOF_MARK_LENGTH => {
+            println!("MASTER <-> REPLICA sync: receiving streamed RDB from master");
+            handler.read_until(mark.as_bytes()).await?
+        }
+
+        _ => {
+            let length = line.strip_prefix('$')
+                .and_then(|length| length.parse::<usize>().ok())
+                .ok_or_else(|| anyhow!("Bad bulk length '{}' reading the RDB file from the master", line))?;
+
+            println!("MASTER <-> REPLICA sync: receiving {} bytes from master", length);
+            handler.read_bytes(length).await?
+        }
+    };
+
+    rdb.ok_or_else(|| anyhow!("Connection closed by the master while sending the RDB file"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn the_backlog_keeps_the_last_writes_that_fit() {
+        let mut backlog = Backlog::new(8, 100);
+        assert_eq!(backlog.since(100), Some(Vec::new()));
+
+        backlog.append(b"abcde");
+        assert_eq!((backlog.start_offset(), backlog.len()), (100, 5));
+        assert_eq!(backlog.since(102), Some(b"cde".to_vec()));
+
+        // Wrapping around drops the oldest bytes, which can no longer be resumed from.
+        backlog.append(b"fghijk");
+        assert_eq!((backlog.start_offset(), backlog.len()), (103, 8));
+        assert_eq!(backlog.since(103), Some(b"defghijk".to_vec()));
+        assert_eq!(backlog.since(111), Some(Vec::new()));
+        assert_eq!(backlog.since(102), None);
+        assert_eq!(backlog.since(112), None);
+
+        // A write larger than the whole backlog only leaves its end.
+        backlog.append(b"0123456789");
+        assert_eq!(backlog.since(113), Some(b"23456789".to_vec()));
+
+        backlog.resize(3);
+        assert_eq!((backlog.start_offset(), backlog.capacity()), (118, 3));
+        assert_eq!(backlog.since(118), Some(b"789".to_vec()));
+    }
+
+    #[test]
+    fn partial_resyncs_follow_both_histories() {
+        let mut replication = Replication::new();
+        assert_eq!(replication.partial_resync(&replication.replid.clone(), 1), None);
+
+        replication.reset("a".repeat(40), 10, 1024);
+        replication.feed(b"write", &HashMap::new());
+        assert_eq!(replication.offset, 15);
+
+        // PSYNC offsets are those of the first byte the replica wants.
+        assert_eq!(replication.partial_resync(&"a".repeat(40), 11), Some(b"write".to_vec()));
+        assert_eq!(replication.partial_resync(&"a".repeat(40), 14), Some(b"te".to_vec()));
+        assert_eq!(replication.partial_resync(&"a".repeat(40), 16), Some(Vec::new()));
+        assert_eq!(replication.partial_resync(&"a".repeat(40), 10), None);
+        assert_eq!(replication.partial_resync(&"a".repeat(40), 17), None);
+        assert_eq!(replication.partial_resync(&"b".repeat(40), 11), None);
+
+        // A promoted replica still resumes the former history up to where it switched.
+        replication.shift_replid("b".repeat(40));
+        replication.feed(b"more", &HashMap::new());
+
+        assert_eq!(replication.partial_resync(&"a".repeat(40), 16), Some(b"more".to_vec()));
+        assert_eq!(replication.partial_resync(&"a".repeat(40), 17), None);
+        assert_eq!(replication.partial_resync(&"b".repeat(40), 17), Some(b"ore".to_vec()));
+    }
+
+    /// Sends `chunks` to a `RespHandler` over a local connection, a write at a time, and returns
+    /// what `receive_rdb` made of them.
+    async fn receive(chunks: Vec<Vec<u8>>) -> Result<Bytes> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let master = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+
+            for chunk in chunks {
+                socket.write_all(&chunk).await.unwrap();
+                socket.flush().await.unwrap();
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        });
+
+        let (mut handler, _sender) = RespHandler::new(TcpStream::connect(address).await.unwrap());
+        let rdb = receive_rdb(&mut handler).await;
+
+        master.await.unwrap();
+        rdb
+    }
+
+    #[tokio::test]
+    async fn receives_an_rdb_file_by_its_length() {
+        let rdb = receive(vec![b"\n\n$12\r\nREDIS".to_vec(), b"0011\xff".to_vec(), b"\r\n".to_vec()]).await.unwrap();
+        assert_eq!(&rdb[..], b"REDIS0011\xff\r\n");
+
+        assert!(receive(vec![b"$11\r\nREDIS".to_vec()]).await.is_err());
+        assert!(receive(vec![b"+OK\r\n".to_vec()]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn receives_an_rdb_file_streamed_up_to_its_mark() {
+        let mark = new_replid();
+        assert_eq!(mark.len(), EOF_MARK_LENGTH);
+
+        // The payload holds part of the mark, and the mark itself is split across writes.
+        let payload = [b"REDIS0011".as_slice(), &mark.as_bytes()[..20], b"\xff"].concat();
+        let (head, tail) = mark.as_bytes().split_at(25);
+
+        let chunks = vec![format!("\n$EOF:{}\r\n", mark).into_bytes(), payload.clone(), head.to_vec(), tail.to_vec()];
+        assert_eq!(&receive(chunks).await.unwrap()[..], &payload[..]);
+
+        // A stream cut before its mark is incomplete.
+        let chunks = vec![format!("$EOF:{}\r\n", mark).into_bytes(), payload, head.to_vec()];
+        assert!(receive(chunks).await.is_err());
+    }
+}
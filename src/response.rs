@@ -1,36 +1,121 @@
 use anyhow::Result;
-use bytes::BytesMut;
+use bytes::{Buf, Bytes, BytesMut};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 
-use crate::parser::{Value, parse_message};
+use crate::parser::{parse_message, ParseError, Value};
 
 pub struct RespHandler {
-    stream: TcpStream,
+    stream: OwnedReadHalf,
     buffer: BytesMut,
 }
 
 impl RespHandler {
-    pub fn new(stream: TcpStream) -> RespHandler {
-        RespHandler {
-            stream,
+    /// Splits the connection, spawning a writer task that drains the returned sender.
+    /// Writes never wait on the socket, so a slow reader can't hold up whoever is sending to it.
+    pub fn new(stream: TcpStream) -> (RespHandler, UnboundedSender<Value>) {
+        let (reader, writer) = stream.into_split();
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        tokio::spawn(write_values(writer, receiver));
+
+        let handler = RespHandler {
+            stream: reader,
             buffer: BytesMut::with_capacity(512),
-        }
+        };
+
+        (handler, sender)
     }
 
     pub async fn read_value(&mut self) -> Result<Option<Value>> {
-        let bytes_read = self.stream.read_buf(&mut self.buffer).await?;
+        Ok(self.read_raw_value().await?.map(|(value, _)| value))
+    }
+
+    /// Reads a value along with the bytes it was parsed from, as a replica must count and relay them.
+    /// Fails with a [`ParseError`] on bytes that aren't RESP, as the connection can't be read past them.
+    pub async fn read_raw_value(&mut self) -> Result<Option<(Value, Bytes)>> {
+        loop {
+            // A message may arrive split across several reads, so keep buffering until it parses.
+            match parse_message(&self.buffer) {
+                Ok((value, parsed)) => return Ok(Some((value, self.buffer.split_to(parsed).freeze()))),
+                Err(ParseError::Incomplete) => {}
+                Err(e) => return Err(e.into())
+            }
 
-        if bytes_read == 0 {
-            return Ok(None);
+            if !self.fill().await? {
+                return Ok(None);
+            }
         }
+    }
 
-        let (value, _) = parse_message(self.buffer.split())?;
-        Ok(Some(value))
+    /// Reads a line without its CRLF, for replies the parser doesn't handle, like errors.
+    pub async fn read_line(&mut self) -> Result<Option<String>> {
+        loop {
+            if let Some(end) = self.buffer.windows(2).position(|window| window == b"\r\n") {
+                let line = String::from_utf8_lossy(&self.buffer[..end]).into_owned();
+                self.buffer.advance(end + 2);
+                return Ok(Some(line));
+            }
+
+            if !self.fill().await? {
+                return Ok(None);
+            }
+        }
     }
 
-    pub async fn write_value(&mut self, value: Value) -> Result<()> {
-        self.stream.write(value.serialize().as_bytes()).await?;
-        Ok(())
+    /// Reads exactly `length` bytes, like an RDB payload which isn't followed by a CRLF.
+    pub async fn read_bytes(&mut self, length: usize) -> Result<Option<Bytes>> {
+        while self.buffer.len() < length {
+            if !self.fill().await? {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(self.buffer.split_to(length).freeze()))
     }
-}
\ No newline at end of file
+
+    /// Reads up to `delimiter` and drops it, like an RDB payload streamed with an EOF marker.
+    pub async fn read_until(&mut self, delimiter: &[u8]) -> Result<Option<Bytes>> {
+        let mut searched = 0;
+
+        loop {
+            if let Some(position) = self.buffer[searched..].windows(delimiter.len()).position(|window| window == delimiter) {
+                let payload = self.buffer.split_to(searched + position).freeze();
+                self.buffer.advance(delimiter.len());
+                return Ok(Some(payload));
+            }
+
+            // The delimiter may straddle what is buffered and what comes next.
+            searched = self.buffer.len().saturating_sub(delimiter.len() - 1);
+
+            if !self.fill().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Resolves once the peer hangs up, keeping whatever it sends meanwhile for the next read.
+    pub async fn closed(&mut self) {
+        while let Ok(true) = self.fill().await {}
+    }
+
+    /// Reads more from the socket, returning false once it is closed.
+    async fn fill(&mut self) -> Result<bool> {
+        Ok(self.stream.read_buf(&mut self.buffer).await? > 0)
+    }
+}
+
+async fn write_values(mut writer: OwnedWriteHalf, mut receiver: UnboundedReceiver<Value>) {
+    while let Some(value) = receiver.recv().await {
+        let result = match value {
+            Value::Raw(bytes) => writer.write_all(&bytes).await,
+            value => writer.write_all(&value.serialize()).await
+        };
+
+        if result.is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,577 @@
+use crate::parser::Value;
+use anyhow::{anyhow, Result};
+use mlua::{Function, HookTriggers, Lua, LuaOptions, MultiValue, RegistryKey, StdLib, Table, Value as LuaValue};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often, in Lua VM instructions, a running script checks whether it was killed.
+const KILL_CHECK_INTERVAL: u32 = 1000;
+
+pub const SCRIPT_KILLED_ERROR: &str = "ERR Script killed by user with SCRIPT KILL...";
+
+/// Flags a function may declare when it is registered.
+const FUNCTION_FLAGS: [&str; 5] = ["no-writes", "allow-oom", "allow-stale", "no-cluster", "allow-cross-slot-keys"];
+
+/// Lua-side half of the `redis` library. `redis.__dispatch` is bound from Rust for each run, and
+/// `__run` tells error replies raised through `redis.call` apart from plain Lua errors in the script.
+/// `__registrar` turns the error returned by the Rust registration callback into a Lua error.
+const REDIS_LIBRARY: &str = r#"
+redis.call = function(...)
+    local reply = redis.__dispatch(...)
+
+    if type(reply) == 'table' and reply.err then
+        error(reply, 2)
+    end
+
+    return reply
+end
+
+redis.pcall = function(...)
+    return redis.__dispatch(...)
+end
+
+redis.error_reply = function(message)
+    return {err = message}
+end
+
+redis.status_reply = function(message)
+    return {ok = message}
+end
+
+function __registrar(register)
+    return function(...)
+        local err = register(...)
+
+        if err then
+            error(err, 2)
+        end
+    end
+end
+
+function __run(script, ...)
+    local ok, result = pcall(script, ...)
+
+    if ok or (type(result) == 'table' and result.err) then
+        return result, false
+    end
+
+    return tostring(result), true
+end
+
+setmetatable(_G, {
+    __index = function(_, name)
+        error("Script attempted to access nonexistent global variable '" .. tostring(name) .. "'", 2)
+    end,
+
+    __newindex = function(_, name)
+        error("Script attempted to create global variable '" .. tostring(name) .. "'", 2)
+    end
+})
+"#;
+
+/// State shared with connections outside the context lock, so SCRIPT KILL and BUSY replies
+/// can be served while a long script is holding it.
+#[derive(Default)]
+pub struct ScriptMonitor {
+    running: Mutex<Option<RunningScript>>,
+    kill_requested: AtomicBool
+}
+
+struct RunningScript {
+    started: Instant,
+    busy_threshold: Duration,
+    wrote: bool
+}
+
+impl ScriptMonitor {
+    fn start(&self, busy_threshold: Duration) {
+        self.kill_requested.store(false, Ordering::SeqCst);
+        *self.running.lock().unwrap() = Some(RunningScript { started: Instant::now(), busy_threshold, wrote: false });
+    }
+
+    fn finish(&self) {
+        *self.running.lock().unwrap() = None;
+    }
+
+    /// Records that the running script changed the dataset, after which it can no longer be killed.
+    pub fn mark_write(&self) {
+        if let Some(script) = self.running.lock().unwrap().as_mut() {
+            script.wrote = true;
+        }
+    }
+
+    /// Whether a script has been running past the busy threshold; other clients then get BUSY replies.
+    pub fn is_busy(&self) -> bool {
+        self.running.lock().unwrap().as_ref().is_some_and(|script| script.started.elapsed() >= script.busy_threshold)
+    }
+
+    /// Asks the running script to abort, which is only allowed while it hasn't written anything.
+    pub fn kill(&self) -> Value {
+        match self.running.lock().unwrap().as_ref() {
+            None => Value::SimpleError("NOTBUSY No scripts in execution right now.".to_string()),
+            Some(script) if script.wrote => Value::SimpleError("UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server in a hard way using the SHUTDOWN NOSAVE command.".to_string()),
+            Some(_) => {
+                self.kill_requested.store(true, Ordering::SeqCst);
+                Value::SimpleString("OK".to_string())
+            }
+        }
+    }
+}
+
+/// The embedded Lua interpreter together with the cache of compiled scripts, keyed by SHA1,
+/// and the function libraries loaded with FUNCTION LOAD.
+pub struct ScriptEngine {
+    lua: Lua,
+    scripts: HashMap<String, RegistryKey>,
+    libraries: BTreeMap<String, Library>,
+    monitor: Arc<ScriptMonitor>
+}
+
+/// A library of functions, declared by a `#!lua name=<library>` header and kept with its source
+/// so it can be listed, dumped and persisted.
+pub struct Library {
+    pub name: String,
+    pub code: String,
+    pub functions: BTreeMap<String, LibraryFunction>
+}
+
+pub struct LibraryFunction {
+    pub description: Option<String>,
+    pub flags: Vec<String>,
+    callback: RegistryKey
+}
+
+impl ScriptEngine {
+    pub fn new(monitor: Arc<ScriptMonitor>) -> Result<ScriptEngine> {
+        let lua = Lua::new_with(StdLib::TABLE | StdLib::STRING | StdLib::MATH, LuaOptions::default())?;
+
+        lua.globals().set("redis", lua.create_table()?)?;
+        lua.load(REDIS_LIBRARY).set_name("@redis_library").exec()?;
+
+        let hook_monitor = Arc::clone(&monitor);
+
+        lua.set_hook(HookTriggers::new().every_nth_instruction(KILL_CHECK_INTERVAL), move |_, _| {
+            if hook_monitor.kill_requested.load(Ordering::SeqCst) {
+                return Err(mlua::Error::RuntimeError(SCRIPT_KILLED_ERROR.to_string()));
+            }
+
+            Ok(())
+        });
+
+        Ok(ScriptEngine { lua, scripts: HashMap::new(), libraries: BTreeMap::new(), monitor })
+    }
+
+    /// Compiles and caches `body`, returning its SHA1 digest.
+    pub fn load(&mut self, body: &str) -> Result<String> {
+        let sha = sha1_hex(body);
+
+        if !self.scripts.contains_key(&sha) {
+            let function = self.lua.load(body).set_name("@user_script").into_function()
+                .map_err(|e| anyhow!("ERR Error compiling script (new function): {}", e))?;
+
+            self.scripts.insert(sha.clone(), self.lua.create_registry_value(function)?);
+        }
+
+        Ok(sha)
+    }
+
+    pub fn exists(&self, sha: &str) -> bool {
+        self.scripts.contains_key(&sha.to_lowercase())
+    }
+
+    pub fn flush(&mut self) {
+        for (_, key) in self.scripts.drain() {
+            let _ = self.lua.remove_registry_value(key);
+        }
+    }
+
+    /// Runs a cached script, handing every `redis.call`/`redis.pcall` to `dispatch` as the command's arguments.
+    /// `busy_threshold` is how long it may run before other clients are told the server is busy.
+    pub fn run<F>(&self, sha: &str, keys: &[Vec<u8>], argv: &[Vec<u8>], busy_threshold: Duration, dispatch: F) -> Value
+    where
+        F: FnMut(Vec<Vec<u8>>) -> Value
+    {
+        let Some(key) = self.scripts.get(&sha.to_lowercase()) else {
+            return Value::SimpleError("NOSCRIPT No matching script. Please use EVAL.".to_string());
+        };
+
+        self.invoke(key, Invocation::Script, keys, argv, sha, busy_threshold, dispatch)
+    }
+
+    /// Calls a library function, which gets the keys and arguments as its two parameters instead of globals.
+    pub fn call_function<F>(&self, name: &str, keys: &[Vec<u8>], argv: &[Vec<u8>], busy_threshold: Duration, dispatch: F) -> Value
+    where
+        F: FnMut(Vec<Vec<u8>>) -> Value
+    {
+        let Some(function) = self.find_function(name) else {
+            return Value::SimpleError("ERR Function not found".to_string());
+        };
+
+        self.invoke(&function.callback, Invocation::Function, keys, argv, name, busy_threshold, dispatch)
+    }
+
+    pub fn find_function(&self, name: &str) -> Option<&LibraryFunction> {
+        self.libraries.values().find_map(|library| library.functions.get(name))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn invoke<F>(&self, key: &RegistryKey, invocation: Invocation, keys: &[Vec<u8>], argv: &[Vec<u8>], label: &str, busy_threshold: Duration, mut dispatch: F) -> Value
+    where
+        F: FnMut(Vec<Vec<u8>>) -> Value
+    {
+        self.monitor.start(busy_threshold);
+
+        let result = self.lua.scope(|scope| {
+            let globals = self.lua.globals();
+            let redis: Table = globals.get("redis")?;
+
+            redis.raw_set("__dispatch", scope.create_function_mut(|lua, args: MultiValue| {
+                let args = match command_args(args) {
+                    Ok(args) => args,
+                    Err(message) => return error_table(lua, &message)
+                };
+
+                resp_to_lua(lua, dispatch(args))
+            })?)?;
+
+            let script: Function = self.lua.registry_value(key)?;
+            let run: Function = globals.get("__run")?;
+
+            let outcome = match invocation {
+                Invocation::Script => {
+                    globals.raw_set("KEYS", strings_table(&self.lua, keys)?)?;
+                    globals.raw_set("ARGV", strings_table(&self.lua, argv)?)?;
+
+                    run.call::<_, (LuaValue, bool)>(script)?
+                }
+
+                Invocation::Function => run.call::<_, (LuaValue, bool)>((script, strings_table(&self.lua, keys)?, strings_table(&self.lua, argv)?))?
+            };
+
+            match outcome {
+                (LuaValue::String(message), true) => Ok(Value::SimpleError(format!("ERR {} script: {}", message.to_string_lossy(), label))),
+                (result, _) => lua_to_resp(result)
+            }
+        });
+
+        let killed = self.monitor.kill_requested.swap(false, Ordering::SeqCst);
+        self.monitor.finish();
+
+        match result {
+            _ if killed => Value::SimpleError(SCRIPT_KILLED_ERROR.to_string()),
+            Ok(reply) => reply,
+            Err(e) => Value::SimpleError(format!("ERR {} script: {}", e, label))
+        }
+    }
+
+    pub fn libraries(&self) -> impl Iterator<Item = &Library> {
+        self.libraries.values()
+    }
+
+    /// Compiles a library and runs its top level to collect the functions it registers, without loading it.
+    pub fn compile_library(&self, code: &str) -> Result<Library> {
+        let name = parse_library_name(code)?;
+
+        // The header becomes a comment, so line numbers in errors still match the source.
+        let chunk = self.lua.load(format!("--{}", code)).set_name("@user_function").into_function()
+            .map_err(|e| anyhow!("ERR Error compiling function: {}", e))?;
+
+        let mut registered: Vec<(String, LibraryFunction)> = Vec::new();
+
+        let outcome = self.lua.scope(|scope| {
+            let globals = self.lua.globals();
+            let redis: Table = globals.get("redis")?;
+
+            let register = scope.create_function_mut(|lua, args: MultiValue| {
+                let (name, function) = match register_function_args(args) {
+                    Ok(registration) => registration,
+                    Err(message) => return Ok(Some(message))
+                };
+
+                if registered.iter().any(|(registered, _)| *registered == name) {
+                    return Ok(Some("Function already exists in the library".to_string()));
+                }
+
+                let callback = lua.create_registry_value(function.callback)?;
+                registered.push((name, LibraryFunction { description: function.description, flags: function.flags, callback }));
+
+                Ok(None)
+            })?;
+
+            let registrar: Function = globals.get("__registrar")?;
+            redis.raw_set("register_function", registrar.call::<_, Function>(register)?)?;
+
+            let run: Function = globals.get("__run")?;
+            let outcome = run.call::<_, (LuaValue, bool)>(chunk);
+
+            redis.raw_set("register_function", LuaValue::Nil)?;
+
+            match outcome? {
+                (LuaValue::String(message), true) => Ok(Err(anyhow!("ERR Error registering functions: {}", message.to_string_lossy()))),
+                _ => Ok(Ok(()))
+            }
+        })?;
+
+        outcome?;
+
+        if registered.is_empty() {
+            return Err(anyhow!("ERR No functions registered"));
+        }
+
+        Ok(Library { name, code: code.to_string(), functions: registered.into_iter().collect() })
+    }
+
+    /// Makes a compiled library callable, replacing one with the same name only if `replace` is set.
+    pub fn add_library(&mut self, library: Library, replace: bool) -> Result<()> {
+        if !replace && self.libraries.contains_key(&library.name) {
+            return Err(anyhow!("ERR Library '{}' already exists", library.name));
+        }
+
+        let clash = self.libraries.values()
+            .filter(|loaded| loaded.name != library.name)
+            .flat_map(|loaded| loaded.functions.keys())
+            .find(|name| library.functions.contains_key(*name));
+
+        if let Some(name) = clash {
+            return Err(anyhow!("ERR Function {} already exists", name));
+        }
+
+        if let Some(replaced) = self.libraries.insert(library.name.clone(), library) {
+            self.drop_library(replaced);
+        }
+
+        Ok(())
+    }
+
+    /// Compiles and adds a library, returning its name.
+    pub fn load_library(&mut self, code: &str, replace: bool) -> Result<String> {
+        let library = self.compile_library(code)?;
+        let name = library.name.clone();
+
+        self.add_library(library, replace)?;
+        Ok(name)
+    }
+
+    pub fn delete_library(&mut self, name: &str) -> bool {
+        match self.libraries.remove(name) {
+            Some(library) => {
+                self.drop_library(library);
+                true
+            }
+
+            None => false
+        }
+    }
+
+    pub fn flush_libraries(&mut self) {
+        for library in std::mem::take(&mut self.libraries).into_values() {
+            self.drop_library(library);
+        }
+    }
+
+    fn drop_library(&self, library: Library) {
+        for function in library.functions.into_values() {
+            let _ = self.lua.remove_registry_value(function.callback);
+        }
+    }
+}
+
+enum Invocation {
+    Script,
+    Function
+}
+
+struct FunctionRegistration<'lua> {
+    callback: Function<'lua>,
+    description: Option<String>,
+    flags: Vec<String>
+}
+
+/// Reads the library name from the `#!<engine> name=<library>` header that starts every library.
+fn parse_library_name(code: &str) -> Result<String> {
+    let header = code.lines().next().unwrap_or_default();
+
+    let Some(metadata) = header.strip_prefix("#!") else {
+        return Err(anyhow!("ERR Missing library metadata"));
+    };
+
+    let mut parts = metadata.split_whitespace();
+    let engine = parts.next().unwrap_or_default();
+
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(anyhow!("ERR Engine '{}' not found", engine));
+    }
+
+    let mut name = None;
+
+    for part in parts {
+        match part.split_once('=') {
+            Some(("name", value)) => name = Some(value),
+            _ => return Err(anyhow!("ERR Invalid metadata value given: {}", part))
+        }
+    }
+
+    match name {
+        None => Err(anyhow!("ERR Library name was not given")),
+        Some(name) if !is_valid_name(name) => Err(anyhow!("ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long")),
+        Some(name) => Ok(name.to_string())
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|char| char.is_ascii_alphanumeric() || char == '_')
+}
+
+/// Accepts both `redis.register_function(name, callback)` and the table form with
+/// `function_name`, `callback`, `flags` and `description`.
+fn register_function_args(args: MultiValue) -> std::result::Result<(String, FunctionRegistration), String> {
+    let mut args = args.into_iter();
+
+    let (name, registration) = match (args.next(), args.next(), args.next()) {
+        (Some(LuaValue::String(name)), Some(LuaValue::Function(callback)), None) => {
+            (name.to_string_lossy().into_owned(), FunctionRegistration { callback, description: None, flags: Vec::new() })
+        }
+
+        (Some(LuaValue::Table(table)), None, None) => {
+            let name = match table.raw_get::<_, LuaValue>("function_name") {
+                Ok(LuaValue::String(name)) => name.to_string_lossy().into_owned(),
+                _ => return Err("function_name argument given to redis.register_function must be a string".to_string())
+            };
+
+            let callback = match table.raw_get::<_, LuaValue>("callback") {
+                Ok(LuaValue::Function(callback)) => callback,
+                _ => return Err("callback argument given to redis.register_function must be a function".to_string())
+            };
+
+            let description = match table.raw_get::<_, LuaValue>("description") {
+                Ok(LuaValue::String(description)) => Some(description.to_string_lossy().into_owned()),
+                Ok(LuaValue::Nil) => None,
+                _ => return Err("description argument given to redis.register_function must be a string".to_string())
+            };
+
+            let flags = match table.raw_get::<_, LuaValue>("flags") {
+                Ok(LuaValue::Table(flags)) => flags.sequence_values::<String>()
+                    .collect::<mlua::Result<Vec<String>>>()
+                    .map_err(|_| "Unknown flag given".to_string())?,
+                Ok(LuaValue::Nil) => Vec::new(),
+                _ => return Err("flags argument to redis.register_function must be a table representing function flags".to_string())
+            };
+
+            if let Some(flag) = flags.iter().find(|flag| !FUNCTION_FLAGS.contains(&flag.as_str())) {
+                return Err(format!("Unknown flag given: {}", flag));
+            }
+
+            (name, FunctionRegistration { callback, description, flags })
+        }
+
+        _ => return Err("wrong number of arguments to redis.register_function".to_string())
+    };
+
+    if !is_valid_name(&name) {
+        return Err("Function names can only contain letters, numbers, or underscores(_) and must be at least one character long".to_string());
+    }
+
+    Ok((name, registration))
+}
+
+pub fn sha1_hex(body: &str) -> String {
+    sha1_smol::Sha1::from(body).digest().to_string()
+}
+
+/// The arguments of a `redis.call`, kept as bytes since Lua strings are binary safe.
+fn command_args(args: MultiValue) -> std::result::Result<Vec<Vec<u8>>, String> {
+    if args.is_empty() {
+        return Err("Please specify at least one argument for this redis lib call".to_string());
+    }
+
+    args.into_iter()
+        .map(|arg| match arg {
+            LuaValue::String(s) => Ok(s.as_bytes().to_vec()),
+            LuaValue::Integer(i) => Ok(i.to_string().into_bytes()),
+            LuaValue::Number(n) => Ok(n.to_string().into_bytes()),
+            _ => Err("Lua redis lib command arguments must be strings or integers".to_string())
+        })
+        .collect()
+}
+
+/// A Lua array of strings, like KEYS and ARGV.
+fn strings_table<'lua>(lua: &'lua Lua, values: &[Vec<u8>]) -> mlua::Result<Table<'lua>> {
+    lua.create_sequence_from(values.iter().map(|value| lua.create_string(value)).collect::<mlua::Result<Vec<_>>>()?)
+}
+
+fn error_table<'lua>(lua: &'lua Lua, message: &str) -> mlua::Result<LuaValue<'lua>> {
+    let table = lua.create_table()?;
+    table.set("err", message)?;
+
+    Ok(LuaValue::Table(table))
+}
+
+/// Converts a command reply following Redis's RESP to Lua rules: statuses and errors become
+/// `{ok = ...}`/`{err = ...}` tables and nils become `false`.
+fn resp_to_lua(lua: &Lua, value: Value) -> mlua::Result<LuaValue<'_>> {
+    Ok(match value {
+        Value::SimpleString(s) => {
+            let table = lua.create_table()?;
+            table.set("ok", s)?;
+
+            LuaValue::Table(table)
+        }
+
+        Value::SimpleError(s) => return error_table(lua, &s),
+        Value::BulkString(s) => LuaValue::String(lua.create_string(&s)?),
+        Value::Integer(i) => LuaValue::Integer(i),
+        Value::Boolean(b) => LuaValue::Boolean(b),
+        Value::Array(values) | Value::Push(values) => {
+            let table = lua.create_table()?;
+
+            for (index, value) in values.into_iter().enumerate() {
+                table.raw_set(index + 1, resp_to_lua(lua, value)?)?;
+            }
+
+            LuaValue::Table(table)
+        }
+
+        Value::Map(pairs) => {
+            let table = lua.create_table()?;
+
+            for (key, value) in pairs {
+                table.raw_set(resp_to_lua(lua, key)?, resp_to_lua(lua, value)?)?;
+            }
+
+            LuaValue::Table(table)
+        }
+
+        Value::NullBulkString | Value::NullArray | Value::Null => LuaValue::Boolean(false),
+        Value::Stream(_) | Value::SortedSet(_) | Value::Set(_) | Value::Hash(_) | Value::Module(_) | Value::Raw(_) => LuaValue::Boolean(false)
+    })
+}
+
+/// Converts a script's return value: numbers are truncated to integers, `true` is 1, `false` and nil are nil,
+/// and tables are `{ok}`/`{err}` replies or arrays cut at the first nil.
+fn lua_to_resp(value: LuaValue) -> mlua::Result<Value> {
+    Ok(match value {
+        LuaValue::String(s) => Value::BulkString(s.as_bytes().to_vec()),
+        LuaValue::Integer(i) => Value::Integer(i),
+        LuaValue::Number(n) => Value::Integer(n as i64),
+        LuaValue::Boolean(true) => Value::Integer(1),
+        LuaValue::Table(table) => {
+            if let LuaValue::String(err) = table.raw_get::<_, LuaValue>("err")? {
+                return Ok(Value::SimpleError(err.to_string_lossy().into_owned()));
+            }
+
+            if let LuaValue::String(ok) = table.raw_get::<_, LuaValue>("ok")? {
+                return Ok(Value::SimpleString(ok.to_string_lossy().into_owned()));
+            }
+
+            Value::Array(
+                table.sequence_values::<LuaValue>()
+                    .map(|value| lua_to_resp(value?))
+                    .collect::<mlua::Result<Vec<Value>>>()?
+            )
+        }
+
+        _ => Value::NullBulkString
+    })
+}
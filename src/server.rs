@@ -0,0 +1,449 @@
+use crate::client::ClientId;
+use crate::commands::{CommandContext, CommandExecutor};
+use crate::config::{ConfigKey, Configuration};
+use crate::module::Module;
+use crate::parser::{ParseError, Value};
+use crate::replication;
+use crate::response::RespHandler;
+use crate::scripting::ScriptMonitor;
+use crate::storage::Storage;
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::sync::Notify;
+use tokio::time::{timeout_at, Instant};
+
+const DEFAULT_PORT: u16 = 6379;
+const EXPIRE_CYCLE_INTERVAL: Duration = Duration::from_millis(100);
+const PERSISTENCE_CYCLE_INTERVAL: Duration = Duration::from_millis(100);
+const REPLICATION_CYCLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Runs the server with the given command line arguments, `args[0]` being the program name.
+/// `modules` are loaded before any module named by `--loadmodule`, as if they had been loaded from a library.
+///
+/// Must run on a multi-threaded runtime, like `#[tokio::main]` starts: commands run under the context's
+/// lock with [`tokio::task::block_in_place`], which a current-thread runtime doesn't allow.
+pub async fn run(args: Vec<String>, modules: Vec<Box<dyn Module>>) -> std::io::Result<()> {
+    if Handle::current().runtime_flavor() != RuntimeFlavor::MultiThread {
+        return Err(std::io::Error::other("The server must run on a multi-threaded Tokio runtime"));
+    }
+
+    let mut config = Configuration::new();
+    let mut module_libraries: Vec<(String, Vec<String>)> = Vec::new();
+    let mut master: Option<(String, u16)> = None;
+
+    if args.len() > 1 {
+        let mut cur_index = 1;
+
+        while cur_index < args.len() {
+            if args[cur_index].starts_with("--") {
+                let arg = &args[cur_index];
+
+                match arg.as_str() {
+                    "--port" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::Port, value);
+                    }
+
+                    // The value is the master's host and port, like `--replicaof "127.0.0.1 6379"`.
+                    "--replicaof" => {
+                        let value: Vec<&str> = args[cur_index + 1].split_whitespace().collect();
+
+                        match value.as_slice() {
+                            [host, port] => match port.parse::<u16>() {
+                                Ok(port) => master = Some((host.to_string(), port)),
+                                Err(_) => println!("Invalid master port! {}", port)
+                            },
+
+                            _ => println!("Invalid Argument! --replicaof expects <host> <port>")
+                        }
+                    }
+
+                    "--repl-backlog-size" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::ReplBacklogSize, value);
+                    }
+
+                    "--repl-timeout" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::ReplTimeout, value);
+                    }
+
+                    "--repl-ping-replica-period" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::ReplPingReplicaPeriod, value);
+                    }
+
+                    "--replica-read-only" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::ReplicaReadOnly, value);
+                    }
+
+                    "--replica-serve-stale-data" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::ReplicaServeStaleData, value);
+                    }
+
+                    "--repl-diskless-sync" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::ReplDisklessSync, value);
+                    }
+
+                    "--repl-diskless-load" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::ReplDisklessLoad, value);
+                    }
+
+                    "--dir" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::Dir, value);
+                    },
+
+                    "--dbfilename" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::DbFilename, value);
+                    }
+
+                    "--save" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::Save, value);
+                    }
+
+                    "--appendonly" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::AppendOnly, value);
+                    }
+
+                    "--appendfsync" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::AppendFsync, value);
+                    }
+
+                    "--appendfilename" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::AppendFilename, value);
+                    }
+
+                    "--appenddirname" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::AppendDirname, value);
+                    }
+
+                    "--aof-load-truncated" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::AofLoadTruncated, value);
+                    }
+
+                    "--auto-aof-rewrite-percentage" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::AutoAofRewritePercentage, value);
+                    }
+
+                    "--auto-aof-rewrite-min-size" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::AutoAofRewriteMinSize, value);
+                    }
+
+                    "--aof-use-rdb-preamble" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::AofUseRdbPreamble, value);
+                    }
+
+                    "--notify-keyspace-events" => {
+                        let value = args[cur_index + 1].as_str();
+                        config.set(ConfigKey::NotifyKeyspaceEvents, value);
+                    }
+
+                    // The value is the library path followed by the module's arguments.
+                    "--loadmodule" => {
+                        let mut value = args[cur_index + 1].split_whitespace().map(str::to_string);
+
+                        if let Some(path) = value.next() {
+                            module_libraries.push((path, value.collect::<Vec<_>>()));
+                        }
+                    }
+
+                    _ => println!("Invalid Argument! {}", args[1]),
+                }
+
+                cur_index += 2;
+            } // TODO -> Check if the argument value is present, if not throw an error, just handle this fucking errors and don't be lazy.
+        }
+
+    }
+
+    let port = config.get(ConfigKey::Port).parse::<u16>().unwrap_or(DEFAULT_PORT);
+    let listener = TcpListener::bind(format!("127.0.0.1:{port}")).await?;
+
+    let append_only = config.get(ConfigKey::AppendOnly) == "yes";
+    let shared_executor = Arc::new(CommandExecutor::new());
+    let mut context = CommandContext::new(Storage::new(), config);
+
+    for module in modules {
+        let name = module.name().to_string();
+
+        if let Err(e) = shared_executor.load_module(module, &mut context) {
+            println!("Module {} failed to load: {}", name, e);
+            std::process::exit(1);
+        }
+    }
+
+    for (path, module_args) in module_libraries {
+        if let Err(e) = shared_executor.load_module_library(&path, module_args, &mut context) {
+            println!("Module {} failed to load: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+
+    // Modules are loaded first, so the values of their types can be read back.
+    // When the AOF is on it holds every write, so the RDB file is left alone.
+    if append_only {
+        match shared_executor.load_append_only_files(&mut context) {
+            Ok(true) => println!("DB loaded from append only file"),
+            Ok(false) => {}
+            Err(e) => {
+                println!("Unable to load the append only file! {}", e);
+                std::process::exit(1);
+            }
+        }
+
+        if let Err(e) = context.open_append_only() {
+            println!("Unable to open the append only file! {}", e);
+            std::process::exit(1);
+        }
+    } else {
+        match context.load_rdb() {
+            Ok(true) => println!("Imported data from RDB file"),
+            Ok(false) => {}
+            Err(e) => println!("Unable to import data from RDB file! {}", e),
+        }
+    }
+
+    if master.is_some() {
+        context.set_master(master);
+    }
+
+    let context = Arc::new(Mutex::new(context));
+
+    tokio::spawn(expire_keys(Arc::clone(&context)));
+    tokio::spawn(run_timers(Arc::clone(&context)));
+    tokio::spawn(run_persistence_cron(Arc::clone(&context)));
+    tokio::spawn(run_replication_cron(Arc::clone(&context)));
+    tokio::spawn(replication::run_replica_link(Arc::clone(&shared_executor), Arc::clone(&context)));
+
+    loop {
+        match listener.accept().await {
+            Ok((_socket, addr)) => {
+                println!("Connection established! {addr}...");
+                let executor = Arc::clone(&shared_executor);
+                let context = Arc::clone(&context);
+
+                tokio::spawn(async move {
+                    handle_client(_socket, addr, executor, context).await;
+                });
+            }
+
+            Err(e) => {
+                println!("An error occurred: {:?}", e);
+            }
+        }
+    }
+}
+
+async fn handle_client(socket: TcpStream, address: SocketAddr, command_executor: Arc<CommandExecutor>, context: Arc<Mutex<CommandContext>>) {
+    let (mut handler, sender) = RespHandler::new(socket);
+
+    let (client_id, closing, stream_updates, script_monitor) = {
+        let mut context = context.lock().unwrap();
+        let client_id = context.register_client(sender.clone(), Some(address));
+
+        (client_id, context.client_closing(client_id).unwrap(), context.stream_updates(), context.script_monitor())
+    };
+
+    loop {
+        let value = tokio::select! {
+            value = handler.read_value() => value,
+            _ = closing.notified() => break
+        };
+
+        let value = match value {
+            Ok(Some(value)) => value,
+            Ok(None) => break,
+
+            // The rest of the connection can't be told apart from the garbage, so hang up after saying why.
+            Err(e) => {
+                if let Some(e) = e.downcast_ref::<ParseError>() {
+                    let _ = sender.send(Value::SimpleError(format!("ERR Protocol error: {}", e)));
+                }
+
+                break;
+            }
+        };
+
+        let (command, args) = match extract_command(value) {
+            Ok(command) => command,
+            Err(e) => {
+                let _ = sender.send(Value::SimpleError(format!("ERR {}", e)));
+                continue;
+            }
+        };
+
+        // A long script holds the context lock, so answer right away rather than queue behind it.
+        if script_monitor.is_busy() {
+            let _ = sender.send(busy_reply(&script_monitor, &command, &args));
+            continue;
+        }
+
+        if !execute(&command_executor, &context, &stream_updates, &mut handler, &closing, client_id, command, args).await {
+            break;
+        }
+    }
+
+    context.lock().unwrap().unregister_client(client_id);
+}
+
+/// Periodically sweeps expired keys so their expiration is announced even if nobody reads them.
+async fn expire_keys(context: Arc<Mutex<CommandContext>>) {
+    let mut interval = tokio::time::interval(EXPIRE_CYCLE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        // Skip the cycle while a command holds the context rather than stall a worker thread on it.
+        if let Ok(mut context) = context.try_lock() {
+            context.expire_keys();
+        }
+    }
+}
+
+/// Collects finished background saves, starts new ones as the `save` rules ask, and flushes the AOF.
+async fn run_persistence_cron(context: Arc<Mutex<CommandContext>>) {
+    let mut interval = tokio::time::interval(PERSISTENCE_CYCLE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Ok(mut context) = context.try_lock() {
+            context.persistence_cron();
+        }
+    }
+}
+
+/// Pings replicas now and then, so they can tell an idle master from a lost one.
+async fn run_replication_cron(context: Arc<Mutex<CommandContext>>) {
+    let mut interval = tokio::time::interval(REPLICATION_CYCLE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Ok(mut context) = context.try_lock() {
+            context.replication_cron();
+        }
+    }
+}
+
+/// Fires module timers as they come due, waking up early whenever a timer is created.
+async fn run_timers(context: Arc<Mutex<CommandContext>>) {
+    let timer_updates = context.lock().unwrap().timer_updates();
+
+    loop {
+        // Registered before firing so a timer created meanwhile can't be missed.
+        let updated = timer_updates.notified();
+        let next_deadline = tokio::task::block_in_place(|| context.lock().unwrap().fire_timers());
+
+        match next_deadline {
+            Some(deadline) => {
+                let _ = timeout_at(Instant::from_std(deadline), updated).await;
+            }
+
+            None => updated.await
+        }
+    }
+}
+
+/// Runs a command and queues its reply, parking the client for as long as it asks to block.
+/// Replies are queued while the context is still locked so they can't be overtaken by messages published in between.
+/// Returns false if the connection closed while the client was blocked.
+#[allow(clippy::too_many_arguments)]
+async fn execute(command_executor: &CommandExecutor, context: &Mutex<CommandContext>, stream_updates: &Notify, handler: &mut RespHandler, closing: &Notify, client_id: ClientId, command: String, mut args: Vec<Value>) -> bool {
+    let mut deadline: Option<Option<Instant>> = None;
+
+    loop {
+        // Registered before running the command so an update landing right after it can't be missed.
+        let updated = stream_updates.notified();
+
+        // Run off the async workers, as a script may hold the context for a long time and the
+        // connections must stay responsive to answer BUSY and SCRIPT KILL meanwhile.
+        let (response, request) = match tokio::task::block_in_place(|| {
+            let mut context = context.lock().unwrap();
+            context.set_client(client_id);
+
+            let response = command_executor.try_exec(command.to_lowercase(), args, &mut context)
+                .unwrap_or_else(|e| Value::SimpleError(format!("ERR {}", e)));
+
+            // Logged before the reply is queued, so with `appendfsync always` a client only hears
+            // back about a write once it is on disk.
+            context.flush_propagated();
+
+            match context.take_blocking_request() {
+                Some(request) => Some((response, request)),
+                None => {
+                    context.reply(client_id, response);
+                    None
+                }
+            }
+        }) {
+            Some(blocked) => blocked,
+            None => return true
+        };
+
+        let deadline = *deadline.get_or_insert_with(|| (!request.timeout.is_zero()).then(|| Instant::now() + request.timeout));
+        args = request.retry_args;
+
+        let woken = async {
+            match deadline {
+                Some(deadline) => timeout_at(deadline, updated).await.is_ok(),
+                None => {
+                    updated.await;
+                    true
+                }
+            }
+        };
+
+        // A client blocked forever must still be let go of as soon as it hangs up.
+        tokio::select! {
+            woken = woken => if !woken {
+                tokio::task::block_in_place(|| context.lock().unwrap().reply(client_id, response));
+                return true;
+            },
+
+            () = handler.closed() => return false,
+            () = closing.notified() => return false
+        }
+    }
+}
+
+/// The reply given to a client while a script has been running past `busy-reply-threshold`.
+fn busy_reply(script_monitor: &ScriptMonitor, command: &str, args: &[Value]) -> Value {
+    let is_script_kill = command == "script" && args.len() == 1
+        && args[0].clone().unpack_as_string().is_some_and(|sub_command| sub_command.eq_ignore_ascii_case("kill"));
+
+    if is_script_kill {
+        return script_monitor.kill();
+    }
+
+    Value::SimpleError("BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSAVE.".to_string())
+}
+
+fn extract_command(value: Value) -> Result<(String, Vec<Value>)> {
+    match value {
+        Value::Array(mut arr) if !arr.is_empty() => match arr.remove(0).unpack_as_string() {
+            Some(command) => Ok((command.to_lowercase(), arr)),
+            None => Err(anyhow::anyhow!("invalid command name, expected a UTF-8 string"))
+        },
+        _ => Err(anyhow::anyhow!("Invalid command format!"))
+    }
+}
\ No newline at end of file
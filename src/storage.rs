@@ -1,60 +1,132 @@
+use crate::notifications::{KeyspaceEvent, NOTIFY_EXPIRED, NOTIFY_GENERIC, NOTIFY_NEW};
 use crate::parser::{Type, Value};
+use crate::rdb;
 use anyhow::{anyhow, Result};
-use bytes::Buf;
 use std::collections::HashMap;
-use std::env::var;
 use std::fs::File;
-use std::io::Read;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::time::SystemTime;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct Storage {
-    values: HashMap<String, DataContainer>
+    values: HashMap<String, DataContainer>,
+    events: Vec<KeyspaceEvent>,
+    pub expiry: ExpiryMode,
+    /// The keys deleted because their time to live elapsed, for the master to tell replicas and the AOF.
+    expired: Vec<String>,
+    /// The keys set, removed or borrowed mutably, for WATCH to notice every write, not only those raising events.
+    touched: Vec<String>
+}
+
+/// What happens to keys whose time to live elapsed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ExpiryMode {
+    /// They are deleted as soon as they are accessed or swept, as on a master.
+    #[default]
+    Delete,
+    /// They look missing but are kept, as on a replica, until the DEL of its master removes them.
+    Hide,
+    /// They are still live, for the writes a replica applies from its master.
+    Ignore
 }
 
 impl Storage {
     pub fn new() -> Storage {
         Storage {
-            values: HashMap::new()
+            values: HashMap::new(),
+            events: Vec::new(),
+            expiry: ExpiryMode::Delete,
+            expired: Vec::new(),
+            touched: Vec::new()
         }
     }
 
-    pub fn load_from_rdb(path: String) -> Result<Storage> {
-        let rdb_file = RDBFile::from(path)?;
-
-        Ok(
-            Storage {
-                values: rdb_file.data
-            }
-        )
-    }
-
     pub fn import_data(&mut self, rdb_file: RDBFile) {
         self.values.extend(rdb_file.data);
     }
 
     pub fn set(&mut self, key: &str, value: Value, expire: Option<SystemTime>) -> Value {
-        self.values.insert(key.to_string(), DataContainer::create(value, expire));
+        self.expire_if_needed(key);
+        self.touched.push(key.to_string());
+
+        if self.values.insert(key.to_string(), DataContainer::create(value, expire)).is_none() {
+            self.notify(NOTIFY_NEW, "new", key);
+        }
+
         Value::SimpleString("OK".to_string())
     }
 
-    pub fn add_all(&mut self, values: HashMap<String, DataContainer>) {
-        self.values.extend(values)
+    pub fn get(&mut self, key: &str) -> Option<Value> {
+        self.expire_if_needed(key);
+        self.values.get(key).filter(|container| self.is_visible(container)).map(|container| container.get_value())
     }
 
-    pub fn get(&mut self, key: &str) -> Option<Value> {
-        match self.values.get(key) {
-            Some(container) => if !container.is_expired() {
-                Some(container.get_value())
-            } else {
-                self.values.remove(&key.to_string());
-                None
-            }
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        self.expire_if_needed(key);
+        let expiry = self.expiry;
+
+        let value = self.values.get_mut(key)
+            .filter(|container| expiry == ExpiryMode::Ignore || !container.is_expired())
+            .map(|container| &mut container.value);
+
+        if value.is_some() {
+            self.touched.push(key.to_string());
+        }
+
+        value
+    }
+
+    pub fn contains_key(&mut self, key: &str) -> bool {
+        self.expire_if_needed(key);
+        self.values.get(key).is_some_and(|container| self.is_visible(container))
+    }
+
+    fn is_visible(&self, container: &DataContainer) -> bool {
+        self.expiry == ExpiryMode::Ignore || !container.is_expired()
+    }
+
+    /// Removes every key whose time to live has elapsed, so expirations are noticed without an access.
+    pub fn remove_expired(&mut self) {
+        let expired: Vec<String> = self.values
+            .iter()
+            .filter(|(_, container)| container.is_expired())
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in expired {
+            self.expire_if_needed(&key);
+        }
+    }
+
+    /// Queues a keyspace event; the command context publishes them once the command is done.
+    pub fn notify(&mut self, class: u32, event: &'static str, key: &str) {
+        self.events.push(KeyspaceEvent { class, event, key: key.to_string() });
+    }
+
+    pub fn take_events(&mut self) -> Vec<KeyspaceEvent> {
+        std::mem::take(&mut self.events)
+    }
 
-            None => None
+    /// Drops `key` if its time to live has elapsed, raising an `expired` event. Only done in
+    /// [`ExpiryMode::Delete`].
+    pub fn expire_if_needed(&mut self, key: &str) {
+        if self.expiry == ExpiryMode::Delete && self.values.get(key).is_some_and(|container| container.is_expired()) {
+            self.values.remove(key);
+            self.notify(NOTIFY_EXPIRED, "expired", key);
+            self.expired.push(key.to_string());
         }
     }
 
+    pub fn take_expired(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.expired)
+    }
+
+    pub fn take_touched(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.touched)
+    }
+
+    #[allow(dead_code)]
     pub fn get_specific(&mut self, value_type: Type) -> Vec<DataContainer> {
         self.values
             .values()
@@ -64,16 +136,34 @@ impl Storage {
     }
 
     pub fn remove(&mut self, key: &str) -> Result<Value> {
-        self.values.remove(key);
+        self.expire_if_needed(key);
+
+        if self.values.remove(key).is_some() {
+            self.touched.push(key.to_string());
+            self.notify(NOTIFY_GENERIC, "del", key);
+        }
+
         Ok(Value::SimpleString("OK".to_string()))
     }
 
-    pub fn keys(&self) -> Vec<String> {
-        self.values.keys().map(|k| k.to_string()).collect()
+    /// Copies every live key with its value and expiry, for saving.
+    pub fn snapshot(&self) -> Vec<(String, Value, Option<SystemTime>)> {
+        self.values
+            .iter()
+            .filter(|(_, container)| !container.is_expired())
+            .map(|(key, container)| (key.clone(), container.value.clone(), container.expire))
+            .collect()
     }
 
-    pub fn get_all(self) -> HashMap<String, DataContainer> {
-         self.values
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.values.iter()
+            .filter(|(_, container)| self.is_visible(container))
+            .map(|(k, _)| k.to_string())
+            .collect()
     }
 }
 
@@ -103,146 +193,133 @@ impl DataContainer {
     }
 }
 
-pub enum RDBValidationResult {
-    Valid,
-    TooShort,
-    MissingRedisMagicString
-}
-
 pub struct RDBFile {
     data: HashMap<String, DataContainer>,
+    functions: Vec<String>
 }
 
 impl RDBFile {
-    pub fn from(file_path: String) -> Result<RDBFile> {
-        if !file_path.ends_with(".rdb") {
-            return Err(anyhow!("File does not end with '.rdb'"));
-        }
+    pub fn from<P: AsRef<Path>>(file_path: P, module_types: rdb::ModuleTypeLookup) -> Result<RDBFile> {
+        RDBFile::read(BufReader::new(File::open(file_path)?), module_types)
+    }
 
-        let mut file = File::open(file_path)?;
+    /// Reads an RDB file from anywhere, like one a master streams to a replica loading it without the disk.
+    pub fn read<R: Read>(reader: R, module_types: rdb::ModuleTypeLookup) -> Result<RDBFile> {
+        let mut reader = rdb::RdbReader::new(reader);
         let mut data: HashMap<String, DataContainer> = HashMap::new();
-        let mut buffer = Vec::new();
-        let mut cursor = 5;
-
-        file.read_to_end(&mut buffer)?;
-
-        match Self::is_valid(&buffer) {
-            RDBValidationResult::TooShort => return Err(anyhow!("Invalid RDB file! File is too short")),
-            RDBValidationResult::MissingRedisMagicString => return Err(anyhow!("Missing RDB file! Missing Redis Magic String")),
-            RDBValidationResult::Valid => {
-                while buffer[cursor] != 0xFF {
-                    if buffer[cursor] != 0xFB {
-                        cursor += 1;
-                        continue;
-                    }
-
-                    cursor += 1;
-                    let hash_table_size = buffer[cursor] as usize;
-                    cursor += 2; // ADDED 1 TO SKIP EXPIRE HASH TABLE SIZE
-
-                    println!("Hash table size: {}", hash_table_size);
+        let mut functions: Vec<String> = Vec::new();
+        let mut skipped = 0;
 
-                    for _ in 0..hash_table_size {
-                        println!("1) Current byte to read: {:?}", buffer[cursor]);
+        reader.read_file(module_types, |item| {
+            match item {
+                rdb::RdbItem::Function(code) => functions.push(code),
 
-                        let expire: Option<SystemTime> = match buffer[cursor] {
-                            0xFD => {
-                                let slice: [u8; 4] = buffer[cursor + 1..cursor + 5].try_into()?;
-                                cursor += 5;
-                                println!("Reading from FD");
-                                Some(UNIX_EPOCH + Duration::from_secs(u32::from_le_bytes(slice) as u64))
-                            }
+                // Only a single database is served, so keys of any other one are dropped.
+                rdb::RdbItem::Key { db, .. } if db != 0 => skipped += 1,
 
-                            0xFC => {
-                                let slice: [u8; 8] = buffer[cursor + 1..cursor + 9].try_into()?;
-                                cursor += 9;
-                                println!("Reading from FC");
-                                Some(UNIX_EPOCH + Duration::from_millis(u64::from_le_bytes(slice)))
-                            }
-
-                            _ => None
-                        };
-
-                        cursor += 1; // ADDED 1 TO SKIP VALUE TYPE
-
-                        println!("2) Current byte to read: {:?}", buffer[cursor]);
-
-                        let (key, key_length) = read_length_encoded_string(&buffer[cursor..])?;
-                        cursor += key_length;
-
-                        let (value, value_length) = read_length_encoded_string(&buffer[cursor..])?;
-                        cursor += value_length;
-
-                        println!("Key: {}, Value: {}, Expiration: {:?}", key, value, expire);
-
-                        let data_container = DataContainer::create(Value::BulkString(value), expire);
-
-                        if data_container.is_expired() {
-                            continue;
-                        }
+                rdb::RdbItem::Key { key, value, expire, .. } => {
+                    let data_container = DataContainer::create(value, expire);
 
+                    if !data_container.is_expired() {
                         data.insert(key, data_container);
                     }
                 }
+
+                rdb::RdbItem::Aux(key, value) if key == "redis-ver" => println!("Loading RDB produced by version {}", value),
+                rdb::RdbItem::Aux(..) => {}
             }
+
+            Ok(())
+        })?;
+
+        if skipped > 0 {
+            println!("Skipped {} keys stored outside of database 0", skipped);
         }
 
-         Ok(
+        Ok(
             RDBFile {
-                data
+                data,
+                functions
             }
         )
     }
 
-    fn is_valid(buffer: &Vec<u8>) -> RDBValidationResult {
-        if buffer.len() < 5 {
-            return RDBValidationResult::TooShort;
-        }
-
-        if String::from_utf8(buffer[..5].to_vec()).unwrap().as_str() != "REDIS" {
-            return RDBValidationResult::MissingRedisMagicString;
-        }
-
-        RDBValidationResult::Valid
+    /// Takes the code of the function libraries stored in the file.
+    pub fn take_functions(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.functions)
     }
 }
 
-fn read_length_encoded_int(bytes: &[u8]) -> Result<u64> {
-    let first_byte = bytes[0];
+/// Serializes a value the way DUMP does: its RDB type and encoding, then the RDB version and a CRC64.
+pub fn dump_value(value: &Value, module_types: rdb::ModuleTypeLookup) -> Result<Vec<u8>> {
+    let mut payload = Vec::new();
 
-    match first_byte {
-        0xFD => Ok(u64::from(first_byte)),
-        0xFC => {
-            let second_byte = bytes[1];
-            Ok(((first_byte as u64 & 0x3F) << 8) | second_byte as u64)
-        }
+    let mut writer = rdb::RdbWriter::new(&mut payload);
+    writer.write_u8(rdb::value_type(value))?;
+    writer.write_value(value, module_types)?;
 
-        0xFF => {
-            let mut buff: [u8; 4] = [0; 4];
-            bytes.reader().read_exact(&mut buff)?;
-            Ok(u32::from_be_bytes(buff) as u64)
-        }
+    rdb::write_payload_footer(&mut payload);
+    Ok(payload)
+}
+
+/// Decodes a DUMP payload with the RDB loader, so every type and encoding it reads can be restored.
+pub fn restore_value(payload: &[u8], module_types: rdb::ModuleTypeLookup) -> Result<Value> {
+    let body = rdb::verify_payload(payload)?;
+    let mut reader = rdb::RdbReader::new(body);
 
-        0xD0 => Ok(u64::from_be_bytes(bytes.try_into()?)),
+    let value = reader.read_u8()
+        .and_then(|value_type| reader.read_value(value_type, module_types))
+        .map_err(|_| anyhow!("ERR Bad data format"))?;
 
-        _ => Err(anyhow!("Error reading length encoded integer!"))
+    // Trailing bytes mean the payload holds something other than a single value.
+    match reader.try_read_u8() {
+        Ok(None) => Ok(value),
+        _ => Err(anyhow!("ERR Bad data format"))
     }
 }
 
-fn read_length_encoded_string(bytes: &[u8]) -> Result<(String, usize)> {
-    if bytes.is_empty() {
-        return Err(anyhow!("Input is empty! cannot read length."));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_message;
+
+    const NO_MODULES: rdb::ModuleTypeLookup = &|_| None;
+
+    /// What Redis answers to `SET mykey 10` then `DUMP mykey`: an int encoded string, RDB version 9.
+    const REDIS_PAYLOAD: &[u8] = b"\x00\xc0\x0a\x09\x00\xbe\x6d\x06\x89\x5a\x28\x00\x0a";
+
+    #[test]
+    fn restores_a_redis_payload() {
+        let value = restore_value(REDIS_PAYLOAD, NO_MODULES).unwrap();
+        assert!(matches!(value, Value::BulkString(bytes) if bytes == b"10"));
     }
 
-    let str_len = bytes[0] as usize;
-    if bytes.len() < str_len + 1 {
-        return Err(anyhow!("Not enough bytes to read the full string. Expected {}, got {}.", str_len, bytes.len() - 1));
+    #[test]
+    fn rejects_a_corrupted_redis_payload() {
+        let mut payload = REDIS_PAYLOAD.to_vec();
+        payload[2] = 0x0b;
+
+        assert!(restore_value(&payload, NO_MODULES).is_err());
     }
 
-    let string_slice = &bytes[1..=str_len];
-    let string = String::from_utf8(string_slice.to_vec())
-        .map_err(|_| anyhow!("Invalid UTF-8 sequence in string"))?
-        .to_string();
+    #[test]
+    fn binary_payloads_survive_the_protocol() {
+        let value = restore_value(REDIS_PAYLOAD, NO_MODULES).unwrap();
+        let binary = Value::BulkString(vec![0xff, 0x00, 0x80, b'\r', b'\n', 0xc3]);
+
+        for value in [value, binary] {
+            let payload = dump_value(&value, NO_MODULES).unwrap();
 
-    Ok((string, str_len + 1))
-}
\ No newline at end of file
+            // Sent as a bulk string and read back, like a client piping DUMP into RESTORE.
+            let wire = Value::BulkString(payload.clone()).serialize();
+            let (parsed, consumed) = parse_message(&wire).unwrap();
+            assert_eq!(consumed, wire.len());
+
+            let received = parsed.unpack_as_bytes().unwrap();
+            assert_eq!(received, payload);
+
+            let restored = restore_value(&received, NO_MODULES).unwrap();
+            assert_eq!(restored.unpack_as_bytes(), value.unpack_as_bytes());
+        }
+    }
+}
@@ -0,0 +1,864 @@
+use crate::parser::Value;
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const INVALID_STREAM_ID_ERROR: &str = "ERR Invalid stream ID specified as stream command argument";
+
+/// Maximum number of entries packed into a single node before a new one is started.
+pub const STREAM_NODE_MAX_ENTRIES: usize = 100;
+
+/// A stream entry ID, ordered first by milliseconds and then by sequence number.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub millis: u64,
+    pub seq: u64
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { millis: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId { millis: u64::MAX, seq: u64::MAX };
+
+    pub fn new(millis: u64, seq: u64) -> StreamId {
+        StreamId { millis, seq }
+    }
+
+    /// Parses `<ms>-<seq>`, or an incomplete `<ms>` whose sequence defaults to `missing_seq`.
+    pub fn parse(id: &str, missing_seq: u64) -> Result<StreamId> {
+        let (millis, seq) = match id.split_once('-') {
+            Some((millis, seq)) => (millis, Some(seq)),
+            None => (id, None)
+        };
+
+        let millis = millis.parse::<u64>().map_err(|_| anyhow!(INVALID_STREAM_ID_ERROR))?;
+        let seq = match seq {
+            Some(seq) => seq.parse::<u64>().map_err(|_| anyhow!(INVALID_STREAM_ID_ERROR))?,
+            None => missing_seq
+        };
+
+        Ok(StreamId::new(millis, seq))
+    }
+
+    /// Parses the start of an XRANGE interval: `-`, `(<id>` for an exclusive bound, or an (incomplete) ID.
+    pub fn parse_range_start(id: &str) -> Result<StreamId> {
+        if id == "-" {
+            return Ok(StreamId::MIN);
+        }
+
+        match id.strip_prefix('(') {
+            Some(id) => StreamId::parse(id, 0)?
+                .next()
+                .ok_or(anyhow!("ERR invalid start ID for the interval")),
+
+            None => StreamId::parse(id, 0)
+        }
+    }
+
+    /// Parses the end of an XRANGE interval: `+`, `(<id>` for an exclusive bound, or an (incomplete) ID.
+    pub fn parse_range_end(id: &str) -> Result<StreamId> {
+        if id == "+" {
+            return Ok(StreamId::MAX);
+        }
+
+        match id.strip_prefix('(') {
+            Some(id) => StreamId::parse(id, u64::MAX)?
+                .prev()
+                .ok_or(anyhow!("ERR invalid end ID for the interval")),
+
+            None => StreamId::parse(id, u64::MAX)
+        }
+    }
+
+    /// The smallest ID strictly greater than this one.
+    pub fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId::new(self.millis, seq)),
+            None => self.millis.checked_add(1).map(|millis| StreamId::new(millis, 0))
+        }
+    }
+
+    /// The largest ID strictly smaller than this one.
+    pub fn prev(self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId::new(self.millis, seq)),
+            None => self.millis.checked_sub(1).map(|millis| StreamId::new(millis, u64::MAX))
+        }
+    }
+}
+
+impl Display for StreamId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.millis, self.seq)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamEntry {
+    pub id: StreamId,
+    pub fields: Vec<(String, String)>
+}
+
+impl StreamEntry {
+    pub fn as_array_value(&self) -> Value {
+        Value::Array(vec![
+            Value::BulkString(self.id.to_string().into()),
+            Value::Array(
+                self.fields
+                    .iter()
+                    .flat_map(|(field, value)| [Value::BulkString(field.clone().into()), Value::BulkString(value.clone().into())])
+                    .collect()
+            ),
+        ])
+    }
+}
+
+/// An append-only log of entries, split into nodes indexed by the ID of their first entry.
+///
+/// Like Redis' rax of listpacks, each node stores its entries as ID deltas from the node's
+/// master ID, and entries sharing the node's master field names only store their values.
+#[derive(Clone, Debug, Default)]
+pub struct Stream {
+    nodes: BTreeMap<StreamId, StreamNode>,
+    length: usize,
+    last_id: StreamId,
+    max_deleted_entry_id: StreamId,
+    entries_added: u64,
+    groups: BTreeMap<String, ConsumerGroup>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrimStrategy {
+    MaxLen(usize),
+    MinId(StreamId)
+}
+
+impl Stream {
+    pub fn new() -> Stream {
+        Stream::default()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// Appends an entry, the caller is responsible for `id` being greater than `last_id`.
+    pub fn append(&mut self, id: StreamId, fields: Vec<(String, String)>) {
+        match self.nodes.last_entry() {
+            Some(mut node) if node.get().entries.len() < STREAM_NODE_MAX_ENTRIES => node.get_mut().push(id, fields),
+            _ => {
+                self.nodes.insert(id, StreamNode::new(id, fields));
+            }
+        }
+
+        self.length += 1;
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    pub fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn max_deleted_entry_id(&self) -> StreamId {
+        self.max_deleted_entry_id
+    }
+
+    /// Number of nodes the entries are packed into.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn groups(&self) -> &BTreeMap<String, ConsumerGroup> {
+        &self.groups
+    }
+
+    pub fn first_id(&self) -> Option<StreamId> {
+        self.first_entry().map(|entry| entry.id)
+    }
+
+    pub fn first_entry(&self) -> Option<StreamEntry> {
+        self.range(StreamId::MIN, StreamId::MAX).next()
+    }
+
+    pub fn get(&self, id: StreamId) -> Option<StreamEntry> {
+        self.range(id, id).next()
+    }
+
+    pub fn last_entry(&self) -> Option<StreamEntry> {
+        self.rev_range(StreamId::MIN, StreamId::MAX).next()
+    }
+
+    /// Overrides the stream's bookkeeping, as done by XSETID and replication.
+    pub fn set_id(&mut self, last_id: StreamId, entries_added: Option<u64>, max_deleted_entry_id: Option<StreamId>) {
+        self.last_id = last_id;
+
+        if let Some(entries_added) = entries_added {
+            self.entries_added = entries_added;
+        }
+
+        if let Some(max_deleted_entry_id) = max_deleted_entry_id {
+            self.max_deleted_entry_id = max_deleted_entry_id;
+        }
+    }
+
+    /// Marks an entry as deleted, dropping its node once no live entries remain. Returns false if it didn't exist.
+    pub fn delete(&mut self, id: StreamId) -> bool {
+        let Some((master_id, node)) = self.nodes.range_mut(..=id).next_back() else {
+            return false;
+        };
+
+        if !node.delete(id) {
+            return false;
+        }
+
+        if node.live == 0 {
+            let master_id = *master_id;
+            self.nodes.remove(&master_id);
+        }
+
+        self.length -= 1;
+        self.max_deleted_entry_id = self.max_deleted_entry_id.max(id);
+
+        true
+    }
+
+    /// Whether entries at or after `start` may have been deleted, which makes consumer group
+    /// counters unreliable.
+    pub fn has_tombstones_after(&self, start: StreamId) -> bool {
+        self.length != 0 && self.max_deleted_entry_id != StreamId::MIN && start <= self.max_deleted_entry_id
+    }
+
+    /// How many entries the group has yet to be delivered, if it can be computed.
+    pub fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+
+        match group.entries_read {
+            Some(entries_read) if !self.has_tombstones_after(group.last_delivered_id) => Some(self.entries_added.saturating_sub(entries_read)),
+            _ => self.estimate_entries_read(group.last_delivered_id).map(|entries_read| self.entries_added.saturating_sub(entries_read))
+        }
+    }
+
+    /// Evicts entries from the head of the stream, returning how many were deleted.
+    ///
+    /// Approximate trimming only ever drops whole nodes, and stops once `limit` entries
+    /// would be exceeded (0 meaning no limit).
+    pub fn trim(&mut self, strategy: TrimStrategy, approximate: bool, limit: usize) -> usize {
+        let mut deleted = 0;
+
+        while let Some(mut first_node) = self.nodes.first_entry() {
+            let node = first_node.get_mut();
+            let node_len = node.live;
+            let node_last_id = node.id_at(node.entries.len() - 1);
+
+            let remove_node = match strategy {
+                TrimStrategy::MaxLen(max_len) => self.length - node_len >= max_len,
+                TrimStrategy::MinId(min_id) => node_last_id < min_id
+            };
+
+            if remove_node {
+                if limit != 0 && deleted + node_len > limit {
+                    break;
+                }
+
+                self.max_deleted_entry_id = self.max_deleted_entry_id.max(node_last_id);
+                self.length -= node_len;
+                deleted += node_len;
+
+                first_node.remove();
+                continue;
+            }
+
+            if approximate {
+                break;
+            }
+
+            let mut removed = 0;
+            let mut cut = 0;
+
+            for index in (0..node.entries.len()).filter(|index| !node.entries[*index].deleted) {
+                let id = node.id_at(index);
+
+                let remove = match strategy {
+                    TrimStrategy::MaxLen(max_len) => self.length - removed > max_len,
+                    TrimStrategy::MinId(min_id) => id < min_id
+                };
+
+                if !remove {
+                    break;
+                }
+
+                self.max_deleted_entry_id = self.max_deleted_entry_id.max(id);
+                removed += 1;
+                cut = index + 1;
+            }
+
+            node.entries.drain(..cut);
+            node.live -= removed;
+
+            self.length -= removed;
+            deleted += removed;
+
+            break;
+        }
+
+        deleted
+    }
+
+    /// Estimates how many entries were ever added up to and including `id`, which is only
+    /// possible while no deletions have fragmented the stream ahead of it.
+    pub fn estimate_entries_read(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+
+        if self.length == 0 && id <= self.max_deleted_entry_id {
+            return Some(self.entries_added);
+        }
+
+        if id == self.last_id {
+            return Some(self.entries_added);
+        }
+
+        if id > self.last_id {
+            return None;
+        }
+
+        let first_id = self.first_id().unwrap_or(self.last_id);
+
+        if self.max_deleted_entry_id == StreamId::MIN || self.max_deleted_entry_id < first_id {
+            if id < first_id {
+                return Some(self.entries_added - self.length as u64);
+            }
+
+            if id == first_id {
+                return Some(self.entries_added - self.length as u64 + 1);
+            }
+        }
+
+        None
+    }
+
+    pub fn group(&self, name: &str) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+
+    pub fn group_mut(&mut self, name: &str) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+
+    /// Creates a consumer group delivering entries after `last_delivered_id`, returning false if it already exists.
+    pub fn create_group(&mut self, name: &str, last_delivered_id: StreamId, entries_read: Option<u64>) -> bool {
+        if self.groups.contains_key(name) {
+            return false;
+        }
+
+        self.groups.insert(name.to_string(), ConsumerGroup::new(last_delivered_id, entries_read));
+        true
+    }
+
+    pub fn destroy_group(&mut self, name: &str) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    /// Delivers up to `count` entries the group has never seen to `consumer`, adding them to
+    /// the pending entries list unless `no_ack` is set.
+    pub fn read_group(&mut self, group_name: &str, consumer: &str, count: Option<usize>, no_ack: bool, now: u64) -> Vec<StreamEntry> {
+        let Some(start) = self.groups.get(group_name).and_then(|group| group.last_delivered_id.next()) else {
+            return vec![];
+        };
+
+        let entries: Vec<StreamEntry> = self.range(start, StreamId::MAX)
+            .take(count.unwrap_or(usize::MAX))
+            .collect();
+
+        let counters: Vec<(bool, Option<u64>)> = entries.iter()
+            .map(|entry| (self.has_tombstones_after(entry.id), self.estimate_entries_read(entry.id)))
+            .collect();
+
+        let group = self.groups.get_mut(group_name).unwrap();
+
+        group.touch_consumer(consumer, now);
+
+        for (entry, (tombstones, estimate)) in entries.iter().zip(counters) {
+            group.entries_read = match group.entries_read {
+                Some(entries_read) if !tombstones => Some(entries_read + 1),
+                _ => estimate
+            };
+
+            group.last_delivered_id = entry.id;
+
+            if !no_ack {
+                group.deliver(entry.id, consumer, now);
+            }
+        }
+
+        if !entries.is_empty() {
+            group.consumers.get_mut(consumer).unwrap().active_time = Some(now);
+        }
+
+        entries
+    }
+
+    /// Iterates the entries with IDs in the inclusive range `[start, end]`, seeking straight to
+    /// the node that may contain `start`.
+    pub fn range(&self, start: StreamId, end: StreamId) -> impl Iterator<Item = StreamEntry> + '_ {
+        let first_node = self.nodes
+            .range(..=start)
+            .next_back()
+            .map(|(master_id, _)| *master_id)
+            .unwrap_or(StreamId::MIN);
+
+        self.nodes
+            .range(first_node..)
+            .flat_map(|(_, node)| node.entries())
+            .skip_while(move |entry| entry.id < start)
+            .take_while(move |entry| entry.id <= end)
+    }
+
+    /// Iterates the entries with IDs in the inclusive range `[start, end]` from newest to oldest.
+    pub fn rev_range(&self, start: StreamId, end: StreamId) -> impl Iterator<Item = StreamEntry> + '_ {
+        self.nodes
+            .range(..=end)
+            .rev()
+            .flat_map(|(_, node)| node.entries().rev())
+            .skip_while(move |entry| entry.id > end)
+            .take_while(move |entry| entry.id >= start)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct StreamNode {
+    master_id: StreamId,
+    master_fields: Vec<String>,
+    entries: Vec<PackedEntry>,
+    live: usize
+}
+
+#[derive(Clone, Debug)]
+struct PackedEntry {
+    millis_delta: u64,
+    seq_delta: u64,
+    deleted: bool,
+    fields: PackedFields
+}
+
+#[derive(Clone, Debug)]
+enum PackedFields {
+    SameAsMaster(Vec<String>),
+    Own(Vec<(String, String)>)
+}
+
+impl StreamNode {
+    fn new(master_id: StreamId, fields: Vec<(String, String)>) -> StreamNode {
+        let mut node = StreamNode {
+            master_id,
+            master_fields: fields.iter().map(|(field, _)| field.clone()).collect(),
+            entries: vec![],
+            live: 0
+        };
+
+        node.push(master_id, fields);
+        node
+    }
+
+    fn push(&mut self, id: StreamId, fields: Vec<(String, String)>) {
+        let same_fields = fields.len() == self.master_fields.len() &&
+            fields.iter().zip(&self.master_fields).all(|((field, _), master)| field == master);
+
+        let fields = if same_fields {
+            PackedFields::SameAsMaster(fields.into_iter().map(|(_, value)| value).collect())
+        } else {
+            PackedFields::Own(fields)
+        };
+
+        self.entries.push(PackedEntry {
+            millis_delta: id.millis.wrapping_sub(self.master_id.millis),
+            seq_delta: id.seq.wrapping_sub(self.master_id.seq),
+            deleted: false,
+            fields
+        });
+
+        self.live += 1;
+    }
+
+    fn delete(&mut self, id: StreamId) -> bool {
+        let Some(index) = (0..self.entries.len()).find(|index| self.id_at(*index) == id) else {
+            return false;
+        };
+
+        if self.entries[index].deleted {
+            return false;
+        }
+
+        self.entries[index].deleted = true;
+        self.live -= 1;
+
+        true
+    }
+
+    fn id_at(&self, index: usize) -> StreamId {
+        let entry = &self.entries[index];
+
+        StreamId::new(
+            self.master_id.millis.wrapping_add(entry.millis_delta),
+            self.master_id.seq.wrapping_add(entry.seq_delta)
+        )
+    }
+
+    fn entries(&self) -> impl DoubleEndedIterator<Item = StreamEntry> + '_ {
+        (0..self.entries.len())
+            .filter(|index| !self.entries[*index].deleted)
+            .map(|index| self.unpack(index))
+    }
+
+    fn unpack(&self, index: usize) -> StreamEntry {
+        let id = self.id_at(index);
+        let entry = &self.entries[index];
+
+        let fields = match &entry.fields {
+            PackedFields::SameAsMaster(values) => self.master_fields.iter().cloned().zip(values.iter().cloned()).collect(),
+            PackedFields::Own(fields) => fields.clone()
+        };
+
+        StreamEntry { id, fields }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PendingEntry {
+    pub consumer: String,
+    pub delivery_time: u64,
+    pub delivery_count: u64
+}
+
+#[derive(Clone, Debug)]
+pub struct Consumer {
+    pub seen_time: u64,
+    pub active_time: Option<u64>,
+    pub pending: BTreeSet<StreamId>
+}
+
+impl Consumer {
+    fn new(now: u64) -> Consumer {
+        Consumer {
+            seen_time: now,
+            active_time: None,
+            pending: BTreeSet::new()
+        }
+    }
+}
+
+/// A consumer group, tracking the last entry it delivered and every delivered entry that is
+/// still waiting to be acknowledged (the pending entries list).
+#[derive(Clone, Debug)]
+pub struct ConsumerGroup {
+    pub last_delivered_id: StreamId,
+    pub entries_read: Option<u64>,
+    pending: BTreeMap<StreamId, PendingEntry>,
+    consumers: BTreeMap<String, Consumer>
+}
+
+impl ConsumerGroup {
+    fn new(last_delivered_id: StreamId, entries_read: Option<u64>) -> ConsumerGroup {
+        ConsumerGroup {
+            last_delivered_id,
+            entries_read,
+            pending: BTreeMap::new(),
+            consumers: BTreeMap::new()
+        }
+    }
+
+    pub fn pending(&self) -> &BTreeMap<StreamId, PendingEntry> {
+        &self.pending
+    }
+
+    pub fn consumers(&self) -> &BTreeMap<String, Consumer> {
+        &self.consumers
+    }
+
+    pub fn create_consumer(&mut self, name: &str, now: u64) -> bool {
+        if self.consumers.contains_key(name) {
+            return false;
+        }
+
+        self.consumers.insert(name.to_string(), Consumer::new(now));
+        true
+    }
+
+    /// Removes a consumer along with its pending entries, returning how many entries it still had pending.
+    pub fn delete_consumer(&mut self, name: &str) -> Option<usize> {
+        let consumer = self.consumers.remove(name)?;
+
+        for id in &consumer.pending {
+            self.pending.remove(id);
+        }
+
+        Some(consumer.pending.len())
+    }
+
+    /// Marks `name` as seen, creating the consumer on first use.
+    pub fn touch_consumer(&mut self, name: &str, now: u64) -> &mut Consumer {
+        let consumer = self.consumers.entry(name.to_string()).or_insert_with(|| Consumer::new(now));
+        consumer.seen_time = now;
+
+        consumer
+    }
+
+    /// Records that `id` was delivered to `consumer`, moving its ownership if another consumer had it pending.
+    pub fn deliver(&mut self, id: StreamId, consumer: &str, now: u64) {
+        match self.pending.get_mut(&id) {
+            Some(pending) => {
+                if pending.consumer != consumer {
+                    if let Some(previous) = self.consumers.get_mut(&pending.consumer) {
+                        previous.pending.remove(&id);
+                    }
+
+                    pending.consumer = consumer.to_string();
+                }
+
+                pending.delivery_time = now;
+                pending.delivery_count += 1;
+            }
+
+            None => {
+                self.pending.insert(id, PendingEntry { consumer: consumer.to_string(), delivery_time: now, delivery_count: 1 });
+            }
+        }
+
+        self.touch_consumer(consumer, now).pending.insert(id);
+    }
+
+    /// Transfers ownership of `id` to `consumer`, creating the pending entry if needed, and resets its delivery time.
+    /// The delivery count is either overridden or incremented when `increment` is set.
+    pub fn claim(&mut self, id: StreamId, consumer: &str, now: u64, delivery_time: u64, delivery_count: Option<u64>, increment: bool) {
+        let pending = self.pending.entry(id).or_insert_with(|| PendingEntry {
+            consumer: consumer.to_string(),
+            delivery_time,
+            delivery_count: 0
+        });
+
+        if pending.consumer != consumer {
+            if let Some(previous) = self.consumers.get_mut(&pending.consumer) {
+                previous.pending.remove(&id);
+            }
+
+            pending.consumer = consumer.to_string();
+        }
+
+        pending.delivery_time = delivery_time;
+        pending.delivery_count = delivery_count.unwrap_or(pending.delivery_count + increment as u64);
+
+        let consumer = self.touch_consumer(consumer, now);
+        consumer.pending.insert(id);
+        consumer.active_time = Some(now);
+    }
+
+    /// Adds a pending entry read back from an RDB file. Its owner is set by [`ConsumerGroup::restore_consumer`].
+    pub fn restore_pending(&mut self, id: StreamId, delivery_time: u64, delivery_count: u64) {
+        self.pending.insert(id, PendingEntry { consumer: String::new(), delivery_time, delivery_count });
+    }
+
+    /// Adds a consumer read back from an RDB file, taking ownership of its restored pending entries.
+    /// Returns false if one of them isn't in the group's pending entries list.
+    pub fn restore_consumer(&mut self, name: &str, seen_time: u64, active_time: Option<u64>, pending: Vec<StreamId>) -> bool {
+        let mut consumer = Consumer::new(seen_time);
+        consumer.active_time = active_time;
+
+        for id in pending {
+            match self.pending.get_mut(&id) {
+                Some(entry) => entry.consumer = name.to_string(),
+                None => return false
+            }
+
+            consumer.pending.insert(id);
+        }
+
+        self.consumers.insert(name.to_string(), consumer);
+        true
+    }
+
+    pub fn ack(&mut self, id: StreamId) -> bool {
+        match self.pending.remove(&id) {
+            Some(pending) => {
+                if let Some(consumer) = self.consumers.get_mut(&pending.consumer) {
+                    consumer.pending.remove(&id);
+                }
+
+                true
+            }
+
+            None => false
+        }
+    }
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_millis() as u64).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::ops::Bound;
+
+    type Model = BTreeMap<StreamId, Vec<(String, String)>>;
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        /// Appends after the last ID, in the same millisecond for a gap of 0. Odd shapes use fields
+        /// other than the node's master fields.
+        Append { gap: u64, shape: u8 },
+        /// Deletes one of the IDs appended so far, which may already be gone.
+        Delete(usize),
+        TrimMaxLen(usize),
+        TrimMinId(usize)
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            8 => (0..3u64, 0..4u8).prop_map(|(gap, shape)| Op::Append { gap, shape }),
+            2 => any::<usize>().prop_map(Op::Delete),
+            1 => (0..300usize).prop_map(Op::TrimMaxLen),
+            1 => any::<usize>().prop_map(Op::TrimMinId)
+        ]
+    }
+
+    fn stream_id() -> impl Strategy<Value = StreamId> {
+        prop_oneof![
+            10 => (0..1000u64, 0..4u64).prop_map(|(millis, seq)| StreamId::new(millis, seq)),
+            1 => Just(StreamId::MIN),
+            1 => Just(StreamId::MAX)
+        ]
+    }
+
+    fn fields(id: StreamId, shape: u8) -> Vec<(String, String)> {
+        let value = id.to_string();
+
+        match shape % 2 {
+            0 => vec![("field".to_string(), value)],
+            _ => (0..shape).map(|index| (format!("field-{}", index), value.clone())).collect()
+        }
+    }
+
+    /// Runs `ops` against both a stream and a plain ordered map of its live entries.
+    fn build(ops: &[Op]) -> (Stream, Model) {
+        let mut stream = Stream::new();
+        let mut model = Model::new();
+        let mut appended: Vec<StreamId> = vec![];
+
+        for op in ops {
+            match *op {
+                Op::Append { gap, shape } => {
+                    let last = stream.last_id();
+                    let id = match (gap, appended.is_empty()) {
+                        (_, true) => StreamId::new(1, 1),
+                        (0, false) => last.next().unwrap(),
+                        (gap, false) => StreamId::new(last.millis + gap, 0)
+                    };
+
+                    stream.append(id, fields(id, shape));
+                    model.insert(id, fields(id, shape));
+                    appended.push(id);
+                }
+
+                Op::Delete(index) if !appended.is_empty() => {
+                    let id = appended[index % appended.len()];
+                    assert_eq!(stream.delete(id), model.remove(&id).is_some(), "deleting {}", id);
+                }
+
+                Op::TrimMaxLen(max_len) => {
+                    let mut expected = 0;
+
+                    while model.len() > max_len {
+                        model.pop_first();
+                        expected += 1;
+                    }
+
+                    assert_eq!(stream.trim(TrimStrategy::MaxLen(max_len), false, 0), expected);
+                }
+
+                Op::TrimMinId(index) if !appended.is_empty() => {
+                    let min_id = appended[index % appended.len()];
+                    let kept = model.split_off(&min_id);
+                    let expected = std::mem::replace(&mut model, kept).len();
+
+                    assert_eq!(stream.trim(TrimStrategy::MinId(min_id), false, 0), expected);
+                }
+
+                Op::Delete(_) | Op::TrimMinId(_) => {}
+            }
+        }
+
+        (stream, model)
+    }
+
+    fn entries<'a>(model: impl Iterator<Item = (&'a StreamId, &'a Vec<(String, String)>)>) -> Vec<StreamEntry> {
+        model.map(|(id, fields)| StreamEntry { id: *id, fields: fields.clone() }).collect()
+    }
+
+    proptest! {
+        #[test]
+        fn ids_order_by_millis_then_seq(a in (any::<u64>(), any::<u64>()), b in (any::<u64>(), any::<u64>())) {
+            prop_assert_eq!(StreamId::new(a.0, a.1).cmp(&StreamId::new(b.0, b.1)), a.cmp(&b));
+        }
+
+        #[test]
+        fn ids_parse_what_they_display(millis in any::<u64>(), seq in any::<u64>()) {
+            let id = StreamId::new(millis, seq);
+            prop_assert_eq!(StreamId::parse(&id.to_string(), 0).unwrap(), id);
+        }
+
+        #[test]
+        fn next_and_prev_are_the_neighbouring_ids(id in stream_id()) {
+            if let Some(next) = id.next() {
+                prop_assert!(next > id);
+                prop_assert_eq!(next.prev(), Some(id));
+            }
+
+            if let Some(prev) = id.prev() {
+                prop_assert!(prev < id);
+                prop_assert_eq!(prev.next(), Some(id));
+            }
+        }
+
+        #[test]
+        fn range_matches_the_model(ops in prop::collection::vec(op(), 0..600), bounds in prop::collection::vec((stream_id(), stream_id()), 1..8)) {
+            let (stream, model) = build(&ops);
+
+            prop_assert_eq!(stream.len(), model.len());
+            prop_assert_eq!(stream.first_id(), model.keys().next().copied());
+
+            for (start, end) in bounds {
+                let expected = if start <= end { entries(model.range(start..=end)) } else { vec![] };
+
+                prop_assert_eq!(stream.range(start, end).collect::<Vec<_>>(), expected.clone());
+                prop_assert_eq!(stream.rev_range(start, end).collect::<Vec<_>>(), expected.into_iter().rev().collect::<Vec<_>>());
+            }
+        }
+
+        #[test]
+        fn read_matches_the_model(ops in prop::collection::vec(op(), 0..600), reads in prop::collection::vec((stream_id(), 1..50usize), 1..8)) {
+            let (stream, model) = build(&ops);
+            prop_assert_eq!(stream.last_entry(), entries(model.iter().next_back().into_iter()).pop());
+
+            // XREAD returns up to COUNT entries strictly after the ID it is given.
+            for (after, count) in reads {
+                let read: Vec<StreamEntry> = match after.next() {
+                    Some(start) => stream.range(start, StreamId::MAX).take(count).collect(),
+                    None => vec![]
+                };
+
+                let expected = entries(model.range((Bound::Excluded(after), Bound::Unbounded)).take(count));
+                prop_assert_eq!(read, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,412 @@
+//! Offline inspection of the files the server persists to, behind the `check-rdb`, `check-aof` and
+//! `rdb-dump` binaries. Nothing here needs a running server or the modules that wrote the data.
+
+use crate::aof::{AofEntry, AofReader, Manifest};
+use crate::module::{ModuleType, ModuleValue};
+use crate::parser::Value;
+use crate::rdb::{RdbItem, RdbReader};
+use crate::stream::{Stream, StreamId};
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What `check-rdb` found walking an RDB file.
+#[derive(Debug, Default)]
+pub struct RdbReport {
+    pub size: u64,
+    pub aux: Vec<(String, String)>,
+    pub functions: u64,
+    pub keys_per_db: BTreeMap<u64, u64>,
+    pub keys_per_type: BTreeMap<String, u64>,
+    pub expires: u64,
+    pub already_expired: u64,
+    /// Set when the file couldn't be read to its end.
+    pub corruption: Option<Corruption>
+}
+
+#[derive(Debug)]
+pub struct Corruption {
+    pub message: String,
+    /// Where the data stopped making sense.
+    pub offset: u64,
+    /// Where the item holding the corrupted data starts, for RDB files.
+    pub item_offset: u64
+}
+
+/// Stands in for the module types of an RDB file, which aren't loaded offline: values are kept as the
+/// bytes the module saved.
+struct OpaqueModuleType {
+    name: String
+}
+
+#[derive(Clone, Debug)]
+struct OpaqueModuleData {
+    encoding_version: u16,
+    payload: Vec<u8>
+}
+
+impl ModuleType for OpaqueModuleType {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn rdb_save(&self, value: &ModuleValue) -> Vec<u8> {
+        value.downcast_ref::<OpaqueModuleData>().map(|data| data.payload.clone()).unwrap_or_default()
+    }
+
+    fn rdb_load(&self, bytes: &[u8], encoding_version: u16) -> Result<ModuleValue> {
+        Ok(ModuleValue::new(&self.name, OpaqueModuleData { encoding_version, payload: bytes.to_vec() }))
+    }
+}
+
+/// Reads an RDB file the way the server loads it, handing every item to `visit`. Fails with the
+/// corruption found, if any, once the items before it were visited.
+fn walk_rdb<F>(path: &Path, visit: F) -> Result<Option<Corruption>>
+where
+    F: FnMut(RdbItem) -> Result<()>
+{
+    let file = File::open(path).map_err(|e| anyhow!("Can't open {}: {}", path.display(), e))?;
+    let mut reader = RdbReader::new(BufReader::new(file));
+    let module_types = |name: &str| Some(Arc::new(OpaqueModuleType { name: name.to_string() }) as Arc<dyn ModuleType>);
+
+    match reader.read_file(&module_types, visit) {
+        Ok(()) => Ok(None),
+        Err(e) => Ok(Some(Corruption { message: e.to_string(), offset: reader.position(), item_offset: reader.item_start() }))
+    }
+}
+
+/// Walks an RDB file, counting what it holds and locating the first corruption.
+pub fn check_rdb(path: &Path) -> Result<RdbReport> {
+    let mut report = RdbReport { size: std::fs::metadata(path)?.len(), ..RdbReport::default() };
+    let now = SystemTime::now();
+
+    report.corruption = walk_rdb(path, |item| {
+        match item {
+            RdbItem::Aux(key, value) => report.aux.push((key, value)),
+            RdbItem::Function(_) => report.functions += 1,
+            RdbItem::Key { db, value, expire, .. } => {
+                *report.keys_per_db.entry(db).or_default() += 1;
+                *report.keys_per_type.entry(type_name(&value)).or_default() += 1;
+
+                if let Some(expire) = expire {
+                    report.expires += 1;
+                    report.already_expired += (expire < now) as u64;
+                }
+            }
+        }
+
+        Ok(())
+    })?;
+
+    Ok(report)
+}
+
+/// Writes every key of an RDB file as a line of JSON: its database, name, type, time to live in
+/// milliseconds (negative once expired, null without one) and value. Returns how many keys were written.
+pub fn dump_rdb<W: Write>(path: &Path, mut out: W) -> Result<u64> {
+    let now = SystemTime::now();
+    let mut keys = 0;
+
+    let corruption = walk_rdb(path, |item| {
+        let RdbItem::Key { db, key, value, expire } = item else {
+            return Ok(());
+        };
+
+        let ttl = match expire {
+            Some(expire) => millis(expire).saturating_sub(millis(now)).to_string(),
+            None => "null".to_string()
+        };
+
+        writeln!(
+            out, "{{\"db\":{},\"key\":{},\"type\":{},\"ttl\":{},\"value\":{}}}",
+            db, json_string(&key), json_string(&type_name(&value)), ttl, json_value(&value)
+        )?;
+
+        keys += 1;
+        Ok(())
+    })?;
+
+    match corruption {
+        Some(corruption) => Err(anyhow!("{} (item starting at offset {})", corruption.message, corruption.item_offset)),
+        None => Ok(keys)
+    }
+}
+
+fn millis(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_millis() as i64,
+        Err(e) => -(e.duration().as_millis() as i64)
+    }
+}
+
+fn type_name(value: &Value) -> String {
+    match value {
+        Value::Module(value) => value.type_name().to_string(),
+        value => value.get_type().to_string().to_lowercase()
+    }
+}
+
+fn json_value(value: &Value) -> String {
+    match value {
+        Value::Array(elements) => json_array(elements.iter().map(|element| json_string(&String::from_utf8_lossy(&element.clone().unpack_as_bytes().unwrap_or_default())))),
+
+        Value::Set(members) => {
+            let mut members: Vec<&String> = members.iter().collect();
+            members.sort();
+            json_array(members.into_iter().map(|member| json_string(member)))
+        }
+
+        Value::Hash(fields) => {
+            let fields: BTreeMap<&String, &String> = fields.iter().collect();
+            json_object(fields.into_iter().map(|(field, value)| (field.as_str(), json_string(value))))
+        }
+
+        Value::SortedSet(sorted_set) => json_array(sorted_set.iter().map(|(member, score)| json_array([json_string(member), json_number(score)].into_iter()))),
+        Value::Stream(stream) => json_stream(stream),
+
+        Value::Module(value) => {
+            let (encoding_version, payload) = value.downcast_ref::<OpaqueModuleData>()
+                .map(|data| (data.encoding_version, data.payload.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()))
+                .unwrap_or_default();
+
+            json_object([("encoding_version", encoding_version.to_string()), ("payload", json_string(&payload))].into_iter())
+        }
+
+        value => json_string(&String::from_utf8_lossy(&value.clone().unpack_as_bytes().unwrap_or_default()))
+    }
+}
+
+fn json_stream(stream: &Stream) -> String {
+    let entries = stream.range(StreamId::MIN, StreamId::MAX).map(|entry| json_object([
+        ("id", json_string(&entry.id.to_string())),
+        ("fields", json_object(entry.fields.iter().map(|(field, value)| (field.as_str(), json_string(value)))))
+    ].into_iter()));
+
+    let groups = stream.groups().iter().map(|(name, group)| {
+        let pending = group.pending().iter().map(|(id, pending)| json_object([
+            ("id", json_string(&id.to_string())),
+            ("consumer", json_string(&pending.consumer)),
+            ("delivery_time", pending.delivery_time.to_string()),
+            ("delivery_count", pending.delivery_count.to_string())
+        ].into_iter()));
+
+        json_object([
+            ("name", json_string(name)),
+            ("last_delivered_id", json_string(&group.last_delivered_id.to_string())),
+            ("entries_read", group.entries_read.map(|entries_read| entries_read.to_string()).unwrap_or("null".to_string())),
+            ("consumers", json_array(group.consumers().keys().map(|consumer| json_string(consumer)))),
+            ("pending", json_array(pending))
+        ].into_iter())
+    });
+
+    json_object([
+        ("last_id", json_string(&stream.last_id().to_string())),
+        ("entries_added", stream.entries_added().to_string()),
+        ("max_deleted_entry_id", json_string(&stream.max_deleted_entry_id().to_string())),
+        ("entries", json_array(entries)),
+        ("groups", json_array(groups))
+    ].into_iter())
+}
+
+fn json_array(elements: impl Iterator<Item = String>) -> String {
+    format!("[{}]", elements.collect::<Vec<_>>().join(","))
+}
+
+fn json_object<'a>(fields: impl Iterator<Item = (&'a str, String)>) -> String {
+    format!("{{{}}}", fields.map(|(name, value)| format!("{}:{}", json_string(name), value)).collect::<Vec<_>>().join(","))
+}
+
+/// JSON has no infinities, so those scores are written as strings.
+fn json_number(number: f64) -> String {
+    if number.is_finite() {
+        number.to_string()
+    } else {
+        json_string(&number.to_string())
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+
+    escaped.push('"');
+    escaped
+}
+
+/// What `check-aof` found in one of the files of an AOF.
+#[derive(Debug)]
+pub struct AofFileReport {
+    pub name: String,
+    pub size: u64,
+    /// Commands read, or keys for an RDB formatted base file.
+    pub items: u64,
+    /// Set when the file couldn't be read to its end.
+    pub corruption: Option<Corruption>
+}
+
+/// Checks an AOF: every file its manifest lists when given the manifest, or else the single file given.
+pub fn check_aof(path: &Path) -> Result<Vec<AofFileReport>> {
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+
+    let Some(file_name) = name.strip_suffix(".manifest") else {
+        return Ok(vec![check_aof_file(path)?]);
+    };
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    let manifest = Manifest::load(dir, file_name)?
+        .ok_or_else(|| anyhow!("Can't open the manifest {}", path.display()))?;
+
+    manifest.base.iter()
+        .chain(&manifest.incremental)
+        .map(|file| check_aof_file(&dir.join(&file.name)))
+        .collect()
+}
+
+/// Checks a single AOF file. An RDB formatted base is walked like `check-rdb` does, other files must hold
+/// whole commands, with every MULTI matched by an EXEC.
+pub fn check_aof_file(path: &Path) -> Result<AofFileReport> {
+    let name = path.display().to_string();
+    let size = std::fs::metadata(path).map_err(|e| anyhow!("Can't open {}: {}", name, e))?.len();
+
+    if name.ends_with(".rdb") {
+        let report = check_rdb(path)?;
+        let items = report.keys_per_db.values().sum();
+
+        return Ok(AofFileReport { name, size, items, corruption: report.corruption });
+    }
+
+    let mut reader = AofReader::new(BufReader::new(File::open(path)?));
+    let mut items = 0;
+    let mut before_multi: Option<u64> = None;
+
+    let corruption = loop {
+        let offset = reader.offset();
+
+        let command_name = match reader.next_entry()? {
+            AofEntry::Command(args) => String::from_utf8_lossy(&args[0]).to_lowercase(),
+            AofEntry::End => match before_multi {
+                Some(before_multi) => break Some(("Reached the end of the file inside a MULTI/EXEC block".to_string(), before_multi)),
+                None => break None
+            },
+
+            AofEntry::Truncated => break Some(("The file ends in the middle of a command".to_string(), before_multi.unwrap_or(offset))),
+            AofEntry::Corrupt(reason) => break Some((format!("Bad file format: {}", reason), before_multi.unwrap_or(offset)))
+        };
+
+        match command_name.as_str() {
+            "multi" if before_multi.is_some() => break Some(("Unexpected MULTI inside a MULTI/EXEC block".to_string(), before_multi.unwrap())),
+            "multi" => before_multi = Some(offset),
+            "exec" if before_multi.is_none() => break Some(("Unexpected EXEC without MULTI".to_string(), offset)),
+            "exec" => before_multi = None,
+            _ => {}
+        }
+
+        items += 1;
+    };
+
+    let corruption = corruption.map(|(message, offset)| Corruption { message, offset, item_offset: offset });
+    Ok(AofFileReport { name, size, items, corruption })
+}
+
+/// Cuts an AOF file at `offset`, dropping the damaged commands from there on.
+pub fn truncate_aof(path: &Path, offset: u64) -> Result<()> {
+    let file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(offset)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Reads the header of an RDB file, returning its format version.
+pub fn rdb_version(path: &Path) -> Result<u16> {
+    let mut header = [0; 9];
+    File::open(path)?.read_exact(&mut header).map_err(|_| anyhow!("The file is too short to be an RDB file"))?;
+
+    match header.strip_prefix(b"REDIS").and_then(|version| std::str::from_utf8(version).ok()?.parse::<u16>().ok()) {
+        Some(version) => Ok(version),
+        None => Err(anyhow!("Wrong signature trying to load DB from file"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdb::{RdbWriter, OPCODE_SELECTDB, TYPE_STRING};
+
+    fn write_temp(name: &str, bytes: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("check-rdb-{}-{}.rdb", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn sample_rdb() -> Vec<u8> {
+        let no_modules = |_: &str| None;
+        let mut writer = RdbWriter::new(Vec::new());
+
+        writer.write_header().unwrap();
+        writer.write_aux("redis-ver", "7.2.0").unwrap();
+        writer.write_select_db(0, 2, 0).unwrap();
+        writer.write_key("first", &Value::BulkString("one".into()), None, &no_modules).unwrap();
+        writer.write_key("second", &Value::BulkString("two".into()), None, &no_modules).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn check_rdb_accepts_a_whole_file() {
+        let path = write_temp("whole", &sample_rdb());
+        let report = check_rdb(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(report.corruption.is_none());
+        assert_eq!(report.keys_per_db.get(&0), Some(&2));
+    }
+
+    #[test]
+    fn check_rdb_reports_a_truncated_file() {
+        let rdb = sample_rdb();
+        let truncated = &rdb[..rdb.len() - 12];
+
+        let path = write_temp("truncated", truncated);
+        let report = check_rdb(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let corruption = report.corruption.expect("a truncated file is corrupt");
+        assert!(corruption.message.contains("Unexpected end of file"), "{}", corruption.message);
+        assert!(corruption.offset <= truncated.len() as u64);
+        assert_eq!(report.keys_per_db.get(&0), Some(&1));
+    }
+
+    #[test]
+    fn check_rdb_reports_an_oversized_length() {
+        // A string value claiming 2^62 bytes behind a 64 bit length, followed by only 3.
+        let mut rdb = b"REDIS0011".to_vec();
+        rdb.extend_from_slice(&[OPCODE_SELECTDB, 0, TYPE_STRING, 1, b'k', 0x81]);
+        rdb.extend_from_slice(&(1u64 << 62).to_be_bytes());
+        rdb.extend_from_slice(b"abc");
+
+        let path = write_temp("oversized", &rdb);
+        let report = check_rdb(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let corruption = report.corruption.expect("an oversized length is corrupt");
+        assert!(corruption.message.contains("Unexpected end of file"), "{}", corruption.message);
+        assert_eq!(corruption.offset, rdb.len() as u64 - 3);
+        assert_eq!(corruption.item_offset, 11);
+    }
+}
@@ -0,0 +1,89 @@
+//! Runs a server with the AOF on, killing and restarting it in the same directory to load what it logged.
+
+mod common;
+
+use common::{Server, TIMEOUT};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// The files the manifest lists, base first.
+fn manifest(server: &Server) -> Vec<String> {
+    let text = std::fs::read_to_string(server.dir.join("appendonlydir/appendonly.aof.manifest")).unwrap();
+    text.lines().map(|line| line.split_whitespace().nth(1).unwrap().to_string()).collect()
+}
+
+fn aof_file(server: &Server, name: &str) -> PathBuf {
+    server.dir.join("appendonlydir").join(name)
+}
+
+#[test]
+fn a_truncated_aof_only_loads_with_aof_load_truncated() {
+    let mut server = Server::start("aof-truncated", &["--appendonly", "yes", "--appendfsync", "always"]);
+    let mut client = server.connect();
+
+    assert_eq!(client.call(&["SET", "a", "1"]), "OK");
+    assert_eq!(client.call(&["SET", "b", "2"]), "OK");
+
+    let incremental = aof_file(&server, &manifest(&server)[1]);
+    let logged = std::fs::metadata(&incremental).unwrap().len();
+
+    // The server dies while writing a command.
+    server.kill();
+    OpenOptions::new().append(true).open(&incremental).unwrap().write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nc").unwrap();
+
+    server.restart(&["--appendonly", "yes", "--aof-load-truncated", "no"]);
+    assert_eq!(server.wait_for_exit().code(), Some(1));
+
+    server.restart(&["--appendonly", "yes"]);
+    let mut client = server.connect();
+
+    assert_eq!(client.call(&["GET", "a"]), "1");
+    assert_eq!(client.call(&["GET", "b"]), "2");
+    assert_eq!(client.call(&["GET", "c"]), "nil");
+
+    // The incomplete command is cut off, so the next write is logged after the last complete one.
+    assert_eq!(std::fs::metadata(&incremental).unwrap().len(), logged);
+    assert_eq!(client.call(&["SET", "c", "3"]), "OK");
+
+    server.restart(&["--appendonly", "yes", "--aof-load-truncated", "no"]);
+    assert_eq!(server.connect().call(&["GET", "c"]), "3");
+}
+
+#[test]
+fn a_rewrite_swaps_in_a_new_base() {
+    for (preamble, base) in [("yes", "appendonly.aof.2.base.rdb"), ("no", "appendonly.aof.2.base.aof")] {
+        let mut server = Server::start(&format!("aof-rewrite-{}", preamble), &["--appendonly", "yes", "--aof-use-rdb-preamble", preamble]);
+        let mut client = server.connect();
+
+        assert_eq!(client.call(&["SET", "before", "rewrite"]), "OK");
+        let first = manifest(&server);
+
+        assert!(client.call(&["BGREWRITEAOF"]).starts_with("Background append only file rewriting"));
+
+        let started = Instant::now();
+
+        while manifest(&server) != [base, "appendonly.aof.2.incr.aof"] {
+            assert!(started.elapsed() < TIMEOUT, "The manifest still lists {:?}", manifest(&server));
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        // The new base holds what the replaced files logged, so they are gone.
+        for replaced in first {
+            assert!(!aof_file(&server, &replaced).exists(), "{} wasn't deleted", replaced);
+        }
+
+        let written = std::fs::read(aof_file(&server, base)).unwrap();
+        assert_eq!(written.starts_with(b"REDIS"), preamble == "yes");
+        assert_eq!(written.starts_with(b"*3\r\n$3\r\nSET\r\n"), preamble == "no");
+
+        assert_eq!(client.call(&["SET", "after", "rewrite"]), "OK");
+
+        server.restart(&["--appendonly", "yes"]);
+        let mut client = server.connect();
+
+        assert_eq!(client.call(&["GET", "before"]), "rewrite");
+        assert_eq!(client.call(&["GET", "after"]), "rewrite");
+    }
+}
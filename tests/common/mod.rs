@@ -0,0 +1,146 @@
+//! Runs `redis-rust` processes for the integration tests, each in a directory of its own, and talks
+//! to them over RESP.
+
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+pub const TIMEOUT: Duration = Duration::from_secs(15);
+
+pub struct Server {
+    process: Child,
+    pub dir: PathBuf,
+    pub port: u16
+}
+
+impl Server {
+    /// Starts a server in a new empty directory, named after `name`.
+    pub fn start(name: &str, args: &[&str]) -> Server {
+        let dir = std::env::temp_dir().join(format!("redis-rust-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let (process, port) = Server::spawn(&dir, args);
+        Server { process, dir, port }
+    }
+
+    /// Kills the server without letting it save anything, as a crash would.
+    pub fn kill(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+
+    /// Kills the server and starts another in the same directory, on another port.
+    pub fn restart(&mut self, args: &[&str]) {
+        self.kill();
+        (self.process, self.port) = Server::spawn(&self.dir, args);
+    }
+
+    /// Waits for the server to exit on its own, failing after a while.
+    pub fn wait_for_exit(&mut self) -> ExitStatus {
+        let started = Instant::now();
+
+        loop {
+            if let Some(status) = self.process.try_wait().unwrap() {
+                return status;
+            }
+
+            assert!(started.elapsed() < TIMEOUT, "The server on port {} didn't exit", self.port);
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    fn spawn(dir: &Path, args: &[&str]) -> (Child, u16) {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        let process = Command::new(env!("CARGO_BIN_EXE_redis-rust"))
+            .current_dir(dir)
+            .args(["--port", &port.to_string()])
+            .args(args)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        (process, port)
+    }
+
+    pub fn connect(&self) -> Client {
+        let started = Instant::now();
+
+        loop {
+            match TcpStream::connect(("127.0.0.1", self.port)) {
+                Ok(stream) => return Client { reader: BufReader::new(stream.try_clone().unwrap()), stream },
+                Err(e) if started.elapsed() > TIMEOUT => panic!("The server on port {} never came up: {}", self.port, e),
+                Err(_) => std::thread::sleep(Duration::from_millis(20))
+            }
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.kill();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub struct Client {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>
+}
+
+impl Client {
+    /// Sends a command and returns its reply, flattened to text: `nil` for nulls, `-` and the
+    /// message for errors, and the elements separated by spaces for arrays.
+    pub fn call(&mut self, args: &[&str]) -> String {
+        let mut command = format!("*{}\r\n", args.len());
+
+        for arg in args {
+            command.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+        }
+
+        self.stream.write_all(command.as_bytes()).unwrap();
+        self.read_reply()
+    }
+
+    pub fn read_reply(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+
+        match line.split_at(1) {
+            ("+" | ":", rest) => rest.to_string(),
+            ("-", _) => line.to_string(),
+            ("$", "-1") | ("*", "-1") | ("_", _) => "nil".to_string(),
+
+            ("$", length) => {
+                let mut bulk = vec![0; length.parse::<usize>().unwrap() + 2];
+                self.reader.read_exact(&mut bulk).unwrap();
+                String::from_utf8_lossy(&bulk[..bulk.len() - 2]).into_owned()
+            }
+
+            ("*", count) => (0..count.parse::<usize>().unwrap()).map(|_| self.read_reply()).collect::<Vec<_>>().join(" "),
+            _ => panic!("Unexpected reply {:?}", line)
+        }
+    }
+
+    /// Calls a command until it replies `expected`, failing after a while.
+    pub fn wait_for(&mut self, args: &[&str], expected: &str) {
+        let started = Instant::now();
+
+        loop {
+            let reply = self.call(args);
+
+            if reply == expected {
+                return;
+            }
+
+            assert!(started.elapsed() < TIMEOUT, "{:?} replied {:?} instead of {:?}", args, reply, expected);
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
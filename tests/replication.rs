@@ -0,0 +1,72 @@
+//! Runs masters and replicas as `redis-rust` processes, each in a directory of its own.
+
+mod common;
+
+use common::Server;
+
+#[test]
+fn replica_syncs_with_the_default_configuration() {
+    let master = Server::start("master", &[]);
+    let mut master_client = master.connect();
+
+    assert_eq!(master_client.call(&["SET", "before", "sync"]), "OK");
+
+    let master_address = format!("127.0.0.1 {}", master.port);
+    let replica = Server::start("replica", &["--replicaof", &master_address]);
+    let mut replica_client = replica.connect();
+
+    // Only a full resync brings the key written before the replica came up.
+    replica_client.wait_for(&["GET", "before"], "sync");
+    assert!(replica.dir.join("dump.rdb").exists(), "the replica keeps the RDB file it was sent");
+
+    assert_eq!(master_client.call(&["SET", "after", "stream"]), "OK");
+    assert_eq!(master_client.call(&["WAIT", "1", "5000"]), "1");
+    assert_eq!(replica_client.call(&["GET", "after"]), "stream");
+
+    assert!(replica_client.call(&["SET", "refused", "write"]).starts_with("-READONLY"));
+}
+
+#[test]
+fn replica_syncs_with_a_diskless_master() {
+    let master = Server::start("diskless-master", &["--repl-diskless-sync", "yes"]);
+    let mut master_client = master.connect();
+
+    assert_eq!(master_client.call(&["XADD", "events", "1-1", "name", "first"]), "1-1");
+
+    let master_address = format!("127.0.0.1 {}", master.port);
+    let replica = Server::start("diskless-replica", &["--replicaof", &master_address]);
+    let mut replica_client = replica.connect();
+
+    replica_client.wait_for(&["XRANGE", "events", "-", "+"], "1-1 name first");
+    assert!(!master.dir.join("dump.rdb").exists(), "the master streams its dataset without saving it");
+
+    assert_eq!(master_client.call(&["XADD", "events", "2-1", "name", "second"]), "2-1");
+    assert_eq!(master_client.call(&["WAIT", "1", "5000"]), "1");
+    assert_eq!(replica_client.call(&["XRANGE", "events", "-", "+"]), "1-1 name first 2-1 name second");
+}
+
+#[test]
+fn promoted_replica_partially_resyncs_the_other_replicas() {
+    let master = Server::start("psync-master", &[]);
+    let mut master_client = master.connect();
+
+    let master_address = format!("127.0.0.1 {}", master.port);
+    let promoted = Server::start("psync-promoted", &["--replicaof", &master_address]);
+    let other = Server::start("psync-other", &["--replicaof", &master_address]);
+    let mut promoted_client = promoted.connect();
+    let mut other_client = other.connect();
+
+    assert_eq!(master_client.call(&["SET", "from", "master"]), "OK");
+    assert_eq!(master_client.call(&["WAIT", "2", "5000"]), "2");
+
+    assert_eq!(promoted_client.call(&["REPLICAOF", "NO", "ONE"]), "OK");
+    assert_eq!(promoted_client.call(&["SET", "from", "promoted"]), "OK");
+
+    // The promoted replica still knows the history of the former master by its second id, so the
+    // other replica resumes from its backlog rather than loading a new RDB file.
+    std::fs::remove_file(other.dir.join("dump.rdb")).unwrap();
+    assert_eq!(other_client.call(&["REPLICAOF", "127.0.0.1", &promoted.port.to_string()]), "OK");
+
+    other_client.wait_for(&["GET", "from"], "promoted");
+    assert!(!other.dir.join("dump.rdb").exists(), "the other replica was sent a new RDB file");
+}